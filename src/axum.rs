@@ -0,0 +1,66 @@
+//! One-call setup for Axum apps.
+
+use crate::optimizer::CACHE_DIR;
+use crate::{ImageCacheRouteWith, ImageOptimizer, PriorityImages};
+use ::axum::extract::Request;
+use ::axum::http::{header, HeaderValue};
+use ::axum::middleware::Next;
+use ::axum::response::Response;
+use ::axum::{Extension, Router};
+use tower_http::services::ServeDir;
+
+/// Mounts everything the optimizer needs on a router:
+///
+/// - the cache handler at the optimizer's handler path,
+/// - the cache directory as static files, so direct links to cached images (e.g. from
+///   [`ImageOptimizer::manifest`]) work whatever the router's fallback is,
+/// - the optimizer as a request extension, so `<Image/>` and the crate's server
+///   functions find it without [`ImageOptimizer::provide_context`].
+///
+/// The state needs no `FromRef` for the optimizer. Layers only apply to the routes
+/// added before them, so attach after the Leptos routes:
+///
+/// ```
+/// # use leptos::prelude::*;
+/// # use leptos_axum::{generate_route_list, LeptosRoutes};
+/// # use leptos_image::*;
+/// # #[cfg(feature = "ssr")]
+/// # async fn main() {
+/// let options = get_configuration(None).unwrap().leptos_options;
+/// let optimizer = ImageOptimizer::new("/__cache/image", options.site_root.to_string(), 1);
+/// let router = axum::Router::new().leptos_routes(&options, generate_route_list(App), App);
+/// let router = leptos_image::axum::attach(router, &optimizer).with_state(options);
+/// # }
+/// # #[component]
+/// # fn App() -> impl IntoView {}
+/// ```
+pub fn attach<S>(router: Router<S>, optimizer: &ImageOptimizer) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    router
+        .image_cache_route_with(optimizer)
+        .nest_service(&format!("/{CACHE_DIR}"), ServeDir::new(&optimizer.cache_dir))
+        .layer(Extension(optimizer.clone()))
+}
+
+/// Middleware adding a `Link` header that preloads the `<Image priority/>`s of each
+/// page, so browsers fetch them before parsing the HTML, and CDNs can turn it into
+/// `103 Early Hints` or HTTP/2 push. See [`PriorityImages`] for images it misses.
+///
+/// ```
+/// # use leptos_image::*;
+/// # fn router(router: axum::Router) -> axum::Router {
+/// router.layer(axum::middleware::from_fn(leptos_image::axum::preload_priority_images))
+/// # }
+/// ```
+pub async fn preload_priority_images(mut request: Request, next: Next) -> Response {
+    let images = PriorityImages::default();
+    request.extensions_mut().insert(images.clone());
+    let mut response = next.run(request).await;
+    let link = images.link_header().and_then(|link| HeaderValue::from_str(&link).ok());
+    if let Some(link) = link {
+        response.headers_mut().append(header::LINK, link);
+    }
+    response
+}
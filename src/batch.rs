@@ -0,0 +1,124 @@
+use crate::optimizer::{CachedImage, Placeholder};
+use leptos::prelude::*;
+use std::collections::HashMap;
+
+/// Placeholders held in memory, keyed by their image. Serialized as pairs, as JSON
+/// maps only have string keys.
+#[doc(hidden)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(from = "Vec<(CachedImage, String)>", into = "Vec<(CachedImage, String)>")]
+pub struct Placeholders(HashMap<CachedImage, String>);
+
+impl From<Vec<(CachedImage, String)>> for Placeholders {
+    fn from(placeholders: Vec<(CachedImage, String)>) -> Self {
+        Self(placeholders.into_iter().collect())
+    }
+}
+
+impl From<Placeholders> for Vec<(CachedImage, String)> {
+    fn from(placeholders: Placeholders) -> Self {
+        placeholders.0.into_iter().collect()
+    }
+}
+
+/// The placeholders of a batch, see [`provide_placeholder_batch`].
+#[derive(Clone, Copy)]
+pub(crate) struct PlaceholderBatch(Resource<Placeholders>);
+
+impl PlaceholderBatch {
+    /// The placeholder held for `image`, `None` until the batch is resolved.
+    pub(crate) fn get(&self, image: &CachedImage) -> Option<Option<String>> {
+        self.0
+            .with(|placeholders| placeholders.as_ref().map(|held| held.0.get(image).cloned()))
+    }
+}
+
+/// Resolves the placeholders of many `<Image/>`s below this point in one request,
+/// e.g. for a gallery rendered with `<For>`. Each image then finds its placeholder
+/// in one lookup, instead of searching every placeholder of the image context.
+///
+/// `srcs` are the sources of the images, tracked like the source of a resource, and
+/// `placeholder` their kind. Pair it with
+/// `ImageOptimizer::with_context_placeholders(false)` so pages only carry the
+/// placeholders of their batches. Images with other placeholder props, or whose
+/// placeholder isn't held in memory yet, are looked up as without a batch.
+///
+/// ```
+/// use leptos::prelude::*;
+/// use leptos_image::*;
+///
+/// #[component]
+/// fn Gallery(photos: Vec<String>) -> impl IntoView {
+///     let srcs = photos.clone();
+///     provide_placeholder_batch(move || srcs.clone(), Placeholder::Blur);
+///     view! {
+///         <For each=move || photos.clone() key=|src| src.clone() let:src>
+///             <Image src=src width=300 height=200 alt="Photo" />
+///         </For>
+///     }
+/// }
+/// ```
+pub fn provide_placeholder_batch(
+    srcs: impl Fn() -> Vec<String> + Send + Sync + 'static,
+    placeholder: Placeholder,
+) {
+    let tenant = use_context::<crate::ImageTenant>().map(|tenant| tenant.0);
+    let resource = Resource::new(srcs, move |srcs| {
+        let images = srcs
+            .iter()
+            .map(|src| crate::image::placeholder_image(src, tenant.clone(), placeholder))
+            .collect();
+        async move { get_placeholders(images).await.unwrap_or_default() }
+    });
+    provide_context(PlaceholderBatch(resource));
+}
+
+#[cfg(feature = "ssr")]
+impl crate::ImageOptimizer {
+    /// Whether the image context carries every placeholder held in memory, `true` by
+    /// default. Turn it off when pages resolve their placeholders with
+    /// [`provide_placeholder_batch`], as the context grows with the placeholders of
+    /// every page and is sent with each of them. Images outside a batch then load
+    /// their placeholders by URL, and show no gradients.
+    ///
+    /// ```
+    /// # use leptos_image::*;
+    /// let optimizer = ImageOptimizer::new("/__cache/image", "./target/site", 1)
+    ///     .with_context_placeholders(false);
+    /// ```
+    pub fn with_context_placeholders(mut self, enabled: bool) -> Self {
+        self.context_placeholders = enabled;
+        self
+    }
+}
+
+#[server(GetPlaceholders)]
+pub(crate) async fn get_placeholders(
+    images: Vec<CachedImage>,
+) -> Result<Placeholders, ServerFnError> {
+    let optimizer = crate::provider::use_optimizer()?;
+    let held = images
+        .into_iter()
+        .filter_map(|image| {
+            let placeholder = optimizer.cache.get(&image)?.value().clone();
+            Some((image, placeholder))
+        })
+        .collect();
+    Ok(Placeholders(held))
+}
+
+#[cfg(all(test, feature = "ssr"))]
+mod batch_tests {
+    use super::*;
+
+    #[test]
+    fn serializes_as_pairs() {
+        let image = crate::image::placeholder_image("/ferris.png", None, Placeholder::Blur);
+        let placeholders = Placeholders::from(vec![(image.clone(), "<svg/>".to_string())]);
+
+        let json = serde_json::to_string(&placeholders).unwrap();
+        let back: Placeholders = serde_json::from_str(&json).unwrap();
+        assert_eq!(back, placeholders);
+        assert_eq!(back.0.get(&image).map(String::as_str), Some("<svg/>"));
+    }
+}
@@ -0,0 +1,92 @@
+//! Command line tools for the leptos_image cache.
+//!
+//! ```text
+//! leptos-image manifest <site-root> [--handler-path PATH] [--asset-prefix URL] [--output FILE]
+//! leptos-image migrate <site-root>
+//! ```
+
+use leptos_image::ImageOptimizer;
+use std::process::ExitCode;
+
+const USAGE: &str = "Usage: leptos-image manifest <site-root> [--handler-path PATH] \
+                     [--asset-prefix URL] [--output FILE]
+       leptos-image migrate <site-root>";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.first().map(String::as_str) {
+        Some("manifest") => manifest(&args[1..]),
+        Some("migrate") => migrate(&args[1..]),
+        Some("-h" | "--help") => {
+            println!("{USAGE}");
+            return ExitCode::SUCCESS;
+        }
+        _ => Err(USAGE.to_string()),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+// Writes the manifest of the cache below the site root as JSON.
+fn manifest(args: &[String]) -> Result<(), String> {
+    let mut root = None;
+    let mut handler_path = "/__cache/image".to_string();
+    let mut asset_prefix = String::new();
+    let mut output = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().cloned().ok_or(format!("Missing value for {arg}"));
+        match arg.as_str() {
+            "--handler-path" => handler_path = value()?,
+            "--asset-prefix" => asset_prefix = value()?,
+            "--output" | "-o" => output = Some(value()?),
+            flag if flag.starts_with('-') => return Err(format!("Unknown option {flag}\n{USAGE}")),
+            path if root.is_none() => root = Some(path.to_string()),
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+    let root = root.ok_or(USAGE.to_string())?;
+
+    let optimizer = ImageOptimizer::new(handler_path, root, 1).with_asset_prefix(asset_prefix);
+    let manifest = optimizer.manifest().map_err(|e| format!("Failed to read cache: {e}"))?;
+    let json = manifest.to_json();
+    match output {
+        Some(output) => std::fs::write(&output, json)
+            .map_err(|e| format!("Failed to write {output}: {e}"))?,
+        None => println!("{json}"),
+    }
+    eprintln!("Listed {} cached images", manifest.images.len());
+    Ok(())
+}
+
+// Moves the cached files below the site root to their current paths, listing those
+// that couldn't be moved. Fails if any couldn't, so deploy scripts notice.
+fn migrate(args: &[String]) -> Result<(), String> {
+    let root = match args {
+        [root] if !root.starts_with('-') => root,
+        _ => return Err(USAGE.to_string()),
+    };
+
+    let optimizer = ImageOptimizer::new("/__cache/image", root, 1);
+    let report = optimizer
+        .migrate_cache()
+        .map_err(|e| format!("Failed to read cache: {e}"))?;
+    for (path, reason) in &report.failed {
+        eprintln!("Couldn't migrate {}: {reason}", path.display());
+    }
+    eprintln!(
+        "Migrated {} cached images, {} already in place",
+        report.migrated, report.unchanged
+    );
+    if report.failed.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("{} cached images weren't migrated", report.failed.len()))
+    }
+}
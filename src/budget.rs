@@ -0,0 +1,151 @@
+use crate::optimizer::{CachedImageOption, CreateImageError, Fit, ImageOptimizer};
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// The budget is counted in MiB, so large budgets fit the semaphore.
+const MIB: u64 = 1024 * 1024;
+
+/// Decoded pixels are RGBA8 at most, after tonemapping.
+const BYTES_PER_PIXEL: u64 = 4;
+
+/// Approximate memory shared by the running encodes, see
+/// [`ImageOptimizer::with_memory_budget`].
+#[derive(Debug)]
+pub(crate) struct MemoryBudget {
+    semaphore: Arc<Semaphore>,
+    // Permits of the whole budget, in MiB rounded down.
+    capacity: u32,
+}
+
+impl MemoryBudget {
+    pub(crate) fn new(max_bytes: u64) -> Self {
+        let capacity = (max_bytes / MIB).clamp(1, u64::from(u32::MAX)) as u32;
+        Self {
+            semaphore: Arc::new(Semaphore::new(capacity as usize)),
+            capacity,
+        }
+    }
+
+    /// Waits until `bytes` fit next to the running encodes. Encodes needing more than
+    /// the whole budget are rejected.
+    pub(crate) async fn reserve(
+        &self,
+        bytes: u64,
+    ) -> Result<OwnedSemaphorePermit, CreateImageError> {
+        // Compared in permits, as requests are rounded up and the budget down.
+        let mib = bytes.div_ceil(MIB).clamp(1, u64::from(u32::MAX)) as u32;
+        if mib > self.capacity {
+            return Err(CreateImageError::MemoryBudgetExceeded(bytes));
+        }
+        let total = self.semaphore.clone();
+        let permit = total.acquire_many_owned(mib).await;
+        Ok(permit.expect("The budget is never closed"))
+    }
+}
+
+/// Approximate peak memory of an encode: the decoded source, a converted copy while
+/// orienting and tonemapping, and the resized image. `0` if the source has no header
+/// the `image` crate can read.
+fn estimate_bytes(source_path: &Path, option: &CachedImageOption) -> u64 {
+    let Ok((width, height)) = image::image_dimensions(source_path) else {
+        return 0;
+    };
+    let (target, output) = match option {
+        CachedImageOption::Resize(resize) => {
+            let scaled = !resize.crops_source() && resize.fit == Fit::Contain;
+            let target = scaled.then_some((resize.width, resize.height));
+            (target, u64::from(resize.width) * u64::from(resize.height))
+        }
+        CachedImageOption::Blur(blur) => (None, u64::from(blur.width) * u64::from(blur.height)),
+    };
+    // Large JPEGs and TIFFs only decode the scale they need.
+    let denominator = crate::decode::scale_denominator(source_path, target).max(1);
+    let source = u64::from(width.div_ceil(denominator)) * u64::from(height.div_ceil(denominator));
+    (source * 2 + output) * BYTES_PER_PIXEL
+}
+
+impl ImageOptimizer {
+    /// Caps the memory of the encodes running at once to roughly `max_bytes`, estimated
+    /// from the source dimensions before decoding.
+    ///
+    /// Encodes wait until their estimate fits next to the running ones, so a few
+    /// 100-megapixel sources are decoded one after another even with a high
+    /// parallelism. Sources needing more than the whole budget fail with
+    /// [`CreateImageError::MemoryBudgetExceeded`].
+    ///
+    /// ```
+    /// # use leptos_image::*;
+    /// let optimizer = ImageOptimizer::new("/__cache/image", "./target/site", 8)
+    ///     .with_memory_budget(2 * 1024 * 1024 * 1024);
+    /// ```
+    pub fn with_memory_budget(mut self, max_bytes: u64) -> Self {
+        self.memory_budget = Some(Arc::new(MemoryBudget::new(max_bytes)));
+        self
+    }
+
+    /// Reserves the memory of an encode of `option` from `source_path`, `None`
+    /// without a budget.
+    pub(crate) async fn reserve_memory(
+        &self,
+        source_path: &Path,
+        option: &CachedImageOption,
+    ) -> Result<Option<OwnedSemaphorePermit>, CreateImageError> {
+        let Some(budget) = &self.memory_budget else {
+            return Ok(None);
+        };
+        budget
+            .reserve(estimate_bytes(source_path, option))
+            .await
+            .map(Some)
+    }
+}
+
+#[cfg(test)]
+mod budget_tests {
+    use super::*;
+    use crate::optimizer::CachedImage;
+    use crate::test_support::{resize_spec, test_root};
+    use futures_util::FutureExt;
+
+    #[test]
+    fn waits_for_room() {
+        let budget = MemoryBudget::new(8 * MIB);
+
+        let running = budget.reserve(6 * MIB).now_or_never().unwrap().unwrap();
+        assert!(budget.reserve(4 * MIB).now_or_never().is_none());
+        assert!(matches!(
+            budget.reserve(9 * MIB).now_or_never(),
+            Some(Err(CreateImageError::MemoryBudgetExceeded(_)))
+        ));
+        drop(running);
+        assert!(budget.reserve(4 * MIB).now_or_never().unwrap().is_ok());
+    }
+
+    #[test]
+    fn rejects_partial_mib() {
+        let budget = MemoryBudget::new(10 * MIB + MIB / 2);
+
+        // Would need 11 of the 10 permits, and wait forever.
+        assert!(matches!(
+            budget.reserve(10 * MIB + MIB / 5).now_or_never(),
+            Some(Err(CreateImageError::MemoryBudgetExceeded(_)))
+        ));
+        assert!(budget.reserve(10 * MIB).now_or_never().unwrap().is_ok());
+    }
+
+    #[test]
+    fn budgets_in_memory_encodes() {
+        let (_, optimizer) = test_root("leptos_image_budget_in_memory");
+        let optimizer = optimizer.with_memory_budget(MIB);
+        let spec = CachedImage::new("/ferris.png", resize_spec(1000, 1000));
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+
+        // Read-only mode encodes within the same budget.
+        let result = runtime.block_on(optimizer.encode_in_memory(&spec));
+        assert!(matches!(
+            result,
+            Err(CreateImageError::MemoryBudgetExceeded(_))
+        ));
+    }
+}
@@ -0,0 +1,325 @@
+use crate::optimizer::ImageOptimizer;
+use futures_util::future::BoxFuture;
+use std::sync::Arc;
+
+/// Why a CDN purge failed.
+#[derive(Debug, thiserror::Error)]
+pub enum CdnError {
+    /// The purge request couldn't be sent, or no response came back.
+    #[error("Request Failed: {0}")]
+    Request(String),
+    /// The CDN answered with an error, e.g. for an invalid token.
+    #[error("Purge Rejected: {0} {1}")]
+    Rejected(u16, String),
+}
+
+/// Removes cached images from the edge caches of a CDN, so it doesn't keep serving
+/// stale images after their source changed.
+///
+/// Set it with [`ImageOptimizer::with_cdn_invalidator`], which calls it with the URLs
+/// of images encoded again or removed from the cache. [`Cloudflare`] and [`Fastly`]
+/// come with the `cloudflare` and `fastly` features.
+///
+/// ```
+/// use futures_util::future::BoxFuture;
+/// use leptos_image::*;
+///
+/// // Purges through a proxy of the app.
+/// #[derive(Debug)]
+/// struct Proxy;
+///
+/// impl CdnInvalidator for Proxy {
+///     fn invalidate<'a>(&'a self, urls: &'a [String]) -> BoxFuture<'a, Result<(), CdnError>> {
+///         Box::pin(async move {
+///             for url in urls {
+///                 println!("PURGE {url}");
+///             }
+///             Ok(())
+///         })
+///     }
+/// }
+/// ```
+pub trait CdnInvalidator: Send + Sync + std::fmt::Debug {
+    /// Purges `urls`, as built by the cache handler with the asset prefix. Relative
+    /// without an asset prefix.
+    fn invalidate<'a>(&'a self, urls: &'a [String]) -> BoxFuture<'a, Result<(), CdnError>>;
+}
+
+impl ImageOptimizer {
+    /// Purges images from a CDN when they're encoded again, e.g. after the source
+    /// changed or the max age passed, and when they're removed by
+    /// [`Self::purge_source`], [`Self::purge_tenant`] or [`Self::evict_lru`].
+    ///
+    /// Purges run in the background, failures are logged and not retried. Set the
+    /// asset prefix to the CDN's URL, or the origin of the invalidator, so it gets
+    /// absolute URLs. Registered through [`Self::on_encode_complete`] and
+    /// [`Self::on_cache_evict`], so it adds to other hooks.
+    ///
+    /// Call it inside the tokio runtime, purges of the [`crate::SourceWatcher`] are
+    /// sent from its thread through it.
+    ///
+    /// ```no_run
+    /// # use leptos_image::*;
+    /// # #[cfg(feature = "cloudflare")]
+    /// # async fn optimizer() {
+    /// let optimizer = ImageOptimizer::new("/__cache/image", "./target/site", 1)
+    ///     .with_asset_prefix("https://example.com")
+    ///     .with_cdn_invalidator(Cloudflare::new("zone-id", "api-token"));
+    /// # }
+    /// ```
+    pub fn with_cdn_invalidator(self, invalidator: impl CdnInvalidator + 'static) -> Self {
+        let invalidator: Arc<dyn CdnInvalidator> = Arc::new(invalidator);
+        let runtime = tokio::runtime::Handle::try_current().ok();
+        let purge = move |url: &str| {
+            let Some(runtime) = tokio::runtime::Handle::try_current().ok().or(runtime.clone())
+            else {
+                tracing::warn!("No runtime to purge {url} from the CDN");
+                return;
+            };
+            let (invalidator, url) = (invalidator.clone(), url.to_string());
+            runtime.spawn(async move {
+                match invalidator.invalidate(std::slice::from_ref(&url)).await {
+                    Ok(()) => tracing::debug!("Purged {url} from the CDN"),
+                    Err(e) => tracing::warn!("Failed to purge {url} from the CDN: {e}"),
+                }
+            });
+        };
+        let evicted = purge.clone();
+        self.on_encode_complete(move |event, _| {
+            if event.replaced {
+                purge(&event.url);
+            }
+        })
+        .on_cache_evict(move |event| evicted(&event.url))
+    }
+}
+
+// Makes relative URLs absolute, for invalidators without an asset prefix.
+#[cfg(any(feature = "cloudflare", feature = "fastly"))]
+fn absolute(origin: &str, url: &str) -> String {
+    if url.starts_with('/') {
+        format!("{}{url}", origin.trim_end_matches('/'))
+    } else {
+        url.to_string()
+    }
+}
+
+// Fails for error statuses, with the body the CDN sent.
+#[cfg(any(feature = "cloudflare", feature = "fastly"))]
+async fn check(response: reqwest::Response) -> Result<(), CdnError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(());
+    }
+    let body = response.text().await.unwrap_or_default();
+    Err(CdnError::Rejected(status.as_u16(), body))
+}
+
+#[cfg(any(feature = "cloudflare", feature = "fastly"))]
+fn request_error(error: reqwest::Error) -> CdnError {
+    CdnError::Request(error.to_string())
+}
+
+/// Purges URLs from a Cloudflare zone with an API token allowed to purge its cache.
+#[cfg(feature = "cloudflare")]
+#[derive(Clone)]
+pub struct Cloudflare {
+    zone_id: String,
+    api_token: String,
+    origin: String,
+    client: reqwest::Client,
+}
+
+// Leaves out the token.
+#[cfg(feature = "cloudflare")]
+impl std::fmt::Debug for Cloudflare {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Cloudflare")
+            .field("zone_id", &self.zone_id)
+            .field("origin", &self.origin)
+            .finish_non_exhaustive()
+    }
+}
+
+/// URLs per purge request accepted on every Cloudflare plan.
+#[cfg(feature = "cloudflare")]
+const CLOUDFLARE_BATCH: usize = 30;
+
+#[cfg(feature = "cloudflare")]
+impl Cloudflare {
+    /// Purges from the zone `zone_id`, authenticated with `api_token`.
+    pub fn new(zone_id: impl Into<String>, api_token: impl Into<String>) -> Self {
+        Self {
+            zone_id: zone_id.into(),
+            api_token: api_token.into(),
+            origin: String::new(),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Prepends `origin`, e.g. `https://example.com`, to relative URLs.
+    pub fn with_origin(mut self, origin: impl Into<String>) -> Self {
+        self.origin = origin.into();
+        self
+    }
+}
+
+#[cfg(feature = "cloudflare")]
+impl CdnInvalidator for Cloudflare {
+    fn invalidate<'a>(&'a self, urls: &'a [String]) -> BoxFuture<'a, Result<(), CdnError>> {
+        Box::pin(async move {
+            let endpoint = format!(
+                "https://api.cloudflare.com/client/v4/zones/{}/purge_cache",
+                self.zone_id
+            );
+            for batch in urls.chunks(CLOUDFLARE_BATCH) {
+                let files: Vec<String> =
+                    batch.iter().map(|url| absolute(&self.origin, url)).collect();
+                let response = self
+                    .client
+                    .post(&endpoint)
+                    .bearer_auth(&self.api_token)
+                    .json(&serde_json::json!({ "files": files }))
+                    .send()
+                    .await
+                    .map_err(request_error)?;
+                check(response).await?;
+            }
+            Ok(())
+        })
+    }
+}
+
+/// Purges URLs from Fastly with an API token allowed to purge the service.
+#[cfg(feature = "fastly")]
+#[derive(Clone)]
+pub struct Fastly {
+    api_token: String,
+    origin: String,
+    soft: bool,
+    client: reqwest::Client,
+}
+
+// Leaves out the token.
+#[cfg(feature = "fastly")]
+impl std::fmt::Debug for Fastly {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Fastly")
+            .field("origin", &self.origin)
+            .field("soft", &self.soft)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "fastly")]
+impl Fastly {
+    /// Purges with `api_token`.
+    pub fn new(api_token: impl Into<String>) -> Self {
+        Self {
+            api_token: api_token.into(),
+            origin: String::new(),
+            soft: false,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Prepends `origin`, e.g. `https://example.com`, to relative URLs.
+    pub fn with_origin(mut self, origin: impl Into<String>) -> Self {
+        self.origin = origin.into();
+        self
+    }
+
+    /// Marks images stale instead of removing them, so Fastly can still serve them
+    /// while fetching the new ones.
+    pub fn with_soft_purge(mut self, soft: bool) -> Self {
+        self.soft = soft;
+        self
+    }
+}
+
+// The purge endpoint of a URL, named by its host and path without the scheme.
+#[cfg(feature = "fastly")]
+fn fastly_endpoint(url: &str) -> String {
+    let url = url.split_once("://").map_or(url, |(_, rest)| rest);
+    format!("https://api.fastly.com/purge/{url}")
+}
+
+#[cfg(feature = "fastly")]
+impl CdnInvalidator for Fastly {
+    fn invalidate<'a>(&'a self, urls: &'a [String]) -> BoxFuture<'a, Result<(), CdnError>> {
+        Box::pin(async move {
+            for url in urls {
+                let mut request = self
+                    .client
+                    .post(fastly_endpoint(&absolute(&self.origin, url)))
+                    .header("Fastly-Key", &self.api_token);
+                if self.soft {
+                    request = request.header("Fastly-Soft-Purge", "1");
+                }
+                check(request.send().await.map_err(request_error)?).await?;
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod cdn_tests {
+    use super::*;
+    use crate::test_support::test_root;
+    use crate::{CachedImage, Resize};
+    use std::sync::Mutex;
+
+    #[derive(Debug)]
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl CdnInvalidator for Recorder {
+        fn invalidate<'a>(&'a self, urls: &'a [String]) -> BoxFuture<'a, Result<(), CdnError>> {
+            self.0.lock().unwrap().extend_from_slice(urls);
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    #[test]
+    fn purges_regenerated_and_removed() {
+        let (_, optimizer) = test_root("leptos_image_cdn");
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let purged = Arc::new(Mutex::new(Vec::new()));
+        let day = std::time::Duration::from_secs(24 * 60 * 60);
+        let optimizer = runtime.block_on(async {
+            optimizer
+                .with_asset_prefix("https://cdn.example.com")
+                .with_max_age(day)
+                .with_cdn_invalidator(Recorder(purged.clone()))
+        });
+        let image = CachedImage::new("/ferris.png", Resize::default());
+        let wait = |count: usize| {
+            for _ in 0..100 {
+                if purged.lock().unwrap().len() >= count {
+                    break;
+                }
+                std::thread::sleep(std::time::Duration::from_millis(10));
+            }
+            purged.lock().unwrap().len()
+        };
+
+        // Only images the CDN may have cached are purged.
+        assert!(runtime.block_on(optimizer.create_image(&image)).unwrap());
+        assert_eq!(wait(1), 0);
+        let save_path = std::path::PathBuf::from(optimizer.get_file_path_from_root(&image));
+        std::fs::File::options()
+            .write(true)
+            .open(save_path.with_extension("qs"))
+            .unwrap()
+            .set_modified(std::time::SystemTime::now() - 2 * day)
+            .unwrap();
+        assert!(runtime.block_on(optimizer.create_image(&image)).unwrap());
+        assert_eq!(wait(1), 1);
+        // Purged from another thread, like the source watcher does.
+        assert_eq!(optimizer.purge_source("/ferris.png").unwrap(), 1);
+        assert_eq!(wait(2), 2);
+        let purged = purged.lock().unwrap();
+        assert_eq!(purged[0], purged[1]);
+        assert!(purged[0].starts_with("https://cdn.example.com/__cache/image?"));
+    }
+}
@@ -0,0 +1,283 @@
+use crate::optimizer::{CreateImageError, ImageOptimizer};
+use std::path::Path;
+use std::time::Duration;
+
+// Longest missing source TTL accepted by `validate`, sources added later would stay
+// 404 for too long.
+const MAX_MISSING_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// A misconfigured optimizer, see [`ImageOptimizer::validate`].
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    /// The site root doesn't exist or isn't a directory.
+    #[error("Root Not Found: {0}, set the root to the site root, e.g. `./target/site`")]
+    RootNotFound(String),
+    /// The cache handler path doesn't start with `/`.
+    #[error("Invalid Handler Path: {0}, handler paths are absolute, e.g. `/__cache/image`")]
+    InvalidHandlerPath(String),
+    /// Images or placeholders can never be created, as their parallelism is zero.
+    #[error("Zero Parallelism: {0} parallelism must be at least 1")]
+    ZeroParallelism(&'static str),
+    /// The missing source TTL is longer than a day.
+    #[error("Missing TTL Too Long: {0:?}, sources added later would stay 404 until it expires")]
+    MissingTtlTooLong(Duration),
+    /// The encoder failed to encode a 1x1 WebP image.
+    #[error("Encoder Unavailable: {0}")]
+    EncoderUnavailable(CreateImageError),
+    /// A required setting is missing, e.g. `LEPTOS_IMAGE_ROOT`.
+    #[error("Missing Setting: {0}")]
+    Missing(&'static str),
+    /// A setting could not be parsed.
+    #[error("Invalid Setting: {0}")]
+    Invalid(String),
+}
+
+// Settings of `ImageOptimizer::from_toml` and `ImageOptimizer::from_env`.
+#[cfg(feature = "config")]
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Settings {
+    root: Option<String>,
+    handler_path: Option<String>,
+    parallelism: Option<usize>,
+    blur_parallelism: Option<usize>,
+    cache_dir: Option<std::path::PathBuf>,
+    asset_prefix: Option<String>,
+    missing_ttl_secs: Option<u64>,
+    inline_limit: Option<u64>,
+    width_ladder: Option<Vec<u32>>,
+    dev_mode: Option<bool>,
+    max_age_secs: Option<u64>,
+    revalidate_sources: Option<bool>,
+    memory_cache_bytes: Option<u64>,
+    memory_cache_entry_bytes: Option<u64>,
+}
+
+#[cfg(feature = "config")]
+impl Settings {
+    fn from_env() -> Result<Self, ConfigError> {
+        fn var<T: std::str::FromStr>(key: &str) -> Result<Option<T>, ConfigError> {
+            match std::env::var(key) {
+                Ok(value) => value
+                    .trim()
+                    .parse()
+                    .map(Some)
+                    .map_err(|_| ConfigError::Invalid(format!("{key}={value}"))),
+                Err(_) => Ok(None),
+            }
+        }
+        let width_ladder = match var::<String>("LEPTOS_IMAGE_WIDTH_LADDER")? {
+            Some(widths) => Some(
+                widths
+                    .split(',')
+                    .map(|width| width.trim().parse())
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| {
+                        ConfigError::Invalid(format!("LEPTOS_IMAGE_WIDTH_LADDER={widths}"))
+                    })?,
+            ),
+            None => None,
+        };
+        Ok(Self {
+            root: match var("LEPTOS_IMAGE_ROOT")? {
+                Some(root) => Some(root),
+                None => var("LEPTOS_SITE_ROOT")?,
+            },
+            handler_path: var("LEPTOS_IMAGE_HANDLER_PATH")?,
+            parallelism: var("LEPTOS_IMAGE_PARALLELISM")?,
+            blur_parallelism: var("LEPTOS_IMAGE_BLUR_PARALLELISM")?,
+            cache_dir: var("LEPTOS_IMAGE_CACHE_DIR")?,
+            asset_prefix: var("LEPTOS_IMAGE_ASSET_PREFIX")?,
+            missing_ttl_secs: var("LEPTOS_IMAGE_MISSING_TTL_SECS")?,
+            inline_limit: var("LEPTOS_IMAGE_INLINE_LIMIT")?,
+            width_ladder,
+            dev_mode: var("LEPTOS_IMAGE_DEV_MODE")?,
+            max_age_secs: var("LEPTOS_IMAGE_MAX_AGE_SECS")?,
+            revalidate_sources: var("LEPTOS_IMAGE_REVALIDATE_SOURCES")?,
+            memory_cache_bytes: var("LEPTOS_IMAGE_MEMORY_CACHE_BYTES")?,
+            memory_cache_entry_bytes: var("LEPTOS_IMAGE_MEMORY_CACHE_ENTRY_BYTES")?,
+        })
+    }
+
+    fn into_optimizer(self) -> Result<ImageOptimizer, ConfigError> {
+        let root = self.root.ok_or(ConfigError::Missing("root"))?;
+        let handler_path = self.handler_path.unwrap_or_else(|| "/__cache/image".to_string());
+        let parallelism = self.parallelism.unwrap_or(1);
+        let mut optimizer = ImageOptimizer::new(handler_path, root, parallelism);
+        if let Some(parallelism) = self.blur_parallelism {
+            optimizer = optimizer.with_blur_parallelism(parallelism);
+        }
+        if let Some(cache_dir) = self.cache_dir {
+            optimizer = optimizer.with_cache_dir(cache_dir);
+        }
+        if let Some(asset_prefix) = self.asset_prefix {
+            optimizer = optimizer.with_asset_prefix(asset_prefix);
+        }
+        if let Some(ttl) = self.missing_ttl_secs {
+            optimizer = optimizer.with_missing_ttl(Duration::from_secs(ttl));
+        }
+        if let Some(max_bytes) = self.inline_limit {
+            optimizer = optimizer.with_inline_limit(max_bytes);
+        }
+        if let Some(widths) = self.width_ladder {
+            optimizer = optimizer.with_width_ladder(widths);
+        }
+        if let Some(dev_mode) = self.dev_mode {
+            optimizer = optimizer.with_dev_mode(dev_mode);
+        }
+        if let Some(max_age) = self.max_age_secs {
+            optimizer = optimizer.with_max_age(Duration::from_secs(max_age));
+        }
+        if let Some(revalidate) = self.revalidate_sources {
+            optimizer = optimizer.with_source_revalidation(revalidate);
+        }
+        if let Some(max_bytes) = self.memory_cache_bytes {
+            let max_entry_bytes = self.memory_cache_entry_bytes.unwrap_or(64 * 1024);
+            #[cfg(feature = "memory-cache")]
+            {
+                optimizer = optimizer.with_memory_cache(max_bytes, max_entry_bytes);
+            }
+            #[cfg(not(feature = "memory-cache"))]
+            tracing::warn!(
+                "Ignoring the memory cache ({max_bytes} bytes, {max_entry_bytes} per image), \
+                 enable the `memory-cache` feature"
+            );
+        }
+        optimizer.build()
+    }
+}
+
+impl ImageOptimizer {
+    /// Checks the configuration, so mistakes surface at startup instead of as 500s on
+    /// the first image request.
+    ///
+    /// Checks that the site root exists, the handler path starts with `/`, image and
+    /// placeholder parallelism are above zero, the missing source TTL is at most a
+    /// day, and the encoder works. Meant to be called before serving, as parallelism
+    /// is read from the permits that are free.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if !Path::new(&self.root_file_path).is_dir() {
+            return Err(ConfigError::RootNotFound(self.root_file_path.clone()));
+        }
+        if !self.api_handler_path.starts_with('/') {
+            return Err(ConfigError::InvalidHandlerPath(self.api_handler_path.clone()));
+        }
+        if self.semaphore.available_permits() == 0 {
+            return Err(ConfigError::ZeroParallelism("Image"));
+        }
+        if self.blur_semaphore.available_permits() == 0 {
+            return Err(ConfigError::ZeroParallelism("Placeholder"));
+        }
+        if self.missing_ttl > MAX_MISSING_TTL {
+            return Err(ConfigError::MissingTtlTooLong(self.missing_ttl));
+        }
+        self.check_encoder().map_err(ConfigError::EncoderUnavailable)
+    }
+
+    /// Finishes configuring the optimizer, returning it once [`Self::validate`] passes.
+    ///
+    /// ```
+    /// use leptos_image::*;
+    ///
+    /// # #[cfg(feature = "ssr")]
+    /// # fn optimizer() -> Result<ImageOptimizer, ConfigError> {
+    /// let optimizer = ImageOptimizer::new("/__cache/image", "./target/site", 4)
+    ///     .with_missing_ttl(std::time::Duration::from_secs(60))
+    ///     .build()?;
+    /// # Ok(optimizer)
+    /// # }
+    /// ```
+    pub fn build(self) -> Result<Self, ConfigError> {
+        self.validate()?;
+        Ok(self)
+    }
+
+    /// Creates an optimizer from environment variables. The optimizer is validated,
+    /// see [`Self::validate`]. Only `root` is required, the settings are (TOML key,
+    /// then variable):
+    ///
+    /// - `root`, `LEPTOS_IMAGE_ROOT`: the site root. Falls back to `LEPTOS_SITE_ROOT`,
+    ///   as set by cargo-leptos.
+    /// - `handler_path`, `LEPTOS_IMAGE_HANDLER_PATH`: defaults to `/__cache/image`.
+    /// - `parallelism`, `LEPTOS_IMAGE_PARALLELISM`: defaults to 1.
+    /// - `blur_parallelism`, `LEPTOS_IMAGE_BLUR_PARALLELISM`, see
+    ///   [`Self::with_blur_parallelism`].
+    /// - `cache_dir`, `LEPTOS_IMAGE_CACHE_DIR`, see [`Self::with_cache_dir`].
+    /// - `asset_prefix`, `LEPTOS_IMAGE_ASSET_PREFIX`, see [`Self::with_asset_prefix`].
+    /// - `missing_ttl_secs`, `LEPTOS_IMAGE_MISSING_TTL_SECS`, see
+    ///   [`Self::with_missing_ttl`].
+    /// - `inline_limit`, `LEPTOS_IMAGE_INLINE_LIMIT`, see [`Self::with_inline_limit`].
+    /// - `width_ladder`, `LEPTOS_IMAGE_WIDTH_LADDER`: comma separated in the variable,
+    ///   see [`Self::with_width_ladder`].
+    /// - `dev_mode`, `LEPTOS_IMAGE_DEV_MODE`, see [`Self::with_dev_mode`].
+    /// - `max_age_secs`, `LEPTOS_IMAGE_MAX_AGE_SECS`, see [`Self::with_max_age`].
+    /// - `revalidate_sources`, `LEPTOS_IMAGE_REVALIDATE_SOURCES`, see
+    ///   [`Self::with_source_revalidation`].
+    /// - `memory_cache_bytes` and `memory_cache_entry_bytes` (64 KiB by default),
+    ///   `LEPTOS_IMAGE_MEMORY_CACHE_BYTES` and `LEPTOS_IMAGE_MEMORY_CACHE_ENTRY_BYTES`:
+    ///   the in-memory tier, ignored without the `memory-cache` feature.
+    #[cfg(feature = "config")]
+    pub fn from_env() -> Result<Self, ConfigError> {
+        Settings::from_env()?.into_optimizer()
+    }
+
+    /// Creates an optimizer from a TOML file with the keys of [`Self::from_env`].
+    /// Unknown keys are rejected, so typos don't go unnoticed. The optimizer is
+    /// validated, see [`Self::validate`].
+    ///
+    /// ```toml
+    /// root = "./target/site"
+    /// parallelism = 4
+    /// cache_dir = "../image-cache"
+    /// width_ladder = [640, 1280, 1920]
+    /// ```
+    #[cfg(feature = "config")]
+    pub fn from_toml(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| ConfigError::Invalid(format!("{}: {e}", path.display())))?;
+        let settings: Settings = toml::from_str(&contents)
+            .map_err(|e| ConfigError::Invalid(format!("{}: {e}", path.display())))?;
+        settings.into_optimizer()
+    }
+}
+
+#[cfg(test)]
+mod config_tests {
+    use super::*;
+
+    #[test]
+    fn actionable_errors() {
+        let root = std::env::temp_dir().join("leptos_image_config");
+        std::fs::create_dir_all(&root).unwrap();
+        let root = root.to_string_lossy();
+        ImageOptimizer::new("/__cache/image", root.clone(), 1).build().unwrap();
+
+        let missing = ImageOptimizer::new("/__cache/image", "/definitely/missing", 1);
+        assert!(matches!(missing.validate(), Err(ConfigError::RootNotFound(_))));
+        let relative = ImageOptimizer::new("__cache/image", root.clone(), 1);
+        assert!(matches!(relative.validate(), Err(ConfigError::InvalidHandlerPath(_))));
+        let zero = ImageOptimizer::new("/__cache/image", root.clone(), 0);
+        assert!(matches!(zero.validate(), Err(ConfigError::ZeroParallelism("Image"))));
+        let ttl = ImageOptimizer::new("/__cache/image", root, 1)
+            .with_missing_ttl(Duration::from_secs(7 * 24 * 60 * 60));
+        assert!(matches!(ttl.validate(), Err(ConfigError::MissingTtlTooLong(_))));
+    }
+
+    #[cfg(feature = "config")]
+    #[test]
+    fn toml_settings() {
+        let dir = std::env::temp_dir().join("leptos_image_config_toml");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("image.toml");
+        let root = dir.to_string_lossy().replace('\\', "/");
+        let contents = format!("root = \"{root}\"\nparallelism = 2\nwidth_ladder = [640, 320]\n");
+        std::fs::write(&path, contents).unwrap();
+        let optimizer = ImageOptimizer::from_toml(&path).unwrap();
+        assert_eq!(optimizer.width_ladder, [320, 640]);
+        assert_eq!(optimizer.semaphore.available_permits(), 2);
+
+        std::fs::write(&path, format!("root = \"{root}\"\nparalelism = 2\n")).unwrap();
+        assert!(matches!(ImageOptimizer::from_toml(&path), Err(ConfigError::Invalid(_))));
+    }
+}
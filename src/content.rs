@@ -0,0 +1,120 @@
+use crate::optimizer::ImageOptimizer;
+use image::{DynamicImage, GenericImageView};
+use std::collections::HashSet;
+
+/// Pixels sampled along each side of a source.
+const SAMPLES: u32 = 128;
+
+/// Distinct colors up to which a source counts as a flat graphic.
+const MAX_GRAPHIC_COLORS: usize = 256;
+
+/// What a source looks like, for picking the output format of
+/// [`crate::OutputFormat::Auto`] images.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ContentProfile {
+    /// Few colors and large flat areas, e.g. logos, charts and screenshots.
+    pub(crate) graphic: bool,
+    /// Some pixels aren't fully opaque.
+    pub(crate) transparent: bool,
+}
+
+/// Samples a grid of pixels, counting distinct colors and pixels equal to their
+/// right neighbor. Photos have noise, so both stay low only for graphics.
+pub(crate) fn analyze(img: &DynamicImage) -> ContentProfile {
+    let (width, height) = img.dimensions();
+    let (step_x, step_y) = ((width / SAMPLES).max(1), (height / SAMPLES).max(1));
+    let mut colors = HashSet::new();
+    let (mut samples, mut flat) = (0_u32, 0_u32);
+    let has_alpha = img.color().has_alpha();
+    let mut transparent = false;
+    for y in (0..height).step_by(step_y as usize) {
+        for x in (0..width).step_by(step_x as usize) {
+            let pixel = img.get_pixel(x, y).0;
+            transparent |= has_alpha && pixel[3] < 255;
+            if colors.len() <= MAX_GRAPHIC_COLORS {
+                colors.insert(pixel);
+            }
+            if x + 1 < width {
+                samples += 1;
+                flat += u32::from(img.get_pixel(x + 1, y).0 == pixel);
+            }
+        }
+    }
+    // Half the neighbors identical is rare in photos, even with smooth skies.
+    let graphic = colors.len() <= MAX_GRAPHIC_COLORS || flat * 2 > samples;
+    ContentProfile {
+        graphic,
+        transparent,
+    }
+}
+
+impl ImageOptimizer {
+    /// The content profile of a source, decoded once per modification of the source.
+    /// JPEG sources are photos without decoding, `None` if the source can't be read.
+    pub(crate) async fn content_profile(&self, src: &str) -> Option<ContentProfile> {
+        let path = self.resolve_source(src).await.ok()?;
+        if matches!(image::ImageFormat::from_path(&path), Ok(image::ImageFormat::Jpeg)) {
+            return Some(ContentProfile {
+                graphic: false,
+                transparent: false,
+            });
+        }
+        let modified = tokio::fs::metadata(&path).await.ok()?.modified().ok()?;
+        let key = (src.to_string(), modified);
+        if let Some(profile) = self.content_profiles.get(&key) {
+            return Some(*profile);
+        }
+
+        let pipeline = self.pipeline.clone();
+        let decode_path = path.clone();
+        // Decoded at full size, so the encode that follows reuses the pixels.
+        let profile = self
+            .run_encode(move || {
+                crate::optimizer::decode_source(&pipeline, &decode_path, None)
+                    .map(|img| analyze(&img))
+            })
+            .await
+            .ok()?
+        .map_err(|e| tracing::warn!("Failed to analyze {}: {e}", path.display()))
+        .ok()?;
+        // Older modifications of the source are stale.
+        self.content_profiles.retain(|(other, _), _| other != src);
+        self.content_profiles.insert(key, profile);
+        Some(profile)
+    }
+}
+
+#[cfg(test)]
+mod content_tests {
+    use super::*;
+
+    #[test]
+    fn photos_and_graphics() {
+        let logo = DynamicImage::ImageRgba8(image::RgbaImage::from_fn(200, 100, |x, _| {
+            if x < 100 {
+                image::Rgba([255, 0, 0, 255])
+            } else {
+                image::Rgba([0, 0, 0, 0])
+            }
+        }));
+        let noise = DynamicImage::ImageRgb8(image::RgbImage::from_fn(200, 100, |x, y| {
+            let channel = |v: u32| (v % 256) as u8;
+            image::Rgb([channel(x * 37 + y * 11), channel(x * 13 + y * 29), channel(x * x + y * y)])
+        }));
+
+        assert_eq!(
+            analyze(&logo),
+            ContentProfile {
+                graphic: true,
+                transparent: true,
+            }
+        );
+        assert_eq!(
+            analyze(&noise),
+            ContentProfile {
+                graphic: false,
+                transparent: false,
+            }
+        );
+    }
+}
@@ -0,0 +1,974 @@
+use crate::optimizer::CreateImageError;
+use image::DynamicImage;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How high bit depth and HDR sources are converted to 8-bit SDR before encoding.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ToneMapping {
+    /// Operator applied to linear HDR (32-bit float) sources, e.g. EXR or Radiance HDR.
+    pub operator: ToneMapOperator,
+    /// Linear exposure multiplier applied to HDR sources before tonemapping.
+    pub exposure: f32,
+    /// Gamma used to encode tonemapped HDR values.
+    pub gamma: f32,
+    /// Ordered dithering when reducing 16-bit sources to 8-bit, avoids banding in gradients.
+    pub dither: bool,
+}
+
+impl Default for ToneMapping {
+    fn default() -> Self {
+        Self {
+            operator: ToneMapOperator::Aces,
+            exposure: 1.0,
+            gamma: 2.2,
+            dither: true,
+        }
+    }
+}
+
+/// Tonemapping operator for HDR sources.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToneMapOperator {
+    /// Clamp values above 1.0, blows out highlights.
+    Clip,
+    /// Reinhard `x / (1 + x)`.
+    Reinhard,
+    /// Filmic ACES approximation.
+    Aces,
+}
+
+impl ToneMapOperator {
+    fn apply(self, x: f32) -> f32 {
+        match self {
+            Self::Clip => x,
+            Self::Reinhard => x / (1.0 + x),
+            // Krzysztof Narkowicz's fit of the ACES curve.
+            Self::Aces => (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14),
+        }
+        .clamp(0.0, 1.0)
+    }
+}
+
+/// What to do with embedded ICC color profiles.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IccPolicy {
+    /// Drop the profile, pixels are assumed to be sRGB.
+    Ignore,
+    /// Convert wide-gamut sources (Display P3, Adobe RGB, ...) to sRGB before encoding.
+    /// Requires the `icc` feature, behaves like `Ignore` otherwise.
+    #[default]
+    ConvertToSrgb,
+}
+
+/// Applies the ICC policy to a decoded 8-bit image.
+pub(crate) fn apply_icc_policy(
+    img: DynamicImage,
+    source_path: &Path,
+    policy: IccPolicy,
+) -> DynamicImage {
+    match policy {
+        IccPolicy::Ignore => img,
+        #[cfg(feature = "icc")]
+        IccPolicy::ConvertToSrgb => match read_icc_profile(source_path) {
+            Some(profile) => convert_to_srgb(img, &profile, source_path),
+            None => img,
+        },
+        #[cfg(not(feature = "icc"))]
+        IccPolicy::ConvertToSrgb => {
+            let _ = source_path;
+            img
+        }
+    }
+}
+
+#[cfg(feature = "icc")]
+fn read_icc_profile(source_path: &Path) -> Option<Vec<u8>> {
+    use image::ImageDecoder;
+
+    let format = image::ImageFormat::from_path(source_path).ok()?;
+    let reader = std::io::BufReader::new(std::fs::File::open(source_path).ok()?);
+    match format {
+        image::ImageFormat::Jpeg => {
+            let mut decoder = image::codecs::jpeg::JpegDecoder::new(reader).ok()?;
+            decoder.icc_profile()
+        }
+        image::ImageFormat::Png => {
+            let mut decoder = image::codecs::png::PngDecoder::new(reader).ok()?;
+            decoder.icc_profile()
+        }
+        _ => None,
+    }
+}
+
+#[cfg(feature = "icc")]
+fn convert_to_srgb(img: DynamicImage, profile: &[u8], source_path: &Path) -> DynamicImage {
+    use lcms2::{Intent, PixelFormat, Profile, Transform};
+
+    let transform = Profile::new_icc(profile).and_then(|source| {
+        Transform::<[u8; 4], [u8; 4]>::new(
+            &source,
+            PixelFormat::RGBA_8,
+            &Profile::new_srgb(),
+            PixelFormat::RGBA_8,
+            Intent::Perceptual,
+        )
+    });
+    let transform = match transform {
+        Ok(transform) => transform,
+        Err(e) => {
+            // e.g. grayscale or CMYK profiles, keep the pixels as they are.
+            tracing::warn!("Ignoring ICC profile of {}: {e}", source_path.display());
+            return img;
+        }
+    };
+
+    let has_alpha = img.color().has_alpha();
+    let rgba = img.into_rgba8();
+    let (width, height) = rgba.dimensions();
+    let mut pixels: Vec<[u8; 4]> = rgba.pixels().map(|pixel| pixel.0).collect();
+    transform.transform_in_place(&mut pixels);
+
+    let converted = image::RgbaImage::from_raw(width, height, pixels.concat())
+        .expect("Pixel count is unchanged");
+    if has_alpha {
+        DynamicImage::ImageRgba8(converted)
+    } else {
+        DynamicImage::ImageRgba8(converted).into_rgb8().into()
+    }
+}
+
+/// How the EXIF orientation of sources is applied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AutoOrient {
+    /// Rotates and flips pixels so images are stored upright.
+    pub enabled: bool,
+    /// Camera makes whose orientation tag is ignored, compared case-insensitively
+    /// as a prefix. Some firmwares rotate the pixels and still write the tag.
+    pub ignore_makes: Vec<String>,
+}
+
+impl Default for AutoOrient {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            ignore_makes: Vec::new(),
+        }
+    }
+}
+
+/// Rotates the image upright according to its EXIF orientation.
+pub(crate) fn auto_orient(
+    img: DynamicImage,
+    source_path: &Path,
+    options: &AutoOrient,
+) -> DynamicImage {
+    match orientation(source_path, options) {
+        Some(orientation) => apply_orientation(img, orientation),
+        None => img,
+    }
+}
+
+/// Dimensions of the upright source, read from its header without decoding.
+pub(crate) fn probe_dimensions(source_path: &Path, options: &AutoOrient) -> Option<(u32, u32)> {
+    let (width, height) = image::image_dimensions(source_path).ok()?;
+    // Orientations 5-8 rotate by 90 degrees.
+    match orientation(source_path, options) {
+        Some(5..=8) => Some((height, width)),
+        _ => Some((width, height)),
+    }
+}
+
+/// The EXIF orientation to apply, if any.
+fn orientation(source_path: &Path, options: &AutoOrient) -> Option<u32> {
+    if !options.enabled {
+        return None;
+    }
+    let (orientation, make) = read_orientation(source_path)?;
+    let quirk = make.is_some_and(|make| {
+        let make = make.to_ascii_lowercase();
+        options
+            .ignore_makes
+            .iter()
+            .any(|ignored| make.starts_with(&ignored.to_ascii_lowercase()))
+    });
+    (!quirk).then_some(orientation)
+}
+
+/// Reads the orientation tag and camera make.
+fn read_orientation(source_path: &Path) -> Option<(u32, Option<String>)> {
+    let extension = source_path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    // libheif and libjxl already apply the orientation while decoding.
+    if matches!(extension.as_str(), "heic" | "heif" | "jxl") {
+        return None;
+    }
+
+    let file = std::fs::File::open(source_path).ok()?;
+    let exif = exif::Reader::new()
+        .read_from_container(&mut std::io::BufReader::new(file))
+        .ok()?;
+    let orientation = exif
+        .get_field(exif::Tag::Orientation, exif::In::PRIMARY)?
+        .value
+        .get_uint(0)?;
+    let make = exif
+        .get_field(exif::Tag::Make, exif::In::PRIMARY)
+        .map(|field| field.display_value().to_string().trim_matches('"').to_string());
+    Some((orientation, make))
+}
+
+fn apply_orientation(img: DynamicImage, orientation: u32) -> DynamicImage {
+    match orientation {
+        2 => img.fliph(),
+        3 => img.rotate180(),
+        4 => img.flipv(),
+        // Transpose.
+        5 => img.rotate90().fliph(),
+        6 => img.rotate90(),
+        // Transverse.
+        7 => img.rotate270().fliph(),
+        8 => img.rotate270(),
+        _ => img,
+    }
+}
+
+const BAYER_4X4: [[u16; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+/// Converts 16-bit and floating point images to 8-bit, other images are returned unchanged.
+pub(crate) fn to_8bit(img: DynamicImage, tone_mapping: &ToneMapping) -> DynamicImage {
+    match img {
+        DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_) => {
+            let ToneMapping {
+                operator,
+                exposure,
+                gamma,
+                ..
+            } = *tone_mapping;
+            let mut hdr = img.into_rgba32f();
+            for pixel in hdr.pixels_mut() {
+                for channel in &mut pixel.0[..3] {
+                    let mapped = operator.apply(channel.max(0.0) * exposure);
+                    *channel = mapped.powf(1.0 / gamma);
+                }
+                pixel.0[3] = pixel.0[3].clamp(0.0, 1.0);
+            }
+            DynamicImage::ImageRgba32F(hdr).into_rgba8().into()
+        }
+        DynamicImage::ImageLuma16(_)
+        | DynamicImage::ImageLumaA16(_)
+        | DynamicImage::ImageRgb16(_)
+        | DynamicImage::ImageRgba16(_) => {
+            let has_alpha = img.color().has_alpha();
+            let wide = img.into_rgba16();
+            let narrow = image::RgbaImage::from_fn(wide.width(), wide.height(), |x, y| {
+                // Spread the bits dropped by the conversion with an ordered dither.
+                let threshold = if tone_mapping.dither {
+                    BAYER_4X4[y as usize % 4][x as usize % 4] * 257 / 16
+                } else {
+                    128
+                };
+                let pixel = wide.get_pixel(x, y);
+                image::Rgba(pixel.0.map(|v| ((v as u32 + threshold as u32) / 257).min(255) as u8))
+            });
+            if has_alpha {
+                DynamicImage::ImageRgba8(narrow)
+            } else {
+                DynamicImage::ImageRgba8(narrow).into_rgb8().into()
+            }
+        }
+        img => img,
+    }
+}
+
+/// Decodes a source image.
+///
+/// Formats the `image` crate can't read are dispatched on their extension to
+/// feature-gated decoders.
+pub(crate) fn open_image<P>(source_path: P) -> Result<DynamicImage, CreateImageError>
+where
+    P: AsRef<Path>,
+{
+    open_image_scaled(source_path, 1)
+}
+
+/// Decodes a source image, JPEGs at `1 / denominator` of their size.
+pub(crate) fn open_image_scaled<P>(
+    source_path: P,
+    denominator: u32,
+) -> Result<DynamicImage, CreateImageError>
+where
+    P: AsRef<Path>,
+{
+    let path = source_path.as_ref();
+    let extension = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+
+    match extension.as_str() {
+        #[cfg(feature = "heif")]
+        "heic" | "heif" => open_heif(path),
+        #[cfg(feature = "jxl")]
+        "jxl" => open_jxl(path),
+        #[cfg(feature = "video")]
+        "mp4" | "m4v" | "mov" | "webm" => open_video(path),
+        #[cfg(feature = "pdf")]
+        "pdf" => open_pdf(path),
+        #[cfg(feature = "raw")]
+        "cr2" | "nef" | "arw" | "dng" => open_raw(path),
+        "jpg" | "jpeg" if denominator > 1 => open_jpeg_scaled(path, denominator),
+        "tif" | "tiff" if denominator > 1 => open_tiff_scaled(path, denominator),
+        _ => Ok(image::open(path)?),
+    }
+}
+
+/// Whether the leading bytes of a source match a supported format and its extension,
+/// so disguised files never reach a decoder.
+pub(crate) async fn is_supported_source(path: &Path) -> std::io::Result<bool> {
+    use tokio::io::AsyncReadExt;
+
+    // Enough to find the root element of SVGs after an XML prolog.
+    let mut header = Vec::with_capacity(4096);
+    tokio::fs::File::open(path)
+        .await?
+        .take(4096)
+        .read_to_end(&mut header)
+        .await?;
+    Ok(matches_extension(path, &header))
+}
+
+fn matches_extension(path: &Path, header: &[u8]) -> bool {
+    let extension = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    let brand = header
+        .get(4..12)
+        .filter(|ftyp| ftyp.starts_with(b"ftyp"))
+        .map(|ftyp| &ftyp[4..]);
+
+    match extension.as_str() {
+        "svg" => {
+            let text = String::from_utf8_lossy(header);
+            let text = text.trim_start_matches('\u{feff}').trim_start();
+            text.starts_with('<') && text.contains("<svg")
+        }
+        "heic" | "heif" => matches!(
+            brand,
+            Some(b"heic" | b"heix" | b"hevc" | b"heim" | b"heis" | b"mif1" | b"msf1")
+        ),
+        // Bare codestream or ISOBMFF container.
+        "jxl" => {
+            header.starts_with(&[0xFF, 0x0A])
+                || header.starts_with(b"\0\0\0\x0cJXL \r\n\x87\n")
+        }
+        // Any ISOBMFF brand, encoders write many.
+        "mp4" | "m4v" | "mov" => cfg!(feature = "video") && brand.is_some(),
+        // EBML header.
+        "webm" => cfg!(feature = "video") && header.starts_with(&[0x1A, 0x45, 0xDF, 0xA3]),
+        "pdf" => cfg!(feature = "pdf") && header.starts_with(b"%PDF-"),
+        // TIFF based, little or big endian.
+        "cr2" | "nef" | "arw" | "dng" => {
+            cfg!(feature = "raw") && (header.starts_with(b"II*\0") || header.starts_with(b"MM\0*"))
+        }
+        _ => match (image::ImageFormat::from_extension(&extension), image::guess_format(header)) {
+            (Some(expected), Ok(found)) => expected == found,
+            _ => false,
+        },
+    }
+}
+
+/// Sources above this many pixels are rejected by default, about 400 MB decoded.
+pub(crate) const DEFAULT_MAX_SOURCE_PIXELS: u64 = 100_000_000;
+
+/// Scale a source is decoded at for `target`, see [`open_image_scaled`].
+pub(crate) fn scale_denominator(source_path: &Path, target: Option<(u32, u32)>) -> u32 {
+    match image::ImageFormat::from_path(source_path) {
+        Ok(image::ImageFormat::Tiff) => tiff_scale_denominator(source_path, target),
+        _ => jpeg_scale_denominator(source_path, target),
+    }
+}
+
+/// Rejects sources that decode to more than `max_pixels` at `1 / denominator` of
+/// their size, before anything is allocated. Formats without a header the `image`
+/// crate can read are let through.
+pub(crate) fn check_source_pixels(
+    source_path: &Path,
+    denominator: u32,
+    max_pixels: u64,
+) -> Result<(), CreateImageError> {
+    let Ok((width, height)) = image::image_dimensions(source_path) else {
+        return Ok(());
+    };
+    let denominator = denominator.max(1);
+    let pixels = u64::from(width.div_ceil(denominator)) * u64::from(height.div_ceil(denominator));
+    if pixels > max_pixels {
+        tracing::warn!(
+            "Source {} has {width}x{height} pixels, more than the {max_pixels} allowed",
+            source_path.display()
+        );
+        return Err(CreateImageError::ImageError(image::ImageError::Limits(
+            image::error::LimitError::from_kind(image::error::LimitErrorKind::DimensionError),
+        )));
+    }
+    Ok(())
+}
+
+/// Largest power of two up to 64 by which a TIFF can be scaled down and still cover
+/// `target`, in either orientation.
+fn tiff_scale_denominator(source_path: &Path, target: Option<(u32, u32)>) -> u32 {
+    let Some((width, height)) = target.filter(|&(w, h)| w > 0 && h > 0) else {
+        return 1;
+    };
+    let Ok((source_width, source_height)) = image::image_dimensions(source_path) else {
+        return 1;
+    };
+    let fit = |w: u32, h: u32| (width as f64 / w as f64).min(height as f64 / h as f64);
+    let ratio = fit(source_width, source_height).max(fit(source_height, source_width));
+
+    [64, 32, 16, 8, 4, 2]
+        .into_iter()
+        .find(|&denominator| ratio * denominator as f64 <= 1.0)
+        .unwrap_or(1)
+}
+
+/// Largest JPEG DCT scaling denominator (1, 2, 4 or 8) whose decode still covers
+/// `target`, in either orientation. Other formats always decode at full size.
+pub(crate) fn jpeg_scale_denominator(source_path: &Path, target: Option<(u32, u32)>) -> u32 {
+    let Some((width, height)) = target.filter(|&(w, h)| w > 0 && h > 0) else {
+        return 1;
+    };
+    let is_jpeg = matches!(
+        image::ImageFormat::from_path(source_path),
+        Ok(image::ImageFormat::Jpeg)
+    );
+    if !is_jpeg {
+        return 1;
+    }
+    let Ok((source_width, source_height)) = image::image_dimensions(source_path) else {
+        return 1;
+    };
+
+    // `resize` fits within the target, the EXIF orientation may still swap the sides.
+    let fit = |w: u32, h: u32| (width as f64 / w as f64).min(height as f64 / h as f64);
+    let ratio = fit(source_width, source_height).max(fit(source_height, source_width));
+
+    [8, 4, 2]
+        .into_iter()
+        .find(|&denominator| ratio * denominator as f64 <= 1.0)
+        .unwrap_or(1)
+}
+
+fn open_jpeg_scaled(path: &Path, denominator: u32) -> Result<DynamicImage, CreateImageError> {
+    use image::ImageDecoder;
+
+    let reader = std::io::BufReader::new(std::fs::File::open(path)?);
+    let mut decoder = image::codecs::jpeg::JpegDecoder::new(reader)?;
+    let (width, height) = decoder.dimensions();
+    let requested = |side: u32| side.div_ceil(denominator).min(u16::MAX as u32) as u16;
+    decoder.scale(requested(width), requested(height))?;
+    Ok(DynamicImage::from_decoder(decoder)?)
+}
+
+/// Decodes an 8-bit TIFF strip by strip (or tile by tile), averaging each
+/// `denominator` x `denominator` block into one pixel, so only the scaled image is
+/// ever held in memory. Other sample formats take the regular full size decode.
+fn open_tiff_scaled(path: &Path, denominator: u32) -> Result<DynamicImage, CreateImageError> {
+    use tiff::decoder::{Decoder, DecodingResult};
+    use tiff::ColorType;
+
+    let tiff_error = |e: tiff::TiffError| CreateImageError::DecodeError(e.to_string());
+
+    let reader = std::io::BufReader::new(std::fs::File::open(path)?);
+    let mut decoder = Decoder::new(reader).map_err(tiff_error)?;
+    let channels = match decoder.colortype().map_err(tiff_error)? {
+        ColorType::Gray(8) => 1,
+        ColorType::RGB(8) => 3,
+        ColorType::RGBA(8) => 4,
+        _ => return Ok(image::open(path)?),
+    };
+    let (width, height) = decoder.dimensions().map_err(tiff_error)?;
+    let (chunk_width, chunk_height) = decoder.chunk_dimensions();
+    let chunks_across = width.div_ceil(chunk_width.max(1));
+    let chunk_count = chunks_across * height.div_ceil(chunk_height.max(1));
+
+    let (scaled_width, scaled_height) = (width.div_ceil(denominator), height.div_ceil(denominator));
+    let mut sums = vec![0_u32; scaled_width as usize * scaled_height as usize * channels];
+    let mut counts = vec![0_u32; scaled_width as usize * scaled_height as usize];
+    for index in 0..chunk_count {
+        let DecodingResult::U8(data) = decoder.read_chunk(index).map_err(tiff_error)? else {
+            return Err(CreateImageError::DecodeError("Unexpected TIFF sample format".into()));
+        };
+        let (data_width, data_height) = decoder.chunk_data_dimensions(index);
+        let left = (index % chunks_across) * chunk_width;
+        let top = (index / chunks_across) * chunk_height;
+        for y in 0..data_height.min(height.saturating_sub(top)) {
+            let row = ((top + y) / denominator * scaled_width) as usize;
+            for x in 0..data_width.min(width.saturating_sub(left)) {
+                let scaled = row + ((left + x) / denominator) as usize;
+                let source = (y * data_width + x) as usize * channels;
+                let Some(samples) = data.get(source..source + channels) else {
+                    continue;
+                };
+                for (sum, sample) in sums[scaled * channels..].iter_mut().zip(samples) {
+                    *sum += u32::from(*sample);
+                }
+                counts[scaled] += 1;
+            }
+        }
+    }
+
+    let pixels: Vec<u8> = sums
+        .chunks(channels)
+        .zip(&counts)
+        .flat_map(|(sums, &count)| sums.iter().map(move |sum| (sum / count.max(1)) as u8))
+        .collect();
+    let invalid = || CreateImageError::DecodeError("Invalid TIFF dimensions".into());
+    Ok(match channels {
+        1 => image::GrayImage::from_raw(scaled_width, scaled_height, pixels)
+            .ok_or_else(invalid)?
+            .into(),
+        3 => image::RgbImage::from_raw(scaled_width, scaled_height, pixels)
+            .ok_or_else(invalid)?
+            .into(),
+        _ => image::RgbaImage::from_raw(scaled_width, scaled_height, pixels)
+            .ok_or_else(invalid)?
+            .into(),
+    })
+}
+
+#[cfg(feature = "heif")]
+fn open_heif(path: &Path) -> Result<DynamicImage, CreateImageError> {
+    use libheif_rs::{ColorSpace, HeifContext, LibHeif, RgbChroma};
+
+    let decode_error = |e: libheif_rs::HeifError| CreateImageError::DecodeError(e.to_string());
+
+    let lib_heif = LibHeif::new();
+    let path = path.to_string_lossy();
+    let context = HeifContext::read_from_file(&path).map_err(decode_error)?;
+    let handle = context.primary_image_handle().map_err(decode_error)?;
+    let decoded = lib_heif
+        .decode(&handle, ColorSpace::Rgb(RgbChroma::Rgba), None)
+        .map_err(decode_error)?;
+
+    let planes = decoded.planes();
+    let plane = planes
+        .interleaved
+        .ok_or_else(|| CreateImageError::DecodeError("HEIF image has no pixel data".into()))?;
+
+    // Rows may be padded, copy them without the stride padding.
+    let (width, height) = (plane.width, plane.height);
+    let row_len = width as usize * 4;
+    let mut pixels = Vec::with_capacity(row_len * height as usize);
+    for row in plane.data.chunks(plane.stride).take(height as usize) {
+        pixels.extend_from_slice(&row[..row_len]);
+    }
+
+    image::RgbaImage::from_raw(width, height, pixels)
+        .map(DynamicImage::ImageRgba8)
+        .ok_or_else(|| CreateImageError::DecodeError("Invalid HEIF dimensions".into()))
+}
+
+#[cfg(feature = "jxl")]
+fn open_jxl(path: &Path) -> Result<DynamicImage, CreateImageError> {
+    use jpegxl_rs::image::ToDynamic;
+
+    let data = std::fs::read(path)?;
+    let decoder = jpegxl_rs::decoder_builder()
+        .build()
+        .map_err(|e| CreateImageError::DecodeError(e.to_string()))?;
+    decoder
+        .decode_to_image(&data)
+        .map_err(|e| CreateImageError::DecodeError(e.to_string()))?
+        .ok_or_else(|| CreateImageError::DecodeError("Unsupported JPEG XL pixel format".into()))
+}
+
+/// Decodes the first frame of a video as its poster.
+#[cfg(feature = "video")]
+fn open_video(path: &Path) -> Result<DynamicImage, CreateImageError> {
+    use ffmpeg_next::format::Pixel;
+    use ffmpeg_next::software::scaling;
+    use ffmpeg_next::util::frame::video::Video;
+
+    let decode_error = |e: ffmpeg_next::Error| CreateImageError::DecodeError(e.to_string());
+
+    ffmpeg_next::init().map_err(decode_error)?;
+    let mut input = ffmpeg_next::format::input(&path).map_err(decode_error)?;
+    let stream = input
+        .streams()
+        .best(ffmpeg_next::media::Type::Video)
+        .ok_or_else(|| CreateImageError::DecodeError("Video has no video stream".into()))?;
+    let index = stream.index();
+    let context = ffmpeg_next::codec::context::Context::from_parameters(stream.parameters())
+        .map_err(decode_error)?;
+    let mut decoder = context.decoder().video().map_err(decode_error)?;
+    let (width, height) = (decoder.width(), decoder.height());
+    let mut scaler = scaling::Context::get(
+        decoder.format(),
+        width,
+        height,
+        Pixel::RGBA,
+        width,
+        height,
+        scaling::Flags::BILINEAR,
+    )
+    .map_err(decode_error)?;
+
+    let mut frame = Video::empty();
+    let mut decoded = false;
+    for (stream, packet) in input.packets() {
+        if stream.index() != index {
+            continue;
+        }
+        decoder.send_packet(&packet).map_err(decode_error)?;
+        if decoder.receive_frame(&mut frame).is_ok() {
+            decoded = true;
+            break;
+        }
+    }
+    // Decoders with frame delay only return the first frame once flushed.
+    if !decoded {
+        decoder.send_eof().map_err(decode_error)?;
+        decoder
+            .receive_frame(&mut frame)
+            .map_err(|_| CreateImageError::DecodeError("Video has no frames".into()))?;
+    }
+
+    let mut rgba = Video::empty();
+    scaler.run(&frame, &mut rgba).map_err(decode_error)?;
+    // Rows may be padded, copy them without the stride padding.
+    let row_len = width as usize * 4;
+    let mut pixels = Vec::with_capacity(row_len * height as usize);
+    for row in rgba.data(0).chunks(rgba.stride(0)).take(height as usize) {
+        pixels.extend_from_slice(&row[..row_len]);
+    }
+
+    image::RgbaImage::from_raw(width, height, pixels)
+        .map(DynamicImage::ImageRgba8)
+        .ok_or_else(|| CreateImageError::DecodeError("Invalid video dimensions".into()))
+}
+
+/// Longest side of rendered PDF pages, in pixels. Pages are vector graphics, this
+/// leaves room for large thumbnails and 2x displays.
+#[cfg(feature = "pdf")]
+const PDF_RENDER_SIZE: i32 = 2048;
+
+/// Renders the first page of a PDF on white.
+#[cfg(feature = "pdf")]
+fn open_pdf(path: &Path) -> Result<DynamicImage, CreateImageError> {
+    use pdfium_render::prelude::{PdfRenderConfig, Pdfium};
+
+    let decode_error = |e: pdfium_render::prelude::PdfiumError| {
+        CreateImageError::DecodeError(e.to_string())
+    };
+
+    let pdfium = Pdfium::new(Pdfium::bind_to_system_library().map_err(decode_error)?);
+    let document = pdfium.load_pdf_from_file(path, None).map_err(decode_error)?;
+    let page = document.pages().get(0).map_err(decode_error)?;
+    let config = PdfRenderConfig::new()
+        .set_target_width(PDF_RENDER_SIZE)
+        .set_maximum_height(PDF_RENDER_SIZE);
+    let bitmap = page.render_with_config(&config).map_err(decode_error)?;
+    Ok(bitmap.as_image())
+}
+
+/// Linear XYZ (D65) to linear sRGB.
+#[cfg(feature = "raw")]
+const XYZ_TO_SRGB: [[f32; 3]; 3] = [
+    [3.2406, -1.5372, -0.4986],
+    [-0.9689, 1.8758, 0.0415],
+    [0.0557, -0.2040, 1.0570],
+];
+
+/// Develops a camera RAW file into linear sRGB at half its resolution.
+///
+/// Each 2x2 block of the color filter array becomes one pixel, which needs no
+/// interpolation and is plenty for the web. White balance and the camera color
+/// matrix come from the file, the optimizer's [`ToneMapping`] then encodes the
+/// linear values to 8-bit like any other HDR source.
+#[cfg(feature = "raw")]
+fn open_raw(path: &Path) -> Result<DynamicImage, CreateImageError> {
+    let raw = rawloader::decode_file(path)
+        .map_err(|e| CreateImageError::DecodeError(e.to_string()))?;
+    let rawloader::RawImageData::Integer(data) = &raw.data else {
+        return Err(CreateImageError::DecodeError("Unsupported floating point RAW".into()));
+    };
+    if raw.cpp != 1 {
+        return Err(CreateImageError::DecodeError("Unsupported RAW without a mosaic".into()));
+    }
+
+    let [top, right, bottom, left] = raw.crops;
+    let width = raw.width.saturating_sub(left + right) / 2;
+    let height = raw.height.saturating_sub(top + bottom) / 2;
+    // Relative to green, some cameras leave the fourth coefficient unset.
+    let white_balance = raw.wb_coeffs.map(|coefficient| {
+        if coefficient.is_finite() && coefficient > 0.0 && raw.wb_coeffs[1] > 0.0 {
+            coefficient / raw.wb_coeffs[1]
+        } else {
+            1.0
+        }
+    });
+    let cam_to_xyz = raw.cam_to_xyz_normalized();
+    let sample = |row: usize, col: usize| {
+        let color = raw.cfa.color_at(row, col);
+        let black = f32::from(raw.blacklevels[color]);
+        let white = f32::from(raw.whitelevels[color]).max(black + 1.0);
+        let value = (f32::from(data[row * raw.width + col]) - black) / (white - black);
+        (color, (value.max(0.0) * white_balance[color]).min(1.0))
+    };
+
+    let mut pixels = Vec::with_capacity(width * height * 3);
+    for y in 0..height {
+        for x in 0..width {
+            let (mut sums, mut counts) = ([0.0_f32; 4], [0_u8; 4]);
+            for (dy, dx) in [(0, 0), (0, 1), (1, 0), (1, 1)] {
+                let (color, value) = sample(top + y * 2 + dy, left + x * 2 + dx);
+                sums[color] += value;
+                counts[color] += 1;
+            }
+            let camera: [f32; 4] = std::array::from_fn(|color| {
+                sums[color] / f32::from(counts[color].max(1))
+            });
+            let xyz: [f32; 3] = std::array::from_fn(|row| {
+                (0..4).map(|col| cam_to_xyz[row][col] * camera[col]).sum()
+            });
+            for row in XYZ_TO_SRGB {
+                pixels.push((0..3).map(|col| row[col] * xyz[col]).sum::<f32>().max(0.0));
+            }
+        }
+    }
+
+    image::Rgb32FImage::from_raw(width as u32, height as u32, pixels)
+        .map(DynamicImage::ImageRgb32F)
+        .ok_or_else(|| CreateImageError::DecodeError("Invalid RAW dimensions".into()))
+}
+
+type DecodeSlot = Arc<Mutex<Option<Arc<DynamicImage>>>>;
+
+/// Short-lived cache of decoded sources, keyed by path and JPEG scaling denominator.
+///
+/// A page usually requests the blur and the resized variant of a source at the same
+/// time. Whichever task comes first decodes, the other waits for it and reuses the pixels.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct DecodeCache {
+    entries: Arc<Mutex<HashMap<(PathBuf, u32), (Instant, DecodeSlot)>>>,
+}
+
+impl DecodeCache {
+    /// How long decoded pixels are kept around after the first decode.
+    const TTL: Duration = Duration::from_secs(10);
+
+    /// Returns the decoded source, running `decode` unless another task already did.
+    pub(crate) fn get_or_decode(
+        &self,
+        path: &Path,
+        denominator: u32,
+        decode: impl FnOnce() -> Result<DynamicImage, CreateImageError>,
+    ) -> Result<Arc<DynamicImage>, CreateImageError> {
+        let slot = {
+            let mut entries = self.entries.lock().expect("Decode cache poisoned");
+            let now = Instant::now();
+            entries.retain(|_, (created, _)| now.duration_since(*created) < Self::TTL);
+            entries
+                .entry((path.to_path_buf(), denominator))
+                .or_insert_with(|| (now, Default::default()))
+                .1
+                .clone()
+        };
+
+        // Held while decoding, so concurrent requests for the same source wait here.
+        let mut slot = slot.lock().expect("Decode slot poisoned");
+        if let Some(img) = slot.as_ref() {
+            return Ok(img.clone());
+        }
+        let img = Arc::new(decode()?);
+        *slot = Some(img.clone());
+        Ok(img)
+    }
+}
+
+#[cfg(test)]
+mod decode_tests {
+    use super::*;
+
+    #[test]
+    fn bit_depth_reduction() {
+        let wide = image::ImageBuffer::from_pixel(4, 4, image::Rgb([0u16, 32896, 65535]));
+        let narrow = to_8bit(DynamicImage::ImageRgb16(wide), &ToneMapping::default());
+        assert_eq!(narrow.color(), image::ColorType::Rgb8);
+        for pixel in narrow.to_rgb8().pixels() {
+            assert_eq!(pixel.0[0], 0);
+            assert!((127..=129).contains(&pixel.0[1]));
+            assert_eq!(pixel.0[2], 255);
+        }
+
+        let hdr = image::ImageBuffer::from_pixel(1, 1, image::Rgb([0.0f32, 1.0, 50.0]));
+        let tone_mapping = ToneMapping {
+            operator: ToneMapOperator::Clip,
+            ..Default::default()
+        };
+        let sdr = to_8bit(DynamicImage::ImageRgb32F(hdr), &tone_mapping).to_rgba8();
+        assert_eq!(sdr.get_pixel(0, 0).0, [0, 255, 255, 255]);
+    }
+
+    #[test]
+    fn source_format_sniffing() {
+        let png = std::fs::read("./example/start-axum/public/cute_ferris.png").unwrap();
+        assert!(matches_extension(Path::new("ferris.png"), &png));
+        assert!(matches_extension(Path::new("FERRIS.PNG"), &png));
+        assert!(!matches_extension(Path::new("ferris.jpg"), &png));
+        assert!(!matches_extension(Path::new("passwd.png"), b"root:x:0:0:root:/root:/bin/sh"));
+        assert!(!matches_extension(Path::new("notes.txt"), b"hello"));
+
+        let svg = b"<?xml version=\"1.0\"?>\n<svg xmlns=\"http://www.w3.org/2000/svg\"/>";
+        assert!(matches_extension(Path::new("logo.svg"), svg));
+        assert!(!matches_extension(Path::new("logo.svg"), b"<html><body></body></html>"));
+        assert!(matches_extension(Path::new("photo.heic"), b"\0\0\0\x18ftypheic\0\0\0\0"));
+        let mp4 = b"\0\0\0\x20ftypisom\0\0\x02\0";
+        assert_eq!(matches_extension(Path::new("intro.mp4"), mp4), cfg!(feature = "video"));
+        let pdf = b"%PDF-1.7\n";
+        assert_eq!(matches_extension(Path::new("report.pdf"), pdf), cfg!(feature = "pdf"));
+        let nef = b"MM\0*\0\0\0\x08";
+        assert_eq!(matches_extension(Path::new("DSC_0001.NEF"), nef), cfg!(feature = "raw"));
+        assert!(!matches_extension(Path::new("DSC_0001.NEF"), &png));
+    }
+
+    fn jpeg_with_orientation(orientation: u16) -> std::path::PathBuf {
+        // Red top left quadrant on black.
+        let img = image::RgbImage::from_fn(32, 16, |x, y| {
+            if x < 16 && y < 8 {
+                image::Rgb([255, 0, 0])
+            } else {
+                image::Rgb([0, 0, 0])
+            }
+        });
+        let mut jpeg = std::io::Cursor::new(Vec::new());
+        DynamicImage::ImageRgb8(img)
+            .write_to(&mut jpeg, image::ImageOutputFormat::Jpeg(95))
+            .unwrap();
+
+        let field = exif::Field {
+            tag: exif::Tag::Orientation,
+            ifd_num: exif::In::PRIMARY,
+            value: exif::Value::Short(vec![orientation]),
+        };
+        let make = exif::Field {
+            tag: exif::Tag::Make,
+            ifd_num: exif::In::PRIMARY,
+            value: exif::Value::Ascii(vec![b"TestCam".to_vec()]),
+        };
+        let mut writer = exif::experimental::Writer::new();
+        writer.push_field(&field);
+        writer.push_field(&make);
+        let mut tiff = std::io::Cursor::new(Vec::new());
+        writer.write(&mut tiff, false).unwrap();
+
+        let bytes = crate::metadata::insert_jpeg_exif(jpeg.into_inner(), tiff.get_ref());
+        let path = std::env::temp_dir().join(format!("leptos_image_orientation_{orientation}.jpg"));
+        std::fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    #[test]
+    fn exif_orientation() {
+        // Corner the red quadrant ends up in, (right, bottom).
+        let expected = [
+            (false, false),
+            (true, false),
+            (true, true),
+            (false, true),
+            (false, false),
+            (true, false),
+            (true, true),
+            (false, true),
+        ];
+        for (orientation, (right, bottom)) in (1..=8).zip(expected) {
+            let path = jpeg_with_orientation(orientation);
+            let img = open_image(&path).unwrap();
+            let img = auto_orient(img, &path, &AutoOrient::default()).to_rgb8();
+
+            let (width, height) = img.dimensions();
+            let probed = probe_dimensions(&path, &AutoOrient::default());
+            assert_eq!(probed, Some((width, height)), "orientation {orientation}");
+            if orientation >= 5 {
+                assert_eq!((width, height), (16, 32), "orientation {orientation}");
+            } else {
+                assert_eq!((width, height), (32, 16), "orientation {orientation}");
+            }
+            let x = if right { width - 3 } else { 2 };
+            let y = if bottom { height - 3 } else { 2 };
+            assert!(img.get_pixel(x, y).0[0] > 200, "orientation {orientation}");
+
+            let ignored = AutoOrient {
+                ignore_makes: vec!["testcam".into()],
+                ..Default::default()
+            };
+            let untouched = auto_orient(open_image(&path).unwrap(), &path, &ignored).to_rgb8();
+            assert_eq!(untouched.dimensions(), (32, 16));
+            assert!(untouched.get_pixel(2, 2).0[0] > 200);
+        }
+    }
+
+    #[test]
+    fn decode_once() {
+        let cache = DecodeCache::default();
+        let path = Path::new("example/start-axum/public/cute_ferris.png");
+        let decode = || Ok(DynamicImage::ImageRgb8(image::RgbImage::new(1, 1)));
+
+        let first = cache.get_or_decode(path, 1, decode).unwrap();
+        let second = cache
+            .get_or_decode(path, 1, || panic!("Source decoded twice"))
+            .unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+
+        // Failed decodes are not cached.
+        let other = Path::new("missing.png");
+        assert!(cache
+            .get_or_decode(other, 1, || Err(CreateImageError::DecodeError("oops".into())))
+            .is_err());
+        assert!(cache.get_or_decode(other, 1, decode).is_ok());
+    }
+
+    #[test]
+    fn tiff_strip_scaling() {
+        // Left half white, right half black, 64x32.
+        let img = image::RgbImage::from_fn(64, 32, |x, _| {
+            if x < 32 {
+                image::Rgb([255, 255, 255])
+            } else {
+                image::Rgb([0, 0, 0])
+            }
+        });
+        let path = std::env::temp_dir().join("leptos_image_strips.tiff");
+        img.save(&path).unwrap();
+        assert_eq!(tiff_scale_denominator(&path, Some((8, 8))), 4);
+        assert_eq!(tiff_scale_denominator(&path, None), 1);
+
+        let scaled = open_image_scaled(&path, 4).unwrap().to_rgb8();
+        assert_eq!(scaled.dimensions(), (16, 8));
+        assert_eq!(scaled.get_pixel(7, 4).0, [255, 255, 255]);
+        assert_eq!(scaled.get_pixel(8, 4).0, [0, 0, 0]);
+
+        assert!(check_source_pixels(&path, 1, 64 * 32).is_ok());
+        assert!(check_source_pixels(&path, 1, 64 * 32 - 1).is_err());
+        assert!(check_source_pixels(&path, 4, 16 * 8).is_ok());
+    }
+
+    #[test]
+    fn jpeg_dct_scaling() {
+        // 32x16 source.
+        let path = jpeg_with_orientation(1);
+        assert_eq!(jpeg_scale_denominator(&path, Some((4, 4))), 8);
+        assert_eq!(jpeg_scale_denominator(&path, Some((8, 8))), 4);
+        assert_eq!(jpeg_scale_denominator(&path, Some((32, 32))), 1);
+        assert_eq!(jpeg_scale_denominator(&path, None), 1);
+
+        let img = open_image_scaled(&path, 4).unwrap();
+        assert_eq!((img.width(), img.height()), (8, 4));
+    }
+}
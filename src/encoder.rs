@@ -0,0 +1,319 @@
+use crate::optimizer::{CreateImageError, OutputFormat, Resize};
+use image::DynamicImage;
+
+/// Describes an encode: the requested options plus the resolved output format.
+#[derive(Debug, Clone, Copy)]
+pub struct EncodeRequest<'a> {
+    /// Options of the requested image.
+    pub options: &'a Resize,
+    /// The output format to encode, never [`OutputFormat::Auto`].
+    pub format: OutputFormat,
+    /// Format of the source file, if known.
+    pub source_format: Option<image::ImageFormat>,
+}
+
+/// Encodes resized images into their final bytes.
+///
+/// Implement this to plug in an alternate backend (libvips, `cwebp`/`avifenc`
+/// subprocesses, GPU encoders, ...) and register it with
+/// [`crate::ImageOptimizer::with_encoder`]. Decoding, resizing and caching stay
+/// with the optimizer. [`DefaultEncoder`] uses the `image` and `webp` crates.
+///
+/// ```
+/// use leptos_image::*;
+///
+/// #[derive(Debug)]
+/// struct PngEverywhere;
+///
+/// impl ImageEncoder for PngEverywhere {
+///     fn encode(
+///         &self,
+///         image: &image::DynamicImage,
+///         request: &EncodeRequest,
+///     ) -> Result<Vec<u8>, CreateImageError> {
+///         let mut bytes = std::io::Cursor::new(Vec::new());
+///         image.write_to(&mut bytes, image::ImageOutputFormat::Png)?;
+///         Ok(bytes.into_inner())
+///     }
+/// }
+/// ```
+pub trait ImageEncoder: Send + Sync + std::fmt::Debug {
+    /// Encodes an image that has already been resized.
+    fn encode(
+        &self,
+        image: &DynamicImage,
+        request: &EncodeRequest,
+    ) -> Result<Vec<u8>, CreateImageError>;
+
+    /// Encodes resized animation frames. Only called for WebP output.
+    ///
+    /// Defaults to the built-in animated WebP encoder.
+    fn encode_animation(
+        &self,
+        frames: Vec<image::Frame>,
+        request: &EncodeRequest,
+    ) -> Result<Vec<u8>, CreateImageError> {
+        encode_animated_webp(frames, request.options)
+    }
+}
+
+/// The built-in encoder, using the `image` and `webp` crates.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DefaultEncoder;
+
+impl ImageEncoder for DefaultEncoder {
+    fn encode(
+        &self,
+        image: &DynamicImage,
+        request: &EncodeRequest,
+    ) -> Result<Vec<u8>, CreateImageError> {
+        let quality = request.options.quality;
+        match (request.format, request.source_format) {
+            // WebP sources in original format go through the WebP encoder below.
+            (OutputFormat::Original, Some(format)) if format != image::ImageFormat::WebP => {
+                encode_original(image, format, quality)
+            }
+            (OutputFormat::Jpeg, _) => encode_jpeg(image, quality),
+            #[cfg(feature = "avif")]
+            (OutputFormat::Avif, _) => encode_avif(image, quality),
+            #[cfg(feature = "jxl")]
+            (OutputFormat::Jxl, _) => encode_jxl(image, quality),
+            #[cfg(feature = "quantize")]
+            (OutputFormat::PalettePng, _) => encode_palette_png(image, quality),
+            _ => encode_webp(image, request.options),
+        }
+    }
+}
+
+/// Encodes in the given source format, e.g. JPEG stays JPEG.
+fn encode_original(
+    img: &image::DynamicImage,
+    format: image::ImageFormat,
+    quality: u8,
+) -> Result<Vec<u8>, CreateImageError> {
+    if format == image::ImageFormat::Jpeg {
+        return encode_jpeg(img, quality);
+    }
+    let mut bytes = std::io::Cursor::new(Vec::new());
+    img.write_to(&mut bytes, image::ImageOutputFormat::from(format))?;
+    Ok(bytes.into_inner())
+}
+
+#[cfg(not(feature = "mozjpeg"))]
+fn encode_jpeg(img: &image::DynamicImage, quality: u8) -> Result<Vec<u8>, CreateImageError> {
+    // JPEG has no alpha channel.
+    let img = image::DynamicImage::ImageRgb8(img.to_rgb8());
+    let mut bytes = std::io::Cursor::new(Vec::new());
+    img.write_to(&mut bytes, image::ImageOutputFormat::Jpeg(quality))?;
+    Ok(bytes.into_inner())
+}
+
+/// Progressive JPEG with optimized scans. mozjpeg's default profile also
+/// enables trellis quantization.
+#[cfg(feature = "mozjpeg")]
+fn encode_jpeg(img: &image::DynamicImage, quality: u8) -> Result<Vec<u8>, CreateImageError> {
+    let rgb = img.to_rgb8();
+    // mozjpeg reports libjpeg errors by unwinding.
+    std::panic::catch_unwind(|| -> std::io::Result<Vec<u8>> {
+        let mut compress = mozjpeg::Compress::new(mozjpeg::ColorSpace::JCS_RGB);
+        compress.set_size(rgb.width() as usize, rgb.height() as usize);
+        compress.set_quality(quality as f32);
+        compress.set_progressive_mode();
+        compress.set_optimize_scans(true);
+        compress.set_optimize_coding(true);
+        let mut started = compress.start_compress(Vec::new())?;
+        started.write_scanlines(rgb.as_raw())?;
+        started.finish()
+    })
+    .map_err(|_| CreateImageError::EncodeError("mozjpeg failed".into()))?
+    .map_err(CreateImageError::IOError)
+}
+
+#[cfg(feature = "avif")]
+fn encode_avif(img: &image::DynamicImage, quality: u8) -> Result<Vec<u8>, CreateImageError> {
+    use image::ImageEncoder;
+
+    let rgba = img.to_rgba8();
+    let mut bytes = Vec::new();
+    image::codecs::avif::AvifEncoder::new_with_speed_quality(&mut bytes, 6, quality).write_image(
+        rgba.as_raw(),
+        rgba.width(),
+        rgba.height(),
+        image::ColorType::Rgba8,
+    )?;
+    Ok(bytes)
+}
+
+/// Quantizes to at most 256 colors with libimagequant, dithered, and writes an
+/// indexed PNG. `quality` is the target quality of the palette.
+#[cfg(feature = "quantize")]
+fn encode_palette_png(img: &image::DynamicImage, quality: u8) -> Result<Vec<u8>, CreateImageError> {
+    let quantize_error = |e: imagequant::Error| CreateImageError::EncodeError(e.to_string());
+    let png_error = |e: png::EncodingError| CreateImageError::EncodeError(e.to_string());
+
+    let rgba = img.to_rgba8();
+    let (width, height) = rgba.dimensions();
+    let pixels: Vec<imagequant::RGBA> = rgba
+        .pixels()
+        .map(|pixel| imagequant::RGBA::new(pixel[0], pixel[1], pixel[2], pixel[3]))
+        .collect();
+
+    let mut attributes = imagequant::new();
+    attributes.set_quality(0, quality.min(100)).map_err(quantize_error)?;
+    let mut image = attributes
+        .new_image(pixels, width as usize, height as usize, 0.0)
+        .map_err(quantize_error)?;
+    let mut quantized = attributes.quantize(&mut image).map_err(quantize_error)?;
+    quantized.set_dithering_level(1.0).map_err(quantize_error)?;
+    let (palette, indexes) = quantized.remapped(&mut image).map_err(quantize_error)?;
+
+    let mut bytes = Vec::new();
+    let mut encoder = png::Encoder::new(&mut bytes, width, height);
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_compression(png::Compression::Best);
+    encoder.set_palette(palette.iter().flat_map(|c| [c.r, c.g, c.b]).collect::<Vec<_>>());
+    // Trailing opaque entries can be left out of the transparency chunk.
+    if let Some(last) = palette.iter().rposition(|c| c.a < 255) {
+        encoder.set_trns(palette[..=last].iter().map(|c| c.a).collect::<Vec<_>>());
+    }
+    let mut writer = encoder.write_header().map_err(png_error)?;
+    writer.write_image_data(&indexes).map_err(png_error)?;
+    writer.finish().map_err(png_error)?;
+    Ok(bytes)
+}
+
+#[cfg(feature = "jxl")]
+fn encode_jxl(img: &image::DynamicImage, quality: u8) -> Result<Vec<u8>, CreateImageError> {
+    use jpegxl_rs::encode::EncoderResult;
+
+    let encode_error = |e: jpegxl_rs::EncodeError| CreateImageError::EncodeError(e.to_string());
+
+    let rgba = img.to_rgba8();
+    let mut encoder = jpegxl_rs::encoder_builder()
+        .has_alpha(true)
+        .build()
+        .map_err(encode_error)?;
+    // Maps the JPEG style quality onto a JPEG XL butteraugli distance.
+    encoder.set_jpeg_quality(quality as f32);
+    let result: EncoderResult<u8> = encoder
+        .encode(rgba.as_raw(), rgba.width(), rgba.height())
+        .map_err(encode_error)?;
+    Ok(result.data)
+}
+
+fn encode_webp(img: &image::DynamicImage, resize: &Resize) -> Result<Vec<u8>, CreateImageError> {
+    use webp::*;
+
+    // Create the WebP encoder for the above image
+    let encoder: Encoder =
+        Encoder::from_image(img).map_err(|e| CreateImageError::EncodeError(e.into()))?;
+    // Encode the image at a specified quality 0-100
+    let webp: WebPMemory = match (resize.near_lossless, resize.auto_quality) {
+        (Some(_), _) => encoder
+            .encode_advanced(&webp_config(resize)?)
+            .map_err(|e| CreateImageError::EncodeError(format!("{e:?}")))?,
+        (None, _) if resize.lossless => encoder.encode_lossless(),
+        #[cfg(feature = "auto-quality")]
+        (None, Some(target)) => encode_webp_auto_quality(&encoder, img, target)?,
+        _ => encoder.encode(resize.quality as f32),
+    };
+    Ok(webp.to_vec())
+}
+
+/// Binary searches the lowest quality whose DSSIM against the original is within `target`
+/// (in millionths).
+#[cfg(feature = "auto-quality")]
+fn encode_webp_auto_quality(
+    encoder: &webp::Encoder,
+    img: &image::DynamicImage,
+    target: u32,
+) -> Result<webp::WebPMemory, CreateImageError> {
+    use rgb::FromSlice;
+
+    const MIN_QUALITY: u8 = 30;
+    const MAX_QUALITY: u8 = 95;
+
+    let target = f64::from(target) / 1_000_000.0;
+    let dssim = dssim_core::Dssim::new();
+    let to_dssim = |img: &image::DynamicImage| {
+        let rgb = img.to_rgb8();
+        dssim
+            .create_image_rgb(
+                rgb.as_raw().as_rgb(),
+                rgb.width() as usize,
+                rgb.height() as usize,
+            )
+            .ok_or_else(|| CreateImageError::EncodeError("Invalid image for DSSIM".into()))
+    };
+    let original = to_dssim(img)?;
+
+    let (mut low, mut high) = (MIN_QUALITY, MAX_QUALITY);
+    let mut best = None;
+    while low <= high {
+        let quality = low + (high - low) / 2;
+        let webp = encoder.encode(quality as f32);
+        let decoded = webp::Decoder::new(&webp)
+            .decode()
+            .ok_or_else(|| CreateImageError::EncodeError("Failed to decode WebP".into()))?
+            .to_image();
+        let (score, _) = dssim.compare(&original, to_dssim(&decoded)?);
+        if f64::from(score) <= target {
+            tracing::debug!("Auto quality {quality} scored {score} (target {target})");
+            best = Some(webp);
+            high = quality - 1;
+        } else {
+            low = quality + 1;
+        }
+    }
+
+    Ok(best.unwrap_or_else(|| encoder.encode(MAX_QUALITY as f32)))
+}
+
+/// WebP encoder settings for the requested quality and lossless mode.
+fn webp_config(resize: &Resize) -> Result<webp::WebPConfig, CreateImageError> {
+    let mut config = webp::WebPConfig::new()
+        .map_err(|_| CreateImageError::EncodeError("Invalid WebP config".into()))?;
+    // In lossless mode quality controls compression effort instead.
+    config.quality = resize.quality as f32;
+    if resize.lossless || resize.near_lossless.is_some() {
+        config.lossless = 1;
+    }
+    if let Some(level) = resize.near_lossless {
+        config.near_lossless = level.min(100) as i32;
+    }
+    Ok(config)
+}
+
+/// Encodes resized frames as an animated WebP.
+pub(crate) fn encode_animated_webp(
+    frames: Vec<image::Frame>,
+    resize: &Resize,
+) -> Result<Vec<u8>, CreateImageError> {
+    use webp::{AnimEncoder, AnimFrame};
+
+    let Some(first) = frames.first() else {
+        return Err(CreateImageError::EncodeError("Animation has no frames".into()));
+    };
+    let (width, height) = first.buffer().dimensions();
+
+    let mut timestamp = 0;
+    let timestamps: Vec<i32> = frames
+        .iter()
+        .map(|frame| {
+            let (numer, denom) = frame.delay().numer_denom_ms();
+            let at = timestamp;
+            timestamp += (numer / denom.max(1)) as i32;
+            at
+        })
+        .collect();
+
+    let config = webp_config(resize)?;
+
+    let mut encoder = AnimEncoder::new(width, height, &config);
+    encoder.set_loop_count(0);
+    for (frame, at) in frames.iter().zip(timestamps) {
+        encoder.add_frame(AnimFrame::from_rgba(frame.buffer(), width, height, at));
+    }
+    Ok(encoder.encode().to_vec())
+}
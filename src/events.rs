@@ -0,0 +1,200 @@
+use crate::optimizer::{
+    collect_files, CachedImage, CreateImageError, ImageOptimizer, SIDECAR_EXTENSION,
+};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// A cached image passed to the hooks of an optimizer, see
+/// [`ImageOptimizer::on_encode_complete`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageEvent {
+    /// The image.
+    pub image: CachedImage,
+    /// URL of the image below the cache handler, with the asset prefix. What browsers
+    /// and CDNs cache.
+    pub url: String,
+    /// The cached file.
+    pub path: PathBuf,
+    /// A file of the image was cached before, e.g. when encoding again after the source
+    /// changed or the max age passed. Always `false` for evictions.
+    pub replaced: bool,
+}
+
+/// The closures registered on an optimizer, called in the order they were added.
+#[derive(Clone, Default)]
+pub(crate) struct Hooks {
+    encode_start: Vec<Arc<dyn Fn(&ImageEvent) + Send + Sync>>,
+    encode_complete: Vec<Arc<dyn Fn(&ImageEvent, Duration) + Send + Sync>>,
+    cache_evict: Vec<Arc<dyn Fn(&ImageEvent) + Send + Sync>>,
+    error: Vec<Arc<dyn Fn(&ImageEvent, &CreateImageError) + Send + Sync>>,
+}
+
+impl std::fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Hooks")
+            .field("encode_start", &self.encode_start.len())
+            .field("encode_complete", &self.encode_complete.len())
+            .field("cache_evict", &self.cache_evict.len())
+            .field("error", &self.error.len())
+            .finish()
+    }
+}
+
+impl ImageOptimizer {
+    /// Calls `hook` before an image is encoded, after it got its encode slot. Images
+    /// shared by concurrent requests are encoded once. Followed by a call of
+    /// [`Self::on_encode_complete`] or [`Self::on_error`], unless another process
+    /// sharing the cache directory encoded the image meanwhile.
+    ///
+    /// Hooks run on the encoding task and delay it, spawn a task or send to a channel
+    /// for slow work. Every hook added is called, so libraries can add their own.
+    ///
+    /// ```
+    /// # use leptos_image::*;
+    /// let optimizer = ImageOptimizer::new("/__cache/image", "./target/site", 1)
+    ///     .on_encode_start(|event| println!("Encoding {}", event.url));
+    /// ```
+    pub fn on_encode_start(mut self, hook: impl Fn(&ImageEvent) + Send + Sync + 'static) -> Self {
+        Arc::make_mut(&mut self.hooks).encode_start.push(Arc::new(hook));
+        self
+    }
+
+    /// Calls `hook` with the encode time after an image was written to the cache, e.g.
+    /// to invalidate CDNs when [`ImageEvent::replaced`] is set, or to notify a job queue.
+    /// See [`Self::on_encode_start`].
+    ///
+    /// ```
+    /// # use leptos_image::*;
+    /// let (sender, receiver) = std::sync::mpsc::channel();
+    /// let optimizer = ImageOptimizer::new("/__cache/image", "./target/site", 1)
+    ///     .on_encode_complete(move |event, took| {
+    ///         let _ = sender.send((event.url.clone(), took));
+    ///     });
+    /// ```
+    pub fn on_encode_complete(
+        mut self,
+        hook: impl Fn(&ImageEvent, Duration) + Send + Sync + 'static,
+    ) -> Self {
+        Arc::make_mut(&mut self.hooks).encode_complete.push(Arc::new(hook));
+        self
+    }
+
+    /// Calls `hook` after a cached image was removed by [`Self::purge_source`],
+    /// [`Self::purge_tenant`] or [`Self::evict_lru`]. Images whose sidecar is gone
+    /// are removed without a call. See [`Self::on_encode_start`].
+    pub fn on_cache_evict(mut self, hook: impl Fn(&ImageEvent) + Send + Sync + 'static) -> Self {
+        Arc::make_mut(&mut self.hooks).cache_evict.push(Arc::new(hook));
+        self
+    }
+
+    /// Calls `hook` once when encoding an image failed or timed out, however many
+    /// requests waited for it. Requests rejected before encoding, e.g. for a missing
+    /// source, aren't reported. See [`Self::on_encode_start`].
+    ///
+    /// ```
+    /// # use leptos_image::*;
+    /// let optimizer = ImageOptimizer::new("/__cache/image", "./target/site", 1)
+    ///     .on_error(|event, error| eprintln!("Failed to encode {}: {error}", event.url));
+    /// ```
+    pub fn on_error(
+        mut self,
+        hook: impl Fn(&ImageEvent, &CreateImageError) + Send + Sync + 'static,
+    ) -> Self {
+        Arc::make_mut(&mut self.hooks).error.push(Arc::new(hook));
+        self
+    }
+
+    fn image_event(&self, image: &CachedImage, replaced: bool) -> ImageEvent {
+        ImageEvent {
+            image: image.clone(),
+            url: self.cache_key.url(image, &self.handler_url()),
+            path: self.cache_file(self.cache_path(image)),
+            replaced,
+        }
+    }
+
+    pub(crate) fn emit_encode_start(&self, image: &CachedImage, replaced: bool) {
+        if !self.hooks.encode_start.is_empty() {
+            let event = self.image_event(image, replaced);
+            self.hooks.encode_start.iter().for_each(|hook| hook(&event));
+        }
+    }
+
+    pub(crate) fn emit_encode_complete(&self, image: &CachedImage, replaced: bool, took: Duration) {
+        if !self.hooks.encode_complete.is_empty() {
+            let event = self.image_event(image, replaced);
+            self.hooks.encode_complete.iter().for_each(|hook| hook(&event, took));
+        }
+    }
+
+    pub(crate) fn emit_error(&self, image: &CachedImage, error: &CreateImageError) {
+        if !self.hooks.error.is_empty() && !matches!(error, CreateImageError::Cancelled) {
+            let event = self.image_event(image, false);
+            self.hooks.error.iter().for_each(|hook| hook(&event, error));
+        }
+    }
+
+    pub(crate) fn emit_evicted(&self, images: impl IntoIterator<Item = CachedImage>) {
+        for image in images {
+            let event = self.image_event(&image, false);
+            self.hooks.cache_evict.iter().for_each(|hook| hook(&event));
+        }
+    }
+
+    /// Whether anything listens for evictions, so purges only read sidecars for them.
+    pub(crate) fn watches_evictions(&self) -> bool {
+        !self.hooks.cache_evict.is_empty()
+    }
+
+    /// The images cached below `dir`, read from their sidecars.
+    pub(crate) fn cached_images_in(&self, dir: &Path) -> Vec<CachedImage> {
+        let mut files = Vec::new();
+        if collect_files(dir, &mut files).is_err() {
+            return Vec::new();
+        }
+        files
+            .iter()
+            .filter(|file| file.extension().is_some_and(|ext| ext == SIDECAR_EXTENSION))
+            .filter_map(|sidecar| std::fs::read_to_string(sidecar).ok())
+            .filter_map(|encoded| serde_qs::from_str::<CachedImage>(&encoded).ok())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod events_tests {
+    use super::*;
+    use crate::test_support::{resize_spec, test_root};
+    use std::sync::Mutex;
+
+    #[test]
+    fn calls_hooks() {
+        let (_, optimizer) = test_root("leptos_image_events");
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let record = |name: &'static str| {
+            let events = events.clone();
+            move |event: &ImageEvent| events.lock().unwrap().push((name, event.clone()))
+        };
+        let (started, evicted, failed) = (record("start"), record("evict"), record("error"));
+        let completed = record("complete");
+        let optimizer = optimizer
+            .with_asset_prefix("https://cdn.example.com/")
+            .on_encode_start(started)
+            .on_encode_complete(move |event, _| completed(event))
+            .on_cache_evict(evicted)
+            .on_error(move |event, _| failed(event));
+        let image = CachedImage::new("/ferris.png", resize_spec(100, 100));
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+
+        assert!(runtime.block_on(optimizer.create_image(&image)).unwrap());
+        assert_eq!(optimizer.purge_source("/ferris.png").unwrap(), 1);
+        let events = events.lock().unwrap();
+        let names: Vec<_> = events.iter().map(|(name, _)| *name).collect();
+        assert_eq!(names, ["start", "complete", "evict"]);
+        let (_, event) = &events[1];
+        assert!(event.url.starts_with("https://cdn.example.com/__cache/image?"));
+        assert_eq!(event.path, PathBuf::from(optimizer.get_file_path_from_root(&image)));
+        assert!(!event.replaced);
+    }
+}
@@ -0,0 +1,239 @@
+use crate::optimizer::{CachedImage, CachedImageOption, ImageOptimizer};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{oneshot, OwnedSemaphorePermit, Semaphore};
+
+/// How queued encodes take turns, see [`ImageOptimizer::with_fair_queueing`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FairQueueing {
+    /// One queue per source directory, cut after this many segments: `1` queues
+    /// `/gallery/2024/a.jpg` with the other images below `/gallery`.
+    SourcePrefix(usize),
+    /// One queue per tenant, see [`crate::provide_image_tenant`]. Images without a
+    /// tenant share a queue.
+    Tenant,
+}
+
+impl FairQueueing {
+    // Name of the queue an image waits in.
+    fn key(&self, image: &CachedImage) -> String {
+        match self {
+            Self::SourcePrefix(depth) => {
+                let src = image.src.trim_start_matches('/');
+                let mut segments: Vec<&str> = src.split('/').collect();
+                // The file name.
+                segments.pop();
+                segments.truncate(*depth);
+                segments.join("/")
+            }
+            Self::Tenant => image.tenant.clone().unwrap_or_default(),
+        }
+    }
+}
+
+type Waiters = VecDeque<oneshot::Sender<OwnedSemaphorePermit>>;
+
+/// Hands the permits of the encode pool to its queues in turn, so one page full of
+/// images from a gallery can't starve the others.
+#[derive(Debug)]
+pub(crate) struct FairQueue {
+    queueing: FairQueueing,
+    semaphore: Arc<Semaphore>,
+    // Queues with waiters, the next to be served first.
+    queues: Mutex<VecDeque<(String, Waiters)>>,
+}
+
+/// A permit of an encode pool, passed on to the next queue in turn when dropped.
+#[derive(Debug)]
+pub(crate) struct EncodePermit {
+    queue: Option<Arc<FairQueue>>,
+    permit: Option<OwnedSemaphorePermit>,
+}
+
+impl EncodePermit {
+    // A permit of a pool without queues, handed out first come, first served.
+    async fn unqueued(semaphore: Arc<Semaphore>) -> Self {
+        let permit = semaphore
+            .acquire_owned()
+            .await
+            .expect("Failed to acquire semaphore");
+        Self {
+            queue: None,
+            permit: Some(permit),
+        }
+    }
+}
+
+impl Drop for EncodePermit {
+    fn drop(&mut self) {
+        let (Some(queue), Some(mut permit)) = (&self.queue, self.permit.take()) else {
+            return;
+        };
+        let mut queues = queue.queues.lock().unwrap();
+        while let Some((key, mut waiters)) = queues.pop_front() {
+            while let Some(waiter) = waiters.pop_front() {
+                match waiter.send(permit) {
+                    Ok(()) => {
+                        if !waiters.is_empty() {
+                            queues.push_back((key, waiters));
+                        }
+                        return;
+                    }
+                    // Dropped while waiting.
+                    Err(returned) => permit = returned,
+                }
+            }
+        }
+        // Nobody is waiting, back to the semaphore. Released under the lock, or a
+        // request queued in between would wait for the next permit.
+        drop(permit);
+    }
+}
+
+/// A request waiting in a [`FairQueue`].
+struct Waiting {
+    queue: Arc<FairQueue>,
+    receiver: oneshot::Receiver<OwnedSemaphorePermit>,
+}
+
+impl Waiting {
+    async fn permit(mut self) -> EncodePermit {
+        let permit = (&mut self.receiver)
+            .await
+            .expect("Waiters are only dropped by sending them a permit");
+        EncodePermit {
+            queue: Some(self.queue.clone()),
+            permit: Some(permit),
+        }
+    }
+}
+
+impl Drop for Waiting {
+    fn drop(&mut self) {
+        // A permit sent just as the request went away goes to the next in turn.
+        self.receiver.close();
+        if let Ok(permit) = self.receiver.try_recv() {
+            drop(EncodePermit {
+                queue: Some(self.queue.clone()),
+                permit: Some(permit),
+            });
+        }
+    }
+}
+
+impl FairQueue {
+    pub(crate) fn new(queueing: FairQueueing, semaphore: Arc<Semaphore>) -> Self {
+        Self {
+            queueing,
+            semaphore,
+            queues: Default::default(),
+        }
+    }
+
+    pub(crate) async fn acquire(self: &Arc<Self>, image: &CachedImage) -> EncodePermit {
+        match self.enqueue(image) {
+            Ok(permit) => permit,
+            Err(waiting) => waiting.permit().await,
+        }
+    }
+
+    // Takes a free permit if nobody is queued, or queues the image.
+    fn enqueue(self: &Arc<Self>, image: &CachedImage) -> Result<EncodePermit, Waiting> {
+        let mut queues = self.queues.lock().unwrap();
+        if queues.is_empty() {
+            if let Ok(permit) = self.semaphore.clone().try_acquire_owned() {
+                return Ok(EncodePermit {
+                    queue: Some(self.clone()),
+                    permit: Some(permit),
+                });
+            }
+        }
+        let (sender, receiver) = oneshot::channel();
+        let key = self.queueing.key(image);
+        match queues.iter_mut().find(|(other, _)| *other == key) {
+            Some((_, waiters)) => waiters.push_back(sender),
+            None => queues.push_back((key, VecDeque::from([sender]))),
+        }
+        Err(Waiting {
+            queue: self.clone(),
+            receiver,
+        })
+    }
+}
+
+impl ImageOptimizer {
+    /// Hands encode permits to one queue per source directory or tenant in turn, so
+    /// every page makes progress while another one waits on a large gallery.
+    /// Placeholders have their own pool and aren't queued.
+    ///
+    /// ```
+    /// # use leptos_image::*;
+    /// let optimizer = ImageOptimizer::new("/__cache/image", "./target/site", 4)
+    ///     .with_fair_queueing(FairQueueing::SourcePrefix(1));
+    /// ```
+    pub fn with_fair_queueing(mut self, queueing: FairQueueing) -> Self {
+        self.fair_queue = Some(Arc::new(FairQueue::new(queueing, self.semaphore.clone())));
+        self
+    }
+
+    /// Waits for a permit of the pool encoding this kind of image.
+    pub(crate) async fn encode_permit(&self, image: &CachedImage) -> EncodePermit {
+        match (&image.option, &self.fair_queue) {
+            (CachedImageOption::Resize(_), Some(queue)) => queue.acquire(image).await,
+            (CachedImageOption::Resize(_), None) => {
+                EncodePermit::unqueued(self.semaphore.clone()).await
+            }
+            // Placeholders are cheap and above the fold, keep them out of the encode queue.
+            (CachedImageOption::Blur(_), _) => {
+                EncodePermit::unqueued(self.blur_semaphore.clone()).await
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod fair_tests {
+    use super::*;
+    use futures_util::FutureExt;
+
+    #[test]
+    fn takes_turns() {
+        let queue = Arc::new(FairQueue::new(
+            FairQueueing::SourcePrefix(1),
+            Arc::new(Semaphore::new(1)),
+        ));
+        let image = |src: &str| CachedImage::new(src, Default::default());
+        let mut permit = queue.enqueue(&image("/gallery/1.jpg")).ok().unwrap();
+        let mut waiting = ["/gallery/2.jpg", "/gallery/3.jpg", "/gallery/4.jpg", "/blog/1.jpg"]
+            .map(|src| Some(queue.enqueue(&image(src)).err().unwrap()));
+
+        for next in [0, 3, 1, 2] {
+            drop(permit);
+            let next = waiting[next].take().unwrap();
+            permit = next.permit().now_or_never().expect("Next in turn");
+        }
+        drop(permit);
+        assert!(queue.enqueue(&image("/gallery/5.jpg")).is_ok());
+    }
+
+    #[test]
+    fn queued_while_released() {
+        let queue = Arc::new(FairQueue::new(
+            FairQueueing::Tenant,
+            Arc::new(Semaphore::new(1)),
+        ));
+        let image = CachedImage::new("/ferris.png", Default::default());
+        for _ in 0..1000 {
+            let permit = queue.enqueue(&image).ok().unwrap();
+            let release = std::thread::spawn(move || drop(permit));
+            let next = queue.enqueue(&image);
+            release.join().unwrap();
+            // Queued before the release or not, the request gets the permit.
+            let next = match next {
+                Ok(permit) => permit,
+                Err(waiting) => waiting.permit().now_or_never().expect("Released permit"),
+            };
+            drop(next);
+        }
+    }
+}
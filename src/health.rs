@@ -0,0 +1,120 @@
+use crate::encoder::EncodeRequest;
+use crate::optimizer::{CreateImageError, ImageOptimizer, OutputFormat, Resize, TEMP_EXTENSION};
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use std::path::Path;
+
+/// Why [`ImageOptimizer::health_check`] failed.
+#[derive(Debug, thiserror::Error)]
+pub enum HealthError {
+    /// The site root doesn't exist or isn't a directory.
+    #[error("Root Not Found: {0}")]
+    RootNotFound(String),
+    /// Creating or writing a file in the cache directory failed.
+    #[error("Cache Not Writable: {0}")]
+    CacheNotWritable(std::io::Error),
+    /// Encoding a test image failed, or it didn't decode back.
+    #[error("Encode Failed: {0}")]
+    EncodeFailed(CreateImageError),
+}
+
+impl ImageOptimizer {
+    /// Checks that the optimizer can serve images: the site root exists, the cache
+    /// directory is writable, and a 1x1 image encodes and decodes back.
+    ///
+    /// Blocks on the file system, call it from [`tokio::task::spawn_blocking`] in
+    /// async code, or mount [`ImageHealthRoute::image_health_route`].
+    pub fn health_check(&self) -> Result<(), HealthError> {
+        let root = Path::new(&self.root_file_path);
+        if !root.is_dir() {
+            return Err(HealthError::RootNotFound(self.root_file_path.clone()));
+        }
+
+        // A temporary file, removed by `sweep_cache` should the check crash.
+        let name = format!("health-{}.{}", std::process::id(), TEMP_EXTENSION);
+        let probe = self.cache_dir.join(name);
+        std::fs::create_dir_all(&self.cache_dir)
+            .and_then(|()| std::fs::write(&probe, b"ok"))
+            .and_then(|()| std::fs::remove_file(&probe))
+            .map_err(HealthError::CacheNotWritable)?;
+
+        self.check_encoder().map_err(HealthError::EncodeFailed)
+    }
+
+    // Encodes a 1x1 WebP with the configured encoder and decodes it back.
+    pub(crate) fn check_encoder(&self) -> Result<(), CreateImageError> {
+        let options = Resize {
+            width: 1,
+            height: 1,
+            quality: 75,
+            ..Default::default()
+        };
+        let request = EncodeRequest {
+            options: &options,
+            format: OutputFormat::Webp,
+            source_format: None,
+        };
+        let image = image::DynamicImage::new_rgba8(1, 1);
+        let bytes = self.pipeline.encoder.encode(&image, &request)?;
+        let decoded = image::load_from_memory(&bytes)?;
+        if (decoded.width(), decoded.height()) != (1, 1) {
+            let error = "Test image changed size".to_string();
+            return Err(CreateImageError::EncodeError(error));
+        }
+        Ok(())
+    }
+}
+
+/// Adds a health check route, see [`ImageHealthRoute::image_health_route`].
+pub trait ImageHealthRoute {
+    /// Adds a route at `path` (e.g. `/healthz`) responding `200 OK` when
+    /// [`ImageOptimizer::health_check`] passes and `503 Service Unavailable` with the
+    /// error otherwise, for readiness probes.
+    ///
+    /// ```
+    /// use leptos_image::*;
+    ///
+    /// # #[cfg(feature = "ssr")]
+    /// # fn router() -> axum::Router {
+    /// let optimizer = ImageOptimizer::new("/__cache/image", "./target/site", 1);
+    /// axum::Router::new().image_health_route("/healthz", &optimizer)
+    /// # }
+    /// ```
+    fn image_health_route(self, path: &str, optimizer: &ImageOptimizer) -> Self;
+}
+
+impl<S> ImageHealthRoute for axum::Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    fn image_health_route(self, path: &str, optimizer: &ImageOptimizer) -> Self {
+        let optimizer = optimizer.clone();
+        let handler = move || async move {
+            let result = tokio::task::spawn_blocking(move || optimizer.health_check()).await;
+            match result {
+                Ok(Ok(())) => (StatusCode::OK, "ok".to_string()),
+                Ok(Err(e)) => (StatusCode::SERVICE_UNAVAILABLE, e.to_string()),
+                Err(e) => (StatusCode::SERVICE_UNAVAILABLE, e.to_string()),
+            }
+            .into_response()
+        };
+        self.route(path, axum::routing::get(handler))
+    }
+}
+
+#[cfg(test)]
+mod health_tests {
+    use super::*;
+    use crate::test_support::test_root;
+
+    #[test]
+    fn checks_root_and_cache() {
+        let (root, optimizer) = test_root("leptos_image_health");
+        optimizer.health_check().unwrap();
+        assert!(root.join("cache/image").read_dir().unwrap().next().is_none());
+
+        let missing = root.join("missing");
+        let missing = ImageOptimizer::new("/__cache/image", missing.to_string_lossy(), 1);
+        assert!(matches!(missing.health_check(), Err(HealthError::RootNotFound(_))));
+    }
+}
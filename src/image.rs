@@ -1,4 +1,5 @@
 use leptos::logging;
+use crate::loader::{ImageLoader, LoaderParams};
 use crate::optimizer::*;
 
 use leptos::prelude::*;
@@ -9,7 +10,8 @@ use base64::{engine::general_purpose, Engine as _};
  * Renders an optimized static image with optional blur placeholder and preload.
  *
  * The width/height properties ensure the layout space is reserved from the start,
- * preventing content shift when the image or placeholder loads.
+ * preventing content shift when the image or placeholder loads. Omitted sizes are
+ * read from the source on the server, so the space is still reserved.
  */
 #[component]
 pub fn Image(
@@ -17,36 +19,135 @@ pub fn Image(
     #[prop(into)]
     src: String,
     /// Resize image height (final image), maintains aspect ratio relative to `width`.
-    height: u32,
+    /// Derived from the source's intrinsic size when omitted.
+    #[prop(optional)]
+    height: Option<u32>,
     /// Resize image width (final image), maintains aspect ratio relative to `height`.
-    width: u32,
+    /// Derived from the source's intrinsic size when omitted.
+    #[prop(optional)]
+    width: Option<u32>,
+    /// Largest width used when both `width` and `height` are omitted.
+    #[prop(default = DEFAULT_MAX_WIDTH)]
+    max_width: u32,
     /// Image quality (0-100).
     #[prop(default = 75_u8)]
     quality: u8,
+    /// Named server-side preset replacing `quality`, `format` and `lossless`, e.g.
+    /// `preset="thumbnail"`, see [`crate::Preset`]. Ignored by custom loaders.
+    #[prop(optional, into)]
+    preset: Option<String>,
     /// Whether to add a blur placeholder before the real image loads.
     #[prop(default = true)]
     blur: bool,
+    /// Kind of placeholder shown when `blur` is set.
+    #[prop(optional)]
+    placeholder: Placeholder,
+    /// Background transparent sources are composited onto in the placeholder, e.g.
+    /// the page color. Placeholders of transparent sources stay transparent without.
+    #[prop(optional, into)]
+    placeholder_matte: Option<Background>,
+    /// How the placeholder is put on the page, see [`PlaceholderRender`].
+    #[prop(optional)]
+    placeholder_render: PlaceholderRender,
     /// Whether to add a preload <link> for this image.
     #[prop(default = false)]
     priority: bool,
     /// Lazy-load the final image.
     #[prop(default = true)]
     lazy: bool,
-    /// Image alt text.
+    /// Image alt text. Required unless the image is `decorative`.
     #[prop(into, optional)]
     alt: String,
+    /// Purely decorative image: renders `alt=""` and hides it from assistive technology.
+    #[prop(default = false)]
+    decorative: bool,
     /// Additional CSS classes for the image.
     #[prop(into, optional)]
     class: MaybeProp<String>,
+    /// Skip the optimizer entirely and render `src` as a plain <img>.
+    /// Width/height are still applied so layout space is reserved.
+    #[prop(default = false)]
+    unoptimized: bool,
+    /// Builds image URLs with a custom loader (e.g. an image CDN) instead of the
+    /// built-in cache handler. Falls back to a loader provided with
+    /// [`crate::provide_image_loader`].
+    #[prop(optional)]
+    loader: Option<ImageLoader>,
+    /// How animated sources (e.g. GIFs) are handled.
+    #[prop(optional)]
+    animation: Animation,
+    /// Frame, duration and frame rate limits of an animated output, e.g.
+    /// `frame_limits=FrameLimits::new().max_duration(Duration::from_secs(3))` for previews.
+    #[prop(optional, into)]
+    frame_limits: Option<FrameLimits>,
+    /// Output format of the optimized image.
+    #[prop(optional)]
+    format: OutputFormat,
+    /// Encode losslessly, for screenshots, diagrams and logos.
+    #[prop(default = false)]
+    lossless: bool,
+    /// Near-lossless WebP level (0-100); lower values compress more. Implies `lossless`.
+    #[prop(optional)]
+    near_lossless: Option<u8>,
+    /// Pick the lowest quality that reaches this DSSIM target, in millionths (e.g. `1000` = 0.001).
+    /// Requires the `auto-quality` feature on the server; `quality` is used otherwise.
+    #[prop(optional)]
+    auto_quality: Option<u32>,
+    /// Embed the optimized image in the server rendered HTML as a `data:` URI, saving a
+    /// request. Only for small above-the-fold images: it applies once the image is cached
+    /// and no larger than the optimizer's inline limit, see `ImageOptimizer::with_inline_limit`.
+    #[prop(default = false)]
+    inline: bool,
+    /// Region of the source to show, in source pixels, e.g. a crop picked in a CMS.
+    /// Applied before resizing, ignored by custom loaders.
+    #[prop(optional)]
+    crop: Option<Crop>,
+    /// Crops the source to this ratio before resizing, e.g. `aspect_ratio="16:9"` for
+    /// uniform card grids. Ignored by custom loaders.
+    #[prop(optional, into)]
+    aspect_ratio: Option<AspectRatio>,
+    /// Point kept in view by `aspect_ratio`, in percent of the source, e.g. `(50, 30)`.
+    #[prop(optional, into)]
+    focal_point: Option<FocalPoint>,
+    /// How the image is fitted into `width` x `height`, see [`Fit`].
+    #[prop(optional)]
+    fit: Fit,
+    /// Resampling filter, e.g. `filter=ResizeFilter::Nearest` for pixel art.
+    #[prop(optional)]
+    filter: ResizeFilter,
+    /// Sharpens the image after resizing, e.g. `sharpen=Sharpen::Auto` for photos.
+    #[prop(optional)]
+    sharpen: Option<Sharpen>,
+    /// Pads a `fit=Fit::Contain` image to exactly `width` x `height`, e.g.
+    /// `background="#ffffff"` for uniform product tiles.
+    #[prop(optional, into)]
+    background: Option<Background>,
+    /// Rounded corner or circle mask baked into the image, e.g. `mask=Mask::Circle`
+    /// for avatars.
+    #[prop(optional)]
+    mask: Option<Mask>,
 ) -> impl IntoView {
-    // If remote (http/https), skip optimization and just return a plain <img>.
-    if src.starts_with("http") {
-        logging::debug_warn!("Image component only supports static images.");
+    if !decorative && alt.is_empty() {
+        logging::debug_warn!(
+            "<Image src={src:?}/> has no alt text, set `decorative=true` if it is decorative."
+        );
+    }
+    let alt = if decorative { String::new() } else { alt };
+    let role = decorative.then_some("presentation");
+    let aria_hidden = decorative.then_some("true");
+
+    // If unoptimized or remote (http/https), skip optimization and just return a plain <img>.
+    if unoptimized || src.starts_with("http") {
+        if !unoptimized {
+            logging::debug_warn!("Image component only supports static images.");
+        }
         let loading = if lazy { "lazy" } else { "eager" };
         return view! {
             <img
                 src=src
                 alt=alt
+                role=role
+                aria-hidden=aria_hidden
                 class=class.get()
                 width=width
                 height=height
@@ -57,67 +158,367 @@ pub fn Image(
             .into_any();
     }
 
+    // A custom loader replaces the optimizer, so there is no cache to consult.
+    if let Some(loader) = loader.or_else(use_context::<ImageLoader>) {
+        // Omitted sizes follow the source when the server can read it, and are
+        // serialized like those of optimized images.
+        let intrinsic = (width.is_none() || height.is_none()).then(|| {
+            #[cfg(feature = "ssr")]
+            let (optimizer, src) = (crate::provider::optimizer_context(), src.clone());
+            Resource::new(|| (), move |_| {
+                #[cfg(feature = "ssr")]
+                let size = optimizer.as_ref().and_then(|optimizer| optimizer.intrinsic_size(&src));
+                #[cfg(not(feature = "ssr"))]
+                let size = None;
+                async move { size }
+            })
+        });
+        let image_view = move |intrinsic: Option<(u32, u32)>| {
+            let (box_width, box_height) = match intrinsic {
+                Some(_) => {
+                    let (width, height) = layout_size(width, height, intrinsic, max_width);
+                    (Some(width), Some(height))
+                }
+                None => (width, height),
+            };
+            let params = LoaderParams {
+                src: &src,
+                width: box_width.unwrap_or(max_width),
+                height,
+                quality,
+                format,
+                blur: false,
+            };
+            let opt_image = loader.url(&params);
+            let srcset = loader.srcset(&params);
+            let sizes = format!("{}px", params.width);
+            // Collected for preload headers, like optimized images below.
+            #[cfg(feature = "ssr")]
+            if priority {
+                if let Some(collector) = crate::priority::priority_collector() {
+                    collector.push(opt_image.clone());
+                }
+            }
+            if blur {
+                let placeholder_url = loader.url(&LoaderParams {
+                    src: &src,
+                    width: 20,
+                    height: None,
+                    quality: 20,
+                    format,
+                    blur: true,
+                });
+                return view! {
+                    <CacheImage
+                        svg=PlaceholderSource::Request(placeholder_url)
+                        render=placeholder_render
+                        opt_image=opt_image
+                        srcset=srcset
+                        sizes=sizes
+                        alt=alt.clone()
+                        decorative=decorative
+                        class=class
+                        priority=priority
+                        lazy=lazy
+                        width=box_width
+                        height=box_height
+                    />
+                }
+                    .into_any();
+            }
+            let loading = if lazy { "lazy" } else { "eager" };
+            let preload = priority.then(|| {
+                view! { <Link rel="preload" as_="image" href=opt_image.clone() /> }
+            });
+            view! {
+                {preload}
+                <img
+                    src=opt_image
+                    srcset=srcset
+                    sizes=sizes
+                    alt=alt.clone()
+                    role=role
+                    aria-hidden=aria_hidden
+                    class=move || class.get()
+                    width=box_width
+                    height=box_height
+                    decoding="async"
+                    loading=loading
+                />
+            }
+                .into_any()
+        };
+        let Some(intrinsic) = intrinsic else {
+            return image_view(None);
+        };
+        return view! { <Suspense>{move || intrinsic.get().map(&image_view)}</Suspense> }
+            .into_any();
+    }
+
+    // SVGs are served as-is, there is nothing to blur.
+    let blur = blur && !is_svg(&src);
+
+    let tenant = use_context::<crate::ImageTenant>().map(|tenant| tenant.0);
+    let cache_key = crate::key::use_cache_key();
+
+    #[cfg(feature = "ssr")]
+    let optimizer = crate::provider::optimizer_context();
+    // Omitted sizes need the source, only the server can read it.
+    let server_size = {
+        #[cfg(feature = "ssr")]
+        let optimizer = optimizer.clone();
+        let src = src.clone();
+        move || {
+            #[cfg(feature = "ssr")]
+            let intrinsic = (width.is_none() || height.is_none())
+                .then(|| optimizer.as_ref().and_then(|optimizer| optimizer.intrinsic_size(&src)))
+                .flatten();
+            #[cfg(not(feature = "ssr"))]
+            let intrinsic = None;
+            layout_size(width, height, intrinsic, max_width)
+        }
+    };
+    // Resolved on the server and serialized, so hydration sees the same size.
+    let size = (width.is_none() || height.is_none()).then(|| {
+        let server_size = server_size.clone();
+        Resource::new(|| (), move |_| {
+            let size = server_size();
+            async move { size }
+        })
+    });
+
+    // Source policies may disable placeholders, resolved on the server like the size.
+    let placeholder_allowed = blur.then(|| {
+        #[cfg(feature = "ssr")]
+        let optimizer = optimizer.clone();
+        #[cfg(feature = "ssr")]
+        let src = src.clone();
+        Resource::new(|| (), move |_| {
+            #[cfg(feature = "ssr")]
+            let allowed = optimizer
+                .as_ref()
+                .map_or(true, |optimizer| optimizer.allows_placeholders(&src));
+            #[cfg(not(feature = "ssr"))]
+            let allowed = true;
+            async move { allowed }
+        })
+    });
+
+    let placeholder_size = placeholder_size(placeholder);
     // Prepare the cache descriptors for blur version and optimized version
     let blur_image = StoredValue::new(CachedImage {
         src: src.clone(),
+        tenant: tenant.clone(),
         option: CachedImageOption::Blur(Blur {
-            width: 20,
-            height: 20,
+            width: placeholder_size,
+            height: placeholder_size,
             svg_width: 100,
             svg_height: 100,
             sigma: 15,
+            crop,
+            aspect_ratio,
+            focal_point,
+            kind: placeholder,
+            matte: placeholder_matte,
         }),
     });
 
     let opt_image = StoredValue::new(CachedImage {
         src: src.clone(),
+        tenant,
         option: CachedImageOption::Resize(Resize {
             quality,
-            width,
-            height,
+            width: 0,
+            height: 0,
+            animation,
+            frame_limits,
+            format,
+            lossless,
+            near_lossless,
+            auto_quality,
+            crop,
+            aspect_ratio,
+            focal_point,
+            fit,
+            filter,
+            sharpen,
+            background,
+            mask,
         }),
     });
+    // The optimized image at its final size.
+    let sized_image = move |(width, height), preset: Option<&crate::Preset>| {
+        let mut image = opt_image.get_value();
+        if let CachedImageOption::Resize(resize) = &mut image.option {
+            resize.width = width;
+            resize.height = height;
+            if let Some(preset) = preset {
+                preset.apply(resize);
+            }
+        }
+        image
+    };
+
+    #[cfg(feature = "ssr")]
+    let server_preset = preset.as_ref().and_then(|name| {
+        let preset = optimizer.as_ref().and_then(|optimizer| optimizer.preset(name).cloned());
+        if preset.is_none() {
+            logging::debug_warn!("<Image src={src:?}/> uses the unknown preset {name:?}.");
+        }
+        preset
+    });
+    // Collected for preload headers, which go out before `<Link>`s in the HTML.
+    #[cfg(feature = "ssr")]
+    if priority && !inline {
+        let collector = crate::priority::priority_collector();
+        if let (Some(optimizer), Some(collector)) = (&optimizer, collector) {
+            let image = sized_image(server_size(), server_preset.as_ref());
+            collector.push(cache_key.url(&image, &optimizer.handler_url()));
+        }
+    }
+    // Resolved on the server and serialized, so hydration sees the same options.
+    let preset = preset.map(|_| {
+        #[cfg(feature = "ssr")]
+        let server_preset = server_preset.clone();
+        Resource::new(|| (), move |_| {
+            #[cfg(feature = "ssr")]
+            let preset = server_preset.clone();
+            #[cfg(not(feature = "ssr"))]
+            let preset: Option<crate::Preset> = None;
+            async move { preset }
+        })
+    });
 
     // We fetch the global image cache resource
     let resource = crate::use_image_cache_resource();
+    let batch = use_context::<crate::batch::PlaceholderBatch>().filter(|_| blur);
     let alt = StoredValue::new(alt);
 
-    return view! {
+    // Figures of the optimized image for the debug badge, read on the server.
+    #[cfg(all(feature = "debug-overlay", debug_assertions))]
+    let debug_info = {
+        #[cfg(feature = "ssr")]
+        let (optimizer, server_size, server_preset) =
+            (optimizer.clone(), server_size.clone(), server_preset.clone());
+        Resource::new(
+            || (),
+            move |_| {
+                #[cfg(feature = "ssr")]
+                let info = optimizer.as_ref().and_then(|optimizer| {
+                    optimizer.debug_info(&sized_image(server_size(), server_preset.as_ref()))
+                });
+                #[cfg(not(feature = "ssr"))]
+                let info = None;
+                async move { info }
+            },
+        )
+    };
+
+    // Resolved on the server and serialized, so hydration sees the same `src`.
+    let inline_uri = inline.then(|| {
+        #[cfg(feature = "ssr")]
+        let optimizer = optimizer.clone();
+        Resource::new(
+            || (),
+            move |_| {
+                #[cfg(feature = "ssr")]
+                let uri = optimizer.as_ref().and_then(|optimizer| {
+                    optimizer.inline_data_uri(&sized_image(server_size(), server_preset.as_ref()))
+                });
+                #[cfg(not(feature = "ssr"))]
+                let uri: Option<String> = None;
+                async move { uri }
+            },
+        )
+    });
+
+    let view = view! {
         <Suspense fallback=move || {
+            if placeholder_render == PlaceholderRender::Element {
+                return view! { <div class="leptos-image-fallback" /> }.into_any();
+            }
             view! {
                 // If you prefer, you could do a placeholder gray box, spinner, etc.
                 <div style=move || {
-                    format!("width: {}px; height: {}px; background-color: #f0f0f0;", width, height)
+                    let width =
+                        width.map(|width| format!("width: {width}px; ")).unwrap_or_default();
+                    let height =
+                        height.map(|height| format!("height: {height}px; ")).unwrap_or_default();
+                    format!("{width}{height}background-color: #f0f0f0;")
                 } />
             }
+                .into_any()
         }>
             // Once the resource is ready, we show the real or blurred image
             {move || {
+                let size = match size {
+                    Some(size) => size.get(),
+                    None => Some(layout_size(width, height, None, max_width)),
+                };
                 resource
                     .get()
-                    .map(|config| {
+                    .zip(size)
+                    .zip(inline_uri.map(|uri| uri.get()).unwrap_or(Some(None)))
+                    .zip(placeholder_allowed.map(|allowed| allowed.get()).unwrap_or(Some(false)))
+                    .zip(preset.map(|preset| preset.get()).unwrap_or(Some(None)))
+                    .zip(
+                        batch
+                            .map(|batch| blur_image.with_value(|image| batch.get(image)))
+                            .unwrap_or(Some(None)),
+                    )
+                    .map(|(((((config, (width, height)), inline_uri), blur), preset), batched)| {
                         let images = &config.cache;
-                        let handler_path = &config.api_handler_path;
-                        let opt_image_url = opt_image.get_value().get_url_encoded(handler_path);
-                        if blur {
-                            let placeholder_svg = images
-                                .iter()
-                                .find(|(c, _)| blur_image.with_value(|b| b == c))
-                                .map(|(_, svg_data)| svg_data.clone());
-                            let svg = if let Some(svg_data) = placeholder_svg {
-                                SvgImage::InMemory(svg_data)
-                            } else {
-                                SvgImage::Request(
-                                    blur_image.get_value().get_url_encoded(handler_path),
-                                )
+                        let handler_path = &config.handler_url();
+                        let inlined = inline_uri.is_some();
+                        let opt_image_url = inline_uri.unwrap_or_else(|| {
+                            let image = sized_image((width, height), preset.as_ref());
+                            cache_key.url(&image, handler_path)
+                        });
+                        // Inlined images are there with the HTML, a placeholder would only flash.
+                        if blur && !inlined {
+                            let placeholder_svg = batched.or_else(|| {
+                                images
+                                    .iter()
+                                    .find(|(c, _)| blur_image.with_value(|b| b == c))
+                                    .map(|(_, svg_data)| svg_data.clone())
+                            });
+                            // Strict CSP blocks `data:` URIs, stacked placeholders load by URL.
+                            let placeholder_svg = placeholder_svg.filter(|_| {
+                                placeholder_render == PlaceholderRender::Background
+                                    || placeholder == Placeholder::Gradient
+                            });
+                            let svg = match (placeholder, placeholder_svg) {
+                                (Placeholder::Lqip, Some(uri)) => PlaceholderSource::Raster(uri),
+                                (Placeholder::Lqip, None) => PlaceholderSource::Raster(
+                                    cache_key.url(&blur_image.get_value(), handler_path),
+                                ),
+                                (Placeholder::Blur, Some(svg_data)) => {
+                                    PlaceholderSource::InMemory(svg_data)
+                                }
+                                (Placeholder::Blur, None) => PlaceholderSource::Request(
+                                    cache_key.url(&blur_image.get_value(), handler_path),
+                                ),
+                                (Placeholder::Gradient, Some(css)) => {
+                                    PlaceholderSource::Gradient(css)
+                                }
+                                (Placeholder::Gradient, None) => {
+                                    // A gradient has no URL, create it for the next render.
+                                    #[cfg(feature = "ssr")]
+                                    if let Some(optimizer) = &optimizer {
+                                        optimizer.spawn_placeholder(blur_image.get_value());
+                                    }
+                                    PlaceholderSource::Gradient("none".to_string())
+                                }
                             };
                             return view! {
                                 // Try to fetch an existing cached placeholder
 
                                 <CacheImage
                                     svg=svg
+                                    render=placeholder_render
                                     opt_image=opt_image_url
                                     alt=alt.get_value()
+                                    decorative=decorative
                                     class=class
                                     priority=priority
                                     lazy=lazy
@@ -137,11 +538,14 @@ pub fn Image(
                                 <img
                                     src=opt_image_url
                                     alt=alt.get_value()
+                                    role=role
+                                    aria-hidden=aria_hidden
                                     class=move || class.get()
                                     width=width
                                     height=height
                                     decoding="async"
                                     loading=loading
+                                    onload=onload_script(None)
                                 />
                             }
                                 .into_any();
@@ -149,67 +553,316 @@ pub fn Image(
                     })
             }}
         </Suspense>
-    }.into_any()
+    }.into_any();
+    #[cfg(all(feature = "debug-overlay", debug_assertions))]
+    let view = crate::overlay::with_overlay(view, debug_info);
+    view
+}
+
+/// How `<Image/>` puts its placeholder on the page.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PlaceholderRender {
+    /// Background of the `<img>` itself, set with an inline `style` and a `data:` URI.
+    #[default]
+    Background,
+    /// A separate `<img>` stacked under the image, loaded by URL and styled by class
+    /// only, for strict `style-src`/`img-src` Content Security Policies. Include
+    /// [`PLACEHOLDER_STYLESHEET`] in your own stylesheet. Gradient placeholders need
+    /// inline styles and are skipped.
+    Element,
+    /// An absolutely positioned element over the image that fades out once it
+    /// loads, so no placeholder shows through transparent images.
+    Overlay,
+}
+
+/// Styles for [`PlaceholderRender::Element`], to add to the app's stylesheet.
+pub const PLACEHOLDER_STYLESHEET: &str = "\
+.leptos-image { position: relative; display: inline-block; overflow: hidden; }
+.leptos-image > img { display: block; }
+.leptos-image > img:last-child { position: relative; }
+.leptos-image-placeholder {
+  position: absolute; inset: 0; width: 100%; height: 100%; object-fit: cover;
 }
+.leptos-image-placeholder[data-placeholder=\"lqip\"] { filter: blur(16px); }
+.leptos-image-fallback { background-color: #f0f0f0; }
+";
 
-enum SvgImage {
+enum PlaceholderSource {
+    /// SVG markup, inlined as a `data:` URI.
     InMemory(String),
+    /// URL of an SVG or an already blurred image.
     Request(String),
+    /// URL or `data:` URI of a tiny image the browser blurs.
+    Raster(String),
+    /// CSS `background-image` value.
+    Gradient(String),
 }
 
 /// Internal component that displays the blurred placeholder (SVG)
 /// in the background of the <img> until the real image is displayed.
 #[component]
 fn CacheImage(
-    svg: SvgImage,
+    svg: PlaceholderSource,
+    render: PlaceholderRender,
     #[prop(into)]
     opt_image: String,
+    // `srcset` and `sizes` of the final image, only built by loaders.
+    #[prop(optional)]
+    srcset: Option<String>,
+    #[prop(optional)]
+    sizes: Option<String>,
     #[prop(into, optional)]
     alt: String,
+    decorative: bool,
     #[prop(into, optional)]
     class: MaybeProp<String>,
     priority: bool,
     lazy: bool,
     // Passed down to maintain the final layout from the start
-    width: u32,
-    height: u32,
+    #[prop(into)]
+    width: Option<u32>,
+    #[prop(into)]
+    height: Option<u32>,
 ) -> impl IntoView {
+    let role = decorative.then_some("presentation");
+    let aria_hidden = decorative.then_some("true");
+    let loading = if lazy { "lazy" } else { "eager" };
+    let preload = move |href: String| {
+        priority.then(|| view! { <Link rel="preload" as_="image" href=href /> })
+    };
+
+    if render == PlaceholderRender::Element {
+        let (placeholder, kind) = match svg {
+            PlaceholderSource::Request(url) => (Some(url), "blur"),
+            PlaceholderSource::Raster(url) => (Some(url), "lqip"),
+            PlaceholderSource::InMemory(_) | PlaceholderSource::Gradient(_) => (None, ""),
+        };
+        return view! {
+            {preload(opt_image.clone())}
+            <span class="leptos-image">
+                {placeholder.map(|src| {
+                    view! {
+                        <img
+                            class="leptos-image-placeholder"
+                            data-placeholder=kind
+                            src=src
+                            alt=""
+                            aria-hidden="true"
+                            width=width
+                            height=height
+                        />
+                    }
+                })}
+                <img
+                    src=opt_image
+                    srcset=srcset
+                    sizes=sizes
+                    alt=alt
+                    role=role
+                    aria-hidden=aria_hidden
+                    class=move || class.get()
+                    decoding="async"
+                    loading=loading
+                    width=width
+                    height=height
+                    onload=onload_script(None)
+                />
+            </span>
+        }
+            .into_any();
+    }
+
     // Construct background SVG or request URL
+    let raster = matches!(svg, PlaceholderSource::Raster(_));
     let background_image = match svg {
-        SvgImage::InMemory(svg_data) => {
+        PlaceholderSource::InMemory(svg_data) => {
             let svg_encoded = general_purpose::STANDARD.encode(svg_data.as_bytes());
             format!("url('data:image/svg+xml;base64,{svg_encoded}')")
         }
-        SvgImage::Request(svg_url) => format!("url('{svg_url}')"),
+        PlaceholderSource::Request(svg_url) | PlaceholderSource::Raster(svg_url) => {
+            format!("url('{svg_url}')")
+        }
+        PlaceholderSource::Gradient(css) => css,
     };
 
+    if render == PlaceholderRender::Overlay {
+        let filter = if raster { "filter: blur(16px);" } else { "" };
+        let overlay = format!(
+            "position: absolute; inset: 0;\
+             pointer-events: none;\
+             transition: opacity 0.3s;\
+             background-size: cover;\
+             background-position: 50% 50%;\
+             background-repeat: no-repeat;\
+             background-image: {background_image};{filter}"
+        );
+        // Set in the markup, so it also fires when the image loads before hydration.
+        let onload = onload_script(Some("this.nextElementSibling.style.opacity='0'"));
+        return view! {
+            {preload(opt_image.clone())}
+            <span style="position: relative; display: inline-block; overflow: hidden;">
+                <img
+                    src=opt_image
+                    srcset=srcset
+                    sizes=sizes
+                    alt=alt
+                    role=role
+                    aria-hidden=aria_hidden
+                    class=move || class.get()
+                    decoding="async"
+                    loading=loading
+                    width=width
+                    height=height
+                    style="display: block;"
+                    onload=onload
+                />
+                <span aria-hidden="true" style=overlay />
+            </span>
+        }
+            .into_any();
+    }
+
+    // The filter would blur the loaded image too, it is dropped once it loads.
+    let filter = if raster { "filter: blur(16px);" } else { "" };
     let style = format!(
         "color: transparent;\
          background-size: cover;\
          background-position: 50% 50%;\
          background-repeat: no-repeat;\
-         background-image: {background_image};"
+         background-image: {background_image};{filter}"
     );
-
-    let loading = if lazy { "lazy" } else { "eager" };
+    let onload = onload_script(raster.then_some("this.style.filter='none'"));
 
     view! {
-        {if priority {
-            view! { <Link rel="preload" as_="image" href=opt_image.clone() /> }.into_any()
-        } else {
-            ().into_any()
-        }}
+        {preload(opt_image.clone())}
 
         // Reserve the space with width/height, apply the blur background
         <img
             src=opt_image
+            srcset=srcset
+            sizes=sizes
             alt=alt.clone()
+            role=role
+            aria-hidden=aria_hidden
             class=move || class.get()
             decoding="async"
             loading=loading
             width=width
             height=height
             style=style
+            onload=onload
         />
     }
+        .into_any()
+}
+
+// Warns when the served image is over twice as wide as displayed, so `width` can be
+// lowered. Only in debug builds, like the other warnings.
+const OVERSIZE_CHECK: &str = "if(this.clientWidth&&this.naturalWidth>\
+    2*this.clientWidth*devicePixelRatio)console.warn('[leptos_image] '+this.currentSrc+\
+    ' is '+this.naturalWidth+'px wide but displayed at '+this.clientWidth+\
+    'px, consider lowering its width.')";
+
+// Inline `onload` handler running `action` and, in debug builds, the size check.
+fn onload_script(action: Option<&str>) -> Option<String> {
+    let check = cfg!(debug_assertions).then_some(OVERSIZE_CHECK);
+    match (action, check) {
+        (Some(action), Some(check)) => Some(format!("{action};{check}")),
+        (action, check) => action.or(check).map(str::to_string),
+    }
+}
+
+/// Pixels along each side of the source a placeholder is made of.
+pub(crate) fn placeholder_size(placeholder: Placeholder) -> u32 {
+    match placeholder {
+        Placeholder::Blur => 20,
+        Placeholder::Lqip => 16,
+        Placeholder::Gradient => 3,
+    }
+}
+
+/// The placeholder `<Image blur/>` requests with the other props at their defaults.
+pub(crate) fn placeholder_image(
+    src: &str,
+    tenant: Option<String>,
+    kind: Placeholder,
+) -> CachedImage {
+    let size = placeholder_size(kind);
+    CachedImage {
+        src: src.to_string(),
+        tenant,
+        option: CachedImageOption::Blur(Blur {
+            width: size,
+            height: size,
+            svg_width: 100,
+            svg_height: 100,
+            sigma: 15,
+            crop: None,
+            aspect_ratio: None,
+            focal_point: None,
+            kind,
+            matte: None,
+        }),
+    }
+}
+
+/// Width of images sized from their source when no `max_width` is given.
+pub const DEFAULT_MAX_WIDTH: u32 = 1920;
+
+/// Final size of an image: given sizes win, omitted ones follow the intrinsic aspect
+/// ratio. Without a probed size the box is square.
+pub(crate) fn layout_size(
+    width: Option<u32>,
+    height: Option<u32>,
+    intrinsic: Option<(u32, u32)>,
+    max_width: u32,
+) -> (u32, u32) {
+    // `value * numerator / denominator`, rounded and at least one pixel.
+    let scale = |value: u32, numerator: u32, denominator: u32| {
+        let denominator = u64::from(denominator.max(1));
+        let scaled = (u64::from(value) * u64::from(numerator) + denominator / 2) / denominator;
+        scaled.clamp(1, u64::from(u32::MAX)) as u32
+    };
+    match (width, height, intrinsic) {
+        (Some(width), Some(height), _) => (width, height),
+        (Some(width), None, Some((source_width, source_height))) => {
+            (width, scale(source_height, width, source_width))
+        }
+        (None, Some(height), Some((source_width, source_height))) => {
+            (scale(source_width, height, source_height), height)
+        }
+        (None, None, Some((source_width, source_height))) => {
+            let width = source_width.min(max_width);
+            (width, scale(source_height, width, source_width))
+        }
+        (Some(size), None, None) | (None, Some(size), None) => (size, size),
+        (None, None, None) => (max_width, max_width),
+    }
+}
+
+#[cfg(test)]
+mod image_tests {
+    use super::*;
+
+    #[test]
+    fn oversize_check_in_debug_builds() {
+        let onload = onload_script(Some("this.style.filter='none'"));
+        if cfg!(debug_assertions) {
+            let onload = onload.unwrap();
+            assert!(onload.starts_with("this.style.filter='none';if(this.clientWidth"));
+            assert!(onload.contains("console.warn"));
+        } else {
+            assert_eq!(onload.as_deref(), Some("this.style.filter='none'"));
+        }
+    }
+
+    #[test]
+    fn omitted_sizes() {
+        assert_eq!(layout_size(Some(300), Some(200), Some((1344, 896)), 1920), (300, 200));
+        assert_eq!(layout_size(Some(300), None, Some((1344, 896)), 1920), (300, 200));
+        assert_eq!(layout_size(None, Some(200), Some((1344, 896)), 1920), (300, 200));
+        assert_eq!(layout_size(None, None, Some((1344, 896)), 672), (672, 448));
+        assert_eq!(layout_size(None, None, Some((1344, 896)), 1920), (1344, 896));
+        assert_eq!(layout_size(None, None, None, 640), (640, 640));
+    }
 }
@@ -0,0 +1,356 @@
+use crate::optimizer::{remove_precompressed, CachedImage, ImageOptimizer};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// SQLite index of the cached images on disk.
+///
+/// Keeps one row per cached file with its source, parameters, size and access
+/// times, so lookups, purges and eviction don't have to walk the cache directory.
+/// Content hashes of the sources find copies of a source under other paths, whose
+/// cached files are then shared instead of encoded again.
+#[derive(Debug, Clone)]
+pub(crate) struct CacheIndex {
+    conn: Arc<Mutex<Connection>>,
+}
+
+/// A cached file, as recorded in the index.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct IndexEntry {
+    /// Path relative to the site root.
+    pub(crate) path: String,
+    pub(crate) bytes: u64,
+}
+
+fn now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+fn normalize(src: &str) -> &str {
+    src.trim_start_matches('/')
+}
+
+// The options of a cached image without its source, equal for copies of a source.
+fn variant(image: &CachedImage) -> String {
+    let image = CachedImage {
+        src: String::new(),
+        ..image.clone()
+    };
+    serde_qs::to_string(&image).unwrap()
+}
+
+impl CacheIndex {
+    pub(crate) fn open(path: &Path) -> rusqlite::Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(
+            "PRAGMA journal_mode = WAL;
+             CREATE TABLE IF NOT EXISTS images (
+                 path TEXT PRIMARY KEY,
+                 src TEXT NOT NULL,
+                 params TEXT NOT NULL,
+                 bytes INTEGER NOT NULL,
+                 created INTEGER NOT NULL,
+                 accessed INTEGER NOT NULL
+             );
+             CREATE INDEX IF NOT EXISTS images_src ON images (src);
+             CREATE INDEX IF NOT EXISTS images_accessed ON images (accessed);
+             CREATE TABLE IF NOT EXISTS sources (
+                 path TEXT PRIMARY KEY,
+                 modified INTEGER NOT NULL,
+                 bytes INTEGER NOT NULL,
+                 hash TEXT NOT NULL
+             );",
+        )?;
+        // Added with deduplication, older indexes don't have them yet.
+        if conn.prepare("SELECT source_hash FROM images LIMIT 0").is_err() {
+            conn.execute_batch(
+                "ALTER TABLE images ADD COLUMN source_hash TEXT;
+                 ALTER TABLE images ADD COLUMN variant TEXT;",
+            )?;
+        }
+        conn.execute_batch(
+            "CREATE INDEX IF NOT EXISTS images_duplicates ON images (source_hash, variant);",
+        )?;
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    fn conn(&self) -> std::sync::MutexGuard<'_, Connection> {
+        self.conn.lock().expect("Cache index poisoned")
+    }
+
+    /// Records the cached file at `path`, below `cache/image` and relative to the site
+    /// root, see [`ImageOptimizer::cache_path`].
+    pub(crate) fn record(
+        &self,
+        image: &CachedImage,
+        path: &str,
+        bytes: u64,
+        source_hash: Option<&str>,
+    ) -> rusqlite::Result<()> {
+        let params_qs = serde_qs::to_string(image).unwrap();
+        let now = now();
+        self.conn().execute(
+            "INSERT INTO images (path, src, params, bytes, created, accessed, source_hash, variant)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?5, ?6, ?7)
+             ON CONFLICT (path) DO UPDATE
+             SET bytes = ?4, created = ?5, accessed = ?5, source_hash = ?6, variant = ?7",
+            params![
+                path,
+                normalize(&image.src),
+                params_qs,
+                bytes as i64,
+                now,
+                source_hash,
+                variant(image)
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// BLAKE3 hash of a source's bytes, hashed again only once the file changed.
+    pub(crate) fn source_hash(&self, source_path: &Path) -> std::io::Result<String> {
+        let metadata = std::fs::metadata(source_path)?;
+        let modified = metadata
+            .modified()?
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_nanos() as i64)
+            .unwrap_or_default();
+        let (key, bytes) = (source_path.to_string_lossy(), metadata.len() as i64);
+        let cached = self
+            .conn()
+            .query_row(
+                "SELECT hash FROM sources WHERE path = ?1 AND modified = ?2 AND bytes = ?3",
+                params![key, modified, bytes],
+                |row| row.get::<_, String>(0),
+            )
+            .optional()
+            .map_err(std::io::Error::other)?;
+        if let Some(hash) = cached {
+            return Ok(hash);
+        }
+
+        let mut hasher = blake3::Hasher::new();
+        std::io::copy(&mut std::fs::File::open(source_path)?, &mut hasher)?;
+        let hash = hasher.finalize().to_hex().to_string();
+        self.conn()
+            .execute(
+                "INSERT INTO sources (path, modified, bytes, hash) VALUES (?1, ?2, ?3, ?4)
+                 ON CONFLICT (path) DO UPDATE SET modified = ?2, bytes = ?3, hash = ?4",
+                params![key, modified, bytes, hash],
+            )
+            .map_err(std::io::Error::other)?;
+        Ok(hash)
+    }
+
+    /// Cached file of another source with the same bytes and options, if any.
+    pub(crate) fn duplicate(
+        &self,
+        image: &CachedImage,
+        path: &str,
+        source_hash: &str,
+    ) -> rusqlite::Result<Option<String>> {
+        self.conn()
+            .query_row(
+                "SELECT path FROM images WHERE source_hash = ?1 AND variant = ?2 AND path != ?3
+                 ORDER BY accessed DESC LIMIT 1",
+                params![source_hash, variant(image), path],
+                |row| row.get(0),
+            )
+            .optional()
+    }
+
+    pub(crate) fn touch(&self, path: &str) -> rusqlite::Result<()> {
+        self.conn().execute(
+            "UPDATE images SET accessed = ?2 WHERE path = ?1",
+            params![path, now()],
+        )?;
+        Ok(())
+    }
+
+    /// All cached images, e.g. to preload placeholders.
+    pub(crate) fn images(&self) -> rusqlite::Result<Vec<CachedImage>> {
+        let conn = self.conn();
+        let mut statement = conn.prepare("SELECT params FROM images")?;
+        let rows = statement.query_map([], |row| row.get::<_, String>(0))?;
+        let mut images = Vec::new();
+        for params_qs in rows {
+            if let Ok(image) = serde_qs::from_str(&params_qs?) {
+                images.push(image);
+            }
+        }
+        Ok(images)
+    }
+
+    /// Removes the rows of a source, returning their paths.
+    pub(crate) fn remove_source(&self, src: &str) -> rusqlite::Result<Vec<String>> {
+        let conn = self.conn();
+        let mut statement = conn.prepare("DELETE FROM images WHERE src = ?1 RETURNING path")?;
+        let rows = statement.query_map(params![normalize(src)], |row| row.get(0))?;
+        rows.collect()
+    }
+
+    /// Removes the rows below a cache directory, e.g. of a tenant.
+    pub(crate) fn remove_dir(&self, dir: &str) -> rusqlite::Result<()> {
+        self.conn().execute(
+            "DELETE FROM images WHERE substr(path, 1, length(?1)) = ?1",
+            params![format!("{dir}/")],
+        )?;
+        Ok(())
+    }
+
+    pub(crate) fn remove(&self, path: &str) -> rusqlite::Result<()> {
+        self.conn()
+            .execute("DELETE FROM images WHERE path = ?1", params![path])?;
+        Ok(())
+    }
+
+    /// Least recently accessed entries that don't fit in `max_bytes`.
+    pub(crate) fn over_budget(&self, max_bytes: u64) -> rusqlite::Result<Vec<IndexEntry>> {
+        let conn = self.conn();
+        let mut statement =
+            conn.prepare("SELECT path, bytes FROM images ORDER BY accessed DESC, rowid DESC")?;
+        let rows = statement.query_map([], |row| {
+            Ok(IndexEntry {
+                path: row.get(0)?,
+                bytes: row.get::<_, i64>(1)? as u64,
+            })
+        })?;
+
+        let mut total = 0;
+        let mut evict = Vec::new();
+        for entry in rows {
+            let entry = entry?;
+            total += entry.bytes;
+            if total > max_bytes {
+                evict.push(entry);
+            }
+        }
+        Ok(evict)
+    }
+}
+
+impl ImageOptimizer {
+    /// Keeps an SQLite index of the cached images at `path`, e.g.
+    /// `./target/site/cache/image/index.sqlite`.
+    ///
+    /// Entries are recorded as images are created. Call
+    /// [`ImageOptimizer::rebuild_index`] once to add an existing cache.
+    pub fn with_index(mut self, path: impl AsRef<Path>) -> rusqlite::Result<Self> {
+        self.index = Some(CacheIndex::open(path.as_ref())?);
+        Ok(self)
+    }
+
+    /// Records every cached image on disk in the index. Returns the number of entries.
+    pub fn rebuild_index(&self) -> std::io::Result<usize> {
+        let Some(index) = &self.index else {
+            return Ok(0);
+        };
+        let mut files = Vec::new();
+        crate::optimizer::collect_files(&self.cache_dir, &mut files)?;
+
+        let mut recorded = 0;
+        for file in files.iter().filter(|file| crate::optimizer::is_cache_entry(file)) {
+            let Some(image) = CachedImage::from_file_path(&file.to_string_lossy()) else {
+                continue;
+            };
+            let bytes = std::fs::metadata(file)?.len();
+            index
+                .record(&image, &self.cache_path(&image), bytes, None)
+                .map_err(std::io::Error::other)?;
+            recorded += 1;
+        }
+        Ok(recorded)
+    }
+
+    /// Deletes the least recently used cached images until the cache fits in
+    /// `max_bytes`. Requires [`ImageOptimizer::with_index`], returns the number of
+    /// deleted images.
+    pub fn evict_lru(&self, max_bytes: u64) -> std::io::Result<usize> {
+        let Some(index) = &self.index else {
+            return Ok(0);
+        };
+        let evict = index.over_budget(max_bytes).map_err(std::io::Error::other)?;
+        if !evict.is_empty() {
+            self.invalidate_memory();
+        }
+        for entry in &evict {
+            let file = self.cache_file(&entry.path);
+            let image = self
+                .watches_evictions()
+                .then(|| CachedImage::from_file_path(&file.to_string_lossy()))
+                .flatten();
+            let _ = std::fs::remove_file(file.with_extension("qs"));
+            remove_precompressed(&file);
+            match std::fs::remove_file(&file) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+            index.remove(&entry.path).map_err(std::io::Error::other)?;
+            self.emit_evicted(image);
+        }
+        self.cache.retain(|image, _| {
+            !evict
+                .iter()
+                .any(|entry| entry.path == self.cache_path(image))
+        });
+        Ok(evict.len())
+    }
+}
+
+#[cfg(test)]
+mod index_tests {
+    use super::*;
+    use crate::optimizer::CachedImageOption;
+    use crate::test_support::resize_spec;
+
+    #[test]
+    fn lru_order() {
+        let index = CacheIndex::open(Path::new(":memory:")).unwrap();
+        let image = |width| CachedImage {
+            src: "/a.png".to_string(),
+            tenant: None,
+            option: CachedImageOption::Resize(resize_spec(width, width)),
+        };
+
+        index.record(&image(100), &image(100).get_file_path(), 100, None).unwrap();
+        index.record(&image(200), &image(200).get_file_path(), 200, None).unwrap();
+        assert_eq!(index.images().unwrap().len(), 2);
+
+        let evict = index.over_budget(250).unwrap();
+        assert_eq!(evict.len(), 1);
+        assert_eq!(evict[0].bytes, 100);
+
+        assert_eq!(index.remove_source("a.png").unwrap().len(), 2);
+        assert!(index.images().unwrap().is_empty());
+    }
+
+    #[test]
+    fn duplicate_sources() {
+        let index = CacheIndex::open(Path::new(":memory:")).unwrap();
+        let image = |src: &str, width| CachedImage {
+            src: src.to_string(),
+            tenant: None,
+            option: CachedImageOption::Resize(resize_spec(width, width)),
+        };
+
+        let source = Path::new("./example/start-axum/public/cute_ferris.png");
+        let hash = index.source_hash(source).unwrap();
+        assert_eq!(index.source_hash(source).unwrap(), hash);
+
+        let duplicate = |src, width| {
+            let image = image(src, width);
+            index.duplicate(&image, &image.get_file_path(), &hash).unwrap()
+        };
+        let original = image("/a.png", 100);
+        index.record(&original, &original.get_file_path(), 100, Some(&hash)).unwrap();
+        assert_eq!(duplicate("/export/a.png", 100), Some(original.get_file_path()));
+        assert_eq!(duplicate("/export/a.png", 200), None);
+        assert_eq!(duplicate("/a.png", 100), None);
+    }
+}
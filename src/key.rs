@@ -0,0 +1,319 @@
+use crate::optimizer::{is_svg, CachedImage, CachedImageOption, OutputFormat, Placeholder};
+use std::sync::Arc;
+
+/// Maps images to the URLs of the cache handler and to their cached files.
+///
+/// The default, [`DefaultCacheKey`], puts the options in the query string and names
+/// files after a hash of them, [`PathCacheKey`] puts them in a path segment. Implement
+/// it for shorter or versioned URLs, or readable file names while debugging. Every
+/// method has a default, so a key only overrides what it changes.
+///
+/// A key must parse every URL it builds, and two images must never share a file.
+/// The server reads URLs back and names files with `ServerCacheKey`, implemented
+/// next to it. Set it on the optimizer with `ImageOptimizer::with_cache_key`,
+/// and provide it to the app with [`provide_image_cache_key`] so the browser builds
+/// the same URLs.
+///
+/// ```
+/// use leptos_image::*;
+///
+/// // Bumping the version invalidates every cached URL, e.g. after an encoder upgrade.
+/// #[derive(Debug)]
+/// struct Versioned;
+///
+/// impl CacheKey for Versioned {
+///     fn url(&self, image: &CachedImage, handler_path: &str) -> String {
+///         format!("{handler_path}?v=2&{}", image.query())
+///     }
+/// }
+///
+/// // Extra parameters are ignored, the defaults read the URLs back.
+/// #[cfg(feature = "ssr")]
+/// impl ServerCacheKey for Versioned {}
+/// ```
+pub trait CacheKey: Send + Sync + std::fmt::Debug {
+    /// URL of the image below the cache handler at `handler_path`.
+    fn url(&self, image: &CachedImage, handler_path: &str) -> String {
+        format!("{handler_path}?{}", image.query())
+    }
+}
+
+/// The server side of a [`CacheKey`], reading back its URLs and naming cached files.
+/// Both methods have defaults, an empty `impl` keeps them.
+#[cfg(feature = "ssr")]
+pub trait ServerCacheKey: CacheKey {
+    /// Reads back the image of a URL built by [`CacheKey::url`], or why it isn't one.
+    /// Reads both URLs of [`DefaultCacheKey`] and [`PathCacheKey`] by default.
+    fn parse_url(&self, url: &str, handler_path: &str) -> Result<CachedImage, String> {
+        let (path, query) = url.split_once('?').unwrap_or((url, ""));
+        if path.trim_end_matches('/') != handler_path.trim_end_matches('/') {
+            return parse_path(path, handler_path);
+        }
+        // Extra parameters, e.g. a version, are ignored.
+        CachedImage::from_query(query)
+    }
+
+    /// Path of the cached file, relative to the cache directory. Must end with
+    /// [`CachedImage::extension`].
+    fn file_path(&self, image: &CachedImage) -> String {
+        image.cache_key()
+    }
+}
+
+/// Options in the query string, files named after a hash of them.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DefaultCacheKey;
+
+impl CacheKey for DefaultCacheKey {}
+
+#[cfg(feature = "ssr")]
+impl ServerCacheKey for DefaultCacheKey {}
+
+/// Options in a path segment instead of the query string, like image CDNs:
+/// `/__cache/image/w_800,h_600,q_75/images/foo.png.webp`. Readable, and cached by
+/// CDNs that ignore query strings.
+///
+/// Files are named like [`DefaultCacheKey`], and query string URLs are still read,
+/// so switching keeps the cache. The extension is only a hint, the handler still
+/// negotiates the format. Sources come back rooted, `foo.png` as `/foo.png`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PathCacheKey;
+
+impl CacheKey for PathCacheKey {
+    fn url(&self, image: &CachedImage, handler_path: &str) -> String {
+        let query = image.query();
+        let mut params = Vec::new();
+        if matches!(image.option, CachedImageOption::Blur(_)) {
+            params.push(BLUR.to_string());
+        }
+        for (key, value) in query.split('&').filter_map(|pair| pair.split_once('=')) {
+            // `option[r][c][x]` becomes `c.x`, the variant is implied.
+            let key = key.replace("%5B", "[").replace("%5D", "]").replace(']', "");
+            let key = match key.split('[').collect::<Vec<_>>().as_slice() {
+                ["src"] => continue,
+                ["option", _, keys @ ..] => keys.join("."),
+                keys => keys.join("."),
+            };
+            let value = value.replace(',', "%2C").replace('/', "%2F");
+            params.push(format!("{key}_{value}"));
+        }
+        format!(
+            "{}/{}/{}.{}",
+            handler_path.trim_end_matches('/'),
+            params.join(","),
+            encode_src(image.src.trim_start_matches('/')),
+            url_extension(image)
+        )
+    }
+}
+
+#[cfg(feature = "ssr")]
+impl ServerCacheKey for PathCacheKey {}
+
+/// Marks placeholders in the options of a [`PathCacheKey`] URL.
+const BLUR: &str = "blur";
+
+// Keeps the source readable, escaping what a query string would decode.
+fn encode_src(src: &str) -> String {
+    src.bytes()
+        .map(|byte| match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                char::from(byte).to_string()
+            }
+            byte => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+// Built without the server features, so the browser builds the same URLs.
+fn url_extension(image: &CachedImage) -> String {
+    match &image.option {
+        CachedImageOption::Resize(_) if is_svg(&image.src) => "svg".into(),
+        CachedImageOption::Resize(resize) => match resize.format {
+            OutputFormat::Auto | OutputFormat::Webp => "webp".into(),
+            OutputFormat::Avif => "avif".into(),
+            OutputFormat::Jpeg => "jpg".into(),
+            OutputFormat::Jxl => "jxl".into(),
+            OutputFormat::PalettePng => "png".into(),
+            OutputFormat::Original => std::path::Path::new(&image.src)
+                .extension()
+                .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+                .unwrap_or_else(|| "webp".into()),
+        },
+        CachedImageOption::Blur(blur) => match blur.kind {
+            Placeholder::Blur => "svg".into(),
+            Placeholder::Lqip => "webp".into(),
+            Placeholder::Gradient => "css".into(),
+        },
+    }
+}
+
+// Turns the path of a `PathCacheKey` URL back into the query string it was built from.
+#[cfg(feature = "ssr")]
+fn parse_path(path: &str, handler_path: &str) -> Result<CachedImage, String> {
+    let rest = path
+        .strip_prefix(handler_path.trim_end_matches('/'))
+        .and_then(|rest| rest.strip_prefix('/'))
+        .ok_or("Not below the cache handler")?;
+    let (params, src) = rest.split_once('/').ok_or("Missing the source")?;
+    let (src, _) = src.rsplit_once('.').ok_or("Missing the extension")?;
+
+    let params: Vec<&str> = params.split(',').filter(|param| !param.is_empty()).collect();
+    let variant = if params.contains(&BLUR) { "b" } else { "r" };
+    let mut query = vec![format!("src=/{src}")];
+    for param in params.into_iter().filter(|param| *param != BLUR) {
+        let (key, value) = param
+            .split_once('_')
+            .ok_or_else(|| format!("Invalid parameter {param}"))?;
+        let key = match key {
+            "t" => key.to_string(),
+            key => format!("option[{variant}][{}]", key.replace('.', "][")),
+        };
+        query.push(format!("{key}={value}"));
+    }
+    CachedImage::from_query(&query.join("&"))
+}
+
+/// Provides a [`CacheKey`] to every `<Image/>` below this point, see
+/// [`CacheKey`]. `ImageOptimizer::provide_context` provides the optimizer's key.
+pub fn provide_image_cache_key(key: impl CacheKey + 'static) {
+    let key: Arc<dyn CacheKey> = Arc::new(key);
+    leptos::prelude::provide_context(key);
+}
+
+#[cfg(feature = "ssr")]
+impl crate::ImageOptimizer {
+    /// Maps images to URLs and cached files with `key`, see [`ServerCacheKey`]. Files
+    /// cached under another key are left behind, purge them after switching.
+    ///
+    /// ```
+    /// # use leptos_image::*;
+    /// let optimizer = ImageOptimizer::new("/__cache/image", "./target/site", 1)
+    ///     .with_cache_key(DefaultCacheKey);
+    /// ```
+    pub fn with_cache_key(mut self, key: impl ServerCacheKey + 'static) -> Self {
+        let key = Arc::new(key);
+        self.url_key = key.clone();
+        self.cache_key = key;
+        self
+    }
+}
+
+/// The key provided to this point, or the default.
+pub(crate) fn use_cache_key() -> Arc<dyn CacheKey> {
+    leptos::prelude::use_context::<Arc<dyn CacheKey>>()
+        .unwrap_or_else(|| Arc::new(DefaultCacheKey))
+}
+
+#[cfg(all(test, feature = "ssr"))]
+mod key_tests {
+    use super::*;
+    use crate::optimizer::{Blur, Crop, Resize};
+    use crate::test_support::resize_spec;
+
+    // Readable keys, `/__cache/image/ferris.png/100x100q75.webp`.
+    #[derive(Debug)]
+    struct Readable;
+
+    impl CacheKey for Readable {
+        fn url(&self, image: &CachedImage, handler_path: &str) -> String {
+            let CachedImageOption::Resize(resize) = &image.option else {
+                return DefaultCacheKey.url(image, handler_path);
+            };
+            let name = format!("{}x{}q{}", resize.width, resize.height, resize.quality);
+            format!("{handler_path}{}/{name}.{}", image.src, image.extension())
+        }
+    }
+
+    impl ServerCacheKey for Readable {
+        fn parse_url(&self, url: &str, handler_path: &str) -> Result<CachedImage, String> {
+            if url.contains('?') {
+                return DefaultCacheKey.parse_url(url, handler_path);
+            }
+            let url = url.strip_prefix(handler_path).ok_or("Not a cache URL")?;
+            let (src, name) = url.rsplit_once('/').ok_or("Missing options")?;
+            let name = name.split('.').next().unwrap_or_default();
+            let (size, quality) = name.split_once('q').ok_or("Missing quality")?;
+            let (width, height) = size.split_once('x').ok_or("Missing size")?;
+            let number = |value: &str| {
+                value.parse::<u32>().map_err(|_| format!("Bad number {value}"))
+            };
+            Ok(CachedImage::new(
+                src,
+                Resize {
+                    width: number(width)?,
+                    height: number(height)?,
+                    quality: number(quality)?.min(100) as u8,
+                    ..Default::default()
+                },
+            ))
+        }
+
+        fn file_path(&self, image: &CachedImage) -> String {
+            self.url(image, "").trim_start_matches('/').to_string()
+        }
+    }
+
+    #[test]
+    fn custom_keys() {
+        let image = CachedImage::new("/ferris.png", resize_spec(100, 100));
+        let url = DefaultCacheKey.url(&image, "/__cache/image");
+        assert_eq!(DefaultCacheKey.parse_url(&url, "/__cache/image"), Ok(image.clone()));
+
+        let url = Readable.url(&image, "/__cache/image");
+        assert_eq!(url, "/__cache/image/ferris.png/100x100q75.webp");
+        assert_eq!(Readable.parse_url(&url, "/__cache/image"), Ok(image.clone()));
+
+        let optimizer = crate::ImageOptimizer::new("/__cache/image", "./target/site", 1)
+            .with_cache_key(Readable);
+        assert!(optimizer
+            .get_file_path_from_root(&image)
+            .replace('\\', "/")
+            .ends_with("cache/image/ferris.png/100x100q75.webp"));
+    }
+
+    #[test]
+    fn path_urls() {
+        let resize = resize_spec(800, 600);
+        let image = CachedImage::new("/images/foo.png", resize.clone());
+        let url = PathCacheKey.url(&image, "/__cache/image");
+        assert_eq!(url, "/__cache/image/w_800,h_600,q_75/images/foo.png.webp");
+        assert_eq!(PathCacheKey.parse_url(&url, "/__cache/image"), Ok(image.clone()));
+        // Query strings are still read.
+        let query = DefaultCacheKey.url(&image, "/__cache/image");
+        assert_eq!(PathCacheKey.parse_url(&query, "/__cache/image"), Ok(image));
+
+        let mut cropped = CachedImage::new(
+            "/my photos/a,b.jpg",
+            Resize {
+                crop: Some(Crop {
+                    x: 10,
+                    y: 20,
+                    width: 300,
+                    height: 200,
+                }),
+                ..resize
+            },
+        );
+        cropped.tenant = Some("acme_corp".to_string());
+        let placeholder = CachedImage {
+            option: CachedImageOption::Blur(Blur {
+                width: 25,
+                height: 25,
+                svg_width: 100,
+                svg_height: 100,
+                sigma: 15,
+                crop: None,
+                aspect_ratio: None,
+                focal_point: None,
+                kind: Placeholder::Blur,
+                matte: None,
+            }),
+            ..cropped.clone()
+        };
+        for image in [cropped, placeholder] {
+            let url = PathCacheKey.url(&image, "/__cache/image");
+            assert_eq!(PathCacheKey.parse_url(&url, "/__cache/image"), Ok(image));
+        }
+    }
+}
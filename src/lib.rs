@@ -35,6 +35,8 @@
 //! 3. **Axum State Configuration**: Incorporate `ImageOptimizer` into your app's Axum state for centralized management.
 //! 4. **Cache Route Configuration**: Add a dedicated route to your router for serving optimized images from the cache.
 //!
+//! Steps 2 to 4 can also be done in one call with `leptos_image::axum::attach`.
+//!
 //! ### Example Implementation
 //!
 //! Here’s how you can integrate the Image Optimizer into your Leptos application:
@@ -101,15 +103,115 @@
 //! ```
 //!
 
+#[cfg(feature = "ssr")]
+pub mod axum;
+mod batch;
+#[cfg(feature = "ssr")]
+mod budget;
+#[cfg(feature = "ssr")]
+mod cdn;
+#[cfg(feature = "ssr")]
+mod config;
+#[cfg(feature = "ssr")]
+mod content;
+#[cfg(feature = "ssr")]
+mod decode;
+#[cfg(feature = "ssr")]
+mod encoder;
+#[cfg(feature = "ssr")]
+mod events;
+#[cfg(feature = "ssr")]
+mod fair;
+#[cfg(feature = "ssr")]
+mod health;
 mod image;
+#[cfg(feature = "sqlite")]
+mod index;
+mod key;
+mod loader;
+#[cfg(feature = "ssr")]
+mod manifest;
+#[cfg(feature = "memory-cache")]
+mod memory;
+#[cfg(feature = "ssr")]
+mod metadata;
+#[cfg(feature = "ssr")]
+mod migrate;
+mod optimized;
 mod optimizer;
+#[cfg(all(feature = "debug-overlay", debug_assertions))]
+mod overlay;
+#[cfg(feature = "otel")]
+mod otel;
+#[cfg(feature = "ssr")]
+mod policy;
+#[cfg(feature = "ssr")]
+mod pool;
+mod prefetch;
+mod preset;
+#[cfg(feature = "ssr")]
+mod priority;
 mod provider;
+mod responsive;
 #[cfg(feature = "ssr")]
 mod routes;
+#[cfg(feature = "ssr")]
+mod stats;
+#[cfg(all(test, feature = "ssr"))]
+mod test_support;
+#[cfg(feature = "testing")]
+pub mod testing;
+#[cfg(feature = "watch")]
+mod watch;
 
+pub use batch::provide_placeholder_batch;
+#[cfg(feature = "cloudflare")]
+pub use cdn::Cloudflare;
+#[cfg(feature = "fastly")]
+pub use cdn::Fastly;
+#[cfg(feature = "ssr")]
+pub use cdn::{CdnError, CdnInvalidator};
+#[cfg(feature = "ssr")]
+pub use config::ConfigError;
+#[cfg(feature = "ssr")]
+pub use decode::{AutoOrient, IccPolicy, ToneMapOperator, ToneMapping};
+#[cfg(feature = "ssr")]
+pub use encoder::*;
+#[cfg(feature = "ssr")]
+pub use events::ImageEvent;
+#[cfg(feature = "ssr")]
+pub use fair::FairQueueing;
+#[cfg(feature = "ssr")]
+pub use health::{HealthError, ImageHealthRoute};
 pub use image::*;
+pub use key::{provide_image_cache_key, CacheKey, DefaultCacheKey, PathCacheKey};
+#[cfg(feature = "ssr")]
+pub use key::ServerCacheKey;
+pub use loader::*;
+#[cfg(feature = "ssr")]
+pub use manifest::{Manifest, ManifestEntry};
+#[cfg(feature = "ssr")]
+pub use metadata::{MetadataPolicy, MetadataTag};
 #[cfg(feature = "ssr")]
-pub use optimizer::ImageOptimizer;
+pub use migrate::MigrationReport;
+pub use optimized::{use_optimized_image, ImageOptions, OptimizedImage};
+#[cfg(feature = "ssr")]
+pub use optimizer::{CreateImageError, EncodeErrorPolicy, ImageOptimizer, ReadOnlyFallback};
+#[cfg(feature = "ssr")]
+pub use policy::SourcePolicy;
+pub use prefetch::{use_image_prefetch, ImagePrefetcher, PrefetchImage};
+pub use preset::Preset;
+#[cfg(feature = "ssr")]
+pub use priority::PriorityImages;
+pub use optimizer::{
+    Animation, AspectRatio, Background, CachedImage, Crop, Fit, FocalPoint, FrameLimits, Mask,
+    OutputFormat, Placeholder, Resize, ResizeFilter, Sharpen,
+};
 pub use provider::*;
+pub use responsive::{responsive_set, ResponsiveSet};
 #[cfg(feature = "ssr")]
 pub use routes::*;
+#[cfg(feature = "ssr")]
+pub use stats::{CacheStats, FormatStats};
+#[cfg(feature = "watch")]
+pub use watch::SourceWatcher;
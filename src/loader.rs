@@ -0,0 +1,165 @@
+use crate::optimizer::OutputFormat;
+use std::sync::Arc;
+
+/// Parameters handed to an [`ImageLoader`] when building an image URL.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LoaderParams<'a> {
+    /// Image source as passed to the `<Image/>` component.
+    pub src: &'a str,
+    /// Requested width in pixels.
+    pub width: u32,
+    /// Requested height in pixels, `None` to keep the source's aspect ratio.
+    pub height: Option<u32>,
+    /// Requested quality (0-100).
+    pub quality: u8,
+    /// Requested output format.
+    pub format: OutputFormat,
+    /// Whether the URL is for a blurred placeholder.
+    pub blur: bool,
+}
+
+/// Maps an image request to a URL, replacing the built-in cache handler.
+///
+/// Use this when images are served by an image CDN. Either pass it to
+/// `<Image loader=.../>` or provide it for the whole app with [`provide_image_loader`].
+///
+/// ```
+/// use leptos_image::*;
+///
+/// let loader = ImageLoader::new(|p| format!("https://img.example.com{}?w={}", p.src, p.width));
+/// let cropped = ImageLoader::new(|p| match p.height {
+///     Some(height) => format!("https://img.example.com{}?w={}&h={height}", p.src, p.width),
+///     None => format!("https://img.example.com{}?w={}", p.src, p.width),
+/// });
+/// ```
+#[derive(Clone)]
+pub struct ImageLoader(Arc<dyn Fn(&LoaderParams) -> String + Send + Sync>);
+
+impl ImageLoader {
+    /// Creates a loader from a URL building function.
+    pub fn new(f: impl Fn(&LoaderParams) -> String + Send + Sync + 'static) -> Self {
+        Self(Arc::new(f))
+    }
+
+    /// Builds the URL for the given parameters.
+    pub fn url(&self, params: &LoaderParams) -> String {
+        (self.0)(params)
+    }
+
+    /// Builds a `srcset` with the URLs at one and two times `params.width`, so
+    /// high density screens get a sharp image.
+    pub fn srcset(&self, params: &LoaderParams) -> String {
+        let double = LoaderParams {
+            width: params.width.saturating_mul(2),
+            height: params.height.map(|height| height.saturating_mul(2)),
+            ..*params
+        };
+        let (url, double_url) = (self.url(params), self.url(&double));
+        format!("{url} {}w, {double_url} {}w", params.width, double.width)
+    }
+
+    /// Cloudinary loader for images in the `upload` delivery type.
+    ///
+    /// `cloud_name` is your Cloudinary cloud name.
+    pub fn cloudinary(cloud_name: impl Into<String>) -> Self {
+        let cloud_name = cloud_name.into();
+        Self::new(move |p| {
+            let mut transforms = format!("w_{},q_{}", p.width, p.quality);
+            if let Some(height) = p.height {
+                transforms.push_str(&format!(",h_{height},c_fill"));
+            }
+            match p.format {
+                OutputFormat::Auto => transforms.push_str(",f_auto"),
+                OutputFormat::Webp => transforms.push_str(",f_webp"),
+                OutputFormat::Avif => transforms.push_str(",f_avif"),
+                OutputFormat::Jpeg => transforms.push_str(",f_jpg"),
+                OutputFormat::Jxl => transforms.push_str(",f_jxl"),
+                OutputFormat::PalettePng => transforms.push_str(",f_png,fl_png8"),
+                OutputFormat::Original => {}
+            }
+            if p.blur {
+                transforms.push_str(",e_blur:1000");
+            }
+            format!(
+                "https://res.cloudinary.com/{cloud_name}/image/upload/{transforms}/{}",
+                p.src.trim_start_matches('/')
+            )
+        })
+    }
+
+    /// Imgix loader.
+    ///
+    /// `domain` is your imgix source domain, e.g. `example.imgix.net`.
+    pub fn imgix(domain: impl Into<String>) -> Self {
+        let domain = domain.into();
+        Self::new(move |p| {
+            let mut url = format!(
+                "https://{domain}/{}?w={}&q={}",
+                p.src.trim_start_matches('/'),
+                p.width,
+                p.quality
+            );
+            if let Some(height) = p.height {
+                url.push_str(&format!("&h={height}&fit=crop"));
+            }
+            match p.format {
+                OutputFormat::Auto => url.push_str("&auto=format"),
+                OutputFormat::Webp => url.push_str("&fm=webp"),
+                OutputFormat::Avif => url.push_str("&fm=avif"),
+                OutputFormat::Jpeg => url.push_str("&fm=pjpg"),
+                OutputFormat::Jxl => url.push_str("&fm=jxl"),
+                OutputFormat::PalettePng => url.push_str("&fm=png8"),
+                OutputFormat::Original => {}
+            }
+            if p.blur {
+                url.push_str("&blur=200");
+            }
+            url
+        })
+    }
+
+    /// Cloudflare Images loader using image transformations.
+    ///
+    /// `zone` is the origin the images are served from, e.g. `https://example.com`.
+    /// Pass an empty string to use relative URLs on the current origin.
+    pub fn cloudflare(zone: impl Into<String>) -> Self {
+        let zone = zone.into();
+        Self::new(move |p| {
+            let mut options = format!("width={},quality={}", p.width, p.quality);
+            if let Some(height) = p.height {
+                options.push_str(&format!(",height={height},fit=cover"));
+            }
+            match p.format {
+                // Cloudflare can't output JPEG XL or palette PNG, let it negotiate instead.
+                OutputFormat::Auto | OutputFormat::Jxl | OutputFormat::PalettePng => {
+                    options.push_str(",format=auto")
+                }
+                OutputFormat::Webp => options.push_str(",format=webp"),
+                OutputFormat::Avif => options.push_str(",format=avif"),
+                OutputFormat::Jpeg => options.push_str(",format=jpeg"),
+                OutputFormat::Original => {}
+            }
+            if p.blur {
+                options.push_str(",blur=50");
+            }
+            format!(
+                "{}/cdn-cgi/image/{options}/{}",
+                zone.trim_end_matches('/'),
+                p.src.trim_start_matches('/')
+            )
+        })
+    }
+}
+
+impl std::fmt::Debug for ImageLoader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("ImageLoader").finish()
+    }
+}
+
+/// Provides an [`ImageLoader`] to every `<Image/>` below this point.
+///
+/// Images with their own `loader` prop take precedence.
+pub fn provide_image_loader(loader: ImageLoader) {
+    leptos::prelude::provide_context(loader);
+}
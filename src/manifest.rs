@@ -0,0 +1,117 @@
+use crate::optimizer::{collect_files, CachedImage, ImageOptimizer, SIDECAR_EXTENSION};
+use serde::Serialize;
+
+/// Every optimized image in the cache, see [`ImageOptimizer::manifest`].
+///
+/// Deployment tooling can upload the listed files to a CDN or object store and
+/// rewrite `request` URLs to `url`, so pages never hit the cache handler.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Manifest {
+    /// Cached images, sorted by path.
+    pub images: Vec<ManifestEntry>,
+}
+
+/// One cached image of a [`Manifest`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ManifestEntry {
+    /// Source image as passed to `<Image/>`.
+    pub src: String,
+    /// Tenant the image was created for, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    /// Encoded options, as in the query string of the cache handler.
+    pub params: String,
+    /// Cache handler URL the component requests.
+    pub request: String,
+    /// Output file below `cache/image`, relative to the site root. With
+    /// [`ImageOptimizer::with_cache_dir`] the file is in the configured directory.
+    pub path: String,
+    /// Public URL of the output file, with the asset prefix applied.
+    pub url: String,
+    /// BLAKE3 hash of the file contents, hex encoded.
+    pub hash: String,
+    /// File size in bytes.
+    pub bytes: u64,
+}
+
+impl Manifest {
+    /// Pretty printed JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("Manifest is serializable")
+    }
+}
+
+impl ImageOptimizer {
+    /// Lists every optimized image and placeholder in the cache directory.
+    ///
+    /// Images are found through their sidecars, entries written before hashed paths
+    /// are skipped until migrated, see [`ImageOptimizer::migrate_cache`].
+    pub fn manifest(&self) -> std::io::Result<Manifest> {
+        let mut files = Vec::new();
+        collect_files(&self.cache_dir, &mut files)?;
+
+        let mut images = Vec::new();
+        for sidecar in files
+            .iter()
+            .filter(|file| file.extension().is_some_and(|ext| ext == SIDECAR_EXTENSION))
+        {
+            let Some(image) = std::fs::read_to_string(sidecar)
+                .ok()
+                .and_then(|encoded| serde_qs::from_str::<CachedImage>(&encoded).ok())
+            else {
+                continue;
+            };
+            let path = self.cache_path(&image).replace('\\', "/");
+            // Sidecars are written first, the encode may still be running or have failed.
+            let Ok(contents) = std::fs::read(self.cache_file(&path)) else {
+                continue;
+            };
+            images.push(ManifestEntry {
+                params: serde_qs::to_string(&image).unwrap(),
+                request: self.cache_key.url(&image, &self.api_handler_path),
+                url: format!("{}/{path}", self.asset_prefix.trim_end_matches('/')),
+                hash: blake3::hash(&contents).to_hex().to_string(),
+                bytes: contents.len() as u64,
+                src: image.src,
+                tenant: image.tenant,
+                path,
+            });
+        }
+        images.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(Manifest { images })
+    }
+}
+
+#[cfg(test)]
+mod manifest_tests {
+    use super::*;
+    use crate::optimizer::CachedImageOption;
+    use crate::test_support::{resize_spec, test_root};
+    use crate::{CacheKey, DefaultCacheKey};
+
+    #[test]
+    fn lists_cached_images() {
+        let (root, optimizer) = test_root("leptos_image_manifest");
+        let optimizer = optimizer.with_asset_prefix("https://cdn.example.com/");
+
+        let spec = CachedImage {
+            src: "/ferris.png".to_string(),
+            tenant: None,
+            option: CachedImageOption::Resize(resize_spec(100, 100)),
+        };
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(optimizer.create_image(&spec)).unwrap();
+
+        let manifest = optimizer.manifest().unwrap();
+        assert_eq!(manifest.images.len(), 1);
+        let entry = &manifest.images[0];
+        assert_eq!(entry.src, "/ferris.png");
+        assert_eq!(entry.path, spec.get_file_path());
+        assert_eq!(entry.url, format!("https://cdn.example.com/{}", entry.path));
+        assert_eq!(entry.request, DefaultCacheKey.url(&spec, "/__cache/image"));
+        let contents = std::fs::read(root.join(&entry.path)).unwrap();
+        assert_eq!(entry.bytes, contents.len() as u64);
+        assert_eq!(entry.hash, blake3::hash(&contents).to_hex().to_string());
+        assert!(manifest.to_json().contains("\"images\""));
+    }
+}
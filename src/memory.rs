@@ -0,0 +1,158 @@
+use crate::optimizer::ImageOptimizer;
+use axum::body::Bytes;
+use axum::http::{header, HeaderMap, HeaderValue, Uri};
+use axum::response::{IntoResponse, Response};
+
+/// Small optimized images held in memory, see [`ImageOptimizer::with_memory_cache`].
+#[derive(Clone)]
+pub(crate) struct MemoryCache {
+    entries: moka::sync::Cache<String, Entry>,
+    max_entry_bytes: u64,
+}
+
+#[derive(Clone)]
+struct Entry {
+    bytes: Bytes,
+    content_type: HeaderValue,
+}
+
+impl std::fmt::Debug for MemoryCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MemoryCache")
+            .field("entries", &self.entries.entry_count())
+            .field("bytes", &self.entries.weighted_size())
+            .field("max_entry_bytes", &self.max_entry_bytes)
+            .finish()
+    }
+}
+
+impl MemoryCache {
+    pub(crate) fn entry_count(&self) -> u64 {
+        self.entries.entry_count()
+    }
+
+    pub(crate) fn weighted_size(&self) -> u64 {
+        self.entries.weighted_size()
+    }
+
+    pub(crate) fn contains(&self, path: &str) -> bool {
+        self.entries.contains_key(path)
+    }
+
+    pub(crate) fn invalidate_all(&self) {
+        self.entries.invalidate_all();
+    }
+
+    pub(crate) fn invalidate(&self, path: &str) {
+        self.entries.invalidate(path);
+    }
+}
+
+impl ImageOptimizer {
+    /// Keeps optimized images of up to `max_entry_bytes` in memory, at most `max_bytes`
+    /// in total, so hot images are served without touching the file system.
+    ///
+    /// Least recently used images are dropped first. Blur placeholders are already held
+    /// in memory and are not included. Disabled in dev mode, where sources may change.
+    pub fn with_memory_cache(mut self, max_bytes: u64, max_entry_bytes: u64) -> Self {
+        let entries = moka::sync::Cache::builder()
+            .weigher(|_, entry: &Entry| entry.bytes.len().try_into().unwrap_or(u32::MAX))
+            .max_capacity(max_bytes)
+            .build();
+        self.memory = Some(MemoryCache {
+            entries,
+            max_entry_bytes,
+        });
+        self
+    }
+}
+
+/// Serves a cached file from memory, loading it on a miss.
+///
+/// Returns `None` for files that should be served from disk: placeholders, large
+/// files, and partial or conditional requests.
+pub(crate) async fn serve(
+    optimizer: &ImageOptimizer,
+    uri: &Uri,
+    headers: &HeaderMap,
+) -> Option<Response> {
+    let memory = optimizer.memory.as_ref().filter(|_| !optimizer.dev_mode)?;
+    let conditional = [
+        header::RANGE,
+        header::IF_RANGE,
+        header::IF_MODIFIED_SINCE,
+        header::IF_UNMODIFIED_SINCE,
+        header::IF_NONE_MATCH,
+    ];
+    if conditional.iter().any(|name| headers.contains_key(name)) {
+        return None;
+    }
+    let path = uri.path();
+    let extension = path.rsplit_once('.').map(|(_, ext)| ext).unwrap_or_default();
+    if extension == "svg" {
+        return None;
+    }
+
+    if let Some(entry) = memory.entries.get(path) {
+        optimizer.metrics.memory_hit();
+        return Some(entry.into_response());
+    }
+    optimizer.metrics.memory_miss();
+
+    let file = optimizer.cache_dir.join(path.trim_start_matches('/'));
+    let size = tokio::fs::metadata(&file).await.ok()?.len();
+    if size > memory.max_entry_bytes {
+        return None;
+    }
+    let bytes = Bytes::from(tokio::fs::read(&file).await.ok()?);
+    let content_type = match extension {
+        "jpg" => "image/jpeg".to_string(),
+        extension => format!("image/{extension}"),
+    };
+    let entry = Entry {
+        bytes,
+        content_type: HeaderValue::from_str(&content_type).ok()?,
+    };
+    memory.entries.insert(path.to_string(), entry.clone());
+    Some(entry.into_response())
+}
+
+impl IntoResponse for Entry {
+    fn into_response(self) -> Response {
+        ([(header::CONTENT_TYPE, self.content_type)], self.bytes).into_response()
+    }
+}
+
+#[cfg(test)]
+mod memory_tests {
+    use super::*;
+    use crate::test_support::test_root;
+
+    #[test]
+    fn serves_hot_images_from_memory() {
+        let (root, optimizer) = test_root("leptos_image_memory");
+        std::fs::create_dir_all(root.join("cache/image")).unwrap();
+        std::fs::write(root.join("cache/image/a.webp"), b"RIFF").unwrap();
+        std::fs::write(root.join("cache/image/big.webp"), [0; 64]).unwrap();
+        let optimizer = optimizer.with_memory_cache(1024, 16);
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let get = |path: &str, headers: &HeaderMap| {
+            runtime.block_on(serve(&optimizer, &path.parse().unwrap(), headers))
+        };
+        let none = HeaderMap::new();
+        let response = get("/a.webp", &none).unwrap();
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "image/webp");
+        // Served from memory once loaded.
+        std::fs::remove_file(root.join("cache/image/a.webp")).unwrap();
+        assert!(get("/a.webp", &none).is_some());
+        assert!(get("/big.webp", &none).is_none());
+
+        let mut range = HeaderMap::new();
+        range.insert(header::RANGE, HeaderValue::from_static("bytes=0-1"));
+        assert!(get("/a.webp", &range).is_none());
+
+        let stats = optimizer.stats();
+        assert_eq!((stats.memory_hits, stats.memory_misses), (1, 2));
+    }
+}
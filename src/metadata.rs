@@ -0,0 +1,190 @@
+use image::DynamicImage;
+use std::path::Path;
+
+/// Which metadata of the source is kept in optimized images.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum MetadataPolicy {
+    /// Remove all metadata, including GPS coordinates.
+    #[default]
+    Strip,
+    /// Copy the listed EXIF tags into WebP and JPEG outputs, everything else is removed.
+    ///
+    /// AVIF and other outputs are always stripped, the encoders can't embed EXIF.
+    Preserve(Vec<MetadataTag>),
+}
+
+impl MetadataPolicy {
+    /// Preserves copyright and artist attribution.
+    pub fn attribution() -> Self {
+        Self::Preserve(vec![MetadataTag::Copyright, MetadataTag::Artist])
+    }
+}
+
+/// EXIF tags that can be preserved.
+///
+/// Location and orientation tags are deliberately not listed, GPS coordinates
+/// never leave the server and images are stored upright.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MetadataTag {
+    /// Copyright notice.
+    Copyright,
+    /// Creator of the image.
+    Artist,
+    /// Title or caption.
+    ImageDescription,
+    /// Camera manufacturer.
+    Make,
+    /// Camera model.
+    Model,
+    /// When the photo was taken.
+    DateTimeOriginal,
+}
+
+impl MetadataTag {
+    fn exif_tag(self) -> exif::Tag {
+        match self {
+            Self::Copyright => exif::Tag::Copyright,
+            Self::Artist => exif::Tag::Artist,
+            Self::ImageDescription => exif::Tag::ImageDescription,
+            Self::Make => exif::Tag::Make,
+            Self::Model => exif::Tag::Model,
+            Self::DateTimeOriginal => exif::Tag::DateTimeOriginal,
+        }
+    }
+}
+
+/// Applies the metadata policy to encoded bytes.
+///
+/// The encoders never write metadata, so stripping is a no-op. Preserved tags are
+/// read from the source and spliced into the output container.
+pub(crate) fn apply_metadata_policy(
+    bytes: Vec<u8>,
+    source_path: &Path,
+    img: &DynamicImage,
+    policy: &MetadataPolicy,
+) -> Vec<u8> {
+    let MetadataPolicy::Preserve(tags) = policy else {
+        return bytes;
+    };
+    let Some(tiff) = read_exif(source_path, tags) else {
+        return bytes;
+    };
+
+    if bytes.starts_with(&[0xFF, 0xD8]) {
+        insert_jpeg_exif(bytes, &tiff)
+    } else if bytes.len() >= 12 && &bytes[..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+        insert_webp_exif(bytes, &tiff, img)
+    } else {
+        bytes
+    }
+}
+
+/// Reads the whitelisted tags from the source, serialized as a TIFF structure.
+fn read_exif(source_path: &Path, tags: &[MetadataTag]) -> Option<Vec<u8>> {
+    let file = std::fs::File::open(source_path).ok()?;
+    let source = exif::Reader::new()
+        .read_from_container(&mut std::io::BufReader::new(file))
+        .ok()?;
+
+    let fields: Vec<&exif::Field> = source
+        .fields()
+        .filter(|field| field.ifd_num == exif::In::PRIMARY)
+        .filter(|field| tags.iter().any(|tag| tag.exif_tag() == field.tag))
+        .collect();
+    if fields.is_empty() {
+        return None;
+    }
+
+    let mut writer = exif::experimental::Writer::new();
+    for field in fields {
+        writer.push_field(field);
+    }
+    let mut tiff = std::io::Cursor::new(Vec::new());
+    match writer.write(&mut tiff, source.little_endian()) {
+        Ok(()) => Some(tiff.into_inner()),
+        Err(e) => {
+            tracing::warn!("Failed to copy EXIF of {}: {e}", source_path.display());
+            None
+        }
+    }
+}
+
+/// Inserts an APP1 segment right after the SOI marker.
+pub(crate) fn insert_jpeg_exif(bytes: Vec<u8>, tiff: &[u8]) -> Vec<u8> {
+    const EXIF_HEADER: &[u8] = b"Exif\0\0";
+
+    let length = EXIF_HEADER.len() + tiff.len() + 2;
+    let Ok(length) = u16::try_from(length) else {
+        return bytes;
+    };
+
+    let mut out = Vec::with_capacity(bytes.len() + length as usize + 2);
+    out.extend_from_slice(&bytes[..2]);
+    out.extend_from_slice(&[0xFF, 0xE1]);
+    out.extend_from_slice(&length.to_be_bytes());
+    out.extend_from_slice(EXIF_HEADER);
+    out.extend_from_slice(tiff);
+    out.extend_from_slice(&bytes[2..]);
+    out
+}
+
+/// Adds an `EXIF` chunk, converting simple (VP8/VP8L) files to the extended format.
+fn insert_webp_exif(bytes: Vec<u8>, tiff: &[u8], img: &DynamicImage) -> Vec<u8> {
+    const EXIF_FLAG: u8 = 0x08;
+    const ALPHA_FLAG: u8 = 0x10;
+
+    let mut out = b"RIFF\0\0\0\0WEBP".to_vec();
+    let chunks = &bytes[12..];
+
+    if chunks.starts_with(b"VP8X") {
+        out.extend_from_slice(chunks);
+        out[20] |= EXIF_FLAG;
+    } else {
+        let mut flags = EXIF_FLAG;
+        if img.color().has_alpha() && chunks.starts_with(b"VP8L") {
+            flags |= ALPHA_FLAG;
+        }
+        let mut header = [0u8; 10];
+        header[0] = flags;
+        header[4..7].copy_from_slice(&(img.width() - 1).to_le_bytes()[..3]);
+        header[7..10].copy_from_slice(&(img.height() - 1).to_le_bytes()[..3]);
+        push_chunk(&mut out, b"VP8X", &header);
+        out.extend_from_slice(chunks);
+    }
+    push_chunk(&mut out, b"EXIF", tiff);
+
+    let riff_size = (out.len() - 8) as u32;
+    out[4..8].copy_from_slice(&riff_size.to_le_bytes());
+    out
+}
+
+fn push_chunk(out: &mut Vec<u8>, fourcc: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(fourcc);
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out.extend_from_slice(data);
+    // Chunks are padded to an even size.
+    if data.len() % 2 == 1 {
+        out.push(0);
+    }
+}
+
+#[cfg(test)]
+mod metadata_tests {
+    use super::*;
+
+    #[test]
+    fn webp_exif_chunk() {
+        let img = DynamicImage::ImageRgb8(image::RgbImage::new(300, 200));
+        let webp = webp::Encoder::from_image(&img).unwrap().encode(75.0).to_vec();
+        let tiff = b"II*\0\x08\0\0\0\0\0\0\0\0";
+
+        let out = insert_webp_exif(webp, tiff, &img);
+        assert_eq!(&out[12..16], b"VP8X");
+        assert_eq!(out[20] & 0x08, 0x08);
+        assert_eq!(u32::from_le_bytes(out[4..8].try_into().unwrap()) as usize, out.len() - 8);
+        assert!(out.windows(4).any(|w| w == b"EXIF"));
+        // Still decodes, with the canvas size taken from the image.
+        let decoded = webp::Decoder::new(&out).decode().unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (300, 200));
+    }
+}
@@ -0,0 +1,137 @@
+use crate::optimizer::{
+    collect_files, create_nested_if_needed, is_cache_entry, remove_empty_dirs,
+    remove_precompressed, sibling_path, CachedImage, ImageOptimizer, PRECOMPRESSED_EXTENSIONS,
+    SIDECAR_EXTENSION,
+};
+use std::path::{Path, PathBuf};
+
+/// What [`ImageOptimizer::migrate_cache`] did to the cache.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MigrationReport {
+    /// Entries moved to their path under the current key.
+    pub migrated: usize,
+    /// Entries already at their path.
+    pub unchanged: usize,
+    /// Entries left where they were, with the reason. They are encoded again when
+    /// requested, and can be deleted.
+    pub failed: Vec<(PathBuf, String)>,
+}
+
+impl ImageOptimizer {
+    /// Moves every cached file to its path under the current [`crate::ServerCacheKey`],
+    /// so upgrades and key changes don't encode the whole cache again. Reads the
+    /// options from the `.qs` sidecars, or from the path for caches written before
+    /// hashed paths. Safe to run again, entries already in place are left alone.
+    ///
+    /// Run it before serving, e.g. with `leptos-image migrate <site-root>`. Only fails
+    /// if the cache directory can't be listed, entries that can't be moved are reported.
+    ///
+    /// ```no_run
+    /// # use leptos_image::*;
+    /// let optimizer = ImageOptimizer::new("/__cache/image", "./target/site", 1);
+    /// let report = optimizer.migrate_cache().unwrap();
+    /// for (path, reason) in &report.failed {
+    ///     eprintln!("Couldn't migrate {}: {reason}", path.display());
+    /// }
+    /// ```
+    pub fn migrate_cache(&self) -> std::io::Result<MigrationReport> {
+        let mut files = Vec::new();
+        collect_files(&self.cache_dir, &mut files)?;
+
+        let mut report = MigrationReport::default();
+        for file in files.into_iter().filter(|file| is_cache_entry(file)) {
+            let Some(image) = CachedImage::from_file_path(&file.to_string_lossy()) else {
+                let reason = "No sidecar, and not a legacy path".to_string();
+                report.failed.push((file, reason));
+                continue;
+            };
+            let destination = self.cache_file(self.cache_path(&image));
+            if destination == file {
+                report.unchanged += 1;
+                continue;
+            }
+            match self.move_entry(&image, &file, &destination) {
+                Ok(()) => report.migrated += 1,
+                Err(e) => report.failed.push((file, e.to_string())),
+            }
+        }
+        remove_empty_dirs(&self.cache_dir);
+        tracing::info!(
+            "Migrated {} cached images, {} already in place, {} failed",
+            report.migrated,
+            report.unchanged,
+            report.failed.len()
+        );
+        Ok(report)
+    }
+
+    // Moves a cache entry with its sidecar and precompressed siblings. Entries encoded
+    // again at the destination since win.
+    fn move_entry(&self, image: &CachedImage, from: &Path, to: &Path) -> std::io::Result<()> {
+        let sidecar = from.with_extension(SIDECAR_EXTENSION);
+        if to.exists() {
+            remove_precompressed(from);
+            std::fs::remove_file(from)?;
+        } else {
+            create_nested_if_needed(to)?;
+            std::fs::rename(from, to)?;
+            for extension in PRECOMPRESSED_EXTENSIONS {
+                let sibling = sibling_path(from, extension);
+                if sibling.exists() {
+                    std::fs::rename(sibling, sibling_path(to, extension))?;
+                }
+            }
+            image.write_sidecar(to)?;
+        }
+        if sidecar != to.with_extension(SIDECAR_EXTENSION) {
+            let _ = std::fs::remove_file(sidecar);
+        }
+
+        #[cfg(feature = "sqlite")]
+        if let Some(index) = &self.index {
+            let relative = from.strip_prefix(&self.cache_dir).unwrap_or(from);
+            let old = Path::new(crate::optimizer::CACHE_DIR).join(relative);
+            let old = old.to_string_lossy().to_string();
+            let bytes = std::fs::metadata(to)?.len();
+            index.remove(&old).map_err(std::io::Error::other)?;
+            index
+                .record(image, &self.cache_path(image), bytes, None)
+                .map_err(std::io::Error::other)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod migrate_tests {
+    use super::*;
+    use crate::test_support::test_root;
+    use crate::{PathCacheKey, Resize};
+
+    #[test]
+    fn rekeys_entries() {
+        let (_, optimizer) = test_root("leptos_image_migrate");
+        let image = CachedImage::new("/ferris.png", Resize::default());
+
+        // Written by an older version, at a path no key maps to.
+        let old = optimizer.cache_dir.join("old/entry.webp");
+        std::fs::create_dir_all(old.parent().unwrap()).unwrap();
+        std::fs::write(&old, b"webp").unwrap();
+        image.write_sidecar(&old).unwrap();
+        let unknown = optimizer.cache_dir.join("unknown.webp");
+        std::fs::write(&unknown, b"webp").unwrap();
+
+        let report = optimizer.migrate_cache().unwrap();
+        assert_eq!(report.migrated, 1);
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(report.failed[0].0, unknown);
+        let migrated = PathBuf::from(optimizer.get_file_path_from_root(&image));
+        assert_eq!(std::fs::read(&migrated).unwrap(), b"webp");
+        assert!(!old.exists());
+
+        // Path keys name files like the default.
+        let optimizer = optimizer.with_cache_key(PathCacheKey);
+        let report = optimizer.migrate_cache().unwrap();
+        assert_eq!((report.migrated, report.unchanged), (0, 1));
+    }
+}
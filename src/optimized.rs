@@ -0,0 +1,212 @@
+use crate::image::{layout_size, DEFAULT_MAX_WIDTH};
+use crate::optimizer::{OutputFormat, Placeholder};
+use crate::responsive::SetContext;
+#[cfg(feature = "ssr")]
+use crate::{image::placeholder_image, optimizer::is_svg};
+use leptos::prelude::*;
+
+/// Props of an image built with [`use_optimized_image`], like those of `<Image/>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageOptions {
+    /// Width of the final image, from the source's aspect ratio when omitted.
+    pub width: Option<u32>,
+    /// Height of the final image, from the source's aspect ratio when omitted.
+    pub height: Option<u32>,
+    /// Image quality (0-100).
+    pub quality: u8,
+    /// Output format of the optimized image.
+    pub format: OutputFormat,
+    /// Whether to resolve a placeholder.
+    pub blur: bool,
+    /// Kind of the placeholder.
+    pub placeholder: Placeholder,
+    /// Widths of the `srcset`, the final width and twice it when empty.
+    pub widths: Vec<u32>,
+}
+
+impl Default for ImageOptions {
+    /// Quality 75 with a blur placeholder, the defaults of `<Image/>`.
+    fn default() -> Self {
+        Self {
+            width: None,
+            height: None,
+            quality: 75,
+            format: OutputFormat::default(),
+            blur: true,
+            placeholder: Placeholder::Blur,
+            widths: Vec::new(),
+        }
+    }
+}
+
+/// The URLs of an optimized image, see [`use_optimized_image`]. Each is `None`
+/// until the image context is loaded.
+#[derive(Debug, Clone, Copy)]
+pub struct OptimizedImage {
+    /// URL of the image at its final size, for `src`.
+    pub url: Signal<Option<String>>,
+    /// Placeholder shown until the image loads, see [`use_optimized_image`].
+    pub placeholder: Signal<Option<String>>,
+    /// `srcset` attribute, each URL with its width.
+    pub srcset: Signal<Option<String>>,
+}
+
+// Everything derived from the image context and the source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Resolved {
+    url: String,
+    placeholder: Option<String>,
+    srcset: String,
+}
+
+/// The URLs `<Image/>` would use for `src`, for building your own markup: cards,
+/// lightboxes or canvas textures. Images are encoded by the cache handler when
+/// requested, and share their cached files with `<Image/>`s of the same props.
+///
+/// The placeholder is a `data:` URI once it is held in memory and its URL before,
+/// for `<img src>` or `background-image: url(...)`. Gradients are CSS for
+/// `background-image`, `None` until they're held. No placeholder comes with SVGs,
+/// `blur: false`, or sources whose policy disables them.
+///
+/// Reads the image context and the tenant like `<Image/>`, so call it below
+/// [`crate::provide_image_context`]. The source's size and policy are read on the
+/// server, so wrap the markup in a `<Suspense>` to hydrate the same URLs.
+///
+/// ```
+/// use leptos::prelude::*;
+/// use leptos_image::*;
+///
+/// #[component]
+/// fn Card() -> impl IntoView {
+///     let options = ImageOptions { width: Some(400), height: Some(300), ..Default::default() };
+///     let OptimizedImage { url, placeholder, srcset } =
+///         use_optimized_image("/card.jpg", options);
+///     view! {
+///         <Suspense>
+///             <div class="card" style=move || {
+///                 let placeholder = placeholder.get().unwrap_or_default();
+///                 format!("background-image: url({placeholder})")
+///             }>
+///                 <img src=move || url.get() srcset=move || srcset.get() alt="Card" />
+///             </div>
+///         </Suspense>
+///     }
+/// }
+/// ```
+pub fn use_optimized_image(src: impl Into<String>, options: ImageOptions) -> OptimizedImage {
+    let src = src.into();
+    let tenant = use_context::<crate::ImageTenant>().map(|tenant| tenant.0);
+    let config = crate::use_image_cache_resource();
+
+    #[cfg(feature = "ssr")]
+    let optimizer = crate::provider::optimizer_context();
+    // Resolved on the server and serialized, so hydration sees the same size.
+    let source = {
+        #[cfg(feature = "ssr")]
+        let (optimizer, src) = (optimizer.clone(), src.clone());
+        Resource::new(|| (), move |_| {
+            #[cfg(feature = "ssr")]
+            let source = optimizer.as_ref().map_or((None, true), |optimizer| {
+                (optimizer.intrinsic_size(&src), optimizer.allows_placeholders(&src))
+            });
+            #[cfg(not(feature = "ssr"))]
+            let source = (None, true);
+            async move { source }
+        })
+    };
+
+    let resolved = Memo::new(move |_| {
+        let (config, (intrinsic, allowed)) = config.get().zip(source.get())?;
+        let context = SetContext::from_config(
+            config,
+            &src,
+            tenant.clone(),
+            intrinsic,
+            options.placeholder,
+        );
+        let resolved = resolve(&context, &src, &options, allowed);
+        // A gradient has no URL, create it for the next render.
+        #[cfg(feature = "ssr")]
+        {
+            let missing = resolved.placeholder.is_none() && allowed && options.blur;
+            if missing && options.placeholder == Placeholder::Gradient && !is_svg(&src) {
+                if let Some(optimizer) = &optimizer {
+                    let image = placeholder_image(&src, tenant.clone(), Placeholder::Gradient);
+                    optimizer.spawn_placeholder(image);
+                }
+            }
+        }
+        Some(resolved)
+    });
+
+    OptimizedImage {
+        url: Signal::derive(move || {
+            resolved.with(|image| image.as_ref().map(|image| image.url.clone()))
+        }),
+        placeholder: Signal::derive(move || {
+            resolved.with(|image| image.as_ref().and_then(|image| image.placeholder.clone()))
+        }),
+        srcset: Signal::derive(move || {
+            resolved.with(|image| image.as_ref().map(|image| image.srcset.clone()))
+        }),
+    }
+}
+
+fn resolve(context: &SetContext, src: &str, options: &ImageOptions, allowed: bool) -> Resolved {
+    let size = layout_size(options.width, options.height, context.intrinsic(), DEFAULT_MAX_WIDTH);
+    let widths = if options.widths.is_empty() {
+        vec![size.0, size.0.saturating_mul(2)]
+    } else {
+        options.widths.clone()
+    };
+    let srcset = context
+        .urls(src, widths, options.quality, options.format, Some(size))
+        .iter()
+        .map(|(width, url)| format!("{url} {width}w"))
+        .collect::<Vec<_>>()
+        .join(", ");
+    Resolved {
+        url: context.url(src, size, options.quality, options.format),
+        placeholder: (options.blur && allowed)
+            .then(|| context.placeholder(src))
+            .flatten(),
+        srcset,
+    }
+}
+
+#[cfg(test)]
+mod optimized_tests {
+    use super::*;
+
+    #[test]
+    fn urls_of_options() {
+        let options = ImageOptions {
+            width: Some(400),
+            height: Some(300),
+            ..Default::default()
+        };
+        let config = crate::provider::ImageConfig::default();
+        let context = SetContext::from_config(config, "/card.jpg", None, None, Placeholder::Blur);
+        let image = resolve(&context, "/card.jpg", &options, true);
+
+        let widths: Vec<&str> = image
+            .srcset
+            .split(", ")
+            .map(|entry| entry.rsplit(' ').next().unwrap())
+            .collect();
+        assert_eq!(widths, ["400w", "800w"]);
+        assert!(image.srcset.starts_with(&format!("{} ", image.url)));
+        assert!(image.placeholder.is_some());
+
+        // Gradients have no URL before they're held.
+        let gradient = SetContext::from_config(
+            crate::provider::ImageConfig::default(),
+            "/card.jpg",
+            None,
+            None,
+            Placeholder::Gradient,
+        );
+        assert_eq!(resolve(&gradient, "/card.jpg", &options, true).placeholder, None);
+        assert_eq!(resolve(&context, "/card.jpg", &options, false).placeholder, None);
+    }
+}
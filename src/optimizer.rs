@@ -6,8 +6,342 @@ use serde::{Deserialize, Serialize};
 pub struct ImageOptimizer {
     pub(crate) api_handler_path: String,
     pub(crate) root_file_path: String,
+    pub(crate) cache_dir: std::path::PathBuf,
+    pub(crate) asset_prefix: String,
     pub(crate) semaphore: std::sync::Arc<tokio::sync::Semaphore>,
+    pub(crate) parallelism: usize,
+    pub(crate) blur_semaphore: std::sync::Arc<tokio::sync::Semaphore>,
     pub(crate) cache: std::sync::Arc<dashmap::DashMap<CachedImage, String>>,
+    pub(crate) missing: std::sync::Arc<dashmap::DashMap<String, std::time::Instant>>,
+    pub(crate) missing_ttl: std::time::Duration,
+    pub(crate) lifecycle: std::sync::Arc<Lifecycle>,
+    pub(crate) dev_mode: bool,
+    pub(crate) max_age: Option<std::time::Duration>,
+    pub(crate) revalidate_sources: bool,
+    pub(crate) metrics: std::sync::Arc<crate::stats::Metrics>,
+    pub(crate) handler: crate::routes::HandlerConfig,
+    pub(crate) on_encode_error: EncodeErrorPolicy,
+    #[cfg(feature = "sqlite")]
+    pub(crate) index: Option<crate::index::CacheIndex>,
+    pub(crate) mounts: Vec<Mount>,
+    pub(crate) policies: Vec<(String, crate::policy::SourcePolicy)>,
+    pub(crate) presets: std::collections::HashMap<String, crate::preset::Preset>,
+    pub(crate) tenant_quota: Option<TenantQuota>,
+    pub(crate) tenant_usage: std::sync::Arc<dashmap::DashMap<String, u64>>,
+    pub(crate) inline_limit: u64,
+    pub(crate) context_placeholders: bool,
+    pub(crate) read_only: Option<ReadOnlyFallback>,
+    #[cfg(feature = "memory-cache")]
+    pub(crate) memory: Option<crate::memory::MemoryCache>,
+    pub(crate) width_ladder: Vec<u32>,
+    pub(crate) speculative: std::sync::Arc<tokio::sync::Semaphore>,
+    pub(crate) pipeline: Pipeline,
+    pub(crate) content_profiles: std::sync::Arc<
+        dashmap::DashMap<(String, std::time::SystemTime), crate::content::ContentProfile>,
+    >,
+    pub(crate) in_flight: std::sync::Arc<dashmap::DashMap<CachedImage, std::sync::Arc<InFlight>>>,
+    pub(crate) fair_queue: Option<std::sync::Arc<crate::fair::FairQueue>>,
+    pub(crate) encode_timeout: Option<std::time::Duration>,
+    pub(crate) memory_budget: Option<std::sync::Arc<crate::budget::MemoryBudget>>,
+    #[cfg(feature = "thread-pool")]
+    pub(crate) encode_pool: Option<std::sync::Arc<rayon::ThreadPool>>,
+    pub(crate) cache_key: std::sync::Arc<dyn crate::ServerCacheKey>,
+    pub(crate) url_key: std::sync::Arc<dyn crate::CacheKey>,
+    pub(crate) hooks: std::sync::Arc<crate::events::Hooks>,
+}
+
+/// Decides whether a tenant may cache another image, given its current disk usage.
+#[cfg(feature = "ssr")]
+#[derive(Clone)]
+pub(crate) struct TenantQuota(std::sync::Arc<dyn Fn(&str, u64) -> bool + Send + Sync>);
+
+#[cfg(feature = "ssr")]
+impl std::fmt::Debug for TenantQuota {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("TenantQuota").finish()
+    }
+}
+
+/// A source directory served under a URL prefix, see [`ImageOptimizer::with_mount`].
+#[cfg(feature = "ssr")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Mount {
+    /// Without leading or trailing slashes, e.g. `uploads`.
+    pub(crate) prefix: String,
+    pub(crate) dir: std::path::PathBuf,
+}
+
+/// What the cache route responds with when a source fails to decode or encode.
+#[cfg(feature = "ssr")]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum EncodeErrorPolicy {
+    /// Respond with the status of the [`crate::HandlerConfig`], 500 by default.
+    #[default]
+    Error,
+    /// Serve the unoptimized source image.
+    ServeOriginal,
+    /// Respond with 404, so the browser shows the `alt` text.
+    Error404,
+    /// Serve this image instead, a path relative to the site root.
+    FallbackImage(String),
+}
+
+/// What the cache route serves in read-only mode for images that weren't pre-generated,
+/// see [`ImageOptimizer::with_read_only`].
+#[cfg(feature = "ssr")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReadOnlyFallback {
+    /// Redirect to the unoptimized source image.
+    RedirectToOriginal,
+    /// Respond with 404, so the browser shows the `alt` text.
+    NotFound,
+    /// Encode in memory and serve without caching, for sources up to `max_source_bytes`.
+    /// Larger sources are redirected to the original.
+    EncodeInMemory {
+        /// Largest source file that is encoded, in bytes.
+        max_source_bytes: u64,
+    },
+}
+
+#[cfg(feature = "ssr")]
+impl CreateImageError {
+    /// Whether the error is caused by the source or the codecs, as opposed to the request.
+    pub(crate) fn is_encode_failure(&self) -> bool {
+        match self {
+            // Oversized sources are rejected rather than served unoptimized.
+            Self::ImageError(image::ImageError::Limits(_)) => false,
+            Self::ImageError(_) | Self::JoinError(_) => true,
+            Self::EncodeError(_) | Self::DecodeError(_) | Self::Timeout(_) => true,
+            _ => false,
+        }
+    }
+
+    // The error of a shared encode outcome for one of its requests. Errors that can't
+    // be cloned are rebuilt with the same status code and message.
+    fn from_shared(error: std::sync::Arc<Self>) -> Self {
+        let error = match std::sync::Arc::try_unwrap(error) {
+            Ok(error) => return error,
+            Err(error) => error,
+        };
+        match &*error {
+            Self::ImageError(image::ImageError::Limits(limits)) => Self::ImageError(
+                image::ImageError::Limits(image::error::LimitError::from_kind(limits.kind())),
+            ),
+            Self::ImageError(image::ImageError::Decoding(e)) => Self::DecodeError(e.to_string()),
+            Self::ImageError(e) => Self::EncodeError(e.to_string()),
+            Self::JoinError(e) => Self::EncodeError(e.to_string()),
+            Self::IOError(e) => Self::IOError(std::io::Error::new(e.kind(), e.to_string())),
+            Self::EncodeError(message) => Self::EncodeError(message.clone()),
+            Self::DecodeError(message) => Self::DecodeError(message.clone()),
+            Self::SourceNotFound(src) => Self::SourceNotFound(src.clone()),
+            Self::UnsupportedFormat(src) => Self::UnsupportedFormat(src.clone()),
+            Self::Forbidden(src) => Self::Forbidden(src.clone()),
+            Self::InvalidParams(message) => Self::InvalidParams(message.clone()),
+            Self::QuotaExceeded(tenant) => Self::QuotaExceeded(tenant.clone()),
+            Self::ReadOnly(src) => Self::ReadOnly(src.clone()),
+            Self::ShuttingDown => Self::ShuttingDown,
+            Self::Cancelled => Self::Cancelled,
+            Self::MemoryBudgetExceeded(bytes) => Self::MemoryBudgetExceeded(*bytes),
+            Self::Timeout(timeout) => Self::Timeout(*timeout),
+        }
+    }
+}
+
+/// What creating an image did.
+#[cfg(feature = "ssr")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Creation {
+    /// The image was already cached.
+    Cached,
+    /// The image was created, with its bytes if it was encoded rather than copied from
+    /// its source or a duplicate, so the first response doesn't read them back.
+    Encoded(Option<axum::body::Bytes>),
+}
+
+#[cfg(feature = "ssr")]
+impl Creation {
+    pub(crate) fn is_encoded(&self) -> bool {
+        matches!(self, Self::Encoded(_))
+    }
+}
+
+/// Tracks running encodes for graceful shutdown.
+#[cfg(feature = "ssr")]
+#[derive(Debug)]
+pub(crate) struct Lifecycle {
+    accepting: std::sync::atomic::AtomicBool,
+    active: std::sync::atomic::AtomicUsize,
+    idle: tokio::sync::Notify,
+}
+
+#[cfg(feature = "ssr")]
+impl Default for Lifecycle {
+    fn default() -> Self {
+        Self {
+            accepting: std::sync::atomic::AtomicBool::new(true),
+            active: Default::default(),
+            idle: Default::default(),
+        }
+    }
+}
+
+#[cfg(feature = "ssr")]
+impl Lifecycle {
+    fn is_accepting(&self) -> bool {
+        self.accepting.load(std::sync::atomic::Ordering::SeqCst)
+    }
+
+    pub(crate) fn active(&self) -> usize {
+        self.active.load(std::sync::atomic::Ordering::SeqCst)
+    }
+
+    fn start(self: &std::sync::Arc<Self>) -> ActiveEncode {
+        self.active.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        ActiveEncode(self.clone())
+    }
+}
+
+/// Held by a running encode, counts it as in flight until dropped.
+#[cfg(feature = "ssr")]
+struct ActiveEncode(std::sync::Arc<Lifecycle>);
+
+#[cfg(feature = "ssr")]
+impl Drop for ActiveEncode {
+    fn drop(&mut self) {
+        if self.0.active.fetch_sub(1, std::sync::atomic::Ordering::SeqCst) == 1 {
+            self.0.idle.notify_waiters();
+        }
+    }
+}
+
+/// Outcome of an encode, shared by every request waiting for it.
+#[cfg(feature = "ssr")]
+pub(crate) type SharedOutcome = Result<Creation, std::sync::Arc<CreateImageError>>;
+
+/// An encode shared by the concurrent requests for an image.
+#[cfg(feature = "ssr")]
+#[derive(Debug)]
+pub(crate) struct InFlight {
+    waiters: std::sync::atomic::AtomicUsize,
+    // Set once every request went away, the encode stops at its next checkpoint.
+    abandoned: std::sync::Arc<std::sync::atomic::AtomicBool>,
+    // Kept once set, so requests joining after the encode finished still get it.
+    outcome: tokio::sync::watch::Sender<Option<SharedOutcome>>,
+}
+
+#[cfg(feature = "ssr")]
+impl Default for InFlight {
+    fn default() -> Self {
+        Self {
+            waiters: Default::default(),
+            abandoned: Default::default(),
+            outcome: tokio::sync::watch::channel(None).0,
+        }
+    }
+}
+
+#[cfg(feature = "ssr")]
+impl InFlight {
+    fn join(self: &std::sync::Arc<Self>) -> FlightWaiter {
+        self.waiters.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        FlightWaiter(self.clone())
+    }
+
+    fn is_abandoned(&self) -> bool {
+        self.abandoned.load(std::sync::atomic::Ordering::SeqCst)
+    }
+
+    // Waits for the encode to finish, or returns its outcome if it already has.
+    async fn outcome(&self) -> SharedOutcome {
+        let mut outcome = self.outcome.subscribe();
+        let outcome = outcome
+            .wait_for(Option::is_some)
+            .await
+            .expect("The sender lives as long as the flight");
+        outcome.clone().expect("Waited for an outcome")
+    }
+}
+
+/// Held by a request waiting on an [`InFlight`] encode, abandons it when the last
+/// one is dropped.
+#[cfg(feature = "ssr")]
+struct FlightWaiter(std::sync::Arc<InFlight>);
+
+#[cfg(feature = "ssr")]
+impl Drop for FlightWaiter {
+    fn drop(&mut self) {
+        if self.0.waiters.fetch_sub(1, std::sync::atomic::Ordering::SeqCst) == 1 {
+            self.0.abandoned.store(true, std::sync::atomic::Ordering::SeqCst);
+        }
+    }
+}
+
+/// Held by the task running an [`InFlight`] encode, removes it when dropped and
+/// fails its waiters if the encode panicked.
+#[cfg(feature = "ssr")]
+struct FlightDone {
+    in_flight: std::sync::Arc<dashmap::DashMap<CachedImage, std::sync::Arc<InFlight>>>,
+    image: CachedImage,
+    flight: std::sync::Arc<InFlight>,
+}
+
+#[cfg(feature = "ssr")]
+impl Drop for FlightDone {
+    fn drop(&mut self) {
+        self.flight.outcome.send_if_modified(|outcome| {
+            let panicked = outcome.is_none();
+            if panicked {
+                let error = CreateImageError::EncodeError("The encode task panicked".to_string());
+                *outcome = Some(Err(std::sync::Arc::new(error)));
+            }
+            panicked
+        });
+        self.in_flight
+            .remove_if(&self.image, |_, flight| std::sync::Arc::ptr_eq(flight, &self.flight));
+    }
+}
+
+/// Settings used by the blocking encode step.
+#[cfg(feature = "ssr")]
+#[derive(Debug, Clone)]
+pub(crate) struct Pipeline {
+    pub(crate) encoder: std::sync::Arc<dyn crate::encoder::ImageEncoder>,
+    pub(crate) tone_mapping: crate::decode::ToneMapping,
+    pub(crate) icc: crate::decode::IccPolicy,
+    pub(crate) metadata: crate::metadata::MetadataPolicy,
+    pub(crate) orientation: crate::decode::AutoOrient,
+    pub(crate) decoded: crate::decode::DecodeCache,
+    pub(crate) deterministic: bool,
+    pub(crate) max_source_pixels: u64,
+    pub(crate) abandoned: std::sync::Arc<std::sync::atomic::AtomicBool>,
+}
+
+#[cfg(feature = "ssr")]
+impl Pipeline {
+    // Stops an encode no request is waiting for anymore.
+    fn check_abandoned(&self) -> Result<(), CreateImageError> {
+        if self.abandoned.load(std::sync::atomic::Ordering::SeqCst) {
+            return Err(CreateImageError::Cancelled);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "ssr")]
+impl Default for Pipeline {
+    fn default() -> Self {
+        Self {
+            encoder: std::sync::Arc::new(crate::encoder::DefaultEncoder),
+            tone_mapping: Default::default(),
+            icc: Default::default(),
+            metadata: Default::default(),
+            orientation: Default::default(),
+            decoded: Default::default(),
+            deterministic: false,
+            max_source_pixels: crate::decode::DEFAULT_MAX_SOURCE_PIXELS,
+            abandoned: Default::default(),
+        }
+    }
 }
 
 #[cfg(feature = "ssr")]
@@ -16,6 +350,8 @@ impl ImageOptimizer {
     /// api_handler_path is the path where the image handler is located in the server router.
     /// Parallelism denotes the number of images that can be created at once.
     /// Useful to limit to prevent overloading the server.
+    /// Blur placeholders have their own pool of the same size, so they are never
+    /// queued behind full size encodes, see [`ImageOptimizer::with_blur_parallelism`].
     pub fn new(
         api_handler_path: impl Into<String>,
         root_file_path: impl Into<String>,
@@ -23,12 +359,509 @@ impl ImageOptimizer {
     ) -> Self {
         let semaphore = tokio::sync::Semaphore::new(parallelism);
         let semaphore = std::sync::Arc::new(semaphore);
+        let blur_semaphore = std::sync::Arc::new(tokio::sync::Semaphore::new(parallelism));
+        let root_file_path = root_file_path.into();
         Self {
             api_handler_path: api_handler_path.into(),
-            root_file_path: root_file_path.into(),
+            cache_dir: std::path::Path::new(&root_file_path).join(CACHE_DIR),
+            root_file_path,
+            asset_prefix: String::new(),
             semaphore,
+            parallelism,
+            blur_semaphore,
             cache: std::sync::Arc::new(dashmap::DashMap::new()),
+            missing: std::sync::Arc::new(dashmap::DashMap::new()),
+            missing_ttl: std::time::Duration::from_secs(30),
+            lifecycle: Default::default(),
+            dev_mode: false,
+            max_age: None,
+            revalidate_sources: false,
+            metrics: Default::default(),
+            handler: Default::default(),
+            on_encode_error: Default::default(),
+            #[cfg(feature = "sqlite")]
+            index: None,
+            mounts: Vec::new(),
+            policies: Vec::new(),
+            presets: crate::preset::default_presets(),
+            tenant_quota: None,
+            tenant_usage: Default::default(),
+            inline_limit: 4096,
+            context_placeholders: true,
+            read_only: None,
+            #[cfg(feature = "memory-cache")]
+            memory: None,
+            width_ladder: Vec::new(),
+            speculative: std::sync::Arc::new(tokio::sync::Semaphore::new(1)),
+            pipeline: Pipeline::default(),
+            content_profiles: Default::default(),
+            in_flight: Default::default(),
+            fair_queue: None,
+            encode_timeout: None,
+            memory_budget: None,
+            #[cfg(feature = "thread-pool")]
+            encode_pool: None,
+            cache_key: std::sync::Arc::new(crate::DefaultCacheKey),
+            url_key: std::sync::Arc::new(crate::DefaultCacheKey),
+            hooks: Default::default(),
+        }
+    }
+
+    /// Sets the number of blur placeholders that can be created at once.
+    pub fn with_blur_parallelism(mut self, parallelism: usize) -> Self {
+        self.blur_semaphore = std::sync::Arc::new(tokio::sync::Semaphore::new(parallelism));
+        self
+    }
+
+    /// Widths to pre-generate once an image is encoded at any width, e.g. the widths of
+    /// your responsive `srcset`s. The siblings keep the aspect ratio and other options,
+    /// and are encoded in the background one at a time. Widths above the source's
+    /// are skipped.
+    pub fn with_width_ladder(mut self, widths: impl IntoIterator<Item = u32>) -> Self {
+        self.width_ladder = widths.into_iter().filter(|width| *width > 0).collect();
+        self.width_ladder.sort_unstable();
+        self.width_ladder.dedup();
+        self
+    }
+
+    // Queues the other ladder widths of a freshly encoded image.
+    fn speculate_widths(&self, cache_image: &CachedImage) {
+        let CachedImageOption::Resize(resize) = &cache_image.option else {
+            return;
+        };
+        if self.width_ladder.is_empty() || resize.width == 0 || is_svg(&cache_image.src) {
+            return;
+        }
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        let source_width = self.intrinsic_size(&cache_image.src).map(|(width, _)| width);
+        for &width in &self.width_ladder {
+            if width == resize.width || source_width.is_some_and(|source| width > source) {
+                continue;
+            }
+            let mut sibling = cache_image.clone();
+            if let CachedImageOption::Resize(sibling) = &mut sibling.option {
+                let height = u64::from(resize.height) * u64::from(width) / u64::from(resize.width);
+                sibling.width = width;
+                sibling.height = height.max(1) as u32;
+            }
+            let optimizer = self.clone();
+            runtime.spawn(async move {
+                let _permit = optimizer.speculative.acquire().await;
+                if let Err(e) = optimizer.create_image_once(&sibling).await {
+                    tracing::debug!("Skipped pre-generating {sibling}: {e}");
+                }
+            });
+        }
+    }
+
+    /// Gives up on encodes running longer than `timeout`, e.g. on a pathological source,
+    /// failing them with [`CreateImageError::Timeout`] and freeing their permit.
+    ///
+    /// A blocking decode can't be interrupted: it keeps its thread until the next
+    /// checkpoint, but no longer holds back the queue and never writes its image.
+    /// Timed out encodes are counted in [`crate::CacheStats::encode_timeouts`].
+    pub fn with_encode_timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.encode_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets how long a missing source is remembered before it is looked up again.
+    /// Defaults to 30 seconds, requests for it fail fast with a 404 in the meantime.
+    pub fn with_missing_ttl(mut self, ttl: std::time::Duration) -> Self {
+        self.missing_ttl = ttl;
+        self
+    }
+
+    /// Sets the largest optimized image, in bytes, that `<Image inline=true/>` embeds
+    /// as a `data:` URI. Defaults to 4 KiB, larger images are linked as usual.
+    pub fn with_inline_limit(mut self, max_bytes: u64) -> Self {
+        self.inline_limit = max_bytes;
+        self
+    }
+
+    /// The cached image as a `data:` URI, if it was already created and fits the
+    /// inline limit. Reads from disk synchronously, so it can be used while rendering.
+    pub(crate) fn inline_data_uri(&self, cache_image: &CachedImage) -> Option<String> {
+        use base64::{engine::general_purpose, Engine as _};
+
+        let mut cache_image = cache_image.clone();
+        // The handler negotiates these, a page can rely on WebP.
+        if let CachedImageOption::Resize(resize) = &mut cache_image.option {
+            if resize.format == OutputFormat::Auto {
+                resize.format = OutputFormat::Webp;
+            }
+        }
+        let path = self.get_file_path_from_root(&cache_image);
+        let size = std::fs::metadata(&path).ok()?.len();
+        if size > self.inline_limit {
+            return None;
+        }
+        let bytes = std::fs::read(&path).ok()?;
+        let encoded = general_purpose::STANDARD.encode(bytes);
+        Some(format!("data:{};base64,{encoded}", cache_image.content_type()))
+    }
+
+    /// Dimensions of the upright source, read from the file header without decoding.
+    /// `None` for SVGs and sources that are missing or can't be read.
+    pub fn intrinsic_size(&self, src: &str) -> Option<(u32, u32)> {
+        if is_svg(src) {
+            return None;
+        }
+        let path = self.resolve_source_blocking(src).ok()?;
+        crate::decode::probe_dimensions(&path, &self.pipeline.orientation)
+    }
+
+    /// Creates a placeholder in the background and holds it in memory, for
+    /// placeholders that can't be requested by URL while rendering.
+    pub(crate) fn spawn_placeholder(&self, image: CachedImage) {
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        let optimizer = self.clone();
+        runtime.spawn(async move {
+            match optimizer.create_image(&image).await {
+                Ok(_) => crate::routes::add_file_to_cache(&optimizer, image).await,
+                Err(e) => tracing::warn!("Failed to create placeholder {image}: {e}"),
+            }
+        });
+    }
+
+    /// Forgets that a source was missing, call this after adding the file.
+    pub fn clear_missing(&self, src: &str) {
+        self.missing.remove(src);
+    }
+
+    /// Re-creates cached images whose source was modified after them, instead of
+    /// serving any cached file that exists. Meant for development:
+    ///
+    /// ```
+    /// # use leptos_image::*;
+    /// let optimizer = ImageOptimizer::new("/__cache/image", "./public", 1)
+    ///     .with_dev_mode(cfg!(debug_assertions));
+    /// ```
+    pub fn with_dev_mode(mut self, dev_mode: bool) -> Self {
+        self.dev_mode = dev_mode;
+        self
+    }
+
+    /// Re-creates cached images older than `max_age` on their next request, instead of
+    /// serving them forever. Use it to roll out improved encoder settings, or for
+    /// sources replaced without purging their images.
+    ///
+    /// The age counts from the encode, also for deterministic output that carries the
+    /// modification time of its source. Images in the memory cache are checked too.
+    ///
+    /// ```
+    /// # use leptos_image::*;
+    /// let optimizer = ImageOptimizer::new("/__cache/image", "./target/site", 1)
+    ///     .with_max_age(std::time::Duration::from_secs(30 * 24 * 60 * 60));
+    /// ```
+    pub fn with_max_age(mut self, max_age: std::time::Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Re-creates cached images whose source was modified after them, for sources
+    /// edited in place. Costs a look at both files on every request. Implied by
+    /// [`Self::with_dev_mode`].
+    pub fn with_source_revalidation(mut self, revalidate: bool) -> Self {
+        self.revalidate_sources = revalidate;
+        self
+    }
+
+    // How cached files are checked before they are served, `None` if they are served
+    // as long as they exist.
+    pub(crate) fn freshness(&self) -> Option<Freshness> {
+        let source_changes = self.dev_mode || self.revalidate_sources;
+        (source_changes || self.max_age.is_some()).then_some(Freshness {
+            max_age: self.max_age,
+            source_changes,
+        })
+    }
+
+    /// Resolves sources under the URL prefix `prefix` against `dir` instead of the root.
+    ///
+    /// Use this for images outside the site root, like user uploads. Optimized images
+    /// are still cached under the root.
+    ///
+    /// ```
+    /// # use leptos_image::*;
+    /// let optimizer = ImageOptimizer::new("/__cache/image", "./target/site", 1)
+    ///     .with_mount("/uploads", "./content/uploads")
+    ///     .with_mount("/shared", "/mnt/shared-assets");
+    /// // `<Image src="/uploads/avatar.png"/>` reads ./content/uploads/avatar.png
+    /// ```
+    pub fn with_mount(
+        mut self,
+        prefix: impl AsRef<str>,
+        dir: impl Into<std::path::PathBuf>,
+    ) -> Self {
+        let prefix = prefix.as_ref().trim_matches('/').to_string();
+        self.mounts.retain(|mount| mount.prefix != prefix);
+        self.mounts.push(Mount {
+            prefix,
+            dir: dir.into(),
+        });
+        // Most specific prefix first.
+        self.mounts.sort_by(|a, b| b.prefix.len().cmp(&a.prefix.len()));
+        self
+    }
+
+    // The allowed root of a source and its path below it, without touching the disk.
+    fn source_location(&self, src: &str) -> (std::path::PathBuf, std::path::PathBuf) {
+        let src = src.trim_start_matches('/');
+        for mount in &self.mounts {
+            let rest = match src.strip_prefix(mount.prefix.as_str()) {
+                Some("") => "",
+                Some(rest) if rest.starts_with('/') => rest,
+                _ => continue,
+            };
+            return (mount.dir.clone(), mount.dir.join(rest.trim_start_matches('/')));
+        }
+        let root = std::path::PathBuf::from(&self.root_file_path);
+        (root, path_from_segments(vec![&self.root_file_path, src]))
+    }
+
+    /// Resolves a source to a path inside the root or one of the mounts.
+    ///
+    /// Sources with `..` or absolute components are rejected before the disk is
+    /// touched, symlinks are followed and must not lead outside their root.
+    /// Sources that don't exist resolve to their lexical path.
+    pub(crate) async fn resolve_source(
+        &self,
+        src: &str,
+    ) -> Result<std::path::PathBuf, CreateImageError> {
+        let (root, path) = self.checked_location(src)?;
+        let resolved = match tokio::fs::canonicalize(&path).await {
+            Ok(resolved) => resolved,
+            Err(_) => return Ok(path),
+        };
+        let root = tokio::fs::canonicalize(&root).await.ok();
+        inside_root(src, path, &resolved, root.as_deref())
+    }
+
+    /// Like [`Self::resolve_source`], for synchronous callers such as rendering.
+    pub(crate) fn resolve_source_blocking(
+        &self,
+        src: &str,
+    ) -> Result<std::path::PathBuf, CreateImageError> {
+        let (root, path) = self.checked_location(src)?;
+        let resolved = match std::fs::canonicalize(&path) {
+            Ok(resolved) => resolved,
+            Err(_) => return Ok(path),
+        };
+        let root = std::fs::canonicalize(&root).ok();
+        inside_root(src, path, &resolved, root.as_deref())
+    }
+
+    // The location of a source, rejecting `..` and absolute components.
+    fn checked_location(
+        &self,
+        src: &str,
+    ) -> Result<(std::path::PathBuf, std::path::PathBuf), CreateImageError> {
+        use std::path::Component;
+
+        let relative = std::path::Path::new(src.trim_start_matches(['/', '\\']));
+        let escapes = relative
+            .components()
+            .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir));
+        if escapes || src.contains('\0') {
+            return Err(CreateImageError::Forbidden(src.to_string()));
+        }
+        Ok(self.source_location(src))
+    }
+
+    /// Enforces per-tenant disk quotas.
+    ///
+    /// Called with the tenant and the bytes it uses on disk before an image of that
+    /// tenant is created. Returning `false` rejects the request with
+    /// [`CreateImageError::QuotaExceeded`], already cached images are still served.
+    ///
+    /// ```
+    /// # use leptos_image::*;
+    /// const QUOTA: u64 = 512 * 1024 * 1024;
+    /// let optimizer = ImageOptimizer::new("/__cache/image", "./public", 1)
+    ///     .with_tenant_quota(|_tenant, used| used < QUOTA);
+    /// ```
+    pub fn with_tenant_quota(
+        mut self,
+        quota: impl Fn(&str, u64) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.tenant_quota = Some(TenantQuota(std::sync::Arc::new(quota)));
+        self
+    }
+
+    /// Removes every cached image and placeholder of a tenant.
+    pub fn purge_tenant(&self, tenant: &str) -> std::io::Result<()> {
+        self.cache
+            .retain(|image, _| image.tenant.as_deref() != Some(tenant));
+        self.invalidate_memory();
+        self.tenant_usage.remove(tenant);
+        #[cfg(feature = "sqlite")]
+        if let Some(index) = &self.index {
+            let dir = tenant_dir(tenant);
+            index
+                .remove_dir(&dir.to_string_lossy())
+                .map_err(std::io::Error::other)?;
+        }
+        let dir = self.cache_file(tenant_dir(tenant));
+        let evicted = if self.watches_evictions() {
+            self.cached_images_in(&dir)
+        } else {
+            Vec::new()
+        };
+        match std::fs::remove_dir_all(dir) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+            _ => {
+                self.emit_evicted(evicted);
+                Ok(())
+            }
+        }
+    }
+
+    /// Bytes a tenant uses on disk. Walks the tenant directory the first time,
+    /// then tracks created images.
+    pub fn tenant_disk_usage(&self, tenant: &str) -> u64 {
+        if let Some(used) = self.tenant_usage.get(tenant) {
+            return *used;
         }
+        let stats = self.tenant_stats(tenant);
+        let used = stats.disk.values().map(|format| format.bytes).sum();
+        self.tenant_usage.insert(tenant.to_string(), used);
+        used
+    }
+
+    /// Sets how the cache route reports errors, see [`crate::HandlerConfig`].
+    pub fn with_handler_config(mut self, handler: crate::routes::HandlerConfig) -> Self {
+        self.handler = handler;
+        self
+    }
+
+    /// Never writes to the cache directory, for hosts with a read-only file system.
+    ///
+    /// Only images generated ahead of time, e.g. at build time or by a previous
+    /// deployment, are served from the cache. [`ImageOptimizer::create_image`] fails
+    /// with [`CreateImageError::ReadOnly`] for the others, and the cache route responds
+    /// according to `fallback`.
+    pub fn with_read_only(mut self, fallback: ReadOnlyFallback) -> Self {
+        self.read_only = Some(fallback);
+        self
+    }
+
+    /// Encodes an image in memory, without the cache. Used by read-only mode.
+    pub(crate) async fn encode_in_memory(
+        &self,
+        cache_image: &CachedImage,
+    ) -> Result<Vec<u8>, CreateImageError> {
+        let source_path = self.resolve_source(&cache_image.src).await?;
+        if !crate::decode::is_supported_source(&source_path).await? {
+            return Err(CreateImageError::UnsupportedFormat(cache_image.src.clone()));
+        }
+        let _permit = self.encode_permit(cache_image).await;
+        let memory = self.reserve_memory(&source_path, &cache_image.option).await?;
+        let mut pipeline = self.pipeline.clone();
+        // A flag of its own, so a timeout only stops this encode.
+        pipeline.abandoned = Default::default();
+        let abandoned = pipeline.abandoned.clone();
+        let option = cache_image.option.clone();
+        let task = self.run_encode(move || {
+            // Held until the pixels are freed, even after a timeout.
+            let _memory = memory;
+            encode_optimized_image(&pipeline, option, &source_path)
+        });
+        self.timed_encode(cache_image, &abandoned, task).await??
+    }
+
+    /// Sets what is served when an image fails to decode or encode.
+    pub fn with_encode_error_policy(mut self, on_encode_error: EncodeErrorPolicy) -> Self {
+        self.on_encode_error = on_encode_error;
+        self
+    }
+
+    /// Replaces the encoder used for optimized images, see [`crate::ImageEncoder`].
+    pub fn with_encoder(mut self, encoder: impl crate::encoder::ImageEncoder + 'static) -> Self {
+        self.pipeline.encoder = std::sync::Arc::new(encoder);
+        self
+    }
+
+    /// Sets a prefix prepended to every generated image and placeholder URL.
+    ///
+    /// Use an origin (e.g. `https://cdn.example.com`) to serve images from a CDN,
+    /// or a sub-path (e.g. `/app`) when the app is mounted behind a reverse proxy.
+    /// The cache route itself is still mounted at `api_handler_path`.
+    pub fn with_asset_prefix(mut self, asset_prefix: impl Into<String>) -> Self {
+        self.asset_prefix = asset_prefix.into();
+        self
+    }
+
+    /// The handler path with the asset prefix applied.
+    pub(crate) fn handler_url(&self) -> String {
+        format!(
+            "{}{}",
+            self.asset_prefix.trim_end_matches('/'),
+            self.api_handler_path
+        )
+    }
+
+    /// Stores optimized images and placeholders in `dir` instead of `cache/image`
+    /// below the site root. Relative paths are resolved against the site root.
+    ///
+    /// Use a directory outside the public root (e.g. `./target/image-cache`) so the
+    /// cache files aren't served by the static file handler. They are still served at
+    /// `/cache/image` by the cache route and [`crate::axum::attach`].
+    pub fn with_cache_dir(mut self, dir: impl AsRef<std::path::Path>) -> Self {
+        self.cache_dir = std::path::Path::new(&self.root_file_path).join(dir);
+        self
+    }
+
+    /// Sets how 16-bit and HDR sources are converted to 8-bit before encoding.
+    pub fn with_tone_mapping(mut self, tone_mapping: crate::decode::ToneMapping) -> Self {
+        self.pipeline.tone_mapping = tone_mapping;
+        self
+    }
+
+    /// Sets how embedded ICC color profiles are handled.
+    pub fn with_icc_policy(mut self, icc: crate::decode::IccPolicy) -> Self {
+        self.pipeline.icc = icc;
+        self
+    }
+
+    /// Sets which EXIF metadata is kept, everything is stripped by default.
+    pub fn with_metadata_policy(mut self, metadata: crate::metadata::MetadataPolicy) -> Self {
+        self.pipeline.metadata = metadata;
+        self
+    }
+
+    /// Makes cache files reproducible: identical sources and options give byte-identical
+    /// files with identical modification times, across runs and machines, so they can
+    /// be diffed, content-addressed and shipped in reproducible builds.
+    ///
+    /// Metadata is always stripped, overriding [`Self::with_metadata_policy`] and
+    /// source policies, and cache files take the modification time of their source.
+    /// The built-in encoders use fixed settings, custom encoders
+    /// (see [`Self::with_encoder`]) have to be deterministic themselves.
+    pub fn with_deterministic(mut self, deterministic: bool) -> Self {
+        self.pipeline.deterministic = deterministic;
+        self
+    }
+
+    /// Sets how EXIF orientation is applied, sources are rotated upright by default.
+    pub fn with_auto_orient(mut self, orientation: crate::decode::AutoOrient) -> Self {
+        self.pipeline.orientation = orientation;
+        self
+    }
+
+    /// Largest number of pixels decoded from a source, 100 megapixels by default.
+    ///
+    /// Larger sources are rejected like other oversized sources, unless they are JPEG
+    /// or TIFF files that decode within the limit at a reduced scale. TIFF scans are
+    /// read strip by strip (or tile by tile) and scaled while reading, so they never
+    /// need their full size in memory.
+    pub fn with_max_source_pixels(mut self, max_source_pixels: u64) -> Self {
+        self.pipeline.max_source_pixels = max_source_pixels;
+        self
     }
 
     /// Creates a context function to provide the optimizer.
@@ -73,6 +906,7 @@ impl ImageOptimizer {
     pub fn provide_context(&self) -> impl Fn() + 'static + Clone + Send {
         let optimizer = self.clone();
         move || {
+            leptos::prelude::provide_context(optimizer.url_key.clone());
             leptos::prelude::provide_context(optimizer.clone());
         }
     }
@@ -81,7 +915,64 @@ impl ImageOptimizer {
         &self,
         cache_image: &CachedImage,
     ) -> Result<bool, CreateImageError> {
-        let root = self.root_file_path.as_str();
+        Ok(self.create(cache_image).await?.is_encoded())
+    }
+
+    /// Creates an image, returning the encoded bytes for the first response.
+    pub(crate) async fn create(
+        &self,
+        cache_image: &CachedImage,
+    ) -> Result<Creation, CreateImageError> {
+        let creation = self.create_image_once(cache_image).await?;
+        if creation.is_encoded() {
+            self.speculate_widths(cache_image);
+        }
+        Ok(creation)
+    }
+
+    /// Creates a batch of images, yielding each with its result as soon as it is done,
+    /// e.g. to report progress while warming the cache. `true` means the image was
+    /// encoded, `false` that it already was.
+    ///
+    /// Source policies apply like in the cache handler. Encodes share the optimizer's
+    /// parallelism with requests, the batch keeps a few more images queued so the
+    /// encode slots never idle.
+    ///
+    /// ```
+    /// # use leptos_image::*;
+    /// # use futures_util::StreamExt;
+    /// # async fn warm(optimizer: ImageOptimizer, sources: Vec<String>) {
+    /// let images = sources.iter().map(|src| {
+    ///     let options = Resize { width: 640, height: 480, quality: 75, ..Default::default() };
+    ///     CachedImage::new(src, options)
+    /// });
+    /// let results = optimizer.create_images(images);
+    /// futures_util::pin_mut!(results);
+    /// while let Some((image, result)) = results.next().await {
+    ///     println!("{}: {result:?}", image.src());
+    /// }
+    /// # }
+    /// ```
+    pub fn create_images(
+        &self,
+        images: impl IntoIterator<Item = CachedImage>,
+    ) -> impl futures_util::Stream<Item = (CachedImage, Result<bool, CreateImageError>)> + '_ {
+        use futures_util::StreamExt;
+
+        futures_util::stream::iter(images)
+            .map(move |mut image| async move {
+                self.apply_policy(&mut image);
+                let result = self.create_image(&image).await;
+                (image, result)
+            })
+            .buffer_unordered(self.parallelism.max(1) * 2)
+    }
+
+    // Creates the image alone, without pre-generating its siblings.
+    async fn create_image_once(
+        &self,
+        cache_image: &CachedImage,
+    ) -> Result<Creation, CreateImageError> {
         {
             let option = if let CachedImageOption::Resize(_) = cache_image.option {
                 "Resize"
@@ -91,153 +982,1165 @@ impl ImageOptimizer {
             tracing::debug!("Creating {option} image for {}", &cache_image.src);
         }
 
-        let relative_path_created = self.get_file_path(&cache_image);
+        let save_path = self.get_file_path_from_root(cache_image);
+        let absolute_src_path = self.resolve_source(&cache_image.src).await?;
+
+        if self.is_fresh(&save_path, &absolute_src_path).await {
+            self.metrics.hit();
+            #[cfg(feature = "sqlite")]
+            if let Some(index) = self.index.clone() {
+                let path = self.cache_path(cache_image);
+                tokio::task::spawn_blocking(move || index.touch(&path));
+            }
+            Ok(Creation::Cached)
+        } else if self.read_only.is_some() {
+            Err(CreateImageError::ReadOnly(cache_image.src.clone()))
+        } else if self.is_missing(&cache_image.src, &absolute_src_path).await {
+            Err(CreateImageError::SourceNotFound(cache_image.src.clone()))
+        } else if !crate::decode::is_supported_source(&absolute_src_path).await? {
+            Err(CreateImageError::UnsupportedFormat(cache_image.src.clone()))
+        } else if !self.lifecycle.is_accepting() {
+            Err(CreateImageError::ShuttingDown)
+        } else if !self.within_quota(cache_image.tenant.as_deref()).await {
+            Err(CreateImageError::QuotaExceeded(
+                cache_image.tenant.clone().unwrap_or_default(),
+            ))
+        } else {
+            self.encode_shared(cache_image, save_path, absolute_src_path).await
+        }
+    }
+
+    // Joins the running encode of an image, or starts one. The encode runs in its own
+    // task, and is abandoned once every request waiting for it is dropped.
+    async fn encode_shared(
+        &self,
+        cache_image: &CachedImage,
+        save_path: String,
+        absolute_src_path: std::path::PathBuf,
+    ) -> Result<Creation, CreateImageError> {
+        loop {
+            let waiter = match self.in_flight.entry(cache_image.clone()) {
+                dashmap::mapref::entry::Entry::Occupied(entry) if !entry.get().is_abandoned() => {
+                    entry.get().join()
+                }
+                entry => {
+                    let flight = entry.insert(Default::default()).clone();
+                    let waiter = flight.join();
+                    let optimizer = self.clone();
+                    let image = cache_image.clone();
+                    let (save_path, absolute_src_path) =
+                        (save_path.clone(), absolute_src_path.clone());
+                    // Outlives the request, but is still recorded as part of it.
+                    let span = tracing::info_span!("encode_image", image = %image);
+                    let encode = async move {
+                        let done = FlightDone {
+                            in_flight: optimizer.in_flight.clone(),
+                            image: image.clone(),
+                            flight,
+                        };
+                        let abandoned = done.flight.abandoned.clone();
+                        let outcome = optimizer
+                            .encode(&image, save_path, absolute_src_path, abandoned)
+                            .await;
+                        if let Err(error) = &outcome {
+                            optimizer.emit_error(&image, error);
+                        }
+                        let outcome = outcome.map_err(std::sync::Arc::new);
+                        done.flight.outcome.send_replace(Some(outcome));
+                    };
+                    tokio::spawn(tracing::Instrument::instrument(encode, span));
+                    waiter
+                }
+            };
+            match waiter.0.outcome().await {
+                // Joined just as the last request left, start over.
+                Err(error) if matches!(*error, CreateImageError::Cancelled) => continue,
+                outcome => return outcome.map_err(CreateImageError::from_shared),
+            }
+        }
+    }
+
+    // Encodes an image in the queue of its kind, unless `abandoned` is set first.
+    async fn encode(
+        &self,
+        cache_image: &CachedImage,
+        save_path: String,
+        absolute_src_path: std::path::PathBuf,
+        abandoned: std::sync::Arc<std::sync::atomic::AtomicBool>,
+    ) -> Result<Creation, CreateImageError> {
+        let _permit = self.encode_permit(cache_image).await;
+        if !self.lifecycle.is_accepting() {
+            return Err(CreateImageError::ShuttingDown);
+        }
+        if abandoned.load(std::sync::atomic::Ordering::SeqCst) {
+            return Err(CreateImageError::Cancelled);
+        }
+        let memory = self.reserve_memory(&absolute_src_path, &cache_image.option).await?;
+        self.metrics.miss();
+        let replaced = tokio::fs::try_exists(&save_path).await.unwrap_or(false);
+        self.emit_encode_start(cache_image, replaced);
+        let started = std::time::Instant::now();
+        let task = self.run_encode({
+            // Moved into the task, so it counts until the encode is done or stops
+            // at a checkpoint after the requests were dropped.
+            let active = self.lifecycle.start();
+            let span = tracing::Span::current();
+            let option = cache_image.option.clone();
+            let mut pipeline = self.pipeline.clone();
+            pipeline.abandoned = abandoned.clone();
+            if let Some(policy) = self.policy(&cache_image.src) {
+                if let Some(metadata) = &policy.metadata {
+                    pipeline.metadata = metadata.clone();
+                }
+            }
+            if pipeline.deterministic {
+                pipeline.metadata = crate::metadata::MetadataPolicy::Strip;
+            }
+            let cache_image = cache_image.clone();
+            let freshness = self.freshness();
+            #[cfg(feature = "sqlite")]
+            let index = self.index.clone();
+            #[cfg(feature = "sqlite")]
+            let cache_dir = self.cache_dir.clone();
+            #[cfg(feature = "sqlite")]
+            let index_path = self.cache_path(&cache_image);
+            move || -> Result<Creation, CreateImageError> {
+                let _active = active;
+                let _span = span.enter();
+                // Held until the pixels are freed, even after a timeout.
+                let _memory = memory;
+                // Only one process sharing the cache directory encodes a given image.
+                pipeline.check_abandoned()?;
+                let _lock = lock_cache_file(&save_path)?;
+                if is_fresh_blocking(&save_path, &absolute_src_path, freshness) {
+                    return Ok(Creation::Cached);
+                }
+                pipeline.check_abandoned()?;
+                cache_image.write_sidecar(&save_path)?;
+                #[cfg(feature = "sqlite")]
+                let source_hash = index.as_ref().and_then(|index| {
+                    index
+                        .source_hash(&absolute_src_path)
+                        .map_err(|e| tracing::warn!("Failed to hash {}: {e}", cache_image))
+                        .ok()
+                });
+                #[cfg(feature = "sqlite")]
+                let shared = match (&index, &source_hash) {
+                    (Some(index), Some(hash)) => share_duplicate(
+                        index,
+                        &cache_image,
+                        &index_path,
+                        hash,
+                        &cache_dir,
+                        &save_path,
+                    ),
+                    _ => false,
+                };
+                #[cfg(not(feature = "sqlite"))]
+                let shared = false;
+                let bytes = if shared {
+                    None
+                } else {
+                    create_optimized_image(&pipeline, option, absolute_src_path, save_path.clone())?
+                };
+                #[cfg(feature = "sqlite")]
+                if let Some(index) = index {
+                    let bytes = std::fs::metadata(&save_path)?.len();
+                    let hash = source_hash.as_deref();
+                    if let Err(e) = index.record(&cache_image, &index_path, bytes, hash) {
+                        tracing::warn!("Failed to index {}: {e}", cache_image);
+                    }
+                }
+                Ok(Creation::Encoded(bytes.map(axum::body::Bytes::from)))
+            }
+        });
+
+        let task = self.timed_encode(cache_image, &abandoned, task).await?;
+        let creation = task??;
+        if creation.is_encoded() {
+            self.metrics.encoded(started.elapsed());
+            self.emit_encode_complete(cache_image, replaced, started.elapsed());
+            if let Some(tenant) = &cache_image.tenant {
+                let bytes = tokio::fs::metadata(self.get_file_path_from_root(cache_image))
+                    .await
+                    .map(|metadata| metadata.len())
+                    .unwrap_or_default();
+                if let Some(mut used) = self.tenant_usage.get_mut(tenant) {
+                    *used += bytes;
+                }
+            }
+        }
+        if creation.is_encoded() && self.freshness().is_some() {
+            // Reloaded from disk by the handler.
+            self.cache.remove(cache_image);
+            #[cfg(feature = "memory-cache")]
+            if let Some(memory) = &self.memory {
+                memory.invalidate(&format!("/{}", self.cache_key.file_path(cache_image)));
+            }
+        }
+        Ok(creation)
+    }
+
+    // Waits for an encode task, abandoning it once it runs past the encode timeout.
+    async fn timed_encode<T>(
+        &self,
+        cache_image: &CachedImage,
+        abandoned: &std::sync::atomic::AtomicBool,
+        task: impl std::future::Future<Output = T>,
+    ) -> Result<T, CreateImageError> {
+        let Some(timeout) = self.encode_timeout else {
+            return Ok(task.await);
+        };
+        tokio::time::timeout(timeout, task).await.map_err(|_| {
+            // Stops the task at its next checkpoint, before it writes the image.
+            abandoned.store(true, std::sync::atomic::Ordering::SeqCst);
+            self.metrics.encode_timed_out();
+            tracing::warn!("Encoding {cache_image} timed out after {timeout:?}");
+            CreateImageError::Timeout(timeout)
+        })
+    }
+
+    /// Stops accepting new encodes and waits up to `timeout` for running ones to finish.
+    ///
+    /// Requests for images that aren't cached yet fail with
+    /// [`CreateImageError::ShuttingDown`] from now on, cached images are still served.
+    /// Encodes still running after the timeout are abandoned; cache files are written
+    /// through a rename, so they never leave partial images behind.
+    /// Returns whether all encodes finished in time.
+    pub async fn shutdown(&self, timeout: std::time::Duration) -> bool {
+        let lifecycle = &self.lifecycle;
+        lifecycle
+            .accepting
+            .store(false, std::sync::atomic::Ordering::SeqCst);
+
+        let drained = tokio::time::timeout(timeout, async {
+            loop {
+                let idle = lifecycle.idle.notified();
+                if lifecycle.active() == 0 {
+                    break;
+                }
+                idle.await;
+            }
+        })
+        .await
+        .is_ok();
+
+        if !drained {
+            tracing::warn!(
+                "Shutdown timed out with {} image encodes running",
+                lifecycle.active()
+            );
+        }
+        drained
+    }
+
+    // Whether the cached file can be served, in dev mode it must be newer than the source.
+    async fn is_fresh(&self, save_path: &std::path::Path, source_path: &std::path::Path) -> bool {
+        let Some(freshness) = self.freshness() else {
+            return file_exists(save_path).await;
+        };
+        let (save_path, source_path) = (save_path.to_path_buf(), source_path.to_path_buf());
+        tokio::task::spawn_blocking(move || {
+            is_fresh_blocking(&save_path, &source_path, Some(freshness))
+        })
+        .await
+        .unwrap_or(false)
+    }
+
+    async fn within_quota(&self, tenant: Option<&str>) -> bool {
+        let (Some(tenant), Some(quota)) = (tenant, self.tenant_quota.clone()) else {
+            return true;
+        };
+        let optimizer = self.clone();
+        let tenant = tenant.to_string();
+        tokio::task::spawn_blocking(move || {
+            let used = optimizer.tenant_disk_usage(&tenant);
+            (quota.0)(&tenant, used)
+        })
+        .await
+        .unwrap_or(false)
+    }
+
+    // Checks the negative cache before touching the file system.
+    async fn is_missing(&self, src: &str, source_path: &std::path::Path) -> bool {
+        if let Some(since) = self.missing.get(src).map(|entry| *entry) {
+            if since.elapsed() < self.missing_ttl {
+                return true;
+            }
+            self.missing.remove(src);
+        }
+        if file_exists(source_path).await {
+            false
+        } else {
+            self.missing.insert(src.to_string(), std::time::Instant::now());
+            true
+        }
+    }
+
+    #[cfg(feature = "ssr")]
+    pub(crate) fn get_file_path_from_root(&self, cache_image: &CachedImage) -> String {
+        let path = self.cache_dir.join(self.cache_key.file_path(cache_image));
+        path.as_path().to_string_lossy().to_string()
+    }
+
+    /// Path of the cached file below `cache/image`, relative to the site root.
+    #[cfg(feature = "ssr")]
+    pub(crate) fn cache_path(&self, cache_image: &CachedImage) -> String {
+        let path = path_from_segments(vec![CACHE_DIR, &self.cache_key.file_path(cache_image)]);
+        path.as_path().to_string_lossy().to_string()
+    }
+
+    // Maps a path below `cache/image`, relative to the site root, to the cache directory.
+    pub(crate) fn cache_file(&self, relative: impl AsRef<std::path::Path>) -> std::path::PathBuf {
+        let relative = relative.as_ref();
+        self.cache_dir
+            .join(relative.strip_prefix(CACHE_DIR).unwrap_or(relative))
+    }
+
+    /// Deletes truncated, empty or otherwise unreadable cache entries, so they are
+    /// re-created on the next request. Meant to be called once at startup.
+    ///
+    /// Also removes orphaned sidecars and temporary files left behind by crashed
+    /// encodes. Returns the number of deleted files.
+    pub fn sweep_cache(&self) -> std::io::Result<usize> {
+        // Younger temporary files may belong to encodes of other processes.
+        const STALE_TEMP: std::time::Duration = std::time::Duration::from_secs(600);
+
+        let mut files = Vec::new();
+        collect_files(&self.cache_dir, &mut files)?;
+
+        let mut removed = 0;
+        for file in &files {
+            let extension = file.extension().unwrap_or_default();
+            let corrupt = if extension == TEMP_EXTENSION {
+                std::fs::metadata(file)
+                    .and_then(|metadata| metadata.modified())
+                    .is_ok_and(|modified| modified.elapsed().unwrap_or_default() > STALE_TEMP)
+            } else if extension == SIDECAR_EXTENSION {
+                // Orphaned unless one of the files has the same stem.
+                !files.iter().any(|other| {
+                    is_cache_entry(other) && other.with_extension(SIDECAR_EXTENSION) == *file
+                })
+            } else if PRECOMPRESSED_EXTENSIONS.iter().any(|ext| extension == *ext) {
+                // Orphaned once the file it compresses is gone.
+                !file.with_extension("").exists()
+            } else if is_cache_entry(file) {
+                std::fs::metadata(file).is_ok_and(|metadata| metadata.len() == 0)
+                    || !is_intact(file)
+            } else {
+                false
+            };
+            if corrupt {
+                tracing::warn!("Removing corrupt cache file {}", file.display());
+                std::fs::remove_file(file)?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Loads blur placeholders cached on disk into memory, so the first render after a
+    /// restart can inline them without waiting for the cache route.
+    ///
+    /// Walks the whole cache directory (or reads the index with the `sqlite` feature),
+    /// reading at most `max_entries` placeholders, 16 at a time.
+    /// Returns the number of loaded placeholders.
+    pub async fn preload_disk_cache(&self, max_entries: usize) -> std::io::Result<usize> {
+        const CONCURRENCY: usize = 16;
+
+        let optimizer = self.clone();
+        let blurs = tokio::task::spawn_blocking(move || optimizer.cached_blurs())
+            .await
+            .map_err(std::io::Error::other)??;
+
+        let mut loaded = 0;
+        let mut tasks = tokio::task::JoinSet::new();
+        let mut blurs = blurs.into_iter().take(max_entries);
+        loop {
+            while tasks.len() < CONCURRENCY {
+                let Some(image) = blurs.next() else {
+                    break;
+                };
+                let path = self.get_file_path_from_root(&image);
+                tasks.spawn(async move {
+                    let value = match tokio::fs::read(path).await {
+                        Ok(bytes) => image.placeholder_value(bytes),
+                        Err(e) => Err(e),
+                    };
+                    (image, value)
+                });
+            }
+            let Some(result) = tasks.join_next().await else {
+                break;
+            };
+            match result.map_err(std::io::Error::other)? {
+                (image, Ok(svg)) => {
+                    self.cache.insert(image, svg);
+                    loaded += 1;
+                }
+                (image, Err(e)) => tracing::warn!("Failed to preload {image}: {e}"),
+            }
+        }
+        tracing::info!("Preloaded {loaded} blur placeholders");
+        Ok(loaded)
+    }
+
+    // Blur placeholders on disk that aren't in memory yet.
+    fn cached_blurs(&self) -> std::io::Result<Vec<CachedImage>> {
+        #[cfg(feature = "sqlite")]
+        if let Some(index) = &self.index {
+            let images = index.images().map_err(std::io::Error::other)?;
+            return Ok(images
+                .into_iter()
+                .filter(|image| matches!(image.option, CachedImageOption::Blur(_)))
+                .filter(|image| !self.cache.contains_key(image))
+                .collect());
+        }
+
+        let mut files = Vec::new();
+        collect_files(&self.cache_dir, &mut files)?;
+        Ok(files
+            .iter()
+            .filter(|file| file.extension().is_some_and(|ext| ext == SIDECAR_EXTENSION))
+            .filter_map(|sidecar| std::fs::read_to_string(sidecar).ok())
+            .filter_map(|encoded| serde_qs::from_str::<CachedImage>(&encoded).ok())
+            .filter(|image| matches!(image.option, CachedImageOption::Blur(_)))
+            .filter(|image| !self.cache.contains_key(image))
+            .collect())
+    }
+
+    // Drops the images held in memory after cache files were removed.
+    pub(crate) fn invalidate_memory(&self) {
+        #[cfg(feature = "memory-cache")]
+        if let Some(memory) = &self.memory {
+            memory.invalidate_all();
+        }
+    }
+
+    /// Removes every optimized image and blur placeholder created from `src`,
+    /// e.g. after the source file changed. Returns the number of deleted files.
+    pub fn purge_source(&self, src: &str) -> std::io::Result<usize> {
+        let normalize = |src: &str| src.trim_start_matches('/').to_string();
+        let src = normalize(src);
+
+        self.cache.retain(|image, _| normalize(&image.src) != src);
+        self.missing.retain(|missing, _| normalize(missing) != src);
+        self.invalidate_memory();
+
+        #[cfg(feature = "sqlite")]
+        if let Some(index) = &self.index {
+            let paths = index.remove_source(&src).map_err(std::io::Error::other)?;
+            let mut removed = 0;
+            for path in paths {
+                let file = self.cache_file(path);
+                let image = self
+                    .watches_evictions()
+                    .then(|| CachedImage::from_file_path(&file.to_string_lossy()))
+                    .flatten();
+                let _ = std::fs::remove_file(file.with_extension(SIDECAR_EXTENSION));
+                remove_precompressed(&file);
+                if std::fs::remove_file(file).is_ok() {
+                    removed += 1;
+                    self.emit_evicted(image);
+                }
+            }
+            return Ok(removed);
+        }
+
+        let mut files = Vec::new();
+        collect_files(&self.cache_dir, &mut files)?;
+
+        let mut removed = 0;
+        for sidecar in files
+            .iter()
+            .filter(|file| file.extension().is_some_and(|ext| ext == SIDECAR_EXTENSION))
+        {
+            let image = std::fs::read_to_string(sidecar)
+                .ok()
+                .and_then(|encoded| serde_qs::from_str::<CachedImage>(&encoded).ok());
+            let Some(image) = image.filter(|image| normalize(&image.src) == src) else {
+                continue;
+            };
+            let file = self.cache_file(self.cache_path(&image));
+            remove_precompressed(&file);
+            if file.exists() {
+                std::fs::remove_file(&file)?;
+                removed += 1;
+                self.emit_evicted([image]);
+            }
+            std::fs::remove_file(sidecar)?;
+        }
+        if removed > 0 {
+            tracing::debug!("Purged {removed} cached images of {src}");
+        }
+        Ok(removed)
+    }
+
+    /// Moves cache entries written with the old base64 path layout to hashed paths.
+    ///
+    /// Entries that fail to parse are left alone. Returns the number of migrated entries,
+    /// see [`Self::migrate_cache`] for what was left behind.
+    pub fn migrate_legacy_cache(&self) -> std::io::Result<usize> {
+        Ok(self.migrate_cache()?.migrated)
+    }
+}
+
+// Writes an optimized image to the cache, returning its bytes unless the source was
+// linked as is.
+#[cfg(feature = "ssr")]
+fn create_optimized_image<P>(
+    pipeline: &Pipeline,
+    config: CachedImageOption,
+    source_path: P,
+    save_path: P,
+) -> Result<Option<Vec<u8>>, CreateImageError>
+where
+    P: AsRef<std::path::Path> + AsRef<std::ffi::OsStr>,
+{
+    let path: &std::path::Path = source_path.as_ref();
+    if let CachedImageOption::Resize(resize) = &config {
+        if !is_svg(&path.to_string_lossy())
+            && source_satisfies(pipeline, resize, resize.output_format(), path)
+        {
+            link_or_copy(path, save_path.as_ref())?;
+            if pipeline.deterministic {
+                pin_modified(save_path.as_ref(), path)?;
+            }
+            return Ok(None);
+        }
+    }
+    #[cfg(feature = "precompress")]
+    let is_blur = matches!(
+        config,
+        CachedImageOption::Blur(Blur {
+            kind: Placeholder::Blur,
+            ..
+        })
+    );
+    let bytes = encode_optimized_image(pipeline, config, path)?;
+    pipeline.check_abandoned()?;
+    write_atomic(&save_path, &bytes)?;
+    #[cfg(feature = "precompress")]
+    if is_blur {
+        write_precompressed(save_path.as_ref(), &bytes);
+    }
+    if pipeline.deterministic {
+        let save_path: &std::path::Path = save_path.as_ref();
+        pin_modified(save_path, path)?;
+        for extension in PRECOMPRESSED_EXTENSIONS {
+            let sibling = sibling_path(save_path, extension);
+            if sibling.exists() {
+                pin_modified(&sibling, path)?;
+            }
+        }
+    }
+    Ok(Some(bytes))
+}
+
+// Gives a cache file the modification time of its source, for reproducible output.
+#[cfg(feature = "ssr")]
+fn pin_modified(file: &std::path::Path, source: &std::path::Path) -> std::io::Result<()> {
+    let modified = std::fs::metadata(source)?.modified()?;
+    std::fs::File::options()
+        .write(true)
+        .open(file)?
+        .set_modified(modified)
+}
+
+/// Encodes an optimized image or blur placeholder, without touching the cache.
+#[cfg(feature = "ssr")]
+pub(crate) fn encode_optimized_image(
+    pipeline: &Pipeline,
+    config: CachedImageOption,
+    path: &std::path::Path,
+) -> Result<Vec<u8>, CreateImageError> {
+    match config {
+        CachedImageOption::Resize(_) if is_svg(&path.to_string_lossy()) => {
+            let svg = std::fs::read_to_string(path)?;
+            Ok(minify_svg(&svg).into_bytes())
+        }
+        CachedImageOption::Resize(resize) => {
+            let request = crate::encoder::EncodeRequest {
+                options: &resize,
+                format: resize.output_format(),
+                source_format: image::ImageFormat::from_path(path).ok(),
+            };
+            if source_satisfies(pipeline, &resize, request.format, path) {
+                return Ok(std::fs::read(path)?);
+            }
+            if resize.animation == Animation::Animate && request.format == OutputFormat::Webp {
+                if let Some(frames) = decode_animation(path)? {
+                    let frames = match resize.frame_limits {
+                        Some(limits) => limit_frames(frames, limits),
+                        None => frames,
+                    };
+                    let frames = resize_frames(frames, &resize);
+                    return pipeline.encoder.encode_animation(frames, &request);
+                }
+            }
+
+            // Crops are in source pixels, decode at full size.
+            // Covering and stretching can need more pixels than fit in the box.
+            let scaled = !resize.crops_source() && resize.fit == Fit::Contain;
+            let target = scaled.then_some((resize.width, resize.height));
+            let img = decode_source(pipeline, path, target)?;
+            pipeline.check_abandoned()?;
+            let img = crop_source(img, resize.crop, resize.aspect_ratio, resize.focal_point)?;
+            let new_img = fit_image(&img, &resize);
+            let new_img = match resize.mask {
+                Some(mask) => mask_image(new_img, mask),
+                None => new_img,
+            };
+            let bytes = pipeline.encoder.encode(&new_img, &request)?;
+            Ok(crate::metadata::apply_metadata_policy(
+                bytes,
+                path,
+                &new_img,
+                &pipeline.metadata,
+            ))
+        }
+        CachedImageOption::Blur(blur) => create_image_blur(pipeline, path, blur),
+    }
+}
+
+/// Whether a WebP source can be served as-is: it already fits the requested size,
+/// and re-encoding it could only lose quality.
+///
+/// Sources carrying metadata, ICC profiles or unwanted animation are still re-encoded,
+/// so the other pipeline settings keep applying. The copy bypasses the encoder.
+#[cfg(feature = "ssr")]
+fn source_satisfies(
+    pipeline: &Pipeline,
+    resize: &Resize,
+    format: OutputFormat,
+    path: &std::path::Path,
+) -> bool {
+    const ANIMATION_FLAG: u8 = 0x02;
+    const XMP_FLAG: u8 = 0x04;
+    const EXIF_FLAG: u8 = 0x08;
+    const ICC_FLAG: u8 = 0x20;
+
+    let is_webp = matches!(
+        image::ImageFormat::from_path(path),
+        Ok(image::ImageFormat::WebP)
+    );
+    let lossless = resize.lossless || resize.near_lossless.is_some();
+    let reshaped = resize.crops_source() || !resize.keeps_size() || resize.mask.is_some();
+    if !is_webp || format != OutputFormat::Webp || lossless || reshaped {
+        return false;
+    }
+    let Ok((width, height)) = image::image_dimensions(path) else {
+        return false;
+    };
+    if width > resize.width || height > resize.height {
+        return false;
+    }
+
+    // Only the extended format carries metadata, the flags are the first byte of VP8X.
+    let mut header = [0u8; 21];
+    let read = std::fs::File::open(path).and_then(|mut file| {
+        use std::io::Read;
+        file.read_exact(&mut header)
+    });
+    if read.is_err() {
+        return false;
+    }
+    if &header[12..16] != b"VP8X" {
+        return true;
+    }
+    let flags = header[20];
+    let mut unwanted = XMP_FLAG | EXIF_FLAG;
+    if pipeline.icc != crate::decode::IccPolicy::Ignore {
+        unwanted |= ICC_FLAG;
+    }
+    if resize.animation == Animation::Poster {
+        unwanted |= ANIMATION_FLAG;
+    }
+    flags & unwanted == 0
+}
+
+/// Hard links the source into the cache, falling back to a copy across file systems.
+#[cfg(feature = "ssr")]
+fn link_or_copy(source: &std::path::Path, destination: &std::path::Path) -> std::io::Result<()> {
+    create_nested_if_needed(destination)?;
+    if std::fs::hard_link(source, destination).is_err() {
+        let temp = temp_path(destination);
+        std::fs::copy(source, &temp)?;
+        std::fs::rename(temp, destination)?;
+    }
+    Ok(())
+}
+
+/// Writes `.gz` and `.br` siblings, picked by the cache route from `Accept-Encoding`.
+///
+/// Failures are only logged, the uncompressed file is always there.
+#[cfg(feature = "precompress")]
+fn write_precompressed(path: &std::path::Path, contents: &[u8]) {
+    use std::io::Write;
+
+    let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+    let gzip = gzip.write_all(contents).and_then(|_| gzip.finish());
+    // Quality 11 with a 4 MiB window, placeholders are small so this is cheap.
+    let mut brotli = brotli::CompressorWriter::new(Vec::new(), 4096, 11, 22);
+    let brotli = brotli.write_all(contents).map(|_| brotli.into_inner());
+
+    for (extension, compressed) in [("gz", gzip), ("br", brotli)] {
+        let sibling = sibling_path(path, extension);
+        let written = compressed.and_then(|bytes| write_atomic(sibling, bytes));
+        if let Err(e) = written {
+            tracing::warn!("Failed to precompress {}: {e}", path.display());
+        }
+    }
+}
+
+// Removes the precompressed siblings of a cached file, if any.
+#[cfg(feature = "ssr")]
+pub(crate) fn remove_precompressed(path: &std::path::Path) {
+    for extension in PRECOMPRESSED_EXTENSIONS {
+        let _ = std::fs::remove_file(sibling_path(path, extension));
+    }
+}
 
-        let save_path = path_from_segments(vec![root, &relative_path_created]);
-        let absolute_src_path = path_from_segments(vec![root, &cache_image.src]);
+/// `path` with `.{extension}` appended, `a.svg` becomes `a.svg.br`.
+#[cfg(feature = "ssr")]
+pub(crate) fn sibling_path(path: &std::path::Path, extension: &str) -> std::path::PathBuf {
+    let mut sibling = path.as_os_str().to_owned();
+    sibling.push(".");
+    sibling.push(extension);
+    sibling.into()
+}
 
-        if file_exists(&save_path).await {
-            Ok(false)
-        } else {
-            let _ = self
-                .semaphore
-                .acquire()
-                .await
-                .expect("Failed to acquire semaphore");
-            let task = tokio::task::spawn_blocking({
-                let option = cache_image.option.clone();
-                move || create_optimized_image(option, absolute_src_path, save_path)
-            });
+/// Writes to a temporary file next to `path` and renames it into place, so readers
+/// never see a partially written cache file.
+#[cfg(feature = "ssr")]
+fn write_atomic<P>(path: P, contents: impl AsRef<[u8]>) -> std::io::Result<()>
+where
+    P: AsRef<std::path::Path>,
+{
+    let path = path.as_ref();
+    create_nested_if_needed(path)?;
+    let temp = temp_path(path);
+    if let Err(e) = std::fs::write(&temp, contents).and_then(|_| std::fs::rename(&temp, path)) {
+        let _ = std::fs::remove_file(&temp);
+        return Err(e);
+    }
+    Ok(())
+}
 
-            match task.await {
-                Err(join_error) => Err(CreateImageError::JoinError(join_error)),
-                Ok(Err(err)) => Err(err),
-                Ok(Ok(_)) => Ok(true),
-            }
-        }
+/// Links (or copies, where links aren't supported) `from` to `path` through a rename.
+#[cfg(feature = "sqlite")]
+fn link_atomic(from: &std::path::Path, path: &std::path::Path) -> std::io::Result<()> {
+    create_nested_if_needed(path)?;
+    let temp = temp_path(path);
+    let linked = std::fs::hard_link(from, &temp)
+        .or_else(|_| std::fs::copy(from, &temp).map(|_| ()))
+        .and_then(|_| std::fs::rename(&temp, path));
+    if linked.is_err() {
+        let _ = std::fs::remove_file(&temp);
     }
+    linked
+}
 
-    #[cfg(feature = "ssr")]
-    pub(crate) fn get_file_path_from_root(&self, cache_image: &CachedImage) -> String {
-        let path = path_from_segments(vec![
-            self.root_file_path.as_ref(),
-            &self.get_file_path(cache_image),
-        ]);
-        path.as_path().to_string_lossy().to_string()
+/// Shares the cached file of a copy of the source, if one was encoded with the same
+/// options. Copies are common after CMS exports, this saves their encodes and disk.
+#[cfg(feature = "sqlite")]
+fn share_duplicate(
+    index: &crate::index::CacheIndex,
+    cache_image: &CachedImage,
+    index_path: &str,
+    source_hash: &str,
+    cache_dir: &std::path::Path,
+    save_path: &std::path::Path,
+) -> bool {
+    // Placeholders are cheap and come with precompressed siblings.
+    if !matches!(cache_image.option, CachedImageOption::Resize(_)) {
+        return false;
+    }
+    let Ok(Some(duplicate)) = index.duplicate(cache_image, index_path, source_hash) else {
+        return false;
+    };
+    let relative = std::path::Path::new(&duplicate);
+    let duplicate = cache_dir.join(relative.strip_prefix(CACHE_DIR).unwrap_or(relative));
+    match link_atomic(&duplicate, save_path) {
+        Ok(()) => {
+            tracing::debug!("Shared {} with {}", cache_image, duplicate.display());
+            true
+        }
+        // Evicted or purged since, encode instead.
+        Err(_) => false,
     }
+}
 
-    pub(crate) fn get_file_path(&self, cache_image: &CachedImage) -> String {
-        use base64::{engine::general_purpose, Engine as _};
-        // I'm worried this name will become too long.
-        // names are limited to 255 bytes on most filesystems.
+// Unique per process and thread, `abc.webp` becomes `abc.webp.{pid}-{thread}.tmp`.
+#[cfg(feature = "ssr")]
+fn temp_path(path: &std::path::Path) -> std::path::PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(
+        ".{}-{:?}.{TEMP_EXTENSION}",
+        std::process::id(),
+        std::thread::current().id()
+    ));
+    name.into()
+}
 
-        let encode = serde_qs::to_string(&cache_image).unwrap();
-        let encode = general_purpose::STANDARD.encode(encode);
+/// Takes the advisory lock guarding a cache file, blocking until other processes
+/// sharing the cache directory are done with it. Released when the file is dropped.
+#[cfg(feature = "ssr")]
+fn lock_cache_file(path: &std::path::Path) -> std::io::Result<std::fs::File> {
+    use fs2::FileExt;
+
+    create_nested_if_needed(path)?;
+    let lock = std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path.with_extension(LOCK_EXTENSION))?;
+    lock.lock_exclusive()?;
+    Ok(lock)
+}
 
-        let mut path = path_from_segments(vec!["cache/image", &encode, &cache_image.src]);
+/// Decodes a source and normalizes it to upright 8-bit sRGB.
+/// Shared between the blur and resize tasks of the same source.
+/// `target` is the size the image is resized to afterwards.
+#[cfg(feature = "ssr")]
+pub(crate) fn decode_source(
+    pipeline: &Pipeline,
+    path: &std::path::Path,
+    target: Option<(u32, u32)>,
+) -> Result<std::sync::Arc<image::DynamicImage>, CreateImageError> {
+    // Thumbnails of large JPEGs and TIFFs only decode the scale they need.
+    let denominator = crate::decode::scale_denominator(path, target);
+    crate::decode::check_source_pixels(path, denominator, pipeline.max_source_pixels)?;
+    pipeline.decoded.get_or_decode(path, denominator, || {
+        let img = crate::decode::open_image_scaled(path, denominator)?;
+        let img = crate::decode::auto_orient(img, path, &pipeline.orientation);
+        let img = crate::decode::to_8bit(img, &pipeline.tone_mapping);
+        Ok(crate::decode::apply_icc_policy(img, path, pipeline.icc))
+    })
+}
 
-        if let CachedImageOption::Resize { .. } = cache_image.option {
-            path.set_extension("webp");
-        } else {
-            path.set_extension("svg");
-        };
+/// Decodes all frames of an animated source.
+/// Returns `None` for still images (including single-frame GIFs).
+#[cfg(feature = "ssr")]
+fn decode_animation<P>(source_path: P) -> Result<Option<Vec<image::Frame>>, CreateImageError>
+where
+    P: AsRef<std::path::Path>,
+{
+    use image::AnimationDecoder;
+
+    let is_gif = source_path
+        .as_ref()
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("gif"));
+    if !is_gif {
+        return Ok(None);
+    }
 
-        path.as_path().to_string_lossy().to_string()
+    let file = std::io::BufReader::new(std::fs::File::open(source_path)?);
+    let decoder = image::codecs::gif::GifDecoder::new(file)?;
+    let frames = decoder.into_frames().collect_frames()?;
+
+    if frames.len() > 1 {
+        Ok(Some(frames))
+    } else {
+        Ok(None)
     }
 }
 
+/// Drops the frames outside `limits`, before they are resized.
 #[cfg(feature = "ssr")]
-fn create_optimized_image<P>(
-    config: CachedImageOption,
-    source_path: P,
-    save_path: P,
-) -> Result<(), CreateImageError>
-where
-    P: AsRef<std::path::Path> + AsRef<std::ffi::OsStr>,
-{
-    use webp::*;
+fn limit_frames(frames: Vec<image::Frame>, limits: FrameLimits) -> Vec<image::Frame> {
+    let delay_ms = |frame: &image::Frame| {
+        let (numer, denom) = frame.delay().numer_denom_ms();
+        numer / denom.max(1)
+    };
+    let every = limits.every.unwrap_or(1).max(1) as usize;
+    let mut kept: Vec<image::Frame> = Vec::new();
+    let mut elapsed = 0;
+    for group in frames.chunks(every) {
+        if limits.max_frames.is_some_and(|max| kept.len() >= max.max(1) as usize)
+            || limits.max_duration_ms.is_some_and(|max| elapsed >= max.max(1))
+        {
+            break;
+        }
+        // The kept frame shows for the whole group, so the timing is unchanged.
+        let delay: u32 = group.iter().map(delay_ms).sum();
+        elapsed += delay;
+        kept.push(image::Frame::from_parts(
+            group[0].buffer().clone(),
+            group[0].left(),
+            group[0].top(),
+            image::Delay::from_numer_denom_ms(delay, 1),
+        ));
+    }
+    kept
+}
 
-    match config {
-        CachedImageOption::Resize(Resize {
-            width,
-            height,
-            quality,
-        }) => {
-            let img = image::open(source_path)?;
-            let new_img = img.resize(
-                width,
-                height,
-                // Cubic Filter.
-                image::imageops::FilterType::CatmullRom,
+/// Resizes every frame to the size the first frame resizes to.
+/// Frames are composited onto the full canvas, so they all share the same size
+/// and the same crop.
+#[cfg(feature = "ssr")]
+fn resize_frames(frames: Vec<image::Frame>, resize: &Resize) -> Vec<image::Frame> {
+    let Some(first) = frames.first() else {
+        return frames;
+    };
+    let region = source_region(
+        first.buffer().dimensions(),
+        resize.crop,
+        resize.aspect_ratio,
+        resize.focal_point,
+    );
+    let frames: Vec<image::Frame> = match region.filter(|_| resize.crops_source()) {
+        Some((x, y, width, height)) => frames
+            .into_iter()
+            .map(|frame| {
+                let delay = frame.delay();
+                let buffer = image::imageops::crop_imm(frame.buffer(), x, y, width, height);
+                image::Frame::from_parts(buffer.to_image(), 0, 0, delay)
+            })
+            .collect(),
+        None => frames,
+    };
+    frames
+        .into_iter()
+        .map(|frame| {
+            let delay = frame.delay();
+            let img = image::DynamicImage::ImageRgba8(frame.into_buffer());
+            let img = fit_image(&img, resize);
+            let img = match resize.mask {
+                Some(mask) => mask_image(img, mask),
+                None => img,
+            };
+            image::Frame::from_parts(img.into_rgba8(), 0, 0, delay)
+        })
+        .collect()
+}
+
+/// Scales a decoded source into the requested box according to `resize.fit`, then
+/// sharpens it if requested.
+#[cfg(feature = "ssr")]
+fn fit_image(img: &image::DynamicImage, resize: &Resize) -> image::DynamicImage {
+    let (width, height) = (resize.width, resize.height);
+    let filter = resize.filter.filter_type();
+    let sharpen = |resized: image::DynamicImage| match resize.sharpen {
+        Some(sharpen) => {
+            let scale = f32::max(
+                img.width() as f32 / resized.width().max(1) as f32,
+                img.height() as f32 / resized.height().max(1) as f32,
             );
-            // Create the WebP encoder for the above image
-            let encoder: Encoder = Encoder::from_image(&new_img).unwrap();
-            // Encode the image at a specified quality 0-100
-            let webp: WebPMemory = encoder.encode(quality as f32);
-            create_nested_if_needed(&save_path)?;
-            std::fs::write(save_path, &*webp)?;
+            sharpen_image(resized, sharpen, scale)
+        }
+        None => resized,
+    };
+    match resize.fit {
+        Fit::Cover => sharpen(img.resize_to_fill(width, height, filter)),
+        Fit::Fill => sharpen(img.resize_exact(width, height, filter)),
+        Fit::Contain => {
+            // Sharpened before padding, so the padding edge stays clean.
+            let resized = sharpen(img.resize(width, height, filter));
+            match resize.background {
+                Some(background) if (resized.width(), resized.height()) != (width, height) => {
+                    let mut canvas =
+                        image::RgbaImage::from_pixel(width, height, image::Rgba(background.0));
+                    let x = (width - resized.width()) / 2;
+                    let y = (height - resized.height()) / 2;
+                    image::imageops::overlay(
+                        &mut canvas,
+                        &resized.to_rgba8(),
+                        i64::from(x),
+                        i64::from(y),
+                    );
+                    image::DynamicImage::ImageRgba8(canvas)
+                }
+                _ => resized,
+            }
+        }
+    }
+}
+
+/// Whether the source is an SVG, which is served as-is instead of being rasterized.
+pub(crate) fn is_svg(src: &str) -> bool {
+    let path = src.split(['?', '#']).next().unwrap_or(src);
+    path.to_ascii_lowercase().ends_with(".svg")
+}
 
-            Ok(())
+/// Conservative SVG minification: drops comments and indentation between tags.
+#[cfg(feature = "ssr")]
+fn minify_svg(svg: &str) -> String {
+    let mut out = String::with_capacity(svg.len());
+    let mut rest = svg;
+    // Strip comments.
+    while let Some(start) = rest.find("<!--") {
+        out.push_str(&rest[..start]);
+        match rest[start..].find("-->") {
+            Some(end) => rest = &rest[start + end + 3..],
+            None => {
+                rest = "";
+                break;
+            }
         }
-        CachedImageOption::Blur(blur) => {
-            let svg = create_image_blur(source_path, blur)?;
-            create_nested_if_needed(&save_path)?;
-            std::fs::write(save_path, &*svg)?;
-            Ok(())
+    }
+    out.push_str(rest);
+
+    // Collapse whitespace runs between tags that contain a line break.
+    // Whitespace without a line break may be significant (e.g. between <tspan>s).
+    let mut minified = String::with_capacity(out.len());
+    let mut chars = out.trim().chars().peekable();
+    while let Some(c) = chars.next() {
+        minified.push(c);
+        if c == '>' {
+            let mut ws = String::new();
+            while let Some(&next) = chars.peek() {
+                if next.is_whitespace() {
+                    ws.push(next);
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            let before_tag = chars.peek() == Some(&'<');
+            if !(before_tag && ws.contains('\n')) {
+                minified.push_str(&ws);
+            }
         }
     }
+    minified
+}
+
+/// Composites a placeholder onto a matte color, keeping it as is without one.
+#[cfg(feature = "ssr")]
+fn apply_matte(img: image::DynamicImage, matte: Option<Background>) -> image::DynamicImage {
+    let Some(matte) = matte else {
+        return img;
+    };
+    let mut canvas = image::RgbaImage::from_pixel(img.width(), img.height(), image::Rgba(matte.0));
+    image::imageops::overlay(&mut canvas, &img.to_rgba8(), 0, 0);
+    image::DynamicImage::ImageRgba8(canvas)
+}
+
+/// Whether any pixel is not fully opaque.
+#[cfg(feature = "ssr")]
+fn has_transparency(img: &image::DynamicImage) -> bool {
+    img.color().has_alpha() && img.to_rgba8().pixels().any(|pixel| pixel.0[3] < 255)
+}
+
+/// CSS background of a 3x3 thumbnail: the corners fade into the center color.
+#[cfg(feature = "ssr")]
+fn gradient_css(thumbnail: &image::DynamicImage) -> String {
+    let thumbnail = thumbnail.to_rgba8();
+    let color = |x, y| {
+        let image::Rgba([r, g, b, a]) = *thumbnail.get_pixel(x, y);
+        match a {
+            255 => format!("#{r:02x}{g:02x}{b:02x}"),
+            a => format!("#{r:02x}{g:02x}{b:02x}{a:02x}"),
+        }
+    };
+    let corners = [
+        ("0% 0%", (0, 0)),
+        ("100% 0%", (2, 0)),
+        ("0% 100%", (0, 2)),
+        ("100% 100%", (2, 2)),
+    ];
+    let mut layers: Vec<String> = corners
+        .into_iter()
+        .map(|(at, (x, y))| format!("radial-gradient(at {at}, {}, transparent 70%)", color(x, y)))
+        .collect();
+    let center = color(1, 1);
+    layers.push(format!("linear-gradient({center}, {center})"));
+    layers.join(", ")
 }
 
 #[cfg(feature = "ssr")]
-fn create_image_blur<P>(source_path: P, blur: Blur) -> Result<String, CreateImageError>
+fn create_image_blur<P>(
+    pipeline: &Pipeline,
+    source_path: P,
+    blur: Blur,
+) -> Result<Vec<u8>, CreateImageError>
 where
     P: AsRef<std::path::Path> + AsRef<std::ffi::OsStr>,
 {
     use webp::*;
 
-    let img = image::open(source_path).map_err(|e| CreateImageError::ImageError(e))?;
-
     let Blur {
         width,
         height,
         svg_height,
         svg_width,
         sigma,
+        crop,
+        aspect_ratio,
+        focal_point,
+        kind,
+        matte,
     } = blur;
 
-    let img = img.resize(width, height, image::imageops::FilterType::Nearest);
+    // Crops are in source pixels, decode at full size.
+    let target = (crop.is_none() && aspect_ratio.is_none()).then_some((width, height));
+    let img = decode_source(pipeline, source_path.as_ref(), target)?;
+    let img = crop_source(img, crop, aspect_ratio, focal_point)?;
+
+    // The browser blurs raster placeholders, smoother pixels upscale better.
+    let filter = match kind {
+        Placeholder::Blur => image::imageops::FilterType::Nearest,
+        Placeholder::Lqip | Placeholder::Gradient => image::imageops::FilterType::Triangle,
+    };
+    if kind == Placeholder::Gradient {
+        let thumbnail = apply_matte(img.resize_exact(3, 3, filter), matte);
+        return Ok(gradient_css(&thumbnail).into_bytes());
+    }
+    let img = apply_matte(img.resize(width, height, filter), matte);
+    // The WebP encoder only takes RGB and RGBA, grayscale sources are widened.
+    let img = if img.color().has_alpha() {
+        image::DynamicImage::ImageRgba8(img.to_rgba8())
+    } else {
+        image::DynamicImage::ImageRgb8(img.to_rgb8())
+    };
 
     // Create the WebP encoder for the above image
-    let encoder: Encoder = Encoder::from_image(&img).unwrap();
+    let encoder: Encoder =
+        Encoder::from_image(&img).map_err(|e| CreateImageError::EncodeError(e.into()))?;
     // Encode the image at a specified quality 0-100
     let webp: WebPMemory = encoder.encode(80.0);
+    if kind == Placeholder::Lqip {
+        return Ok(webp.to_vec());
+    }
 
     // Encode the image to base64
     use base64::{engine::general_purpose, Engine as _};
     let encoded = general_purpose::STANDARD.encode(&*webp);
 
     let uri = format!("data:image/webp;base64,{}", encoded);
+    // Forces the blurred edges opaque, which would turn transparent areas black.
+    let opaque = if has_transparency(&img) {
+        ""
+    } else {
+        concat!(
+            "        <feComponentTransfer>\n",
+            "            <feFuncA type=\"discrete\" tableValues=\"1 1\"/> \n",
+            "        </feComponentTransfer> \n",
+        )
+    };
 
     let svg = format!(
         r#"
 <svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink" width="100%" height="100%" viewBox="0 0 {svg_width} {svg_height}" preserveAspectRatio="none">
     <filter id="a" filterUnits="userSpaceOnUse" color-interpolation-filters="sRGB"> 
         <feGaussianBlur stdDeviation="{sigma}" edgeMode="duplicate"/> 
-        <feComponentTransfer>
-            <feFuncA type="discrete" tableValues="1 1"/> 
-        </feComponentTransfer> 
-    </filter> 
+{opaque}    </filter> 
     <image filter="url(#a)" x="0" y="0" height="100%" width="100%" href="{uri}"/>
 </svg>
 "#,
     );
 
-    Ok(svg)
+    Ok(svg.into_bytes())
 }
 
+/// An optimized image or placeholder of a source, as requested from the cache handler.
 #[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize, Hash)]
 pub struct CachedImage {
     pub(crate) src: String,
+    #[serde(rename = "t", default, skip_serializing_if = "Option::is_none")]
+    pub(crate) tenant: Option<String>,
     pub(crate) option: CachedImageOption,
 }
 
@@ -262,15 +2165,509 @@ pub(crate) enum CachedImageOption {
     Blur(Blur),
 }
 
-#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize, Hash)]
+/// Options of a resized (optimized) image.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize, Hash)]
 #[serde(rename = "r")]
-pub(crate) struct Resize {
+pub struct Resize {
+    /// Maximum width, aspect ratio is maintained.
     #[serde(rename = "w")]
     pub width: u32,
+    /// Maximum height, aspect ratio is maintained.
     #[serde(rename = "h")]
     pub height: u32,
+    /// Quality (0-100).
     #[serde(rename = "q")]
     pub quality: u8,
+    /// How animated sources are handled.
+    #[serde(rename = "a", default, skip_serializing_if = "Animation::is_default")]
+    pub animation: Animation,
+    /// Limits of an animated output, e.g. for small previews.
+    #[serde(rename = "fl", default, skip_serializing_if = "Option::is_none")]
+    pub frame_limits: Option<FrameLimits>,
+    /// Requested output format.
+    #[serde(rename = "f", default, skip_serializing_if = "OutputFormat::is_default")]
+    pub format: OutputFormat,
+    /// Lossless encoding.
+    #[serde(rename = "l", default, skip_serializing_if = "is_false")]
+    pub lossless: bool,
+    /// libwebp near-lossless level (0-100). Lower values preprocess more; 100 is plain lossless.
+    #[serde(rename = "nl", default, skip_serializing_if = "Option::is_none")]
+    pub near_lossless: Option<u8>,
+    /// Target DSSIM in millionths (e.g. `1000` = 0.001). Overrides `quality` with the
+    /// lowest quality that reaches the target. Requires the `auto-quality` feature.
+    #[serde(rename = "aq", default, skip_serializing_if = "Option::is_none")]
+    pub auto_quality: Option<u32>,
+    /// Region of the source to keep, applied before resizing.
+    #[serde(rename = "c", default, skip_serializing_if = "Option::is_none")]
+    pub crop: Option<Crop>,
+    /// Aspect ratio to crop the source (or its `crop` region) to, applied before resizing.
+    #[serde(rename = "ar", default, skip_serializing_if = "Option::is_none")]
+    pub aspect_ratio: Option<AspectRatio>,
+    /// Point kept in view by the `aspect_ratio` crop, the center by default.
+    #[serde(rename = "fp", default, skip_serializing_if = "Option::is_none")]
+    pub focal_point: Option<FocalPoint>,
+    /// How the image is fitted into the `width` x `height` box.
+    #[serde(rename = "fit", default, skip_serializing_if = "Fit::is_default")]
+    pub fit: Fit,
+    /// Resampling filter used to scale the source.
+    #[serde(rename = "rf", default, skip_serializing_if = "ResizeFilter::is_default")]
+    pub filter: ResizeFilter,
+    /// Unsharp mask applied after resizing, for downscaled photos that come out soft.
+    #[serde(rename = "sh", default, skip_serializing_if = "Option::is_none")]
+    pub sharpen: Option<Sharpen>,
+    /// Pads a [`Fit::Contain`] image to exactly the box with this color.
+    #[serde(rename = "bg", default, skip_serializing_if = "Option::is_none")]
+    pub background: Option<Background>,
+    /// Alpha mask applied to the output, e.g. for avatars.
+    #[serde(rename = "m", default, skip_serializing_if = "Option::is_none")]
+    pub mask: Option<Mask>,
+}
+
+/// Alpha mask of an optimized image, with anti-aliased edges.
+///
+/// The mask is part of the file, so it also applies where CSS can't, like emails
+/// and Open Graph previews. Needs an output format with alpha, JPEG fills with black.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(into = "String", try_from = "String")]
+pub enum Mask {
+    /// Rounded corners with this radius, in output pixels.
+    Rounded(u32),
+    /// Fully rounded ends: a circle for square images, a pill otherwise.
+    Circle,
+}
+
+impl From<Mask> for String {
+    fn from(mask: Mask) -> Self {
+        match mask {
+            Mask::Rounded(radius) => format!("r{radius}"),
+            Mask::Circle => "circle".to_string(),
+        }
+    }
+}
+
+impl TryFrom<String> for Mask {
+    type Error = String;
+
+    fn try_from(mask: String) -> Result<Self, Self::Error> {
+        match mask.as_str() {
+            "circle" => Ok(Self::Circle),
+            _ => mask
+                .strip_prefix('r')
+                .and_then(|radius| radius.parse().ok())
+                .map(Self::Rounded)
+                .ok_or(format!("Invalid mask {mask:?}")),
+        }
+    }
+}
+
+/// Makes the pixels outside the mask transparent, blending the edge pixels.
+#[cfg(feature = "ssr")]
+fn mask_image(img: image::DynamicImage, mask: Mask) -> image::DynamicImage {
+    let mut rgba = img.into_rgba8();
+    let (width, height) = (rgba.width() as f32, rgba.height() as f32);
+    let radius = match mask {
+        Mask::Rounded(radius) => (radius as f32).min(width.min(height) / 2.0),
+        Mask::Circle => width.min(height) / 2.0,
+    };
+    if radius > 0.0 {
+        for (x, y, pixel) in rgba.enumerate_pixels_mut() {
+            let (x, y) = (x as f32 + 0.5, y as f32 + 0.5);
+            // Distance to the nearest corner circle, zero along the straight edges.
+            let dx = x - x.clamp(radius, width - radius);
+            let dy = y - y.clamp(radius, height - radius);
+            let coverage = (radius - (dx * dx + dy * dy).sqrt() + 0.5).clamp(0.0, 1.0);
+            pixel.0[3] = (f32::from(pixel.0[3]) * coverage).round() as u8;
+        }
+    }
+    image::DynamicImage::ImageRgba8(rgba)
+}
+
+/// Unsharp mask applied to an optimized image after resizing.
+///
+/// Encoded in the URL as `auto` or `u{amount}_{radius}_{threshold}`, with the amount
+/// in percent and the radius in tenths of a pixel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(into = "String", try_from = "String")]
+pub enum Sharpen {
+    /// Strength picked from the scale factor: none when upscaling, more the further
+    /// the source is scaled down.
+    Auto,
+    /// Explicit unsharp mask, see [`Sharpen::unsharp`].
+    Unsharp {
+        /// Strength in percent of the difference to the blurred image.
+        amount: u16,
+        /// Blur radius (sigma) in tenths of a pixel.
+        radius: u16,
+        /// Minimum difference (0-255) a pixel needs to be sharpened, keeps flat areas
+        /// free of noise.
+        threshold: u8,
+    },
+}
+
+impl Sharpen {
+    /// An unsharp mask of `amount` (e.g. `0.5`), `radius` in pixels (e.g. `0.8`) and
+    /// `threshold` (0-255).
+    pub fn unsharp(amount: f32, radius: f32, threshold: u8) -> Self {
+        Self::Unsharp {
+            amount: (amount.max(0.0) * 100.0).round() as u16,
+            radius: (radius.max(0.0) * 10.0).round() as u16,
+            threshold,
+        }
+    }
+
+    // Amount, radius and threshold used for an image scaled down by `scale`.
+    #[cfg(feature = "ssr")]
+    fn parameters(self, scale: f32) -> (f32, f32, u8) {
+        match self {
+            Self::Auto if scale <= 1.0 => (0.0, 0.0, 0),
+            Self::Auto => ((0.25 * scale.log2()).clamp(0.1, 0.8), 0.6, 2),
+            Self::Unsharp {
+                amount,
+                radius,
+                threshold,
+            } => (f32::from(amount) / 100.0, f32::from(radius) / 10.0, threshold),
+        }
+    }
+}
+
+impl From<Sharpen> for String {
+    fn from(sharpen: Sharpen) -> Self {
+        match sharpen {
+            Sharpen::Auto => "auto".to_string(),
+            Sharpen::Unsharp {
+                amount,
+                radius,
+                threshold,
+            } => format!("u{amount}_{radius}_{threshold}"),
+        }
+    }
+}
+
+impl TryFrom<String> for Sharpen {
+    type Error = String;
+
+    fn try_from(sharpen: String) -> Result<Self, Self::Error> {
+        if sharpen == "auto" {
+            return Ok(Self::Auto);
+        }
+        let parsed = sharpen.strip_prefix('u').and_then(|parameters| {
+            let mut parameters = parameters.split('_');
+            let parsed = Self::Unsharp {
+                amount: parameters.next()?.parse().ok()?,
+                radius: parameters.next()?.parse().ok()?,
+                threshold: parameters.next()?.parse().ok()?,
+            };
+            parameters.next().is_none().then_some(parsed)
+        });
+        parsed.ok_or(format!("Invalid sharpen {sharpen:?}"))
+    }
+}
+
+/// Adds `amount` times the difference to a blurred copy to the color channels whose
+/// difference exceeds the threshold, leaving alpha alone.
+#[cfg(feature = "ssr")]
+fn sharpen_image(img: image::DynamicImage, sharpen: Sharpen, scale: f32) -> image::DynamicImage {
+    let (amount, radius, threshold) = sharpen.parameters(scale);
+    if amount <= 0.0 || radius <= 0.0 {
+        return img;
+    }
+    let has_alpha = img.color().has_alpha();
+    let mut rgba = img.into_rgba8();
+    let blurred = image::imageops::blur(&rgba, radius);
+    for (pixel, blurred) in rgba.pixels_mut().zip(blurred.pixels()) {
+        for channel in 0..3 {
+            let diff = f32::from(pixel.0[channel]) - f32::from(blurred.0[channel]);
+            if diff.abs() > f32::from(threshold) {
+                let value = f32::from(pixel.0[channel]) + amount * diff;
+                pixel.0[channel] = value.round().clamp(0.0, 255.0) as u8;
+            }
+        }
+    }
+    let rgba = image::DynamicImage::ImageRgba8(rgba);
+    if has_alpha {
+        rgba
+    } else {
+        image::DynamicImage::ImageRgb8(rgba.into_rgb8())
+    }
+}
+
+/// How an image is fitted into the requested box.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Fit {
+    /// Scale to fit inside the box, keeping the aspect ratio. The image can come out
+    /// smaller than the box unless a background pads it.
+    #[default]
+    Contain,
+    /// Scale to cover the box, keeping the aspect ratio, and crop the overflow centered.
+    Cover,
+    /// Stretch to exactly the box.
+    Fill,
+}
+
+impl Fit {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Resampling filter used to scale an image.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum ResizeFilter {
+    /// Nearest neighbor, keeps hard edges for pixel art and icons.
+    Nearest,
+    /// Linear, fast and soft.
+    Triangle,
+    /// Cubic, a good default for most images.
+    #[default]
+    CatmullRom,
+    /// Lanczos with window 3, the sharpest for photographs and also the slowest.
+    Lanczos3,
+}
+
+impl ResizeFilter {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    #[cfg(feature = "ssr")]
+    fn filter_type(self) -> image::imageops::FilterType {
+        use image::imageops::FilterType;
+        match self {
+            Self::Nearest => FilterType::Nearest,
+            Self::Triangle => FilterType::Triangle,
+            Self::CatmullRom => FilterType::CatmullRom,
+            Self::Lanczos3 => FilterType::Lanczos3,
+        }
+    }
+}
+
+/// RGBA padding color, e.g. `"#ffffff"`, `"#00000080"` or `"transparent"`.
+///
+/// Transparent padding needs an output format with alpha, JPEG pads with black.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(into = "String", try_from = "String")]
+pub struct Background(pub [u8; 4]);
+
+impl Background {
+    /// Fully transparent.
+    pub const TRANSPARENT: Self = Self([0, 0, 0, 0]);
+
+    /// Parses `#rgb`, `#rrggbb`, `#rrggbbaa` (`#` optional) or `transparent`.
+    pub fn parse(color: &str) -> Option<Self> {
+        let color = color.trim();
+        if color.eq_ignore_ascii_case("transparent") {
+            return Some(Self::TRANSPARENT);
+        }
+        let hex = color.strip_prefix('#').unwrap_or(color);
+        let hex = match hex.len() {
+            3 => hex.chars().flat_map(|c| [c, c]).collect(),
+            6 | 8 => hex.to_string(),
+            _ => return None,
+        };
+        let mut rgba = [255; 4];
+        for (channel, i) in rgba.iter_mut().zip((0..hex.len()).step_by(2)) {
+            *channel = u8::from_str_radix(hex.get(i..i + 2)?, 16).ok()?;
+        }
+        Some(Self(rgba))
+    }
+}
+
+impl From<&str> for Background {
+    /// Parses the color, anything invalid is transparent.
+    fn from(color: &str) -> Self {
+        Self::parse(color).unwrap_or(Self::TRANSPARENT)
+    }
+}
+
+impl From<Background> for String {
+    fn from(Background([r, g, b, a]): Background) -> Self {
+        format!("{r:02x}{g:02x}{b:02x}{a:02x}")
+    }
+}
+
+impl TryFrom<String> for Background {
+    type Error = String;
+
+    fn try_from(color: String) -> Result<Self, Self::Error> {
+        Self::parse(&color).ok_or(format!("Invalid color {color:?}"))
+    }
+}
+
+/// Region of a source image, in pixels of the upright (auto-oriented) source.
+///
+/// Regions reaching past the source are clipped to it.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize, Hash)]
+pub struct Crop {
+    /// Left edge.
+    pub x: u32,
+    /// Top edge.
+    pub y: u32,
+    /// Width of the region.
+    #[serde(rename = "w")]
+    pub width: u32,
+    /// Height of the region.
+    #[serde(rename = "h")]
+    pub height: u32,
+}
+
+impl Crop {
+    /// The region clipped to a `width` x `height` image, `None` if nothing is left.
+    #[cfg(feature = "ssr")]
+    pub(crate) fn clip(self, width: u32, height: u32) -> Option<(u32, u32, u32, u32)> {
+        let x = self.x.min(width);
+        let y = self.y.min(height);
+        let crop_width = self.width.min(width - x);
+        let crop_height = self.height.min(height - y);
+        (crop_width > 0 && crop_height > 0).then_some((x, y, crop_width, crop_height))
+    }
+}
+
+/// Width to height ratio, e.g. `(16, 9)` or `"16:9"`.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize, Hash)]
+pub struct AspectRatio {
+    /// Width part of the ratio.
+    #[serde(rename = "w")]
+    pub width: u32,
+    /// Height part of the ratio.
+    #[serde(rename = "h")]
+    pub height: u32,
+}
+
+impl From<(u32, u32)> for AspectRatio {
+    fn from((width, height): (u32, u32)) -> Self {
+        Self { width, height }
+    }
+}
+
+impl From<&str> for AspectRatio {
+    /// Parses `"16:9"` (or `"16/9"`), anything else is a square.
+    fn from(ratio: &str) -> Self {
+        let parsed = ratio.split_once([':', '/']).and_then(|(width, height)| {
+            Some((width.trim().parse().ok()?, height.trim().parse().ok()?))
+        });
+        let (width, height) = parsed.unwrap_or((1, 1));
+        Self { width, height }
+    }
+}
+
+/// Point of interest of a source, in percent of its width and height from the top left.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize, Hash)]
+pub struct FocalPoint {
+    /// Horizontal position (0-100).
+    pub x: u8,
+    /// Vertical position (0-100).
+    pub y: u8,
+}
+
+impl Default for FocalPoint {
+    fn default() -> Self {
+        Self { x: 50, y: 50 }
+    }
+}
+
+impl From<(u8, u8)> for FocalPoint {
+    fn from((x, y): (u8, u8)) -> Self {
+        Self { x, y }
+    }
+}
+
+/// Region of a `width` x `height` source kept by the crop options, `None` if nothing is left.
+///
+/// The explicit crop applies first, the aspect ratio crop takes the largest region of
+/// that ratio, as close to centered on the focal point as it fits.
+#[cfg(feature = "ssr")]
+pub(crate) fn source_region(
+    (width, height): (u32, u32),
+    crop: Option<Crop>,
+    aspect_ratio: Option<AspectRatio>,
+    focal_point: Option<FocalPoint>,
+) -> Option<(u32, u32, u32, u32)> {
+    let (x, y, width, height) = match crop {
+        Some(crop) => crop.clip(width, height)?,
+        None => (0, 0, width, height),
+    };
+    let Some(ratio) = aspect_ratio.filter(|ratio| ratio.width > 0 && ratio.height > 0) else {
+        return (width > 0 && height > 0).then_some((x, y, width, height));
+    };
+    if width == 0 || height == 0 {
+        return None;
+    }
+    let (ratio_width, ratio_height) = (u64::from(ratio.width), u64::from(ratio.height));
+    let (crop_width, crop_height) =
+        if u64::from(width) * ratio_height > u64::from(height) * ratio_width {
+            let crop_width = u64::from(height) * ratio_width / ratio_height;
+            (crop_width.max(1) as u32, height)
+        } else {
+            let crop_height = u64::from(width) * ratio_height / ratio_width;
+            (width, crop_height.max(1) as u32)
+        };
+    let focal_point = focal_point.unwrap_or_default();
+    let offset = |space: u32, size: u32, percent: u8| {
+        let center = u64::from(space) * u64::from(percent.min(100)) / 100;
+        (center.saturating_sub(u64::from(size) / 2) as u32).min(space - size)
+    };
+    Some((
+        x + offset(width, crop_width, focal_point.x),
+        y + offset(height, crop_height, focal_point.y),
+        crop_width,
+        crop_height,
+    ))
+}
+
+// Applies the crop options to a decoded source, failing for regions outside it.
+#[cfg(feature = "ssr")]
+fn crop_source(
+    img: std::sync::Arc<image::DynamicImage>,
+    crop: Option<Crop>,
+    aspect_ratio: Option<AspectRatio>,
+    focal_point: Option<FocalPoint>,
+) -> Result<std::sync::Arc<image::DynamicImage>, CreateImageError> {
+    if crop.is_none() && aspect_ratio.is_none() {
+        return Ok(img);
+    }
+    match source_region((img.width(), img.height()), crop, aspect_ratio, focal_point) {
+        Some((x, y, width, height)) => {
+            Ok(std::sync::Arc::new(img.crop_imm(x, y, width, height)))
+        }
+        None => Err(CreateImageError::InvalidParams(format!(
+            "Crop {crop:?} is outside the {}x{} source",
+            img.width(),
+            img.height()
+        ))),
+    }
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+impl Resize {
+    /// Whether a source fitting inside the box comes out at its own size.
+    #[cfg(feature = "ssr")]
+    pub(crate) fn keeps_size(&self) -> bool {
+        self.fit == Fit::Contain && self.background.is_none()
+    }
+
+    /// Whether only a region of the source is kept.
+    #[cfg(feature = "ssr")]
+    pub(crate) fn crops_source(&self) -> bool {
+        self.crop.is_some() || self.aspect_ratio.is_some()
+    }
+
+    /// The format that is actually encoded, after feature fallbacks.
+    #[cfg(feature = "ssr")]
+    pub(crate) fn output_format(&self) -> OutputFormat {
+        match self.format {
+            OutputFormat::Avif if cfg!(feature = "avif") => OutputFormat::Avif,
+            OutputFormat::Jxl if cfg!(feature = "jxl") => OutputFormat::Jxl,
+            OutputFormat::PalettePng if cfg!(feature = "quantize") => OutputFormat::PalettePng,
+            OutputFormat::Original => OutputFormat::Original,
+            OutputFormat::Jpeg => OutputFormat::Jpeg,
+            _ => OutputFormat::Webp,
+        }
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize, Hash)]
@@ -286,66 +2683,432 @@ pub(crate) struct Blur {
     pub svg_height: u32,
     #[serde(rename = "s")]
     pub sigma: u8,
+    #[serde(rename = "c", default, skip_serializing_if = "Option::is_none")]
+    pub crop: Option<Crop>,
+    #[serde(rename = "ar", default, skip_serializing_if = "Option::is_none")]
+    pub aspect_ratio: Option<AspectRatio>,
+    #[serde(rename = "fp", default, skip_serializing_if = "Option::is_none")]
+    pub focal_point: Option<FocalPoint>,
+    #[serde(rename = "k", default, skip_serializing_if = "Placeholder::is_default")]
+    pub kind: Placeholder,
+    /// Background transparent sources are composited onto, they stay transparent without.
+    #[serde(rename = "mt", default, skip_serializing_if = "Option::is_none")]
+    pub matte: Option<Background>,
+}
+
+/// Kind of placeholder shown while the optimized image loads.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Placeholder {
+    /// SVG with a Gaussian blur filter over a tiny embedded image.
+    #[default]
+    Blur,
+    /// Tiny raster image, blurred with CSS by the component. Looks the same in every
+    /// browser, where the SVG filter renders poorly in some (e.g. Safari).
+    Lqip,
+    /// CSS gradient between colors sampled at the corners and center, no image bytes
+    /// and no `data:` URI. Only shown once the placeholder is held in memory.
+    Gradient,
+}
+
+impl Placeholder {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Limits of an animated output, keeping previews of long animations small.
+///
+/// Frames past either maximum are dropped, so the animation ends early and loops.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize, Hash)]
+pub struct FrameLimits {
+    /// Maximum number of frames, counted after `every`.
+    #[serde(rename = "n", default, skip_serializing_if = "Option::is_none")]
+    pub max_frames: Option<u32>,
+    /// Maximum duration in milliseconds.
+    #[serde(rename = "d", default, skip_serializing_if = "Option::is_none")]
+    pub max_duration_ms: Option<u32>,
+    /// Keeps every nth frame, each showing for the frames it replaces.
+    #[serde(rename = "e", default, skip_serializing_if = "Option::is_none")]
+    pub every: Option<u32>,
+}
+
+impl FrameLimits {
+    /// No limits yet.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keeps at most `max_frames` frames.
+    pub fn max_frames(mut self, max_frames: u32) -> Self {
+        self.max_frames = Some(max_frames);
+        self
+    }
+
+    /// Stops the animation after `max_duration`.
+    pub fn max_duration(mut self, max_duration: std::time::Duration) -> Self {
+        self.max_duration_ms = Some(max_duration.as_millis().min(u32::MAX as u128) as u32);
+        self
+    }
+
+    /// Keeps every nth frame, e.g. `2` halves the frame rate.
+    pub fn every(mut self, every: u32) -> Self {
+        self.every = Some(every);
+        self
+    }
+}
+
+/// How animated sources (e.g. GIFs) are handled.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Animation {
+    /// Keep every frame and output an animated WebP.
+    #[default]
+    Animate,
+    /// Only keep the first frame, e.g. as a poster for a play-on-click UI.
+    Poster,
+}
+
+impl Animation {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Output format of an optimized image.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// Let the server (or image CDN) pick the best format.
+    /// The cache handler negotiates JPEG XL, AVIF or WebP from the `Accept` header.
+    #[default]
+    Auto,
+    /// WebP.
+    Webp,
+    /// AVIF. Requires the `avif` feature, falls back to WebP otherwise.
+    Avif,
+    /// Re-encode in the source format (JPEG stays JPEG, PNG stays PNG).
+    Original,
+    /// JPEG, progressive when the `mozjpeg` feature is enabled.
+    Jpeg,
+    /// JPEG XL. Requires the `jxl` feature, falls back to WebP otherwise.
+    Jxl,
+    /// Palette quantized PNG, often smaller than WebP for logos, charts and screenshots.
+    /// Requires the `quantize` feature, falls back to WebP otherwise.
+    #[serde(rename = "png8")]
+    PalettePng,
 }
 
+impl OutputFormat {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Errors that can occur while creating an optimized image.
 #[cfg(feature = "ssr")]
 #[derive(Debug, thiserror::Error)]
 pub enum CreateImageError {
     // Unexpected(String),
+    /// Decoding or encoding with the `image` crate failed.
     #[error("Image Error: {0}")]
     ImageError(#[from] image::ImageError),
+    /// The blocking encode task panicked or was cancelled.
     #[error("Join Error: {0}")]
     JoinError(#[from] tokio::task::JoinError),
+    /// Reading the source or writing the cache failed.
     #[error("IO Error: {0}")]
     IOError(#[from] std::io::Error),
+    /// The encoder failed.
+    #[error("Encode Error: {0}")]
+    EncodeError(String),
+    /// A feature-gated decoder failed.
+    #[error("Decode Error: {0}")]
+    DecodeError(String),
+    /// The source image does not exist.
+    #[error("Source Not Found: {0}")]
+    SourceNotFound(String),
+    /// The source is not a supported image, or its content doesn't match its extension.
+    #[error("Unsupported Format: {0}")]
+    UnsupportedFormat(String),
+    /// The source resolves outside the root and mounts.
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
+    /// The request parameters could not be parsed.
+    #[error("Invalid Params: {0}")]
+    InvalidParams(String),
+    /// The tenant is over its disk quota, see [`ImageOptimizer::with_tenant_quota`].
+    #[error("Quota Exceeded: {0}")]
+    QuotaExceeded(String),
+    /// The image wasn't generated ahead of time and the optimizer is read-only.
+    #[error("Read Only: {0}")]
+    ReadOnly(String),
+    /// The optimizer is shutting down and no longer creates images.
+    #[error("Shutting Down")]
+    ShuttingDown,
+    /// Every request for the image went away before it was encoded.
+    #[error("Cancelled")]
+    Cancelled,
+    /// The encode would need more memory, in bytes, than
+    /// [`ImageOptimizer::with_memory_budget`] allows.
+    #[error("Memory Budget Exceeded: {0} bytes")]
+    MemoryBudgetExceeded(u64),
+    /// The encode ran longer than [`ImageOptimizer::with_encode_timeout`].
+    #[error("Timeout: {0:?}")]
+    Timeout(std::time::Duration),
 }
 
-impl CachedImage {
-    pub(crate) fn get_url_encoded(&self, handler_path: impl AsRef<str>) -> String {
-        let params = serde_qs::to_string(&self).unwrap();
-        format!("{}?{}", handler_path.as_ref(), params)
+impl CachedImage {
+    /// The optimized image of `src` with these options.
+    pub fn new(src: impl Into<String>, options: Resize) -> Self {
+        Self {
+            src: src.into(),
+            tenant: None,
+            option: CachedImageOption::Resize(options),
+        }
+    }
+
+    /// The source image.
+    pub fn src(&self) -> &str {
+        &self.src
+    }
+
+    /// The cache namespace, see [`crate::provide_image_tenant`].
+    pub fn tenant(&self) -> Option<&str> {
+        self.tenant.as_deref()
+    }
+
+    /// The options as a query string, e.g. for a [`crate::CacheKey`].
+    pub fn query(&self) -> String {
+        serde_qs::to_string(&self).unwrap()
     }
 
+    /// Reads options written by [`Self::query`].
     #[cfg(feature = "ssr")]
-    pub(crate) fn get_file_path(&self) -> String {
+    pub fn from_query(query: &str) -> Result<Self, String> {
+        serde_qs::from_str(query).map_err(|e| e.to_string())
+    }
+
+    /// MIME type of the optimized image.
+    #[cfg(feature = "ssr")]
+    pub(crate) fn content_type(&self) -> String {
+        match self.extension().as_str() {
+            "svg" => "image/svg+xml".into(),
+            "css" => "text/css".into(),
+            "jpg" => "image/jpeg".into(),
+            extension => format!("image/{extension}"),
+        }
+    }
+
+    /// File extension of the optimized image.
+    #[cfg(feature = "ssr")]
+    pub fn extension(&self) -> String {
+        match &self.option {
+            // SVG sources are passed through rather than rasterized.
+            CachedImageOption::Resize(_) if is_svg(&self.src) => "svg".into(),
+            CachedImageOption::Resize(resize) => match resize.output_format() {
+                OutputFormat::Avif => "avif".into(),
+                OutputFormat::Jpeg => "jpg".into(),
+                OutputFormat::Jxl => "jxl".into(),
+                OutputFormat::PalettePng => "png".into(),
+                // Sources the `image` crate can't encode (e.g. HEIC) fall back to WebP.
+                OutputFormat::Original => std::path::Path::new(&self.src)
+                    .extension()
+                    .filter(|ext| image::ImageFormat::from_extension(ext).is_some())
+                    .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+                    .unwrap_or_else(|| "webp".into()),
+                _ => "webp".into(),
+            },
+            CachedImageOption::Blur(blur) => match blur.kind {
+                Placeholder::Blur => "svg".into(),
+                Placeholder::Lqip => "webp".into(),
+                Placeholder::Gradient => "css".into(),
+            },
+        }
+    }
+
+    /// What the in-memory cache holds for a placeholder file: SVG markup, or a
+    /// `data:` URI for raster placeholders.
+    #[cfg(feature = "ssr")]
+    pub(crate) fn placeholder_value(&self, bytes: Vec<u8>) -> std::io::Result<String> {
         use base64::{engine::general_purpose, Engine as _};
-        // I'm worried this name will become too long.
-        // names are limited to 255 bytes on most filesystems.
 
-        let encode = serde_qs::to_string(&self).unwrap();
-        let encode = general_purpose::STANDARD.encode(encode);
+        match &self.option {
+            CachedImageOption::Blur(Blur {
+                kind: Placeholder::Lqip,
+                ..
+            }) => {
+                let encoded = general_purpose::STANDARD.encode(bytes);
+                Ok(format!("data:{};base64,{encoded}", self.content_type()))
+            }
+            _ => String::from_utf8(bytes).map_err(std::io::Error::other),
+        }
+    }
 
-        let mut path = path_from_segments(vec!["cache/image", &encode, &self.src]);
+    /// Path of the cached file below `cache/image` with the default key, relative to
+    /// the site root. Optimizers map it with [`ImageOptimizer::cache_path`].
+    #[cfg(all(test, feature = "ssr"))]
+    pub(crate) fn get_file_path(&self) -> String {
+        let path = path_from_segments(vec![CACHE_DIR, &self.cache_key()]);
+        path.as_path().to_string_lossy().to_string()
+    }
 
-        if let CachedImageOption::Resize { .. } = self.option {
-            path.set_extension("webp");
-        } else {
-            path.set_extension("svg");
+    /// Path of the cached file with [`crate::DefaultCacheKey`], relative to the cache
+    /// directory.
+    ///
+    /// Named after a hash of the options, so it stays short whatever the `src`.
+    /// The options are kept in a `.qs` sidecar next to it, see [`Self::from_file_path`].
+    #[cfg(feature = "ssr")]
+    pub(crate) fn cache_key(&self) -> String {
+        let encode = serde_qs::to_string(&self).unwrap();
+        let hash = blake3::hash(encode.as_bytes()).to_hex();
+        let hash = &hash[..32];
+
+        let mut path = match &self.tenant {
+            Some(tenant) => path_from_segments(vec!["tenants", &tenant_name(tenant)]),
+            None => std::path::PathBuf::new(),
         };
+        path.push(&hash[..2]);
+        path.push(hash);
+        path.set_extension(self.extension());
 
         path.as_path().to_string_lossy().to_string()
     }
 
-    #[allow(dead_code)]
+    /// Writes the `.qs` sidecar holding the options of a cached file.
+    #[cfg(feature = "ssr")]
+    pub(crate) fn write_sidecar(&self, file_path: &std::path::Path) -> std::io::Result<()> {
+        let encode = serde_qs::to_string(&self).unwrap();
+        write_atomic(file_path.with_extension(SIDECAR_EXTENSION), encode)
+    }
+
+    /// Reads the options of a cached file from its sidecar, or from the path itself
+    /// for caches written before hashed paths.
     #[cfg(feature = "ssr")]
-    // TODO: Fix this. Super Yuck.
     pub(crate) fn from_file_path(path: &str) -> Option<Self> {
+        let sidecar = std::path::Path::new(path).with_extension(SIDECAR_EXTENSION);
+        std::fs::read_to_string(sidecar)
+            .ok()
+            .and_then(|encoded| serde_qs::from_str(&encoded).ok())
+            .or_else(|| Self::from_legacy_file_path(path))
+    }
+
+    /// Parses `cache/image/{base64 options}/{src}` paths. The standard base64 alphabet
+    /// contains `/`, so the options may span several segments.
+    #[cfg(feature = "ssr")]
+    fn from_legacy_file_path(path: &str) -> Option<Self> {
         use base64::{engine::general_purpose, Engine as _};
-        path.split('/')
-            .filter_map(|s| {
+
+        let segments: Vec<&str> = path.split('/').collect();
+        (0..segments.len()).find_map(|start| {
+            (start + 1..=segments.len()).find_map(|end| {
                 general_purpose::STANDARD
-                    .decode(s)
+                    .decode(segments[start..end].join("/"))
                     .ok()
                     .and_then(|s| String::from_utf8(s).ok())
+                    .and_then(|encoded| serde_qs::from_str(&encoded).ok())
             })
-            .find_map(|encoded| serde_qs::from_str(&encoded).ok())
+        })
     }
+}
 
-    #[cfg(feature = "ssr")]
-    pub(crate) fn from_url_encoded(url: &str) -> Result<CachedImage, serde_qs::Error> {
-        let url = url.split('?').filter(|s| *s != "?").last().unwrap_or(url);
-        let result: Result<CachedImage, serde_qs::Error> = serde_qs::from_str(url);
-        result
+/// Prefix of cache paths and URLs, and the cache directory below the site root
+/// unless [`ImageOptimizer::with_cache_dir`] moves it.
+#[cfg(feature = "ssr")]
+pub(crate) const CACHE_DIR: &str = "cache/image";
+
+#[cfg(feature = "ssr")]
+pub(crate) const SIDECAR_EXTENSION: &str = "qs";
+
+/// Cache directory of a tenant, relative to the site root.
+#[cfg(feature = "ssr")]
+pub(crate) fn tenant_dir(tenant: &str) -> std::path::PathBuf {
+    path_from_segments(vec![CACHE_DIR, "tenants", &tenant_name(tenant)])
+}
+
+// Tenant names are reduced to `[A-Za-z0-9_-]` so they can't escape their directory.
+#[cfg(feature = "ssr")]
+fn tenant_name(tenant: &str) -> String {
+    let tenant: String = tenant
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' => c,
+            _ => '_',
+        })
+        .collect();
+    if tenant.is_empty() {
+        "_".into()
+    } else {
+        tenant
+    }
+}
+
+#[cfg(feature = "ssr")]
+const LOCK_EXTENSION: &str = "lock";
+#[cfg(feature = "ssr")]
+pub(crate) const TEMP_EXTENSION: &str = "tmp";
+
+// Extensions of the `Accept-Encoding` siblings served by the cache route.
+#[cfg(feature = "ssr")]
+pub(crate) const PRECOMPRESSED_EXTENSIONS: [&str; 2] = ["gz", "br"];
+
+#[cfg(feature = "ssr")]
+pub(crate) fn collect_files(
+    dir: &std::path::Path,
+    files: &mut Vec<std::path::PathBuf>,
+) -> std::io::Result<()> {
+    if !dir.exists() {
+        return Ok(());
+    }
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+// Optimized images, as opposed to sidecars, locks and temporary files.
+#[cfg(feature = "ssr")]
+pub(crate) fn is_cache_entry(path: &std::path::Path) -> bool {
+    path.extension().is_some_and(|ext| {
+        ext != SIDECAR_EXTENSION
+            && ext != LOCK_EXTENSION
+            && ext != TEMP_EXTENSION
+            && !PRECOMPRESSED_EXTENSIONS.iter().any(|precompressed| ext == *precompressed)
+    })
+}
+
+// Whether a cached file looks complete: readable image header, or a closed SVG.
+#[cfg(feature = "ssr")]
+fn is_intact(path: &std::path::Path) -> bool {
+    let is_svg = path.extension().is_some_and(|ext| ext == "svg");
+    if is_svg {
+        return std::fs::read_to_string(path)
+            .is_ok_and(|svg| svg.trim_end().ends_with("</svg>"));
+    }
+    match image::ImageFormat::from_path(path) {
+        Ok(format) if format.reading_enabled() => image::image_dimensions(path).is_ok(),
+        // Formats we can't decode here (e.g. JPEG XL) are only checked for being non-empty.
+        _ => true,
+    }
+}
+
+// Best effort, directories that still have files fail to delete.
+#[cfg(feature = "ssr")]
+pub(crate) fn remove_empty_dirs(dir: &std::path::Path) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            remove_empty_dirs(&path);
+            let _ = std::fs::remove_dir(&path);
+        }
     }
 }
 
@@ -359,6 +3122,23 @@ fn path_from_segments(segments: Vec<&str>) -> std::path::PathBuf {
         .collect()
 }
 
+// Keeps a source whose canonical path is below the canonical root of its location.
+#[cfg(feature = "ssr")]
+fn inside_root(
+    src: &str,
+    path: std::path::PathBuf,
+    resolved: &std::path::Path,
+    root: Option<&std::path::Path>,
+) -> Result<std::path::PathBuf, CreateImageError> {
+    match root {
+        Some(root) if resolved.starts_with(root) => Ok(path),
+        _ => {
+            tracing::warn!("Source {src} resolves outside its root: {}", resolved.display());
+            Err(CreateImageError::Forbidden(src.to_string()))
+        }
+    }
+}
+
 #[cfg(feature = "ssr")]
 async fn file_exists<P>(path: P) -> bool
 where
@@ -368,7 +3148,46 @@ where
 }
 
 #[cfg(feature = "ssr")]
-fn create_nested_if_needed<P>(path: P) -> std::io::Result<()>
+fn is_fresh_blocking(
+    save_path: &std::path::Path,
+    source_path: &std::path::Path,
+    freshness: Option<Freshness>,
+) -> bool {
+    let modified = |path: &std::path::Path| {
+        std::fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    };
+    let Some(cached) = modified(save_path) else {
+        return false;
+    };
+    let Some(freshness) = freshness else {
+        return true;
+    };
+    // The sidecar is written with each encode, the file may carry its source's time.
+    let encoded = modified(&save_path.with_extension(SIDECAR_EXTENSION)).unwrap_or(cached);
+    if let Some(max_age) = freshness.max_age {
+        if encoded.elapsed().is_ok_and(|age| age > max_age) {
+            return false;
+        }
+    }
+    match modified(source_path) {
+        Some(source) if freshness.source_changes => encoded >= source,
+        _ => true,
+    }
+}
+
+/// When cached files are created again instead of served, see
+/// [`ImageOptimizer::with_max_age`] and [`ImageOptimizer::with_source_revalidation`].
+#[cfg(feature = "ssr")]
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Freshness {
+    pub(crate) max_age: Option<std::time::Duration>,
+    pub(crate) source_changes: bool,
+}
+
+#[cfg(feature = "ssr")]
+pub(crate) fn create_nested_if_needed<P>(path: P) -> std::io::Result<()>
 where
     P: AsRef<std::ffi::OsStr>,
 {
@@ -383,52 +3202,80 @@ where
 #[cfg(test)]
 mod optimizer_tests {
     use super::*;
+    use crate::test_support::{resize_spec, test_root, TEST_IMAGE};
 
     #[test]
     fn url_encode() {
+        use crate::{CacheKey, ServerCacheKey};
+
         let img = CachedImage {
             src: "test.jpg".to_string(),
+            tenant: None,
             option: CachedImageOption::Resize(Resize {
                 quality: 75,
                 width: 100,
                 height: 100,
+                ..Default::default()
             }),
         };
 
-        let encoded = img.get_url_encoded("/cache/image/test");
-        let decoded: CachedImage = CachedImage::from_url_encoded(&encoded).unwrap();
+        let encoded = crate::DefaultCacheKey.url(&img, "/cache/image/test");
+        let decoded = crate::DefaultCacheKey.parse_url(&encoded, "/cache/image/test").unwrap();
 
         dbg!(encoded);
         assert!(img == decoded);
     }
 
-    const TEST_IMAGE: &str = "./example/start-axum/public/cute_ferris.png";
-
     #[test]
     fn file_path() {
         let spec = CachedImage {
             src: TEST_IMAGE.to_string(),
+            tenant: None,
             option: CachedImageOption::Blur(Blur {
                 width: 25,
                 height: 25,
                 svg_height: 100,
                 svg_width: 100,
                 sigma: 20,
+                crop: None,
+                aspect_ratio: None,
+                focal_point: None,
+                kind: Placeholder::Blur,
+                matte: None,
             }),
         };
 
         let file_path = spec.get_file_path();
 
         dbg!(spec.get_file_path());
+        assert!(file_path.len() < 64);
 
+        create_nested_if_needed(&file_path).unwrap();
+        spec.write_sidecar(std::path::Path::new(&file_path)).unwrap();
         let result = CachedImage::from_file_path(&file_path).unwrap();
 
         assert_eq!(spec, result);
     }
 
+    #[test]
+    fn legacy_file_path() {
+        use base64::{engine::general_purpose, Engine as _};
+
+        let spec = CachedImage {
+            src: "/deep/path/to/image.png".to_string(),
+            tenant: None,
+            option: CachedImageOption::Resize(resize_spec(640, 480)),
+        };
+        let encode = general_purpose::STANDARD.encode(serde_qs::to_string(&spec).unwrap());
+        let legacy = format!("cache/image/{encode}/deep/path/to/image.webp");
+
+        assert_eq!(CachedImage::from_legacy_file_path(&legacy), Some(spec));
+    }
+
     #[test]
     fn create_blur() {
         let result = create_image_blur(
+            &Pipeline::default(),
             TEST_IMAGE.to_string(),
             Blur {
                 width: 25,
@@ -436,51 +3283,781 @@ mod optimizer_tests {
                 svg_height: 100,
                 svg_width: 100,
                 sigma: 20,
+                crop: None,
+                aspect_ratio: None,
+                focal_point: None,
+                kind: Placeholder::Blur,
+                matte: None,
             },
         );
         assert!(result.is_ok());
-        println!("{}", result.unwrap());
+        println!("{}", String::from_utf8_lossy(&result.unwrap()));
+
+        let lqip = Blur {
+            width: 16,
+            height: 16,
+            svg_height: 100,
+            svg_width: 100,
+            sigma: 20,
+            crop: None,
+            aspect_ratio: None,
+            focal_point: None,
+            kind: Placeholder::Lqip,
+            matte: None,
+        };
+        let webp =
+            create_image_blur(&Pipeline::default(), TEST_IMAGE.to_string(), lqip.clone()).unwrap();
+        assert_eq!(image::guess_format(&webp).unwrap(), image::ImageFormat::WebP);
+
+        let gradient = Blur {
+            kind: Placeholder::Gradient,
+            ..lqip
+        };
+        let css = create_image_blur(&Pipeline::default(), TEST_IMAGE.to_string(), gradient);
+        let css = String::from_utf8(css.unwrap()).unwrap();
+        assert!(css.starts_with("radial-gradient(at 0% 0%, #"), "{css}");
+        assert!(css.ends_with(')'), "{css}");
+    }
+
+    #[test]
+    fn transparent_blur() {
+        let source = std::env::temp_dir().join("leptos_image_transparent.png");
+        image::RgbaImage::from_fn(32, 32, |x, _| image::Rgba([255, 0, 0, (x * 8) as u8]))
+            .save(&source)
+            .unwrap();
+        let blur = |matte| Blur {
+            width: 20,
+            height: 20,
+            svg_height: 100,
+            svg_width: 100,
+            sigma: 15,
+            crop: None,
+            aspect_ratio: None,
+            focal_point: None,
+            kind: Placeholder::Blur,
+            matte,
+        };
+        let create = |matte| {
+            let svg = create_image_blur(&Pipeline::default(), &source, blur(matte)).unwrap();
+            String::from_utf8(svg).unwrap()
+        };
+        assert!(!create(None).contains("feFuncA"));
+        assert!(create(Some("#ffffff".into())).contains("feFuncA"));
+
+        let gray = std::env::temp_dir().join("leptos_image_transparent_gray.png");
+        image::GrayAlphaImage::from_pixel(32, 32, image::LumaA([128, 100]))
+            .save(&gray)
+            .unwrap();
+        assert!(create_image_blur(&Pipeline::default(), &gray, blur(None)).is_ok());
     }
 
     #[test]
     fn create_and_save_blur() {
         let spec = CachedImage {
             src: TEST_IMAGE.to_string(),
+            tenant: None,
             option: CachedImageOption::Blur(Blur {
                 width: 25,
                 height: 25,
                 svg_height: 100,
                 svg_width: 100,
                 sigma: 20,
+                crop: None,
+                aspect_ratio: None,
+                focal_point: None,
+                kind: Placeholder::Blur,
+                matte: None,
             }),
         };
 
         let file_path = spec.get_file_path();
 
-        let result = create_optimized_image(spec.option, TEST_IMAGE.to_string(), file_path.clone());
+        let result = create_optimized_image(
+            &Pipeline::default(),
+            spec.option,
+            TEST_IMAGE.to_string(),
+            file_path.clone(),
+        );
 
         assert!(result.is_ok());
 
         println!("Saved SVG at {file_path}");
     }
 
+    #[test]
+    fn svg_passthrough() {
+        assert!(is_svg("/logo.svg"));
+        assert!(is_svg("/logo.SVG?v=2"));
+        assert!(!is_svg("/logo.png"));
+
+        let svg = "<svg>\n  <!-- comment -->\n  <rect/>\n  <text><tspan>a</tspan> <tspan>b</tspan></text>\n</svg>\n";
+        assert_eq!(
+            minify_svg(svg),
+            "<svg><rect/><text><tspan>a</tspan> <tspan>b</tspan></text></svg>"
+        );
+    }
+
     #[test]
     fn create_opt_image() {
         let spec = CachedImage {
             src: TEST_IMAGE.to_string(),
+            tenant: None,
             option: CachedImageOption::Resize(Resize {
                 quality: 75,
                 width: 100,
                 height: 100,
+                ..Default::default()
             }),
         };
 
         let file_path = spec.get_file_path();
 
-        let result = create_optimized_image(spec.option, TEST_IMAGE.to_string(), file_path.clone());
+        let result = create_optimized_image(
+            &Pipeline::default(),
+            spec.option,
+            TEST_IMAGE.to_string(),
+            file_path.clone(),
+        );
 
         assert!(result.is_ok());
 
         println!("Saved WebP at {file_path}");
     }
+
+    #[test]
+    fn fit_and_background() {
+        let img = image::open(TEST_IMAGE).unwrap();
+        let resize = |fit, background| Resize {
+            fit,
+            background,
+            ..resize_spec(100, 100)
+        };
+        assert_eq!(fit_image(&img, &resize(Fit::Contain, None)).height(), 67);
+        assert_eq!(fit_image(&img, &resize(Fit::Cover, None)).height(), 100);
+        assert_eq!(fit_image(&img, &resize(Fit::Fill, None)).width(), 100);
+
+        let padded = fit_image(&img, &resize(Fit::Contain, Some("#fff".into()))).to_rgba8();
+        assert_eq!(padded.dimensions(), (100, 100));
+        assert_eq!(padded.get_pixel(0, 0).0, [255, 255, 255, 255]);
+
+        assert_eq!(Background::parse("#00000080"), Some(Background([0, 0, 0, 128])));
+        assert_eq!(Background::parse("Transparent"), Some(Background::TRANSPARENT));
+        assert_eq!(Background::parse("#12345"), None);
+        let encoded = serde_qs::to_string(&resize(Fit::Contain, Some("#fff".into()))).unwrap();
+        assert!(encoded.contains("bg=ffffffff"), "{encoded}");
+    }
+
+    #[test]
+    fn sharpening() {
+        // A soft edge gets steeper, flat areas stay untouched.
+        let img = image::DynamicImage::ImageLuma8(image::GrayImage::from_fn(16, 4, |x, _| {
+            image::Luma([(x * 16).min(255) as u8])
+        }))
+        .into_rgb8();
+        let img = image::DynamicImage::ImageRgb8(img);
+        let sharpened = sharpen_image(img.clone(), Sharpen::unsharp(1.0, 1.0, 0), 1.0);
+        assert_ne!(sharpened.to_rgb8(), img.to_rgb8());
+        assert!(!sharpened.color().has_alpha());
+        let flat = image::DynamicImage::ImageRgb8(image::RgbImage::new(8, 8));
+        assert_eq!(sharpen_image(flat.clone(), Sharpen::Auto, 4.0), flat);
+        // No sharpening when upscaling.
+        assert_eq!(sharpen_image(img.clone(), Sharpen::Auto, 0.5), img);
+
+        let unsharp = Sharpen::unsharp(0.5, 0.8, 2);
+        assert_eq!(String::from(unsharp), "u50_8_2");
+        assert_eq!(Sharpen::try_from("u50_8_2".to_string()), Ok(unsharp));
+        assert_eq!(Sharpen::try_from("auto".to_string()), Ok(Sharpen::Auto));
+        assert!(Sharpen::try_from("u50_8".to_string()).is_err());
+    }
+
+    #[test]
+    fn frame_limits() {
+        let frames = || -> Vec<image::Frame> {
+            (0..10)
+                .map(|_| {
+                    image::Frame::from_parts(
+                        image::RgbaImage::new(4, 4),
+                        0,
+                        0,
+                        image::Delay::from_numer_denom_ms(100, 1),
+                    )
+                })
+                .collect()
+        };
+        let limited = limit_frames(frames(), FrameLimits::new().every(3));
+        assert_eq!(limited.len(), 4);
+        assert_eq!(limited[0].delay().numer_denom_ms(), (300, 1));
+        assert_eq!(limited[3].delay().numer_denom_ms(), (100, 1));
+
+        let limits = FrameLimits::new().max_duration(std::time::Duration::from_millis(450));
+        assert_eq!(limit_frames(frames(), limits).len(), 5);
+        let limits = FrameLimits::new().every(2).max_frames(2);
+        assert_eq!(limit_frames(frames(), limits).len(), 2);
+    }
+
+    #[test]
+    fn resize_filters() {
+        // A 2x2 checkerboard scaled up keeps only its two colors with `Nearest`.
+        let img = image::DynamicImage::ImageLuma8(image::GrayImage::from_fn(2, 2, |x, y| {
+            image::Luma([if (x + y) % 2 == 0 { 0 } else { 255 }])
+        }));
+        let resize = |filter| Resize {
+            width: 8,
+            height: 8,
+            fit: Fit::Fill,
+            filter,
+            ..Default::default()
+        };
+        let nearest = fit_image(&img, &resize(ResizeFilter::Nearest)).to_luma8();
+        assert!(nearest.pixels().all(|pixel| matches!(pixel.0[0], 0 | 255)));
+        let smooth = fit_image(&img, &resize(ResizeFilter::Lanczos3)).to_luma8();
+        assert!(smooth.pixels().any(|pixel| !matches!(pixel.0[0], 0 | 255)));
+
+        // The default filter keeps existing cache keys.
+        let encoded = serde_qs::to_string(&resize(ResizeFilter::CatmullRom)).unwrap();
+        assert!(!encoded.contains("rf="), "{encoded}");
+        let encoded = serde_qs::to_string(&resize(ResizeFilter::Lanczos3)).unwrap();
+        assert!(encoded.contains("rf=lanczos3"), "{encoded}");
+    }
+
+    #[test]
+    fn masks() {
+        let img = image::DynamicImage::ImageRgb8(image::RgbImage::new(40, 20));
+        let circle = mask_image(img.clone(), Mask::Circle).to_rgba8();
+        assert_eq!(circle.get_pixel(0, 0).0[3], 0);
+        assert_eq!(circle.get_pixel(20, 10).0[3], 255);
+        assert_eq!(circle.get_pixel(20, 0).0[3], 255);
+
+        let rounded = mask_image(img, Mask::Rounded(4)).to_rgba8();
+        assert_eq!(rounded.get_pixel(0, 0).0[3], 0);
+        assert_eq!(rounded.get_pixel(4, 0).0[3], 255);
+
+        assert_eq!(Mask::try_from(String::from(Mask::Rounded(12))), Ok(Mask::Rounded(12)));
+        assert_eq!(Mask::try_from("circle".to_string()), Ok(Mask::Circle));
+        assert!(Mask::try_from("square".to_string()).is_err());
+    }
+
+    #[test]
+    fn region_crop() {
+        let crop = Crop {
+            x: 10,
+            y: 20,
+            width: 200,
+            height: 100,
+        };
+        let resize = |crop| {
+            CachedImageOption::Resize(Resize {
+                crop: Some(crop),
+                ..resize_spec(100, 100)
+            })
+        };
+        let path = std::path::Path::new(TEST_IMAGE);
+        let encoded = encode_optimized_image(&Pipeline::default(), resize(crop), path).unwrap();
+        let img = image::load_from_memory(&encoded).unwrap();
+        assert_eq!((img.width(), img.height()), (100, 50));
+
+        assert_eq!(crop.clip(100, 50), Some((10, 20, 90, 30)));
+        assert_eq!(
+            source_region((200, 100), None, Some((1, 1).into()), None),
+            Some((50, 0, 100, 100))
+        );
+        assert_eq!(
+            source_region((200, 100), Some(crop), Some("16:9".into()), Some((0, 100).into())),
+            Some((10, 20, 142, 80))
+        );
+        let outside = Crop { x: 5000, ..crop };
+        let result = encode_optimized_image(&Pipeline::default(), resize(outside), path);
+        assert!(matches!(result, Err(CreateImageError::InvalidParams(_))));
+    }
+
+    #[test]
+    fn missing_source() {
+        let optimizer = ImageOptimizer::new("/__cache/image", ".", 1);
+        let spec = CachedImage {
+            src: "missing.png".to_string(),
+            tenant: None,
+            option: CachedImageOption::Resize(resize_spec(100, 100)),
+        };
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let result = runtime.block_on(optimizer.create_image(&spec));
+        assert!(matches!(result, Err(CreateImageError::SourceNotFound(_))));
+        assert!(optimizer.missing.contains_key("missing.png"));
+
+        optimizer.clear_missing("missing.png");
+        assert!(optimizer.missing.is_empty());
+    }
+
+    #[test]
+    fn webp_passthrough() {
+        let dir = std::env::temp_dir().join("leptos_image_passthrough");
+        std::fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("small.webp");
+        let img = image::DynamicImage::ImageRgb8(image::RgbImage::new(50, 40));
+        let webp = webp::Encoder::from_image(&img).unwrap().encode(80.0);
+        std::fs::write(&source, &*webp).unwrap();
+
+        let pipeline = Pipeline::default();
+        assert!(source_satisfies(&pipeline, &resize_spec(100, 100), OutputFormat::Webp, &source));
+        assert!(!source_satisfies(&pipeline, &resize_spec(25, 100), OutputFormat::Webp, &source));
+        assert!(!source_satisfies(&pipeline, &resize_spec(100, 100), OutputFormat::Jpeg, &source));
+
+        let save_path = dir.join("cache.webp");
+        let _ = std::fs::remove_file(&save_path);
+        create_optimized_image(
+            &pipeline,
+            CachedImageOption::Resize(resize_spec(100, 100)),
+            source.clone(),
+            save_path.clone(),
+        )
+        .unwrap();
+        assert_eq!(std::fs::read(&save_path).unwrap(), std::fs::read(&source).unwrap());
+    }
+
+    #[test]
+    fn sweep_corrupt_entries() {
+        let (root, optimizer) = test_root("leptos_image_sweep");
+
+        let dir = root.join(CACHE_DIR).join("ab");
+        std::fs::create_dir_all(&dir).unwrap();
+        let img = image::DynamicImage::ImageRgb8(image::RgbImage::new(8, 8));
+        let webp = webp::Encoder::from_image(&img).unwrap().encode(80.0);
+        std::fs::write(dir.join("intact.webp"), &*webp).unwrap();
+        std::fs::write(dir.join("intact.qs"), "").unwrap();
+        std::fs::write(dir.join("empty.webp"), "").unwrap();
+        std::fs::write(dir.join("truncated.webp"), &webp[..10]).unwrap();
+        std::fs::write(dir.join("orphan.qs"), "").unwrap();
+
+        assert_eq!(optimizer.sweep_cache().unwrap(), 3);
+        assert!(dir.join("intact.webp").exists());
+        assert!(dir.join("intact.qs").exists());
+    }
+
+    #[test]
+    fn shutdown_drains() {
+        let optimizer = ImageOptimizer::new("/__cache/image", ".", 1);
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+
+        let active = optimizer.lifecycle.start();
+        let drained = runtime.block_on(async {
+            let shutdown = optimizer.shutdown(std::time::Duration::from_secs(5));
+            let finish = async move {
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+                drop(active);
+            };
+            tokio::join!(shutdown, finish).0
+        });
+        assert!(drained);
+        assert!(!optimizer.lifecycle.is_accepting());
+
+        let _stuck = optimizer.lifecycle.start();
+        let drained = runtime.block_on(optimizer.shutdown(std::time::Duration::from_millis(10)));
+        assert!(!drained);
+    }
+
+    #[test]
+    fn abandoned_encode() {
+        let (_, optimizer) = test_root("leptos_image_abandoned");
+        let spec = CachedImage::new("/ferris.png", resize_spec(100, 100));
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+
+        runtime.block_on(async {
+            // Queued behind a busy pool, then dropped by its only request.
+            let permit = optimizer.semaphore.clone().acquire_owned().await.unwrap();
+            let request = optimizer.create_image(&spec);
+            let timeout = tokio::time::timeout(std::time::Duration::from_millis(20), request);
+            assert!(timeout.await.is_err());
+            assert_eq!(optimizer.in_flight.len(), 1);
+            drop(permit);
+            while !optimizer.in_flight.is_empty() {
+                tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+            }
+        });
+        assert_eq!(optimizer.stats().misses, 0);
+        assert!(!std::path::Path::new(&optimizer.get_file_path_from_root(&spec)).exists());
+        assert!(runtime.block_on(optimizer.create_image(&spec)).unwrap());
+    }
+
+    #[test]
+    fn encode_timeout() {
+        use crate::encoder::ImageEncoder;
+
+        #[derive(Debug)]
+        struct Stuck;
+
+        impl ImageEncoder for Stuck {
+            fn encode(
+                &self,
+                image: &image::DynamicImage,
+                request: &crate::encoder::EncodeRequest,
+            ) -> Result<Vec<u8>, CreateImageError> {
+                std::thread::sleep(std::time::Duration::from_millis(200));
+                crate::encoder::DefaultEncoder.encode(image, request)
+            }
+        }
+
+        let (_, optimizer) = test_root("leptos_image_timeout");
+        let optimizer = optimizer
+            .with_encoder(Stuck)
+            .with_encode_timeout(std::time::Duration::from_millis(20));
+        let spec = CachedImage::new("/ferris.png", resize_spec(100, 100));
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+
+        let result = runtime.block_on(optimizer.create_image(&spec));
+        assert!(matches!(result, Err(CreateImageError::Timeout(_))));
+        assert_eq!(optimizer.stats().encode_timeouts, 1);
+        // The permit is free while the encode finishes in the background.
+        assert_eq!(optimizer.semaphore.available_permits(), 1);
+        // Read-only mode encodes in memory under the same timeout.
+        let result = runtime.block_on(optimizer.encode_in_memory(&spec));
+        assert!(matches!(result, Err(CreateImageError::Timeout(_))));
+        assert_eq!(optimizer.stats().encode_timeouts, 2);
+        std::thread::sleep(std::time::Duration::from_millis(400));
+        assert!(!std::path::Path::new(&optimizer.get_file_path_from_root(&spec)).exists());
+    }
+
+    #[test]
+    fn returns_encoded_bytes() {
+        let (_, optimizer) = test_root("leptos_image_encoded_bytes");
+        let spec = CachedImage::new("/ferris.png", resize_spec(100, 100));
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+
+        let Creation::Encoded(Some(bytes)) = runtime.block_on(optimizer.create(&spec)).unwrap()
+        else {
+            panic!("Expected the encoded bytes");
+        };
+        let file = std::fs::read(optimizer.get_file_path_from_root(&spec)).unwrap();
+        assert_eq!(bytes, file);
+        assert_eq!(runtime.block_on(optimizer.create(&spec)).unwrap(), Creation::Cached);
+    }
+
+    #[test]
+    fn max_age() {
+        let (_, optimizer) = test_root("leptos_image_max_age");
+        let day = std::time::Duration::from_secs(24 * 60 * 60);
+        let optimizer = optimizer.with_max_age(day);
+        let spec = CachedImage::new("/ferris.png", resize_spec(100, 100));
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+
+        assert!(runtime.block_on(optimizer.create_image(&spec)).unwrap());
+        assert!(!runtime.block_on(optimizer.create_image(&spec)).unwrap());
+        // Encoded two days ago.
+        let save_path = std::path::PathBuf::from(optimizer.get_file_path_from_root(&spec));
+        std::fs::File::options()
+            .write(true)
+            .open(save_path.with_extension(SIDECAR_EXTENSION))
+            .unwrap()
+            .set_modified(std::time::SystemTime::now() - 2 * day)
+            .unwrap();
+        assert!(runtime.block_on(optimizer.create_image(&spec)).unwrap());
+        assert!(!runtime.block_on(optimizer.create_image(&spec)).unwrap());
+    }
+
+    #[test]
+    fn late_waiters() {
+        let optimizer = ImageOptimizer::new("/__cache/image", ".", 1);
+        let spec = CachedImage::new("/ferris.png", Resize::default());
+        let save_path = optimizer.get_file_path_from_root(&spec);
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+
+        // Finished, but not removed yet.
+        let flight = std::sync::Arc::new(InFlight::default());
+        let error = std::sync::Arc::new(CreateImageError::ImageError(image::ImageError::Limits(
+            image::error::LimitError::from_kind(image::error::LimitErrorKind::DimensionError),
+        )));
+        flight.outcome.send_replace(Some(Err(error)));
+        optimizer.in_flight.insert(spec.clone(), flight.clone());
+        for _ in 0..2 {
+            let outcome = runtime.block_on(optimizer.encode_shared(
+                &spec,
+                save_path.clone(),
+                TEST_IMAGE.into(),
+            ));
+            assert!(matches!(
+                outcome,
+                Err(CreateImageError::ImageError(image::ImageError::Limits(_)))
+            ));
+        }
+
+        flight.outcome.send_replace(Some(Ok(Creation::Encoded(None))));
+        let outcome = optimizer.encode_shared(&spec, save_path, TEST_IMAGE.into());
+        assert!(runtime.block_on(outcome).unwrap().is_encoded());
+    }
+
+    #[test]
+    fn purge_changed_source() {
+        let (_, optimizer) = test_root("leptos_image_purge");
+
+        let spec = CachedImage {
+            src: "/ferris.png".to_string(),
+            tenant: None,
+            option: CachedImageOption::Resize(resize_spec(100, 100)),
+        };
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        assert!(runtime.block_on(optimizer.create_image(&spec)).unwrap());
+        assert!(!runtime.block_on(optimizer.create_image(&spec)).unwrap());
+
+        let stats = optimizer.stats();
+        assert_eq!((stats.hits, stats.misses, stats.encodes), (1, 1, 1));
+        assert_eq!(stats.disk["webp"].entries, 1);
+
+        assert_eq!(optimizer.purge_source("/ferris.png").unwrap(), 1);
+        assert!(!std::path::Path::new(&optimizer.get_file_path_from_root(&spec)).exists());
+    }
+
+    #[test]
+    fn batch_progress() {
+        use futures_util::StreamExt;
+
+        let (root, _) = test_root("leptos_image_batch");
+        let optimizer = ImageOptimizer::new("/__cache/image", root.to_string_lossy(), 2);
+
+        let image = |src: &str, width| {
+            let options = resize_spec(width, width);
+            CachedImage::new(src, options)
+        };
+        let batch = [image("/ferris.png", 50), image("/ferris.png", 100), image("/gone.png", 50)];
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let results: Vec<_> = runtime.block_on(optimizer.create_images(batch).collect());
+        assert_eq!(results.len(), 3);
+        let created = results.iter().filter(|(_, result)| matches!(result, Ok(true))).count();
+        assert_eq!(created, 2);
+        assert!(results.iter().any(|(image, result)| {
+            image.src() == "/gone.png" && matches!(result, Err(CreateImageError::SourceNotFound(_)))
+        }));
+    }
+
+    #[test]
+    fn deterministic_output() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let spec = CachedImage {
+            src: "/ferris.png".to_string(),
+            tenant: None,
+            option: CachedImageOption::Resize(resize_spec(100, 100)),
+        };
+        let outputs: Vec<_> = ["a", "b"]
+            .iter()
+            .map(|run| {
+                let (root, optimizer) = test_root(&format!("leptos_image_deterministic_{run}"));
+                let optimizer = optimizer
+                    .with_deterministic(true)
+                    .with_metadata_policy(crate::MetadataPolicy::attribution());
+                runtime.block_on(optimizer.create_image(&spec)).unwrap();
+
+                let file = optimizer.get_file_path_from_root(&spec);
+                let modified = |path| std::fs::metadata(path).unwrap().modified().unwrap();
+                assert_eq!(modified(root.join("ferris.png")), modified(file.clone().into()));
+                std::fs::read(file).unwrap()
+            })
+            .collect();
+        assert_eq!(outputs[0], outputs[1]);
+    }
+
+    #[test]
+    fn cache_dir_outside_root() {
+        let (root, optimizer) = test_root("leptos_image_cache_dir/public");
+        let dir = root.parent().unwrap();
+        let _ = std::fs::remove_dir_all(dir.join("private"));
+        let optimizer = optimizer.with_cache_dir("../private");
+        assert_eq!(optimizer.cache_dir, root.join("../private"));
+
+        let spec = CachedImage {
+            src: "/ferris.png".to_string(),
+            tenant: None,
+            option: CachedImageOption::Resize(resize_spec(100, 100)),
+        };
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        assert!(runtime.block_on(optimizer.create_image(&spec)).unwrap());
+        assert!(dir.join("private").join(spec.cache_key()).exists());
+        assert!(!root.join(CACHE_DIR).exists());
+        assert_eq!(optimizer.stats().disk["webp"].entries, 1);
+
+        assert_eq!(optimizer.purge_source("/ferris.png").unwrap(), 1);
+        assert!(!dir.join("private").join(spec.cache_key()).exists());
+    }
+
+    #[test]
+    fn tenant_quota() {
+        let (_, optimizer) = test_root("leptos_image_tenants");
+        let optimizer = optimizer.with_tenant_quota(|_, used| used == 0);
+
+        let spec = |tenant: &str, width| CachedImage {
+            src: "/ferris.png".to_string(),
+            tenant: Some(tenant.to_string()),
+            option: CachedImageOption::Resize(resize_spec(width, width)),
+        };
+        assert!(spec("../acme", 100).get_file_path().starts_with("cache/image/tenants/___acme/"));
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        assert!(runtime.block_on(optimizer.create_image(&spec("acme", 100))).unwrap());
+        assert!(matches!(
+            runtime.block_on(optimizer.create_image(&spec("acme", 200))),
+            Err(CreateImageError::QuotaExceeded(_))
+        ));
+        // Other tenants have their own quota, cached images are still served.
+        assert!(runtime.block_on(optimizer.create_image(&spec("other", 200))).unwrap());
+        assert!(!runtime.block_on(optimizer.create_image(&spec("acme", 100))).unwrap());
+        assert_eq!(optimizer.tenant_stats("acme").disk["webp"].entries, 1);
+
+        optimizer.purge_tenant("acme").unwrap();
+        assert!(optimizer.tenant_stats("acme").disk.is_empty());
+        assert_eq!(optimizer.tenant_stats("other").disk["webp"].entries, 1);
+    }
+
+    #[test]
+    fn width_ladder() {
+        let (root, optimizer) = test_root("leptos_image_ladder");
+        let optimizer = optimizer.with_width_ladder([4000, 200, 50, 100]);
+        assert_eq!(optimizer.width_ladder, [50, 100, 200, 4000]);
+
+        let spec = |width, height| CachedImage {
+            src: "/ferris.png".to_string(),
+            tenant: None,
+            option: CachedImageOption::Resize(resize_spec(width, height)),
+        };
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        assert!(runtime.block_on(optimizer.create_image(&spec(100, 80))).unwrap());
+        let siblings = [spec(50, 40), spec(200, 160)];
+        runtime.block_on(async {
+            for _ in 0..100 {
+                if siblings.iter().all(|image| root.join(image.get_file_path()).exists()) {
+                    break;
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            }
+        });
+        for image in &siblings {
+            assert!(root.join(image.get_file_path()).exists(), "{image}");
+        }
+        // Wider than the source.
+        assert!(!root.join(spec(4000, 3200).get_file_path()).exists());
+    }
+
+    #[test]
+    fn inline_small_images() {
+        let (_, optimizer) = test_root("leptos_image_inline");
+
+        let spec = CachedImage {
+            src: "/ferris.png".to_string(),
+            tenant: None,
+            option: CachedImageOption::Resize(Resize {
+                quality: 50,
+                width: 16,
+                height: 16,
+                format: OutputFormat::Auto,
+                ..Default::default()
+            }),
+        };
+        // Nothing is encoded while rendering.
+        assert_eq!(optimizer.inline_data_uri(&spec), None);
+
+        let mut webp = spec.clone();
+        if let CachedImageOption::Resize(resize) = &mut webp.option {
+            resize.format = OutputFormat::Webp;
+        }
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(optimizer.create_image(&webp)).unwrap();
+        let uri = optimizer.inline_data_uri(&spec).unwrap();
+        assert!(uri.starts_with("data:image/webp;base64,"));
+
+        let optimizer = optimizer.with_inline_limit(10);
+        assert_eq!(optimizer.inline_data_uri(&spec), None);
+    }
+
+    #[test]
+    fn read_only_mode() {
+        let (root, optimizer) = test_root("leptos_image_read_only");
+        let optimizer = optimizer
+            .with_read_only(ReadOnlyFallback::EncodeInMemory { max_source_bytes: 1 << 20 });
+
+        let spec = CachedImage {
+            src: "/ferris.png".to_string(),
+            tenant: None,
+            option: CachedImageOption::Resize(resize_spec(100, 100)),
+        };
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let result = runtime.block_on(optimizer.create_image(&spec));
+        assert!(matches!(result, Err(CreateImageError::ReadOnly(_))));
+
+        let bytes = runtime.block_on(optimizer.encode_in_memory(&spec)).unwrap();
+        assert_eq!(&bytes[8..12], b"WEBP");
+        assert!(!root.join(CACHE_DIR).exists());
+    }
+
+    #[test]
+    fn preload_nested_blurs() {
+        let (root, optimizer) = test_root("leptos_image_preload");
+        std::fs::create_dir_all(root.join("nested/dir")).unwrap();
+        std::fs::copy(TEST_IMAGE, root.join("nested/dir/ferris.png")).unwrap();
+
+        let blur = |width| CachedImage {
+            src: "/nested/dir/ferris.png".to_string(),
+            tenant: None,
+            option: CachedImageOption::Blur(Blur {
+                width,
+                height: width,
+                svg_height: 100,
+                svg_width: 100,
+                sigma: 15,
+                crop: None,
+                aspect_ratio: None,
+                focal_point: None,
+                kind: Placeholder::Blur,
+                matte: None,
+            }),
+        };
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        for width in [10, 20, 30] {
+            runtime.block_on(optimizer.create_image(&blur(width))).unwrap();
+        }
+
+        assert_eq!(runtime.block_on(optimizer.preload_disk_cache(2)).unwrap(), 2);
+        assert_eq!(optimizer.cache.len(), 2);
+        assert_eq!(runtime.block_on(optimizer.preload_disk_cache(10)).unwrap(), 1);
+    }
+
+    #[test]
+    fn mounted_sources() {
+        let optimizer = ImageOptimizer::new("/__cache/image", "public", 1)
+            .with_mount("/uploads/", "/srv/uploads")
+            .with_mount("uploads/avatars", "/srv/avatars");
+
+        let path = |src| optimizer.source_location(src).1;
+        assert_eq!(path("/logo.png"), std::path::Path::new("public/logo.png"));
+        assert_eq!(path("/uploads/a.png"), std::path::Path::new("/srv/uploads/a.png"));
+        assert_eq!(path("/uploads/avatars/b.png"), std::path::Path::new("/srv/avatars/b.png"));
+        assert_eq!(path("/uploadsx/c.png"), std::path::Path::new("public/uploadsx/c.png"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn source_traversal() {
+        let (root, optimizer) = test_root("leptos_image_traversal/public");
+        let dir = root.parent().unwrap();
+        std::fs::copy(TEST_IMAGE, dir.join("secret.png")).unwrap();
+        std::os::unix::fs::symlink(dir.join("secret.png"), root.join("escape.png")).unwrap();
+        std::os::unix::fs::symlink(root.join("ferris.png"), root.join("alias.png")).unwrap();
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let resolve = |src| runtime.block_on(optimizer.resolve_source(src));
+        for src in ["../secret.png", "/nested/../../secret.png", "/./../secret.png"] {
+            assert!(matches!(resolve(src), Err(CreateImageError::Forbidden(_))), "{src}");
+        }
+        assert!(matches!(resolve("/escape.png"), Err(CreateImageError::Forbidden(_))));
+        assert_eq!(resolve("/alias.png").unwrap(), root.join("alias.png"));
+        // Absolute sources are relative to the root.
+        assert_eq!(resolve("//secret.png").unwrap(), root.join("secret.png"));
+        // Headers outside the root aren't read either.
+        assert_eq!(optimizer.intrinsic_size("/../secret.png"), None);
+        assert_eq!(optimizer.intrinsic_size("/escape.png"), None);
+        assert!(optimizer.intrinsic_size("/alias.png").is_some());
+
+        let spec = CachedImage {
+            src: "/escape.png".to_string(),
+            tenant: None,
+            option: CachedImageOption::Resize(resize_spec(100, 100)),
+        };
+        let result = runtime.block_on(optimizer.create_image(&spec));
+        assert!(matches!(result, Err(CreateImageError::Forbidden(_))));
+    }
 }
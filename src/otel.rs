@@ -0,0 +1,40 @@
+use axum::http::HeaderMap;
+use opentelemetry::propagation::Extractor;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+// Reads the trace context headers of a request, e.g. `traceparent`.
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|name| name.as_str()).collect()
+    }
+}
+
+/// Makes the span of an image request a child of the trace it was sent from, using
+/// the globally registered propagator. Requests without trace context stay roots.
+pub(crate) fn set_parent(span: &tracing::Span, headers: &HeaderMap) {
+    let context = opentelemetry::global::get_text_map_propagator(|propagator| {
+        propagator.extract(&HeaderExtractor(headers))
+    });
+    span.set_parent(context);
+}
+
+#[cfg(test)]
+mod otel_tests {
+    use super::*;
+
+    #[test]
+    fn reads_trace_context() {
+        let mut headers = HeaderMap::new();
+        let traceparent = "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01";
+        headers.insert("traceparent", traceparent.parse().unwrap());
+        let extractor = HeaderExtractor(&headers);
+        assert_eq!(extractor.get("traceparent"), Some(traceparent));
+        assert_eq!(extractor.keys(), ["traceparent"]);
+    }
+}
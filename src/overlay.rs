@@ -0,0 +1,102 @@
+use leptos::prelude::*;
+
+/// What the debug overlay of an `<Image/>` shows, read on the server.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub(crate) struct DebugInfo {
+    /// Size of the source file.
+    pub(crate) source_bytes: Option<u64>,
+    /// Intrinsic size of the source.
+    pub(crate) source_size: Option<(u32, u32)>,
+    /// Size of the cached file, if it exists.
+    pub(crate) served_bytes: Option<u64>,
+    /// Requested size.
+    pub(crate) size: (u32, u32),
+    /// Output format, lowercase.
+    pub(crate) format: String,
+}
+
+#[cfg(feature = "ssr")]
+impl crate::ImageOptimizer {
+    // Reads the overlay figures of an optimized image, `None` for placeholders.
+    pub(crate) fn debug_info(&self, image: &crate::optimizer::CachedImage) -> Option<DebugInfo> {
+        use crate::optimizer::{CachedImageOption, OutputFormat};
+
+        let CachedImageOption::Resize(resize) = &image.option else {
+            return None;
+        };
+        let mut image = image.clone();
+        // Negotiated by the handler, browsers showing the overlay accept WebP.
+        if let CachedImageOption::Resize(resize) = &mut image.option {
+            if resize.format == OutputFormat::Auto {
+                resize.format = OutputFormat::Webp;
+            }
+        }
+        let format = serde_json::to_value(resize.output_format())
+            .ok()
+            .and_then(|format| format.as_str().map(str::to_string))
+            .unwrap_or_default();
+        let bytes = |path: &std::path::Path| std::fs::metadata(path).ok().map(|m| m.len());
+        Some(DebugInfo {
+            source_bytes: self
+                .resolve_source_blocking(&image.src)
+                .ok()
+                .and_then(|path| bytes(&path)),
+            source_size: self.intrinsic_size(&image.src),
+            served_bytes: bytes(self.get_file_path_from_root(&image).as_ref()),
+            size: (resize.width, resize.height),
+            format,
+        })
+    }
+}
+
+fn kib(bytes: u64) -> String {
+    format!("{:.1} KiB", bytes as f64 / 1024.0)
+}
+
+/// Puts a badge with the optimization figures over an image.
+pub(crate) fn with_overlay(view: AnyView, info: Resource<Option<DebugInfo>>) -> AnyView {
+    let badge = move || {
+        info.get().flatten().map(|info| {
+            let (width, height) = info.size;
+            let source = match info.source_size {
+                Some((source_width, source_height)) => {
+                    format!("{source_width}×{source_height} → {width}×{height}")
+                }
+                None => format!("{width}×{height}"),
+            };
+            let bytes = match (info.served_bytes, info.source_bytes) {
+                (Some(served), Some(source)) if source > 0 => format!(
+                    "{} / {} ({}%)",
+                    kib(served),
+                    kib(source),
+                    served * 100 / source
+                ),
+                (Some(served), _) => kib(served),
+                (None, _) => "not cached yet".to_string(),
+            };
+            let cache = if info.served_bytes.is_some() { "hit" } else { "miss" };
+            // Served files larger than their source are worth a look.
+            let background = match (info.served_bytes, info.source_bytes) {
+                (Some(served), Some(source)) if served > source => "rgba(180, 0, 0, 0.8)",
+                _ => "rgba(0, 0, 0, 0.7)",
+            };
+            let style = format!(
+                "position: absolute; top: 4px; left: 4px; z-index: 1; padding: 2px 4px; \
+                 font: 11px/1.3 monospace; color: #fff; background: {background}; \
+                 pointer-events: none; white-space: nowrap;"
+            );
+            view! {
+                <span class="leptos-image-debug" style=style>
+                    {format!("{} · {source} · {bytes} · {cache}", info.format)}
+                </span>
+            }
+        })
+    };
+    view! {
+        <span style="position: relative; display: inline-block;">
+            {view}
+            <Suspense>{badge}</Suspense>
+        </span>
+    }
+    .into_any()
+}
@@ -0,0 +1,193 @@
+use crate::metadata::MetadataPolicy;
+use crate::optimizer::{CachedImage, CachedImageOption, ImageOptimizer, OutputFormat};
+
+/// Optimization rules for the sources below a path prefix, see
+/// [`ImageOptimizer::with_policy`].
+///
+/// Applied by the cache handler on top of the request parameters, so pages can't
+/// ask for more than the policy allows. Unset rules leave the request as is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourcePolicy {
+    pub(crate) max_width: Option<u32>,
+    pub(crate) max_height: Option<u32>,
+    pub(crate) quality: Option<u8>,
+    pub(crate) lossless: Option<bool>,
+    pub(crate) format: Option<OutputFormat>,
+    pub(crate) metadata: Option<MetadataPolicy>,
+    pub(crate) placeholders: bool,
+}
+
+impl Default for SourcePolicy {
+    fn default() -> Self {
+        Self {
+            max_width: None,
+            max_height: None,
+            quality: None,
+            lossless: None,
+            format: None,
+            metadata: None,
+            placeholders: true,
+        }
+    }
+}
+
+impl SourcePolicy {
+    /// A policy that changes nothing yet.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Scales larger requests down to fit `max_width` x `max_height`, keeping the
+    /// aspect ratio.
+    pub fn max_size(mut self, max_width: u32, max_height: u32) -> Self {
+        self.max_width = Some(max_width);
+        self.max_height = Some(max_height);
+        self
+    }
+
+    /// Scales wider requests down to `max_width`, keeping the aspect ratio.
+    pub fn max_width(mut self, max_width: u32) -> Self {
+        self.max_width = Some(max_width);
+        self
+    }
+
+    /// Encodes at this quality, whatever the request asks for.
+    pub fn quality(mut self, quality: u8) -> Self {
+        self.quality = Some(quality);
+        self
+    }
+
+    /// Forces lossless (or lossy) encoding, e.g. for logos.
+    pub fn lossless(mut self, lossless: bool) -> Self {
+        self.lossless = Some(lossless);
+        self
+    }
+
+    /// Encodes to this format, whatever the request asks for.
+    pub fn format(mut self, format: OutputFormat) -> Self {
+        self.format = Some(format);
+        self
+    }
+
+    /// Replaces the optimizer's [`MetadataPolicy`], e.g. to strip EXIF from uploads.
+    pub fn metadata(mut self, metadata: MetadataPolicy) -> Self {
+        self.metadata = Some(metadata);
+        self
+    }
+
+    /// Disables placeholders: `<Image/>` renders none and the handler refuses them.
+    pub fn without_placeholders(mut self) -> Self {
+        self.placeholders = false;
+        self
+    }
+
+    // Changes the options of a requested image to follow the policy.
+    fn apply(&self, image: &mut CachedImage) {
+        let CachedImageOption::Resize(resize) = &mut image.option else {
+            return;
+        };
+        let factor = [
+            self.max_width.map(|max| (max, resize.width)),
+            self.max_height.map(|max| (max, resize.height)),
+        ]
+        .into_iter()
+        .flatten()
+        .filter(|(_, size)| *size > 0)
+        .map(|(max, size)| max as f32 / size as f32)
+        .fold(1.0_f32, f32::min);
+        if factor < 1.0 {
+            resize.width = ((resize.width as f32 * factor).floor() as u32).max(1);
+            resize.height = ((resize.height as f32 * factor).floor() as u32).max(1);
+        }
+        if let Some(quality) = self.quality {
+            resize.quality = quality;
+        }
+        if let Some(lossless) = self.lossless {
+            resize.lossless = lossless;
+        }
+        if let Some(format) = self.format {
+            resize.format = format;
+        }
+    }
+}
+
+impl ImageOptimizer {
+    /// Applies `policy` to the sources below `prefix`, e.g. `/uploads` or
+    /// `/uploads/**`. The most specific prefix wins, policies aren't combined.
+    ///
+    /// ```
+    /// # use leptos_image::*;
+    /// let optimizer = ImageOptimizer::new("/__cache/image", "./target/site", 1)
+    ///     .with_policy(
+    ///         "/uploads/**",
+    ///         SourcePolicy::new().max_width(1600).metadata(MetadataPolicy::Strip),
+    ///     )
+    ///     .with_policy("/logos", SourcePolicy::new().lossless(true).without_placeholders());
+    /// ```
+    pub fn with_policy(mut self, prefix: impl AsRef<str>, policy: SourcePolicy) -> Self {
+        let prefix = prefix.as_ref().trim_end_matches(['*', '/']);
+        let prefix = prefix.trim_start_matches('/').to_string();
+        self.policies.retain(|(other, _)| *other != prefix);
+        self.policies.push((prefix, policy));
+        // Most specific prefix first.
+        self.policies.sort_by(|a, b| b.0.len().cmp(&a.0.len()));
+        self
+    }
+
+    /// The policy of a source, if one applies.
+    pub(crate) fn policy(&self, src: &str) -> Option<&SourcePolicy> {
+        let src = src.trim_start_matches('/');
+        self.policies
+            .iter()
+            .find(|(prefix, _)| match src.strip_prefix(prefix.as_str()) {
+                Some(rest) => prefix.is_empty() || rest.is_empty() || rest.starts_with('/'),
+                None => false,
+            })
+            .map(|(_, policy)| policy)
+    }
+
+    /// Changes a requested image to follow the policy of its source.
+    pub(crate) fn apply_policy(&self, image: &mut CachedImage) {
+        if let Some(policy) = self.policy(&image.src) {
+            policy.apply(image);
+        }
+    }
+
+    /// Whether placeholders may be created for a source.
+    pub(crate) fn allows_placeholders(&self, src: &str) -> bool {
+        self.policy(src).map_or(true, |policy| policy.placeholders)
+    }
+}
+
+#[cfg(test)]
+mod policy_tests {
+    use super::*;
+    use crate::optimizer::Resize;
+
+    #[test]
+    fn most_specific_prefix() {
+        let optimizer = ImageOptimizer::new("/__cache/image", "./target/site", 1)
+            .with_policy("/uploads/**", SourcePolicy::new().max_width(1600).quality(60))
+            .with_policy("/uploads/logos", SourcePolicy::new().without_placeholders());
+
+        let mut image = CachedImage {
+            src: "/uploads/photo.jpg".to_string(),
+            tenant: None,
+            option: CachedImageOption::Resize(Resize {
+                quality: 90,
+                width: 3200,
+                height: 2400,
+                ..Default::default()
+            }),
+        };
+        optimizer.apply_policy(&mut image);
+        let CachedImageOption::Resize(resize) = &image.option else {
+            unreachable!()
+        };
+        assert_eq!((resize.width, resize.height, resize.quality), (1600, 1200, 60));
+
+        assert!(optimizer.allows_placeholders("/uploads/photo.jpg"));
+        assert!(!optimizer.allows_placeholders("/uploads/logos/acme.png"));
+        assert!(optimizer.policy("/uploadsmore/a.png").is_none());
+    }
+}
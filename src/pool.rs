@@ -0,0 +1,69 @@
+use crate::optimizer::{CreateImageError, ImageOptimizer};
+
+impl ImageOptimizer {
+    /// Runs decodes and encodes on `threads` threads owned by the optimizer, instead
+    /// of tokio's blocking pool shared with the rest of the app (database drivers,
+    /// file IO). Image work then can't starve other blocking tasks, or be starved by
+    /// them. Size it like the parallelism, extra threads stay idle.
+    ///
+    /// Panics if the threads can't be spawned.
+    ///
+    /// ```
+    /// # use leptos_image::*;
+    /// let optimizer = ImageOptimizer::new("/__cache/image", "./target/site", 4)
+    ///     .with_encode_threads(4);
+    /// ```
+    #[cfg(feature = "thread-pool")]
+    pub fn with_encode_threads(mut self, threads: usize) -> Self {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads.max(1))
+            .thread_name(|index| format!("leptos-image-encode-{index}"))
+            .build()
+            .expect("Failed to spawn the encode threads");
+        self.encode_pool = Some(std::sync::Arc::new(pool));
+        self
+    }
+
+    /// Runs CPU heavy image work on the encode threads, or tokio's blocking pool
+    /// without them.
+    pub(crate) async fn run_encode<T: Send + 'static>(
+        &self,
+        work: impl FnOnce() -> T + Send + 'static,
+    ) -> Result<T, CreateImageError> {
+        #[cfg(feature = "thread-pool")]
+        if let Some(pool) = &self.encode_pool {
+            let (sender, receiver) = tokio::sync::oneshot::channel();
+            pool.spawn(move || {
+                let output = std::panic::catch_unwind(std::panic::AssertUnwindSafe(work));
+                // The request may be gone.
+                let _ = sender.send(output);
+            });
+            return match receiver.await {
+                Ok(Ok(output)) => Ok(output),
+                _ => Err(CreateImageError::EncodeError("The encode thread panicked".into())),
+            };
+        }
+        tokio::task::spawn_blocking(work)
+            .await
+            .map_err(CreateImageError::JoinError)
+    }
+}
+
+#[cfg(all(test, feature = "thread-pool"))]
+mod pool_tests {
+    use super::*;
+
+    #[test]
+    fn runs_on_encode_threads() {
+        let optimizer = ImageOptimizer::new("/__cache/image", ".", 1).with_encode_threads(2);
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+
+        let name = runtime.block_on(optimizer.run_encode(|| {
+            std::thread::current().name().map(str::to_string)
+        }));
+        assert!(name.unwrap().unwrap().starts_with("leptos-image-encode-"));
+
+        let panicked = runtime.block_on(optimizer.run_encode(|| panic!("Decoder bug")));
+        assert!(matches!(panicked, Err(CreateImageError::EncodeError(_))));
+    }
+}
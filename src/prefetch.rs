@@ -0,0 +1,184 @@
+use crate::key::CacheKey;
+use crate::optimizer::{CachedImage, CachedImageOption, Placeholder, Resize};
+use crate::provider::ImageConfig;
+use leptos::prelude::*;
+use std::sync::Arc;
+
+/// An image of another route to prefetch, with the props of its `<Image/>`, see
+/// [`use_image_prefetch`]. Props left out are at their defaults.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrefetchImage {
+    /// Image source, like `<Image src/>`.
+    pub src: String,
+    /// Width of the final image, like `<Image width/>`.
+    pub width: u32,
+    /// Height of the final image, like `<Image height/>`.
+    pub height: u32,
+    /// Image quality (0-100), like `<Image quality/>`.
+    pub quality: u8,
+    /// Whether to prefetch the placeholder too, like `<Image blur/>`.
+    pub blur: bool,
+    /// Kind of the placeholder, like `<Image placeholder/>`.
+    pub placeholder: Placeholder,
+}
+
+impl PrefetchImage {
+    /// An image at quality 75 with a blur placeholder, the defaults of `<Image/>`.
+    pub fn new(src: impl Into<String>, width: u32, height: u32) -> Self {
+        Self {
+            src: src.into(),
+            width,
+            height,
+            quality: 75,
+            blur: true,
+            placeholder: Placeholder::Blur,
+        }
+    }
+
+    /// The images `<Image/>` requests for it, the optimized one first.
+    fn cached_images(&self, tenant: Option<String>) -> Vec<CachedImage> {
+        let mut images = vec![CachedImage {
+            src: self.src.clone(),
+            tenant: tenant.clone(),
+            option: CachedImageOption::Resize(Resize {
+                width: self.width,
+                height: self.height,
+                quality: self.quality,
+                ..Default::default()
+            }),
+        }];
+        if self.blur && !crate::optimizer::is_svg(&self.src) {
+            let placeholder = crate::image::placeholder_image(&self.src, tenant, self.placeholder);
+            images.push(placeholder);
+        }
+        images
+    }
+}
+
+/// Prefetches the images of other routes, see [`use_image_prefetch`].
+#[derive(Clone, Copy)]
+pub struct ImagePrefetcher {
+    config: Resource<ImageConfig>,
+    tenant: StoredValue<Option<String>>,
+    cache_key: StoredValue<Arc<dyn CacheKey>>,
+}
+
+impl std::fmt::Debug for ImagePrefetcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ImagePrefetcher").finish_non_exhaustive()
+    }
+}
+
+/// Prefetches the optimized images and placeholders of a route before navigating
+/// to it, e.g. when a link is hovered or focused, so its hero images are already
+/// cached when it renders.
+///
+/// Each image is encoded on the server with [`crate::warm_images`], then fetched into
+/// the browser cache with a `<link rel="prefetch">`. Images are only prefetched once
+/// per page load, and only in the browser. Call it where `<Image/>` would be called,
+/// below [`crate::provide_image_context`] and the tenant.
+///
+/// ```
+/// use leptos::prelude::*;
+/// use leptos_image::*;
+///
+/// #[component]
+/// fn GalleryLink() -> impl IntoView {
+///     let prefetcher = use_image_prefetch();
+///     let prefetch = move |_| {
+///         prefetcher.prefetch([PrefetchImage::new("/hero.jpg", 1200, 600)]);
+///     };
+///     view! { <a href="/gallery" on:mouseenter=prefetch>"Gallery"</a> }
+/// }
+/// ```
+pub fn use_image_prefetch() -> ImagePrefetcher {
+    ImagePrefetcher {
+        config: crate::provider::use_image_cache_resource(),
+        tenant: StoredValue::new(use_context::<crate::ImageTenant>().map(|tenant| tenant.0)),
+        cache_key: StoredValue::new(crate::key::use_cache_key()),
+    }
+}
+
+impl ImagePrefetcher {
+    /// Prefetches `images`, see [`use_image_prefetch`]. Does nothing on the server.
+    pub fn prefetch(&self, images: impl IntoIterator<Item = PrefetchImage>) {
+        let tenant = self.tenant.get_value();
+        let images: Vec<CachedImage> = images
+            .into_iter()
+            .flat_map(|image| image.cached_images(tenant.clone()))
+            .collect();
+        #[cfg(feature = "hydrate")]
+        let images: Vec<CachedImage> = images
+            .into_iter()
+            .filter(|image| PREFETCHED.with(|done| done.borrow_mut().insert(image.clone())))
+            .collect();
+
+        // Gradients have no URL, they are inlined once held in memory.
+        let handler_url = self.config.get_untracked().map(|config| config.handler_url());
+        let cache_key = self.cache_key.get_value();
+        let urls: Vec<String> = images
+            .iter()
+            .filter(|image| match &image.option {
+                CachedImageOption::Blur(blur) => blur.kind != Placeholder::Gradient,
+                CachedImageOption::Resize(_) => true,
+            })
+            .filter_map(|image| Some(cache_key.url(image, handler_url.as_deref()?)))
+            .collect();
+
+        #[cfg(feature = "hydrate")]
+        if !images.is_empty() {
+            leptos::task::spawn_local(async move {
+                // Encoded first, so the prefetches are served from the cache.
+                if let Err(e) = crate::provider::warm_images(images).await {
+                    leptos::logging::debug_warn!("Failed to warm prefetched images: {e}");
+                }
+                urls.iter().for_each(|url| append_prefetch_link(url));
+            });
+        }
+        #[cfg(not(feature = "hydrate"))]
+        let _ = (images, urls);
+    }
+}
+
+#[cfg(feature = "hydrate")]
+thread_local! {
+    // Images prefetched since the page loaded.
+    static PREFETCHED: std::cell::RefCell<std::collections::HashSet<CachedImage>> =
+        Default::default();
+}
+
+#[cfg(feature = "hydrate")]
+fn append_prefetch_link(url: &str) {
+    let document = document();
+    let (Some(head), Ok(link)) = (document.head(), document.create_element("link")) else {
+        return;
+    };
+    let _ = link.set_attribute("rel", "prefetch");
+    let _ = link.set_attribute("as", "image");
+    let _ = link.set_attribute("href", url);
+    let _ = head.append_child(&link);
+}
+
+#[cfg(test)]
+mod prefetch_tests {
+    use super::*;
+    use crate::test_support::resize_spec;
+
+    #[test]
+    fn images_of_image_props() {
+        let images = PrefetchImage::new("/hero.jpg", 1200, 600).cached_images(None);
+        assert_eq!(images.len(), 2);
+        assert_eq!(
+            images[0],
+            CachedImage::new("/hero.jpg", resize_spec(1200, 600))
+        );
+        assert!(matches!(
+            &images[1].option,
+            CachedImageOption::Blur(blur) if blur.width == 20 && blur.kind == Placeholder::Blur
+        ));
+
+        // SVGs have no placeholder.
+        let logo = PrefetchImage::new("/logo.svg", 100, 100);
+        assert_eq!(logo.cached_images(None).len(), 1);
+    }
+}
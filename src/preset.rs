@@ -0,0 +1,106 @@
+use crate::optimizer::{OutputFormat, Resize};
+use serde::{Deserialize, Serialize};
+
+/// A named bundle of encode options, picked with `<Image preset="card"/>`.
+///
+/// Presets are defined on the server with [`crate::ImageOptimizer::with_preset`] and
+/// replace the component's `quality`, `format` and `lossless` props. They are resolved
+/// into the request URL, so changing a preset re-keys every image using it.
+///
+/// `thumbnail` (quality 60), `standard` (75) and `high` (90) are defined by default.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize, Hash)]
+pub struct Preset {
+    /// Quality (0-100).
+    pub quality: Option<u8>,
+    /// Output format.
+    pub format: Option<OutputFormat>,
+    /// Lossless encoding.
+    pub lossless: Option<bool>,
+}
+
+impl Preset {
+    /// A preset encoding at `quality`.
+    pub fn quality(quality: u8) -> Self {
+        Self {
+            quality: Some(quality),
+            ..Default::default()
+        }
+    }
+
+    /// Also sets the output format.
+    pub fn with_format(mut self, format: OutputFormat) -> Self {
+        self.format = Some(format);
+        self
+    }
+
+    /// Also sets lossless encoding.
+    pub fn with_lossless(mut self, lossless: bool) -> Self {
+        self.lossless = Some(lossless);
+        self
+    }
+
+    pub(crate) fn apply(&self, resize: &mut Resize) {
+        if let Some(quality) = self.quality {
+            resize.quality = quality;
+        }
+        if let Some(format) = self.format {
+            resize.format = format;
+        }
+        if let Some(lossless) = self.lossless {
+            resize.lossless = lossless;
+        }
+    }
+}
+
+#[cfg(feature = "ssr")]
+pub(crate) fn default_presets() -> std::collections::HashMap<String, Preset> {
+    [
+        ("thumbnail", Preset::quality(60)),
+        ("standard", Preset::quality(75)),
+        ("high", Preset::quality(90)),
+    ]
+    .into_iter()
+    .map(|(name, preset)| (name.to_string(), preset))
+    .collect()
+}
+
+#[cfg(feature = "ssr")]
+impl crate::ImageOptimizer {
+    /// Defines (or replaces) a named preset for `<Image preset=.../>`, see [`Preset`].
+    ///
+    /// ```
+    /// # use leptos_image::*;
+    /// let optimizer = ImageOptimizer::new("/__cache/image", "./target/site", 1)
+    ///     .with_preset("card", Preset::quality(70))
+    ///     .with_preset("hero", Preset::quality(85).with_format(OutputFormat::Avif));
+    /// ```
+    pub fn with_preset(mut self, name: impl Into<String>, preset: Preset) -> Self {
+        self.presets.insert(name.into(), preset);
+        self
+    }
+
+    /// The preset with this name, if defined.
+    pub(crate) fn preset(&self, name: &str) -> Option<&Preset> {
+        self.presets.get(name)
+    }
+}
+
+#[cfg(all(test, feature = "ssr"))]
+mod preset_tests {
+    use super::*;
+
+    #[test]
+    fn replaces_encode_options() {
+        let optimizer = crate::ImageOptimizer::new("/__cache/image", "./target/site", 1)
+            .with_preset("hero", Preset::quality(85).with_format(OutputFormat::Avif));
+        assert_eq!(optimizer.preset("thumbnail"), Some(&Preset::quality(60)));
+
+        let mut resize = Resize {
+            quality: 75,
+            ..Default::default()
+        };
+        optimizer.preset("hero").unwrap().apply(&mut resize);
+        assert_eq!((resize.quality, resize.format), (85, OutputFormat::Avif));
+        assert!(!resize.lossless);
+    }
+}
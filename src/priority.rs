@@ -0,0 +1,80 @@
+use std::sync::{Arc, Mutex};
+
+/// The URLs of the `priority` images a page rendered, for preloading them before the
+/// HTML arrives: a `Link` header, `103 Early Hints` or HTTP/2 push.
+///
+/// `<Image priority/>` adds its URL while rendering on the server, when a collector
+/// is in the context or in the request extensions. Images rendered inside a
+/// `<Suspense>` of the app may be added after the headers were sent.
+/// [`crate::axum::preload_priority_images`] sets the `Link` header for every page:
+///
+/// ```
+/// # use leptos_image::*;
+/// let images = PriorityImages::default();
+/// images.push("/__cache/image?src=/hero.jpg".to_string());
+/// assert_eq!(
+///     images.link_header().as_deref(),
+///     Some("</__cache/image?src=/hero.jpg>; rel=preload; as=image; fetchpriority=high")
+/// );
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct PriorityImages(Arc<Mutex<Vec<String>>>);
+
+impl PriorityImages {
+    /// Adds the URL of a priority image, once.
+    pub fn push(&self, url: String) {
+        let mut urls = self.0.lock().unwrap();
+        if !urls.contains(&url) {
+            urls.push(url);
+        }
+    }
+
+    /// The URLs added so far, in render order.
+    pub fn urls(&self) -> Vec<String> {
+        self.0.lock().unwrap().clone()
+    }
+
+    /// A `Link` header value preloading every URL, `None` without priority images.
+    pub fn link_header(&self) -> Option<String> {
+        let urls = self.urls();
+        (!urls.is_empty()).then(|| {
+            urls.iter()
+                .map(|url| format!("<{url}>; rel=preload; as=image; fetchpriority=high"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        })
+    }
+}
+
+/// The collector of the page being rendered, provided as context or attached to
+/// the request by [`crate::axum::preload_priority_images`].
+pub(crate) fn priority_collector() -> Option<PriorityImages> {
+    use leptos::prelude::use_context;
+
+    use_context::<PriorityImages>().or_else(|| {
+        use_context::<axum::http::request::Parts>()
+            .and_then(|parts| parts.extensions.get::<PriorityImages>().cloned())
+    })
+}
+
+#[cfg(test)]
+mod priority_tests {
+    use super::*;
+
+    #[test]
+    fn collects_once() {
+        let images = PriorityImages::default();
+        assert_eq!(images.link_header(), None);
+
+        let collected = images.clone();
+        collected.push("/a.webp".to_string());
+        collected.push("/b.webp".to_string());
+        collected.push("/a.webp".to_string());
+        assert_eq!(images.urls(), ["/a.webp", "/b.webp"]);
+        assert_eq!(
+            images.link_header().unwrap(),
+            "</a.webp>; rel=preload; as=image; fetchpriority=high, \
+             </b.webp>; rel=preload; as=image; fetchpriority=high"
+        );
+    }
+}
@@ -39,15 +39,41 @@ pub fn new_image_resource() -> Resource<ImageConfig> {
     )
 }
 
+/// Cache namespace of the images below this point, see [`provide_image_tenant`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageTenant(pub String);
+
+/// Scopes every `<Image/>` below this point to a tenant.
+///
+/// Optimized images of a tenant are cached in their own directory, so they can be
+/// purged, measured and limited separately, see `ImageOptimizer::purge_tenant`.
+/// The tenant is part of the image URL, check access in your own middleware if
+/// tenants must not read each other's images.
+pub fn provide_image_tenant(tenant: impl Into<String>) {
+    leptos::prelude::provide_context(ImageTenant(tenant.into()));
+}
+
 type ImageResource = Resource<ImageConfig>;
 
 #[doc(hidden)]
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize, Default)]
 pub struct ImageConfig {
     pub(crate) api_handler_path: String,
+    pub(crate) asset_prefix: String,
     pub(crate) cache: Vec<(CachedImage, String)>,
 }
 
+impl ImageConfig {
+    /// The handler path with the asset prefix applied.
+    pub(crate) fn handler_url(&self) -> String {
+        format!(
+            "{}{}",
+            self.asset_prefix.trim_end_matches('/'),
+            self.api_handler_path
+        )
+    }
+}
+
 pub(crate) fn use_image_cache_resource() -> Resource<ImageConfig> {
     use_context::<Resource<ImageConfig>>().expect("Missing Image Resource")
 }
@@ -58,25 +84,95 @@ pub(crate) async fn get_image_config() -> Result<ImageConfig, ServerFnError> {
     let optimizer = use_optimizer()?;
     tracing::info!("2");
 
-    let cache = optimizer
-        .cache
-        .iter()
-        .map(|entry| (entry.key().clone(), entry.value().clone()))
-        .collect();
+    // Pages with placeholder batches only carry their own.
+    let cache = if optimizer.context_placeholders {
+        optimizer
+            .cache
+            .iter()
+            .map(|entry| (entry.key().clone(), entry.value().clone()))
+            .collect()
+    } else {
+        Vec::new()
+    };
 
     let api_handler_path = optimizer.api_handler_path.clone();
+    let asset_prefix = optimizer.asset_prefix.clone();
 
     Ok(ImageConfig {
         api_handler_path,
+        asset_prefix,
         cache,
     })
 }
 
+/// Encodes an image ahead of time, e.g. on link hover, so it is cached by the time
+/// the next page asks for it. Returns whether the image was encoded, `false` if it
+/// already was.
+///
+/// The parameters match `<Image src width height quality/>` with the other props
+/// left at their defaults. Sources are resolved like in the cache handler.
+#[server(WarmImage)]
+pub async fn warm_image(
+    src: String,
+    width: u32,
+    height: u32,
+    quality: u8,
+) -> Result<bool, ServerFnError> {
+    use crate::optimizer::{CachedImage, CachedImageOption};
+
+    let optimizer = use_optimizer()?;
+    let mut image = CachedImage {
+        src,
+        tenant: None,
+        option: CachedImageOption::Resize(crate::Resize {
+            width,
+            height,
+            quality,
+            ..Default::default()
+        }),
+    };
+    optimizer.apply_policy(&mut image);
+    optimizer
+        .create_image(&image)
+        .await
+        .map_err(|e| ServerFnError::ServerError(e.to_string()))
+}
+
+/// Encodes a batch of images and placeholders ahead of time, like [`warm_image`] in
+/// one request. Returns how many were encoded, images that fail are skipped.
+///
+/// Called by [`crate::ImagePrefetcher`], which builds the images like `<Image/>`.
+#[server(WarmImages)]
+pub async fn warm_images(images: Vec<CachedImage>) -> Result<usize, ServerFnError> {
+    use futures_util::StreamExt;
+
+    let optimizer = use_optimizer()?;
+    let results = optimizer.create_images(images);
+    futures_util::pin_mut!(results);
+    let mut encoded = 0;
+    while let Some((image, result)) = results.next().await {
+        match result {
+            Ok(created) => encoded += usize::from(created),
+            Err(e) => tracing::debug!("Failed to warm {image}: {e}"),
+        }
+    }
+    Ok(encoded)
+}
+
 #[cfg(feature = "ssr")]
 pub(crate) fn use_optimizer() -> Result<crate::ImageOptimizer, ServerFnError> {
     //use axum::{extract::Query, http::Method};
     //use leptos_axum::extract;
     tracing::debug!("Calling use_optimizer");
-    use_context::<crate::ImageOptimizer>()
-        .ok_or_else(|| ServerFnError::ServerError("Image Optimizer Missing.".into()))
+    optimizer_context().ok_or_else(|| ServerFnError::ServerError("Image Optimizer Missing.".into()))
+}
+
+/// The optimizer provided as context, or attached to the request by
+/// [`crate::axum::attach`].
+#[cfg(feature = "ssr")]
+pub(crate) fn optimizer_context() -> Option<crate::ImageOptimizer> {
+    use_context::<crate::ImageOptimizer>().or_else(|| {
+        use_context::<axum::http::request::Parts>()
+            .and_then(|parts| parts.extensions.get::<crate::ImageOptimizer>().cloned())
+    })
 }
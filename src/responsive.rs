@@ -0,0 +1,283 @@
+use crate::image::{layout_size, placeholder_image, DEFAULT_MAX_WIDTH};
+use crate::key::CacheKey;
+use crate::optimizer::{is_svg, CachedImage, CachedImageOption, OutputFormat, Placeholder, Resize};
+use crate::provider::ImageConfig;
+use base64::{engine::general_purpose, Engine as _};
+use leptos::prelude::*;
+use std::sync::Arc;
+
+/// The URLs of an image at several widths, for `<img srcset>` or `<picture>` markup
+/// built by hand, see [`responsive_set`].
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ResponsiveSet {
+    /// `srcset` attribute, each URL with its width, e.g. `/__cache/image?... 640w, ...`.
+    pub srcset: String,
+    /// `sizes` attribute, the viewport width up to the widest image.
+    pub sizes: String,
+    /// URL of the widest image, for `src`.
+    pub fallback: String,
+    /// Blur placeholder for `background-image: url(...)`, a `data:` URI once it is held
+    /// in memory and its URL before. `None` for SVGs.
+    pub placeholder: Option<String>,
+}
+
+// What a set is built from, known to the optimizer on the server and to the image
+// context in the browser.
+pub(crate) struct SetContext {
+    handler_url: String,
+    cache_key: Arc<dyn CacheKey>,
+    tenant: Option<String>,
+    intrinsic: Option<(u32, u32)>,
+    placeholder: Placeholder,
+    held_placeholder: Option<String>,
+}
+
+impl SetContext {
+    /// The context in the browser, where the image context stands in for the optimizer.
+    pub(crate) fn from_config(
+        config: ImageConfig,
+        src: &str,
+        tenant: Option<String>,
+        intrinsic: Option<(u32, u32)>,
+        placeholder: Placeholder,
+    ) -> Self {
+        let image = placeholder_image(src, tenant.clone(), placeholder);
+        Self {
+            handler_url: config.handler_url(),
+            cache_key: crate::key::use_cache_key(),
+            tenant,
+            intrinsic,
+            placeholder,
+            held_placeholder: config
+                .cache
+                .into_iter()
+                .find_map(|(cached, value)| (cached == image).then_some(value)),
+        }
+    }
+
+    /// Size of the source, only known on the server.
+    pub(crate) fn intrinsic(&self) -> Option<(u32, u32)> {
+        self.intrinsic
+    }
+
+    /// The URL of `src` resized to `width` x `height`.
+    pub(crate) fn url(
+        &self,
+        src: &str,
+        (width, height): (u32, u32),
+        quality: u8,
+        format: OutputFormat,
+    ) -> String {
+        let image = CachedImage {
+            src: src.to_string(),
+            tenant: self.tenant.clone(),
+            option: CachedImageOption::Resize(Resize {
+                width,
+                height,
+                quality,
+                format,
+                ..Default::default()
+            }),
+        };
+        self.cache_key.url(&image, &self.handler_url)
+    }
+
+    /// The URLs of `src` at `widths`, sorted and capped to the source's width. Heights
+    /// follow the aspect ratio of `aspect`, the source's without.
+    pub(crate) fn urls(
+        &self,
+        src: &str,
+        widths: impl IntoIterator<Item = u32>,
+        quality: u8,
+        format: OutputFormat,
+        aspect: Option<(u32, u32)>,
+    ) -> Vec<(u32, String)> {
+        // Widths above the source's would only upscale it.
+        let source_width = self.intrinsic.map(|(width, _)| width);
+        let mut widths: Vec<u32> = widths
+            .into_iter()
+            .filter(|width| *width > 0)
+            .map(|width| source_width.map_or(width, |source| width.min(source)))
+            .collect();
+        widths.sort_unstable();
+        widths.dedup();
+        if widths.is_empty() {
+            widths.push(source_width.map_or(DEFAULT_MAX_WIDTH, |source| {
+                source.min(DEFAULT_MAX_WIDTH)
+            }));
+        }
+
+        widths
+            .into_iter()
+            .map(|width| {
+                let size = layout_size(Some(width), None, aspect.or(self.intrinsic), width);
+                (width, self.url(src, size, quality, format))
+            })
+            .collect()
+    }
+
+    /// The placeholder of `src`: a `data:` URI once it is held in memory and its URL
+    /// before, or the CSS of a gradient. `None` for SVGs and gradients not held yet.
+    pub(crate) fn placeholder(&self, src: &str) -> Option<String> {
+        if is_svg(src) {
+            return None;
+        }
+        match (self.placeholder, &self.held_placeholder) {
+            (Placeholder::Blur, Some(svg)) => {
+                let encoded = general_purpose::STANDARD.encode(svg.as_bytes());
+                Some(format!("data:image/svg+xml;base64,{encoded}"))
+            }
+            (_, Some(held)) => Some(held.clone()),
+            (Placeholder::Gradient, None) => None,
+            (_, None) => {
+                let image = placeholder_image(src, self.tenant.clone(), self.placeholder);
+                Some(self.cache_key.url(&image, &self.handler_url))
+            }
+        }
+    }
+
+    fn build(
+        self,
+        src: &str,
+        widths: impl IntoIterator<Item = u32>,
+        quality: u8,
+        format: OutputFormat,
+    ) -> ResponsiveSet {
+        let urls = self.urls(src, widths, quality, format, None);
+        let widest = urls[urls.len() - 1].0;
+        ResponsiveSet {
+            srcset: urls
+                .iter()
+                .map(|(width, url)| format!("{url} {width}w"))
+                .collect::<Vec<_>>()
+                .join(", "),
+            sizes: format!("(max-width: {widest}px) 100vw, {widest}px"),
+            fallback: urls[urls.len() - 1].1.clone(),
+            placeholder: self.placeholder(src),
+        }
+    }
+}
+
+/// The URLs `<Image/>` would use for `src` at each of `widths`, for building your own
+/// markup: `<picture>` elements, art direction, or server-side templates. Heights
+/// keep the source's aspect ratio, widths above the source's are capped to it.
+///
+/// Reads the optimizer and the tenant from the context, like `<Image/>`. Only the
+/// server can read the source's size, so call it in a resource to get the same set
+/// when hydrating. Without a server, e.g. in handlers running in the browser, images
+/// are square. Outside a component, use `ImageOptimizer::responsive_set`.
+///
+/// ```
+/// use leptos::prelude::*;
+/// use leptos_image::*;
+///
+/// #[component]
+/// fn Hero() -> impl IntoView {
+///     let set = Resource::new(
+///         || (),
+///         |_| async { responsive_set("/hero.jpg", [640, 1280], 75, OutputFormat::Auto) },
+///     );
+///     view! {
+///         <Suspense>
+///             {move || set.get().map(|set| view! {
+///                 <img srcset=set.srcset sizes=set.sizes src=set.fallback alt="Hero" />
+///             })}
+///         </Suspense>
+///     }
+/// }
+/// ```
+pub fn responsive_set(
+    src: &str,
+    widths: impl IntoIterator<Item = u32>,
+    quality: u8,
+    format: OutputFormat,
+) -> ResponsiveSet {
+    let tenant = use_context::<crate::ImageTenant>().map(|tenant| tenant.0);
+    #[cfg(feature = "ssr")]
+    if let Some(optimizer) = crate::provider::optimizer_context() {
+        return optimizer
+            .set_context(src, tenant, Placeholder::Blur)
+            .build(src, widths, quality, format);
+    }
+    let config = use_context::<Resource<ImageConfig>>()
+        .and_then(|config| config.get_untracked())
+        .unwrap_or_default();
+    SetContext::from_config(config, src, tenant, None, Placeholder::Blur)
+        .build(src, widths, quality, format)
+}
+
+#[cfg(feature = "ssr")]
+impl crate::ImageOptimizer {
+    /// The URLs of `src` at each of `widths` below the cache handler, see
+    /// [`responsive_set`]. For server-side templates and handlers, without a Leptos
+    /// context.
+    ///
+    /// ```
+    /// # use leptos_image::*;
+    /// let optimizer = ImageOptimizer::new("/__cache/image", "./target/site", 1);
+    /// let set = optimizer.responsive_set("/hero.jpg", [640, 1280], 75, OutputFormat::Webp);
+    /// let img = format!(
+    ///     r#"<img srcset="{}" sizes="{}" src="{}">"#,
+    ///     set.srcset, set.sizes, set.fallback
+    /// );
+    /// ```
+    pub fn responsive_set(
+        &self,
+        src: &str,
+        widths: impl IntoIterator<Item = u32>,
+        quality: u8,
+        format: OutputFormat,
+    ) -> ResponsiveSet {
+        self.set_context(src, None, Placeholder::Blur)
+            .build(src, widths, quality, format)
+    }
+
+    fn set_context(
+        &self,
+        src: &str,
+        tenant: Option<String>,
+        placeholder: Placeholder,
+    ) -> SetContext {
+        let image = placeholder_image(src, tenant.clone(), placeholder);
+        SetContext {
+            handler_url: self.handler_url(),
+            cache_key: self.url_key.clone(),
+            tenant,
+            intrinsic: self.intrinsic_size(src),
+            placeholder,
+            held_placeholder: self.cache.get(&image).map(|value| value.value().clone()),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "ssr"))]
+mod responsive_tests {
+    use super::*;
+
+    #[test]
+    fn widths_of_source() {
+        let root = "./example/start-axum/public";
+        let optimizer = crate::ImageOptimizer::new("/__cache/image", root, 1);
+        let (source_width, source_height) = optimizer.intrinsic_size("/cute_ferris.png").unwrap();
+        let set = optimizer.responsive_set(
+            "/cute_ferris.png",
+            [source_width * 2, 100, source_width],
+            75,
+            OutputFormat::Webp,
+        );
+
+        let widths: Vec<&str> = set
+            .srcset
+            .split(", ")
+            .map(|entry| entry.rsplit(' ').next().unwrap())
+            .collect();
+        assert_eq!(widths, ["100w", &format!("{source_width}w")]);
+        let widest = CachedImage::from_query(set.fallback.split_once('?').unwrap().1).unwrap();
+        let CachedImageOption::Resize(resize) = widest.option else {
+            panic!("Not resized: {widest:?}");
+        };
+        assert_eq!((resize.width, resize.height), (source_width, source_height));
+        assert_eq!(set.sizes, format!("(max-width: {source_width}px) 100vw, {source_width}px"));
+        assert!(set.placeholder.unwrap().starts_with("/__cache/image?"));
+    }
+}
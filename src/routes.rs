@@ -1,15 +1,18 @@
-use crate::optimizer::{CachedImage, CachedImageOption, CreateImageError, ImageOptimizer};
+use crate::optimizer::{
+    CachedImage, CachedImageOption, CreateImageError, Creation, EncodeErrorPolicy,
+    ImageOptimizer, OutputFormat, ReadOnlyFallback, Resize,
+};
 use axum::extract::FromRef;
 use axum::response::Response as AxumResponse;
 use axum::{
     body::Body,
-    http::{Request, Response, Uri},
+    http::{header, HeaderMap, HeaderValue, Request, Response, StatusCode, Uri},
     response::IntoResponse,
 };
 use std::convert::Infallible;
 use tower::util::ServiceExt;
 use tower_http::services::fs::ServeFileSystemResponseBody;
-use tower_http::services::ServeDir;
+use tower_http::services::{ServeDir, ServeFile};
 
 /// This trait prevents using incorrect route for image cache handler.
 pub trait ImageCacheRoute<S>
@@ -69,23 +72,99 @@ where
     ImageOptimizer: FromRef<S>,
 {
     fn image_cache_route(self, state: &S) -> Self {
-        let optimizer = ImageOptimizer::from_ref(state);
+        self.image_cache_route_with(&ImageOptimizer::from_ref(state))
+    }
+}
+
+/// Adds the cache handler route with the optimizer passed directly, see
+/// [`ImageCacheRouteWith::image_cache_route_with`].
+pub trait ImageCacheRouteWith {
+    /// Adds a route to the app for serving cached images, cloning the optimizer into
+    /// the handler. Works with any state type, including `()`, no `FromRef` needed.
+    ///
+    /// ```
+    /// use leptos_image::*;
+    ///
+    /// # #[cfg(feature = "ssr")]
+    /// # fn router() -> axum::Router {
+    /// let optimizer = ImageOptimizer::new("/__cache/image", "./target/site", 1);
+    /// axum::Router::new().image_cache_route_with(&optimizer)
+    /// # }
+    /// ```
+    fn image_cache_route_with(self, optimizer: &ImageOptimizer) -> Self;
+}
 
+impl<S> ImageCacheRouteWith for axum::Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    fn image_cache_route_with(self, optimizer: &ImageOptimizer) -> Self {
+        let optimizer = optimizer.clone();
         let path = optimizer.api_handler_path.clone();
-        let handler = move |req: Request<Body>| image_cache_handler_inner(optimizer, req);
+        let handler = move |req: Request<Body>| {
+            let span = request_span(&req);
+            tracing::Instrument::instrument(image_cache_handler_inner(optimizer, req), span)
+        };
 
-        self.route(&path, axum::routing::get(handler))
+        // Options in a path segment, see `PathCacheKey`.
+        let options_path = format!("{}/*options", path.trim_end_matches('/'));
+        self.route(&path, axum::routing::get(handler.clone()))
+            .route(&options_path, axum::routing::get(handler))
     }
 }
 
+// Span of an image request, with the `otel` feature a child of the page render that
+// sent it. Encodes started by the request are recorded below it.
+fn request_span(req: &Request<Body>) -> tracing::Span {
+    let span = tracing::info_span!("image_request", uri = %req.uri());
+    #[cfg(feature = "otel")]
+    crate::otel::set_parent(&span, req.headers());
+    span
+}
+
 async fn image_cache_handler_inner(optimizer: ImageOptimizer, req: Request<Body>) -> AxumResponse {
-    let root = optimizer.root_file_path.clone();
-    let cache_result = check_cache_image(&optimizer, req.uri().clone()).await;
+    let started = std::time::Instant::now();
+    let request = optimizer
+        .handler
+        .access_log
+        .then(|| (req.uri().clone(), req.headers().get(header::ACCEPT).cloned()));
+    let mut served = None;
+    let response = image_response(&optimizer, req, &mut served).await;
+    if let Some((uri, accept)) = request {
+        log_access(&uri, accept.as_ref(), served.as_ref(), &response, started.elapsed());
+    }
+    response
+}
+
+// Serves a request, recording the image and its encode time in `served` once found.
+async fn image_response(
+    optimizer: &ImageOptimizer,
+    req: Request<Body>,
+    served: &mut Option<(CachedImage, Option<std::time::Duration>)>,
+) -> AxumResponse {
+    if let Some(fallback) = optimizer.read_only.clone() {
+        return read_only_response(optimizer, &fallback, req).await;
+    }
+    let cache_dir = optimizer.cache_dir.clone();
+    let cache_result = check_cache_image(optimizer, req.uri().clone(), req.headers()).await;
 
     match cache_result {
-        Ok(Some(uri)) => {
-            let response = execute_file_handler(uri, &root).await.unwrap();
-            response.into_response()
+        Ok(Some(lookup)) => {
+            *served = Some((lookup.image, lookup.encode_time));
+            let vary = lookup.vary;
+            let mut response = match lookup.fresh {
+                Some(response) => response,
+                None => serve_cached(optimizer, lookup.uri, &cache_dir, req.headers()).await,
+            };
+            if optimizer.handler.diagnostic_headers {
+                add_diagnostic_headers(&mut response, lookup.encode_time);
+            }
+            if !vary.is_empty() {
+                // Appended, `ServeDir` varies on `Accept-Encoding` for precompressed files.
+                let vary = HeaderValue::from_str(&vary.join(", ")).unwrap();
+                response.headers_mut().append(header::VARY, vary);
+            }
+            response
         }
 
         Ok(None) => Response::builder()
@@ -94,72 +173,682 @@ async fn image_cache_handler_inner(optimizer: ImageOptimizer, req: Request<Body>
             .unwrap()
             .into_response(),
 
-        Err(e) => {
-            tracing::error!("Failed to create image: {:?}", e);
-            Response::builder()
-                .status(500)
-                .body("Error creating image".to_string())
-                .unwrap()
-                .into_response()
+        Err(e) if e.is_encode_failure() => encode_error_response(optimizer, req, e).await,
+
+        Err(e) => error_response(&optimizer.handler, e),
+    }
+}
+
+// Whether a served image was encoded for the request.
+fn cache_status(response: &AxumResponse, encode_time: Option<std::time::Duration>) -> &'static str {
+    match encode_time {
+        Some(_) => "miss",
+        None if response.status() == StatusCode::NOT_MODIFIED => "revalidated",
+        None => "hit",
+    }
+}
+
+// Emits the access log record of a request, see `HandlerConfig::with_access_log`.
+fn log_access(
+    uri: &Uri,
+    accept: Option<&HeaderValue>,
+    served: Option<&(CachedImage, Option<std::time::Duration>)>,
+    response: &AxumResponse,
+    elapsed: std::time::Duration,
+) {
+    let bytes = response
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok()?.parse::<u64>().ok())
+        .or_else(|| axum::body::HttpBody::size_hint(response.body()).exact());
+    let (src, format, cache) = match served {
+        Some((image, encode_time)) => (
+            image.src.as_str(),
+            image.extension(),
+            cache_status(response, *encode_time),
+        ),
+        None => ("", String::new(), "none"),
+    };
+    tracing::info!(
+        target: "leptos_image::access",
+        src,
+        // `PathCacheKey` URLs have their options in the path.
+        params = uri.query().unwrap_or(uri.path()),
+        format = %format,
+        bytes,
+        cache,
+        status = response.status().as_u16(),
+        duration_ms = elapsed.as_millis() as u64,
+        accept = accept.and_then(|value| value.to_str().ok()).unwrap_or_default(),
+        "Image request"
+    );
+}
+
+// Tells whether the response was encoded for the request, see
+// `HandlerConfig::with_diagnostic_headers`.
+fn add_diagnostic_headers(response: &mut AxumResponse, encode_time: Option<std::time::Duration>) {
+    let cache = cache_status(response, encode_time);
+    let headers = response.headers_mut();
+    headers.insert("x-image-cache", HeaderValue::from_static(cache));
+    if let Some(encode_time) = encode_time {
+        headers.insert("x-image-encode-ms", HeaderValue::from(encode_time.as_millis() as u64));
+    }
+}
+
+// Serves a cached file from memory or disk.
+#[cfg_attr(not(feature = "memory-cache"), allow(unused_variables))]
+async fn serve_cached(
+    optimizer: &ImageOptimizer,
+    uri: Uri,
+    cache_dir: &std::path::Path,
+    headers: &HeaderMap,
+) -> AxumResponse {
+    #[cfg(feature = "memory-cache")]
+    let memory = crate::memory::serve(optimizer, &uri, headers).await;
+    #[cfg(not(feature = "memory-cache"))]
+    let memory = None;
+    match memory {
+        Some(response) => response,
+        None => execute_file_handler(uri, cache_dir, headers)
+            .await
+            .unwrap()
+            .into_response(),
+    }
+}
+
+async fn encode_error_response(
+    optimizer: &ImageOptimizer,
+    req: Request<Body>,
+    error: CreateImageError,
+) -> AxumResponse {
+    optimizer.metrics.encode_failed();
+    let fallback = match &optimizer.on_encode_error {
+        EncodeErrorPolicy::Error => return error_response(&optimizer.handler, error),
+        EncodeErrorPolicy::Error404 => {
+            tracing::error!("Failed to create image: {:?}", error);
+            return (StatusCode::NOT_FOUND, "Image not found.").into_response();
+        }
+        EncodeErrorPolicy::ServeOriginal => optimizer
+            .cache_key
+            .parse_url(&req.uri().to_string(), &optimizer.api_handler_path)
+            .map(|image| image.src)
+            .ok(),
+        EncodeErrorPolicy::FallbackImage(path) => Some(path.clone()),
+    };
+    tracing::error!("Failed to create image, serving {fallback:?} instead: {:?}", error);
+
+    let Some(fallback) = fallback else {
+        return error_response(&optimizer.handler, error);
+    };
+    // Sources may live in a mount outside the root.
+    let path = match optimizer.resolve_source(&fallback).await {
+        Ok(path) => path,
+        Err(e) => return error_response(&optimizer.handler, e),
+    };
+    let request = Request::builder().body(Body::empty()).unwrap();
+    let mut response = ServeFile::new(path)
+        .oneshot(request)
+        .await
+        .unwrap()
+        .into_response();
+    // The next request should retry the encode.
+    response
+        .headers_mut()
+        .insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    response
+}
+
+/// Controls how the cache handler reports failures.
+///
+/// By default errors map to 403 (source outside the root), 404 (missing source, or
+/// not pre-generated in read-only mode), 413 (source over the decoder limits or the
+/// memory budget), 415 (not an image), 422 (invalid parameters), 503 (shutting down),
+/// 507 (tenant quota exceeded) and 500 otherwise, with a plain text body.
+///
+/// ```
+/// use leptos_image::*;
+/// use axum::http::StatusCode;
+///
+/// let handler = HandlerConfig::default()
+///     .with_json_errors(true)
+///     // Hide missing sources behind a generic error.
+///     .with_status(|error| match error {
+///         CreateImageError::SourceNotFound(_) => StatusCode::BAD_REQUEST,
+///         error => HandlerConfig::default_status(error),
+///     });
+/// let optimizer =
+///     ImageOptimizer::new("/__cache/image", "./public", 1).with_handler_config(handler);
+/// ```
+#[derive(Clone)]
+pub struct HandlerConfig {
+    json_errors: bool,
+    client_hints: bool,
+    content_aware_format: bool,
+    diagnostic_headers: bool,
+    access_log: bool,
+    status: std::sync::Arc<dyn Fn(&CreateImageError) -> StatusCode + Send + Sync>,
+}
+
+impl Default for HandlerConfig {
+    fn default() -> Self {
+        Self {
+            json_errors: false,
+            client_hints: true,
+            content_aware_format: false,
+            diagnostic_headers: false,
+            access_log: false,
+            status: std::sync::Arc::new(Self::default_status),
         }
     }
 }
 
+impl std::fmt::Debug for HandlerConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HandlerConfig")
+            .field("json_errors", &self.json_errors)
+            .field("client_hints", &self.client_hints)
+            .field("content_aware_format", &self.content_aware_format)
+            .field("diagnostic_headers", &self.diagnostic_headers)
+            .field("access_log", &self.access_log)
+            .finish_non_exhaustive()
+    }
+}
+
+impl HandlerConfig {
+    /// Responds with `{"status": 404, "code": "source_not_found", "error": "..."}` bodies.
+    pub fn with_json_errors(mut self, json_errors: bool) -> Self {
+        self.json_errors = json_errors;
+        self
+    }
+
+    /// Whether `Sec-CH-Width`, `Sec-CH-DPR` and `Save-Data` request headers pick the
+    /// served variant, enabled by default.
+    ///
+    /// With a DPR above 1 the image is scaled up to 3x, but never beyond the source.
+    /// `Sec-CH-Width` then caps the width to what the layout needs, and `Save-Data: on`
+    /// lowers the quality to at most [`SAVE_DATA_QUALITY`]. Browsers only send the
+    /// width and DPR hints once the page opted in, see [`accept_client_hints`].
+    pub fn with_client_hints(mut self, client_hints: bool) -> Self {
+        self.client_hints = client_hints;
+        self
+    }
+
+    /// Whether [`OutputFormat::Auto`] also looks at the source, disabled by default.
+    ///
+    /// Flat graphics (few colors or large flat areas, e.g. logos, charts and
+    /// screenshots) are then encoded as lossless WebP, photos with transparency as
+    /// lossy WebP, and opaque photos as the best format in the `Accept` header.
+    /// Each source is analyzed once per modification. The chosen format is part of
+    /// the cache key, so enabling this re-encodes graphics once.
+    pub fn with_content_aware_format(mut self, content_aware_format: bool) -> Self {
+        self.content_aware_format = content_aware_format;
+        self
+    }
+
+    /// Whether responses tell how they were served, disabled by default.
+    ///
+    /// `x-image-cache` is `miss` when the image was encoded for the request, `hit` when
+    /// it was already cached and `revalidated` for a `304 Not Modified`. Misses also
+    /// carry `x-image-encode-ms`. Meant for CDN logs and browser devtools while tuning,
+    /// leave it off in production.
+    pub fn with_diagnostic_headers(mut self, diagnostic_headers: bool) -> Self {
+        self.diagnostic_headers = diagnostic_headers;
+        self
+    }
+
+    /// Whether every request emits an `info` event with target `leptos_image::access`,
+    /// disabled by default. Its fields are the `src`, the request `params`, the served
+    /// `format`, `bytes` out, `cache` status (see [`Self::with_diagnostic_headers`],
+    /// `none` for errors), response `status`, `duration_ms` and the client's `accept`
+    /// header. Logged as JSON with e.g. `tracing_subscriber::fmt().json()`, enough to
+    /// tune presets and spot abuse without a metrics stack.
+    pub fn with_access_log(mut self, access_log: bool) -> Self {
+        self.access_log = access_log;
+        self
+    }
+
+    /// Replaces the error to status code mapping.
+    pub fn with_status(
+        mut self,
+        status: impl Fn(&CreateImageError) -> StatusCode + Send + Sync + 'static,
+    ) -> Self {
+        self.status = std::sync::Arc::new(status);
+        self
+    }
+
+    /// The default error to status code mapping.
+    pub fn default_status(error: &CreateImageError) -> StatusCode {
+        match error {
+            CreateImageError::SourceNotFound(_) | CreateImageError::ReadOnly(_) => {
+                StatusCode::NOT_FOUND
+            }
+            CreateImageError::Forbidden(_) => StatusCode::FORBIDDEN,
+            CreateImageError::UnsupportedFormat(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            CreateImageError::InvalidParams(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            CreateImageError::ImageError(image::ImageError::Limits(_))
+            | CreateImageError::MemoryBudgetExceeded(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            CreateImageError::QuotaExceeded(_) => StatusCode::INSUFFICIENT_STORAGE,
+            CreateImageError::ShuttingDown => StatusCode::SERVICE_UNAVAILABLE,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+// Machine readable name of the error, for JSON bodies.
+fn error_code(error: &CreateImageError) -> &'static str {
+    match error {
+        CreateImageError::ImageError(image::ImageError::Limits(_)) => "source_too_large",
+        CreateImageError::ImageError(_) => "image_error",
+        CreateImageError::JoinError(_) => "join_error",
+        CreateImageError::IOError(_) => "io_error",
+        CreateImageError::EncodeError(_) => "encode_error",
+        CreateImageError::DecodeError(_) => "decode_error",
+        CreateImageError::SourceNotFound(_) => "source_not_found",
+        CreateImageError::Forbidden(_) => "forbidden",
+        CreateImageError::UnsupportedFormat(_) => "unsupported_format",
+        CreateImageError::InvalidParams(_) => "invalid_params",
+        CreateImageError::QuotaExceeded(_) => "quota_exceeded",
+        CreateImageError::ReadOnly(_) => "read_only",
+        CreateImageError::ShuttingDown => "shutting_down",
+        CreateImageError::Cancelled => "cancelled",
+        CreateImageError::MemoryBudgetExceeded(_) => "memory_budget_exceeded",
+        CreateImageError::Timeout(_) => "timeout",
+    }
+}
+
+fn error_response(config: &HandlerConfig, error: CreateImageError) -> AxumResponse {
+    let status = (config.status)(&error);
+    if status.is_server_error() {
+        tracing::error!("Failed to create image: {:?}", error);
+    } else {
+        tracing::debug!("Image request failed: {error}");
+    }
+
+    // Internal errors may contain file system paths, only describe them generically.
+    let message = if status.is_server_error() {
+        "Error creating image".to_string()
+    } else {
+        error.to_string()
+    };
+
+    if config.json_errors {
+        let body = serde_json::json!({
+            "status": status.as_u16(),
+            "code": error_code(&error),
+            "error": message,
+        });
+        (status, axum::Json(body)).into_response()
+    } else {
+        (status, message).into_response()
+    }
+}
+
+// Request headers `ServeDir` uses for partial, conditional and precompressed responses.
+static FORWARDED_HEADERS: [header::HeaderName; 6] = [
+    header::ACCEPT_ENCODING,
+    header::RANGE,
+    header::IF_RANGE,
+    header::IF_MODIFIED_SINCE,
+    header::IF_UNMODIFIED_SINCE,
+    header::IF_NONE_MATCH,
+];
+
 async fn execute_file_handler(
     uri: Uri,
-    root: &str,
+    cache_dir: &std::path::Path,
+    headers: &HeaderMap,
 ) -> Result<Response<ServeFileSystemResponseBody>, Infallible> {
-    let req = Request::builder()
+    let mut req = Request::builder()
         .uri(uri.clone())
         .body(Body::empty())
         .unwrap();
-    ServeDir::new(root).oneshot(req).await
+    for name in &FORWARDED_HEADERS {
+        if let Some(value) = headers.get(name) {
+            req.headers_mut().insert(name.clone(), value.clone());
+        }
+    }
+    let serve_dir = ServeDir::new(cache_dir);
+    #[cfg(feature = "precompress")]
+    let serve_dir = serve_dir.precompressed_br().precompressed_gzip();
+    serve_dir.oneshot(req).await
 }
 
-async fn check_cache_image(
+// Redirects to the source itself. `Location` is rebuilt from the checked source,
+// so a source like `//evil.example/x.png` can't send visitors to another site.
+async fn redirect_to_source(optimizer: &ImageOptimizer, src: &str) -> AxumResponse {
+    match redirect_location(optimizer, src).await {
+        Ok(location) => {
+            (StatusCode::TEMPORARY_REDIRECT, [(header::LOCATION, location)]).into_response()
+        }
+        Err(e) => error_response(&optimizer.handler, e),
+    }
+}
+
+// The path of a source on this site, refusing the schemes and leading `//` that
+// would point to another host.
+async fn redirect_location(
     optimizer: &ImageOptimizer,
-    uri: Uri,
-) -> Result<Option<Uri>, CreateImageError> {
-    let cache_image = {
-        let url = uri.to_string();
+    src: &str,
+) -> Result<HeaderValue, CreateImageError> {
+    let forbidden = || CreateImageError::Forbidden(src.to_string());
+    let relative = src.trim_start_matches(['/', '\\']);
+    let scheme = relative.split(['/', '\\']).next().is_some_and(|first| first.contains(':'));
+    if src.len() - relative.len() > 1 || scheme {
+        return Err(forbidden());
+    }
+    // Rejects `..`, absolute paths and links out of the root.
+    optimizer.resolve_source(src).await?;
+    let segments: Vec<&str> =
+        relative.split('/').filter(|segment| !segment.is_empty() && *segment != ".").collect();
+    HeaderValue::from_str(&format!("/{}", segments.join("/"))).map_err(|_| forbidden())
+}
 
-        if let Some(img) = CachedImage::from_url_encoded(&url).ok() {
-            let result = optimizer.create_image(&img).await;
+// Serves pre-generated images only, never writing to disk.
+async fn read_only_response(
+    optimizer: &ImageOptimizer,
+    fallback: &ReadOnlyFallback,
+    req: Request<Body>,
+) -> AxumResponse {
+    let (cache_image, vary) = match requested_image(optimizer, req.uri(), req.headers()).await {
+        Ok(requested) => requested,
+        Err(e) => return error_response(&optimizer.handler, e),
+    };
+    let vary = HeaderValue::from_str(&vary.join(", ")).unwrap();
 
-            if let Ok(true) = result {
-                tracing::info!("Created Image: {}", img);
+    let file_path = optimizer.cache_key.file_path(&cache_image);
+    let exists = tokio::fs::metadata(optimizer.get_file_path_from_root(&cache_image))
+        .await
+        .is_ok();
+    if exists {
+        if let Ok(uri) = format!("/{file_path}").parse::<Uri>() {
+            let cache_dir = &optimizer.cache_dir;
+            let response = execute_file_handler(uri, cache_dir, req.headers()).await.unwrap();
+            let mut response = response.into_response();
+            response.headers_mut().append(header::VARY, vary);
+            return response;
+        }
+    }
+
+    let redirect = || redirect_to_source(optimizer, &cache_image.src);
+    match fallback {
+        ReadOnlyFallback::RedirectToOriginal => redirect().await,
+        ReadOnlyFallback::NotFound => {
+            let error = CreateImageError::ReadOnly(cache_image.src.clone());
+            error_response(&optimizer.handler, error)
+        }
+        ReadOnlyFallback::EncodeInMemory { max_source_bytes } => {
+            let size = match optimizer.resolve_source(&cache_image.src).await {
+                Ok(path) => tokio::fs::metadata(path).await.map(|metadata| metadata.len()).ok(),
+                Err(e) => return error_response(&optimizer.handler, e),
+            };
+            match size {
+                None => {
+                    let error = CreateImageError::SourceNotFound(cache_image.src.clone());
+                    return error_response(&optimizer.handler, error);
+                }
+                Some(size) if size > *max_source_bytes => return redirect().await,
+                Some(_) => {}
             }
+            match optimizer.encode_in_memory(&cache_image).await {
+                Ok(bytes) => {
+                    let content_type = HeaderValue::from_str(&cache_image.content_type()).unwrap();
+                    let mut response = bytes.into_response();
+                    let headers = response.headers_mut();
+                    headers.insert(header::CONTENT_TYPE, content_type);
+                    headers.append(header::VARY, vary);
+                    response
+                }
+                Err(e) if e.is_encode_failure() => encode_error_response(optimizer, req, e).await,
+                Err(e) => error_response(&optimizer.handler, e),
+            }
+        }
+    }
+}
+
+// Parses the request, resolving the format and client hints. Also returns the request
+// headers that picked the variant.
+async fn requested_image(
+    optimizer: &ImageOptimizer,
+    uri: &Uri,
+    headers: &HeaderMap,
+) -> Result<(CachedImage, Vec<&'static str>), CreateImageError> {
+    let mut cache_image = optimizer
+        .cache_key
+        .parse_url(&uri.to_string(), &optimizer.api_handler_path)
+        .map_err(CreateImageError::InvalidParams)?;
+    let resize = matches!(cache_image.option, CachedImageOption::Resize(_));
+    if !resize && !optimizer.allows_placeholders(&cache_image.src) {
+        return Err(CreateImageError::Forbidden(cache_image.src));
+    }
+    let mut vary = Vec::new();
+    if optimizer.handler.client_hints && resize {
+        apply_client_hints(optimizer, &mut cache_image, headers).await;
+        vary.extend(CLIENT_HINTS);
+    }
+    // After the client hints, so they can't scale past the policy.
+    optimizer.apply_policy(&mut cache_image);
+    if negotiate_format(optimizer, &mut cache_image, headers).await {
+        vary.push("Accept");
+    }
+    Ok((cache_image, vary))
+}
+
+// A requested image, ready to be served.
+struct Lookup {
+    // The image, after negotiating the format and client hints.
+    image: CachedImage,
+    // Path of the cached file below the cache directory.
+    uri: Uri,
+    // Request headers that picked the variant.
+    vary: Vec<&'static str>,
+    // Response of an image that was just encoded, served from memory instead of
+    // reading it back from disk.
+    fresh: Option<AxumResponse>,
+    // How long the image took to create, `None` if it was already cached.
+    encode_time: Option<std::time::Duration>,
+}
 
-            result?;
+// Creates the requested image if needed.
+async fn check_cache_image(
+    optimizer: &ImageOptimizer,
+    uri: Uri,
+    headers: &HeaderMap,
+) -> Result<Option<Lookup>, CreateImageError> {
+    let (cache_image, vary) = requested_image(optimizer, &uri, headers).await?;
 
-            img
-        } else {
-            return Ok(None);
+    #[cfg(feature = "memory-cache")]
+    let in_memory = optimizer.memory.as_ref().is_some_and(|memory| {
+        let file_path = optimizer.cache_key.file_path(&cache_image);
+        optimizer.freshness().is_none() && memory.contains(&format!("/{file_path}"))
+    });
+    #[cfg(not(feature = "memory-cache"))]
+    let in_memory = false;
+    // Images in memory exist on disk, skip the file system checks.
+    let started = std::time::Instant::now();
+    let creation = if in_memory {
+        Creation::Cached
+    } else {
+        optimizer.create(&cache_image).await?
+    };
+    let encode_time = creation.is_encoded().then(|| started.elapsed());
+    if creation.is_encoded() {
+        tracing::info!("Created Image: {}", cache_image);
+    }
+    let fresh = match creation {
+        // Placeholders may be served precompressed, and partial or conditional requests
+        // (the forwarded headers after `Accept-Encoding`) are left to `ServeDir`.
+        Creation::Encoded(Some(bytes))
+            if matches!(cache_image.option, CachedImageOption::Resize(_))
+                && !FORWARDED_HEADERS[1..].iter().any(|name| headers.contains_key(name)) =>
+        {
+            let content_type = HeaderValue::from_str(&cache_image.content_type()).unwrap();
+            Some(([(header::CONTENT_TYPE, content_type)], bytes).into_response())
         }
+        _ => None,
     };
 
-    let file_path = cache_image.get_file_path();
+    let file_path = optimizer.cache_key.file_path(&cache_image);
 
-    add_file_to_cache(optimizer, cache_image).await;
+    add_file_to_cache(optimizer, cache_image.clone()).await;
 
     let uri_string = "/".to_string() + &file_path;
     let maybe_uri = (uri_string).parse::<Uri>().ok();
 
     if let Some(uri) = maybe_uri {
-        Ok(Some(uri))
+        Ok(Some(Lookup {
+            image: cache_image,
+            uri,
+            vary,
+            fresh,
+            encode_time,
+        }))
     } else {
         tracing::error!("Failed to create uri: File path {file_path}");
         Ok(None)
     }
 }
 
+// Resolves `OutputFormat::Auto` to a concrete format the client accepts.
+async fn negotiate_format(
+    optimizer: &ImageOptimizer,
+    image: &mut CachedImage,
+    headers: &HeaderMap,
+) -> bool {
+    let profile = match &image.option {
+        CachedImageOption::Resize(resize) if resize.format == OutputFormat::Auto => {
+            if optimizer.handler.content_aware_format {
+                optimizer.content_profile(&image.src).await
+            } else {
+                None
+            }
+        }
+        _ => return false,
+    };
+    let CachedImageOption::Resize(resize) = &mut image.option else {
+        return false;
+    };
+    if let Some(profile) = profile.filter(|profile| profile.graphic || profile.transparent) {
+        resize.format = OutputFormat::Webp;
+        resize.lossless |= profile.graphic;
+        // The format doesn't depend on the request headers.
+        return false;
+    }
+    let accept = headers
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    resize.format = if cfg!(feature = "jxl") && accept.contains("image/jxl") {
+        OutputFormat::Jxl
+    } else if cfg!(feature = "avif") && accept.contains("image/avif") {
+        OutputFormat::Avif
+    } else {
+        OutputFormat::Webp
+    };
+    true
+}
+
+/// Highest quality served to clients that send `Save-Data: on`.
+pub const SAVE_DATA_QUALITY: u8 = 50;
+
+const CLIENT_HINTS: [&str; 3] = ["Sec-CH-Width", "Sec-CH-DPR", "Save-Data"];
+
+/// Middleware that asks browsers for the client hints the cache handler uses.
+///
+/// Add it to the routes serving your pages, the `Accept-CH` header has to be on the
+/// document rather than on the images.
+///
+/// ```
+/// use axum::{middleware, Router};
+///
+/// let router: Router<()> =
+///     Router::new().layer(middleware::from_fn(leptos_image::accept_client_hints));
+/// ```
+pub async fn accept_client_hints(
+    req: Request<Body>,
+    next: axum::middleware::Next,
+) -> AxumResponse {
+    let mut response = next.run(req).await;
+    response.headers_mut().insert(
+        header::HeaderName::from_static("accept-ch"),
+        HeaderValue::from_static("Sec-CH-Width, Sec-CH-DPR"),
+    );
+    response
+}
+
+// Adjusts the size and quality of a resize to the client hints of the request.
+async fn apply_client_hints(
+    optimizer: &ImageOptimizer,
+    cache_image: &mut CachedImage,
+    headers: &HeaderMap,
+) {
+    let hint = |names: &[&str]| {
+        names
+            .iter()
+            .find_map(|name| headers.get(*name))
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.trim().to_string())
+    };
+    let dpr = hint(&["sec-ch-dpr", "dpr"]).and_then(|dpr| dpr.parse::<f32>().ok());
+    let hint_width = hint(&["sec-ch-width", "width"]).and_then(|width| width.parse::<u32>().ok());
+    let save_data = hint(&["save-data"]).is_some_and(|value| value.eq_ignore_ascii_case("on"));
+
+    // Never upscale past the source, read from the header only.
+    let source = match dpr {
+        Some(dpr) if dpr > 1.0 => {
+            match optimizer.resolve_source(&cache_image.src).await {
+                Ok(path) => {
+                    tokio::task::spawn_blocking(move || image::image_dimensions(path).ok())
+                        .await
+                        .ok()
+                        .flatten()
+                }
+                Err(_) => None,
+            }
+        }
+        _ => None,
+    };
+
+    let CachedImageOption::Resize(resize) = &mut cache_image.option else {
+        return;
+    };
+    scale_for_hints(resize, dpr.zip(source), hint_width);
+    if save_data && !resize.lossless {
+        resize.quality = resize.quality.min(SAVE_DATA_QUALITY);
+    }
+}
+
+fn scale_for_hints(resize: &mut Resize, dpr: Option<(f32, (u32, u32))>, hint_width: Option<u32>) {
+    if resize.width == 0 || resize.height == 0 {
+        return;
+    }
+    let mut factor = 1.0;
+    if let Some((dpr, (source_width, source_height))) = dpr {
+        // Half steps keep the number of cached variants small.
+        let dpr = (dpr.clamp(1.0, 3.0) * 2.0).round() / 2.0;
+        let fit = f32::min(
+            source_width as f32 / resize.width as f32,
+            source_height as f32 / resize.height as f32,
+        );
+        factor = dpr.min(fit).max(1.0);
+    }
+    if let Some(hint_width) = hint_width.filter(|width| *width > 0) {
+        // Rounded up to 32px steps, for the same reason.
+        let hint_width = hint_width.div_ceil(32) * 32;
+        factor = factor.min(hint_width as f32 / resize.width as f32);
+    }
+    if factor != 1.0 {
+        resize.width = ((resize.width as f32 * factor).ceil() as u32).max(1);
+        resize.height = ((resize.height as f32 * factor).ceil() as u32).max(1);
+    }
+}
+
 // When the image is created, it will be added to the cache.
 // Mostly helpful for dev server startup.
-async fn add_file_to_cache(optimizer: &ImageOptimizer, image: CachedImage) {
+pub(crate) async fn add_file_to_cache(optimizer: &ImageOptimizer, image: CachedImage) {
     if let CachedImageOption::Blur(_) = image.option {
         if optimizer.cache.get(&image).is_none() {
             let path = optimizer.get_file_path_from_root(&image);
-            match tokio::fs::read_to_string(path).await {
+            match tokio::fs::read(path).await.and_then(|bytes| image.placeholder_value(bytes)) {
                 Ok(data) => {
                     optimizer.cache.insert(image, data);
                     tracing::debug!("Added image to cache (size {})", optimizer.cache.len())
@@ -171,3 +860,142 @@ async fn add_file_to_cache(optimizer: &ImageOptimizer, image: CachedImage) {
         }
     }
 }
+
+#[cfg(test)]
+mod routes_tests {
+    use super::*;
+    use crate::test_support::{resize_spec, test_dir, test_root};
+
+    #[test]
+    fn error_statuses() {
+        let status = HandlerConfig::default_status;
+        assert_eq!(
+            status(&CreateImageError::SourceNotFound("/a.png".into())),
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            status(&CreateImageError::InvalidParams("missing field".into())),
+            StatusCode::UNPROCESSABLE_ENTITY
+        );
+        assert_eq!(status(&CreateImageError::ShuttingDown), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            status(&CreateImageError::EncodeError("oops".into())),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+
+        let config = HandlerConfig::default()
+            .with_json_errors(true)
+            .with_status(|_| StatusCode::BAD_REQUEST);
+        let response = error_response(&config, CreateImageError::ShuttingDown);
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            HeaderValue::from_static("application/json")
+        );
+    }
+
+    #[test]
+    fn diagnostic_headers() {
+        let mut miss = "image".into_response();
+        add_diagnostic_headers(&mut miss, Some(std::time::Duration::from_millis(42)));
+        assert_eq!(miss.headers()["x-image-cache"], "miss");
+        assert_eq!(miss.headers()["x-image-encode-ms"], "42");
+
+        let mut revalidated = StatusCode::NOT_MODIFIED.into_response();
+        add_diagnostic_headers(&mut revalidated, None);
+        assert_eq!(revalidated.headers()["x-image-cache"], "revalidated");
+        assert!(!revalidated.headers().contains_key("x-image-encode-ms"));
+    }
+
+    #[test]
+    fn range_requests() {
+        let root = test_dir("leptos_image_range");
+        std::fs::create_dir_all(root.join("cache/image")).unwrap();
+        std::fs::write(root.join("cache/image/a.webp"), b"0123456789").unwrap();
+        let uri: Uri = "/cache/image/a.webp".parse().unwrap();
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let serve = |headers: &[(header::HeaderName, &str)]| {
+            let headers: HeaderMap = headers
+                .iter()
+                .map(|(name, value)| (name.clone(), HeaderValue::from_str(value).unwrap()))
+                .collect();
+            runtime.block_on(execute_file_handler(uri.clone(), &root, &headers)).unwrap()
+        };
+
+        let response = serve(&[(header::RANGE, "bytes=2-5")]);
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes 2-5/10");
+        assert_eq!(response.headers()[header::CONTENT_LENGTH], "4");
+
+        let response = serve(&[(header::RANGE, "bytes=20-30")]);
+        assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+
+        let response = serve(&[]);
+        assert_eq!(response.status(), StatusCode::OK);
+        let modified = response.headers()[header::LAST_MODIFIED].to_str().unwrap().to_string();
+        let response = serve(&[(header::IF_MODIFIED_SINCE, &modified)]);
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+    }
+
+    #[cfg(feature = "precompress")]
+    #[test]
+    fn precompressed_placeholders() {
+        let root = test_dir("leptos_image_precompressed");
+        std::fs::create_dir_all(root.join("cache/image")).unwrap();
+        std::fs::write(root.join("cache/image/a.svg"), "<svg></svg>").unwrap();
+        std::fs::write(root.join("cache/image/a.svg.br"), "br").unwrap();
+        std::fs::write(root.join("cache/image/a.svg.gz"), "gz").unwrap();
+        let uri: Uri = "/cache/image/a.svg".parse().unwrap();
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let encoding = |accept: Option<&str>| {
+            let mut headers = HeaderMap::new();
+            if let Some(accept) = accept {
+                headers.insert(header::ACCEPT_ENCODING, HeaderValue::from_str(accept).unwrap());
+            }
+            let response = runtime
+                .block_on(execute_file_handler(uri.clone(), &root, &headers))
+                .unwrap();
+            assert_eq!(response.headers()[header::CONTENT_TYPE], "image/svg+xml");
+            response.headers().get(header::CONTENT_ENCODING).cloned()
+        };
+        assert_eq!(encoding(Some("gzip, br")).unwrap(), "br");
+        assert_eq!(encoding(Some("gzip")).unwrap(), "gzip");
+        assert_eq!(encoding(None), None);
+    }
+
+    #[test]
+    fn client_hint_sizes() {
+        let scaled = |dpr, hint_width| {
+            let mut resize = resize_spec(400, 300);
+            scale_for_hints(&mut resize, dpr, hint_width);
+            (resize.width, resize.height)
+        };
+        assert_eq!(scaled(None, None), (400, 300));
+        assert_eq!(scaled(Some((2.0, (2000, 2000))), None), (800, 600));
+        // Rounded to half steps and capped by the source.
+        assert_eq!(scaled(Some((2.1, (2000, 2000))), None), (800, 600));
+        assert_eq!(scaled(Some((3.0, (600, 600))), None), (600, 450));
+        assert_eq!(scaled(Some((2.0, (200, 200))), None), (400, 300));
+        // The layout width wins, rounded up to 32px.
+        assert_eq!(scaled(None, Some(190)), (192, 144));
+        assert_eq!(scaled(Some((2.0, (2000, 2000))), Some(500)), (512, 384));
+        assert_eq!(scaled(None, Some(1000)), (400, 300));
+    }
+
+    #[test]
+    fn redirects_stay_on_site() {
+        let (_, optimizer) = test_root("leptos_image_redirect");
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let redirect = |src| runtime.block_on(redirect_to_source(&optimizer, src));
+        let location = |src| redirect(src).headers().get(header::LOCATION).cloned();
+
+        assert_eq!(location("/ferris.png"), Some(HeaderValue::from_static("/ferris.png")));
+        assert_eq!(location("/./a//b.png"), Some(HeaderValue::from_static("/a/b.png")));
+        for src in ["//evil.example/x.png", "/\\evil.example/x.png", "https://evil.example/x.png"] {
+            assert_eq!(redirect(src).status(), StatusCode::FORBIDDEN, "{src}");
+        }
+        assert_eq!(redirect("/../x.png").status(), StatusCode::FORBIDDEN);
+    }
+}
@@ -0,0 +1,166 @@
+use crate::optimizer::{collect_files, is_cache_entry, tenant_dir, ImageOptimizer};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Snapshot of the optimizer caches and counters, see [`ImageOptimizer::stats`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct CacheStats {
+    /// Blur placeholders held in memory.
+    pub blur_entries: usize,
+    /// Size of the in-memory blur placeholders.
+    pub blur_bytes: u64,
+    /// Cached files on disk by extension, e.g. `webp`.
+    pub disk: BTreeMap<String, FormatStats>,
+    /// Requests served from an existing cache file.
+    pub hits: u64,
+    /// Requests that had to create an image.
+    pub misses: u64,
+    /// Encodes running right now.
+    pub in_flight: usize,
+    /// Images created since startup.
+    pub encodes: u64,
+    /// Time spent creating images since startup, in milliseconds.
+    pub total_encode_ms: u64,
+    /// Images that failed to decode or encode since startup.
+    pub encode_errors: u64,
+    /// Encodes that ran longer than [`ImageOptimizer::with_encode_timeout`] since startup.
+    pub encode_timeouts: u64,
+    /// Optimized images held in memory, see [`ImageOptimizer::with_memory_cache`].
+    pub memory_entries: u64,
+    /// Size of the images held in memory.
+    pub memory_bytes: u64,
+    /// Requests served from memory.
+    pub memory_hits: u64,
+    /// Requests that looked in memory and had to read the file.
+    pub memory_misses: u64,
+}
+
+/// Disk usage of one output format.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct FormatStats {
+    /// Number of cached files.
+    pub entries: usize,
+    /// Total size of the cached files.
+    pub bytes: u64,
+}
+
+/// Counters updated by the optimizer.
+#[derive(Debug, Default)]
+pub(crate) struct Metrics {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    encodes: AtomicU64,
+    encode_micros: AtomicU64,
+    encode_errors: AtomicU64,
+    encode_timeouts: AtomicU64,
+    memory_hits: AtomicU64,
+    memory_misses: AtomicU64,
+}
+
+impl Metrics {
+    pub(crate) fn hit(&self) {
+        self.hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn miss(&self) {
+        self.misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[cfg_attr(not(feature = "memory-cache"), allow(dead_code))]
+    pub(crate) fn memory_hit(&self) {
+        self.memory_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[cfg_attr(not(feature = "memory-cache"), allow(dead_code))]
+    pub(crate) fn memory_miss(&self) {
+        self.memory_misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn encode_failed(&self) {
+        self.encode_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn encode_timed_out(&self) {
+        self.encode_timeouts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn encoded(&self, elapsed: Duration) {
+        self.encodes.fetch_add(1, Ordering::Relaxed);
+        self.encode_micros
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+}
+
+impl ImageOptimizer {
+    /// Collects cache statistics, e.g. for a health dashboard.
+    ///
+    /// Walks the disk cache, so call it from a blocking context in async code.
+    pub fn stats(&self) -> CacheStats {
+        let metrics = &self.metrics;
+        #[cfg(feature = "memory-cache")]
+        let (memory_entries, memory_bytes) = self
+            .memory
+            .as_ref()
+            .map(|memory| (memory.entry_count(), memory.weighted_size()))
+            .unwrap_or_default();
+        #[cfg(not(feature = "memory-cache"))]
+        let (memory_entries, memory_bytes) = (0, 0);
+        CacheStats {
+            blur_entries: self.cache.len(),
+            blur_bytes: self.cache.iter().map(|entry| entry.value().len() as u64).sum(),
+            disk: self.disk_stats(&self.cache_dir),
+            hits: metrics.hits.load(Ordering::Relaxed),
+            misses: metrics.misses.load(Ordering::Relaxed),
+            in_flight: self.lifecycle.active(),
+            encodes: metrics.encodes.load(Ordering::Relaxed),
+            total_encode_ms: metrics.encode_micros.load(Ordering::Relaxed) / 1000,
+            encode_errors: metrics.encode_errors.load(Ordering::Relaxed),
+            encode_timeouts: metrics.encode_timeouts.load(Ordering::Relaxed),
+            memory_entries,
+            memory_bytes,
+            memory_hits: metrics.memory_hits.load(Ordering::Relaxed),
+            memory_misses: metrics.memory_misses.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Cache statistics of one tenant, see [`crate::provide_image_tenant`].
+    ///
+    /// Only the blur and disk figures are scoped to the tenant, counters are left at zero.
+    pub fn tenant_stats(&self, tenant: &str) -> CacheStats {
+        let blurs = self
+            .cache
+            .iter()
+            .filter(|entry| entry.key().tenant.as_deref() == Some(tenant))
+            .map(|entry| entry.value().len() as u64);
+        let (blur_entries, blur_bytes) = blurs.fold((0, 0), |(n, b), len| (n + 1, b + len));
+        CacheStats {
+            blur_entries,
+            blur_bytes,
+            disk: self.disk_stats(&self.cache_file(tenant_dir(tenant))),
+            ..Default::default()
+        }
+    }
+
+    // Disk usage by extension below a cache directory.
+    fn disk_stats(&self, dir: &Path) -> BTreeMap<String, FormatStats> {
+        let mut files = Vec::new();
+        if let Err(e) = collect_files(dir, &mut files) {
+            tracing::warn!("Failed to read image cache directory: {e}");
+        }
+
+        let mut disk = BTreeMap::<String, FormatStats>::new();
+        for file in files.iter().filter(|file| is_cache_entry(file)) {
+            let extension = file
+                .extension()
+                .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+                .unwrap_or_default();
+            let format = disk.entry(extension).or_default();
+            format.entries += 1;
+            format.bytes += std::fs::metadata(file).map(|m| m.len()).unwrap_or_default();
+        }
+        disk
+    }
+}
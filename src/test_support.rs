@@ -0,0 +1,34 @@
+//! Fixtures shared by the test modules.
+
+use crate::optimizer::{ImageOptimizer, Resize};
+use std::path::PathBuf;
+
+/// Source image of the tests.
+pub(crate) const TEST_IMAGE: &str = "./example/start-axum/public/cute_ferris.png";
+
+/// An empty directory `name` below the temp dir.
+pub(crate) fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(name);
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// A site root `name` below the temp dir holding `ferris.png`, and an optimizer
+/// encoding one image at a time from it.
+pub(crate) fn test_root(name: &str) -> (PathBuf, ImageOptimizer) {
+    let root = test_dir(name);
+    std::fs::copy(TEST_IMAGE, root.join("ferris.png")).unwrap();
+    let optimizer = ImageOptimizer::new("/__cache/image", root.to_string_lossy(), 1);
+    (root, optimizer)
+}
+
+/// A `width` x `height` resize at quality 75.
+pub(crate) fn resize_spec(width: u32, height: u32) -> Resize {
+    Resize {
+        quality: 75,
+        width,
+        height,
+        ..Default::default()
+    }
+}
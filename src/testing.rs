@@ -0,0 +1,156 @@
+//! Utilities to unit test views containing `<Image/>`, without an optimizer, a file
+//! system or a tokio runtime.
+//!
+//! ```
+//! use leptos::prelude::*;
+//! use leptos_image::testing::{MockOptimizer, RequestedImage};
+//! use leptos_image::Image;
+//!
+//! # fn test() {
+//! let mock = MockOptimizer::new();
+//! let html = Owner::new().with(|| {
+//!     mock.provide_context();
+//!     view! { <Image src="/ferris.png" width=100 height=100 alt="Ferris"/> }.to_html()
+//! });
+//! assert!(mock.requested(&html).contains(&RequestedImage::Optimized {
+//!     src: "/ferris.png".to_string(),
+//!     options: mock.resize(100, 100, 75),
+//! }));
+//! # }
+//! ```
+//!
+//! Resources still need an executor, e.g. `Executor::init_futures_executor()` from
+//! `leptos::task`, once per test binary.
+
+use crate::optimizer::{CachedImage, CachedImageOption};
+use crate::provider::ImageConfig;
+use crate::{CacheKey, DefaultCacheKey, Resize};
+use leptos::prelude::*;
+
+/// Stands in for the image cache of a server: every `<Image/>` below
+/// [`MockOptimizer::provide_context`] links to the cache handler with deterministic
+/// URLs, and no placeholder is cached.
+#[derive(Debug, Clone)]
+pub struct MockOptimizer {
+    handler_path: String,
+}
+
+impl Default for MockOptimizer {
+    fn default() -> Self {
+        Self {
+            handler_path: "/__cache/image".to_string(),
+        }
+    }
+}
+
+/// An image a rendered view requested from the cache handler.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RequestedImage {
+    /// An optimized image.
+    Optimized {
+        /// Source image as passed to `<Image/>`.
+        src: String,
+        /// Requested options.
+        options: Resize,
+    },
+    /// A placeholder of a source.
+    Placeholder {
+        /// Source image as passed to `<Image/>`.
+        src: String,
+    },
+}
+
+impl MockOptimizer {
+    /// A mock with the default `/__cache/image` handler path.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the handler path the URLs are built with.
+    pub fn with_handler_path(mut self, handler_path: impl Into<String>) -> Self {
+        self.handler_path = handler_path.into();
+        self
+    }
+
+    /// Provides the image context, in place of [`crate::provide_image_context`].
+    pub fn provide_context(&self) {
+        let config = ImageConfig {
+            api_handler_path: self.handler_path.clone(),
+            asset_prefix: String::new(),
+            cache: Vec::new(),
+        };
+        let resource = Resource::new_blocking(
+            || (),
+            move |_| {
+                let config = config.clone();
+                async move { config }
+            },
+        );
+        provide_context(resource);
+    }
+
+    /// Options `<Image width height quality/>` requests with the other props left at
+    /// their defaults, to compare with [`RequestedImage::Optimized`].
+    pub fn resize(&self, width: u32, height: u32, quality: u8) -> Resize {
+        Resize {
+            width,
+            height,
+            quality,
+            ..Default::default()
+        }
+    }
+
+    /// URL of an optimized image, as rendered by `<Image/>`.
+    pub fn url(&self, src: impl Into<String>, options: Resize) -> String {
+        let image = CachedImage {
+            src: src.into(),
+            tenant: None,
+            option: CachedImageOption::Resize(options),
+        };
+        DefaultCacheKey.url(&image, &self.handler_path)
+    }
+
+    /// Images requested by rendered HTML, in order of appearance, including the
+    /// placeholders and preloads.
+    pub fn requested(&self, html: &str) -> Vec<RequestedImage> {
+        let prefix = format!("{}?", self.handler_path);
+        html.match_indices(&prefix)
+            .filter_map(|(start, _)| {
+                let query = &html[start + prefix.len()..];
+                let end = query
+                    .find(|c: char| matches!(c, '"' | '\'' | ')' | ' ' | '<' | '>'))
+                    .unwrap_or(query.len());
+                let query = query[..end].replace("&amp;", "&");
+                serde_qs::from_str::<CachedImage>(&query).ok()
+            })
+            .map(|image| match image.option {
+                CachedImageOption::Resize(options) => RequestedImage::Optimized {
+                    src: image.src,
+                    options,
+                },
+                CachedImageOption::Blur(_) => RequestedImage::Placeholder { src: image.src },
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod testing_tests {
+    use super::*;
+
+    #[test]
+    fn parses_requested_urls() {
+        let mock = MockOptimizer::new();
+        let url = mock.url("/a.png", mock.resize(100, 50, 75)).replace('&', "&amp;");
+        let html = format!(r#"<img src="{url}" style="background-image: url({url})">"#);
+        let requested = mock.requested(&html);
+        assert_eq!(requested.len(), 2);
+        assert_eq!(
+            requested[0],
+            RequestedImage::Optimized {
+                src: "/a.png".to_string(),
+                options: mock.resize(100, 50, 75),
+            }
+        );
+    }
+}
@@ -0,0 +1,91 @@
+use crate::optimizer::ImageOptimizer;
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use std::path::{Path, PathBuf};
+
+/// Watches the source images of an [`ImageOptimizer`] and purges their cached
+/// derivatives when they change or are deleted.
+///
+/// Stops watching when dropped.
+///
+/// ```no_run
+/// use leptos_image::*;
+///
+/// let optimizer = ImageOptimizer::new("/__cache/image", "./public", 1);
+/// // Keep the watcher alive for as long as the server runs.
+/// let _watcher = optimizer.watch().expect("Failed to watch sources");
+/// ```
+pub struct SourceWatcher {
+    _watcher: notify::RecommendedWatcher,
+}
+
+impl std::fmt::Debug for SourceWatcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SourceWatcher").finish_non_exhaustive()
+    }
+}
+
+impl ImageOptimizer {
+    /// Starts watching `root_file_path` and the mounts for changed sources,
+    /// see [`SourceWatcher`].
+    pub fn watch(&self) -> notify::Result<SourceWatcher> {
+        let root = Path::new(&self.root_file_path).canonicalize()?;
+        // Created upfront so events of written images can be told apart.
+        std::fs::create_dir_all(&self.cache_dir)?;
+        let cache_dir = self.cache_dir.canonicalize()?;
+
+        // (URL prefix, directory), most specific directory first.
+        let mut dirs = vec![(String::new(), root)];
+        for mount in &self.mounts {
+            dirs.push((mount.prefix.clone(), mount.dir.canonicalize()?));
+        }
+        dirs.sort_by(|a, b| b.1.as_os_str().len().cmp(&a.1.as_os_str().len()));
+
+        let handler = {
+            let optimizer = self.clone();
+            let dirs = dirs.clone();
+            move |event: notify::Result<Event>| match event {
+                Ok(event) => optimizer.handle_source_event(&dirs, &cache_dir, event),
+                Err(e) => tracing::error!("Source watcher error: {e}"),
+            }
+        };
+        let mut watcher = notify::recommended_watcher(handler)?;
+        for (_, dir) in &dirs {
+            watcher.watch(dir, RecursiveMode::Recursive)?;
+            tracing::info!("Watching {} for changed images", dir.display());
+        }
+
+        Ok(SourceWatcher { _watcher: watcher })
+    }
+
+    fn handle_source_event(&self, dirs: &[(String, PathBuf)], cache_dir: &Path, event: Event) {
+        if !matches!(
+            event.kind,
+            EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+        ) {
+            return;
+        }
+        for path in event.paths.iter().filter(|path| !path.starts_with(cache_dir)) {
+            let Some(src) = dirs
+                .iter()
+                .find_map(|(prefix, dir)| source_of(prefix, dir, path))
+            else {
+                continue;
+            };
+            if let Err(e) = self.purge_source(&src) {
+                tracing::error!("Failed to purge cached images of {src}: {e}");
+            }
+        }
+    }
+}
+
+// The `src` an image is requested with.
+fn source_of(prefix: &str, dir: &Path, path: &Path) -> Option<String> {
+    let relative: PathBuf = path.strip_prefix(dir).ok()?.to_path_buf();
+    let is_image = image::ImageFormat::from_path(&relative).is_ok()
+        || relative.extension().is_some_and(|ext| ext == "svg");
+    let relative = relative.to_string_lossy().replace('\\', "/");
+    is_image.then(|| match prefix {
+        "" => format!("/{relative}"),
+        prefix => format!("/{prefix}/{relative}"),
+    })
+}